@@ -101,14 +101,10 @@ fn main() -> anyhow::Result<()> {
     }
 
     // Create a (forward) pipeline for our triangle
-    let my_mesh_pipeline_handle = graphics.create_forward_pipeline(
-        my_render_plan_handle,
-        0,
-        &[],
-        vertex_shader,
-        fragment_shader,
-        my_mesh_handle,
-    )?;
+    // No descriptor sets needed, the triangle doesn't use any resource
+    let my_mesh_pipeline_handle = graphics
+        .new_forward_pipeline(my_render_plan_handle, 0, my_mesh_handle)
+        .build(vertex_shader, fragment_shader)?;
 
     // Create a renderer for each render target
     let mut my_renderers_handles = Vec::with_capacity(my_render_targets_handles.len());
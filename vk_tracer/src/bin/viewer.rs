@@ -0,0 +1,287 @@
+//! Minimal model viewer: `cargo run --features viewer --bin vk-tracer-viewer -- <model.glb>`.
+//! Drag with the left button to turn around the model and scroll to zoom.
+
+use anyhow::Context;
+use nalgebra_glm as glm;
+use std::{io::Cursor, path::PathBuf, time::Instant};
+use vk_tracer::{
+    ash::vk::ShaderStageFlags,
+    mem::ImageViewFatHandle,
+    prelude::*,
+    shaderc::{OptimizationLevel, ShaderKind},
+    utils::{Camera, OrbitController, ShaderCompiler},
+};
+use winit::{
+    event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent},
+    event_loop::{ControlFlow, EventLoop},
+    window::WindowBuilder,
+};
+
+const VERTEX_GLSL: &str = include_str!("../../examples/shaders/model.vert.glsl");
+const FRAGMENT_GLSL: &str = include_str!("../../examples/shaders/model.frag.glsl");
+
+#[derive(Copy, Clone, Uniform)]
+struct CameraUbo {
+    mvp: glsl_layout::mat4,
+    light_position: glsl_layout::vec3,
+}
+
+fn get_camera_ubo(camera: &Camera) -> CameraUbo {
+    CameraUbo {
+        mvp: camera.compute_mvp(&glm::identity()).into(),
+        light_position: glm::vec3(-7.0, 5.0, 5.0).into(),
+    }
+}
+
+/// Everything that depends on the size of the swapchain.
+struct Targets {
+    depth_image: ImageViewFatHandle,
+    render_targets: Vec<RenderTargetHandle>,
+    renderers: Vec<RendererHandle>,
+}
+
+fn main() -> anyhow::Result<()> {
+    env_logger::init();
+
+    let model_path = std::env::args()
+        .nth(1)
+        .map(PathBuf::from)
+        .context("Usage: vk-tracer-viewer <model.glb>")?;
+
+    let (vertex_shader, fragment_shader) = {
+        let mut compiler = ShaderCompiler::new()?;
+        compiler.set_optimization_level(OptimizationLevel::Performance);
+        (
+            compiler.compile_source(VERTEX_GLSL, "model.vert", ShaderKind::Vertex, "main")?,
+            compiler.compile_source(FRAGMENT_GLSL, "model.frag", ShaderKind::Fragment, "main")?,
+        )
+    };
+
+    let event_loop = EventLoop::new();
+    let window = WindowBuilder::new()
+        .with_title(format!("vk-tracer viewer - {}", model_path.display()))
+        .with_resizable(true)
+        .build(&event_loop)?;
+
+    let mut graphics = VkTracerApp::builder()
+        .pick_best_physical_device()
+        .with_app_info("vk-tracer viewer".into(), (1, 0, 0))
+        .with_debug_utils()
+        .build(Some((&window, window.inner_size().into())))?;
+    let swapchain = graphics.create_swapchain_with_surface()?;
+    let model = graphics.load_dropped_file::<VertexXyzUvNorm>(&model_path)?;
+
+    let mut camera = Camera::new_perspective(glm::vec3(5.0, 4.0, 4.0), glm::zero(), 1.0, 70.0);
+    camera.aspect_auto(window.inner_size().into());
+    let mut controller = OrbitController::looking_at(glm::vec3(5.0, 4.0, 4.0), glm::zero());
+
+    // One UBO per frame in flight, so the camera of the next frame can be written while the
+    // previous ones are still rendering
+    let frames_in_flight = graphics.get_swapchain_frames_in_flight(swapchain)?;
+    let camera_ubos = (0..frames_in_flight)
+        .map(|_| graphics.create_ubo([get_camera_ubo(&camera).std140()]))
+        .collect::<Result<Vec<_>>>()?;
+    let descriptor_sets = (0..frames_in_flight)
+        .fold(graphics.new_descriptor_sets(), |pool, _| {
+            pool.new_set(
+                DescriptorSetBuilder::new()
+                    .ubo(0, ShaderStageFlags::VERTEX | ShaderStageFlags::FRAGMENT),
+            )
+        })
+        .build()?;
+    for (set, ubo) in descriptor_sets.iter().zip(camera_ubos.iter()) {
+        graphics.write_descriptor_set_ubo(*set, 0, *ubo)?;
+    }
+
+    let swapchain_images = graphics.get_images_from_swapchain(swapchain)?;
+    let depth_image = graphics.create_transient_depth_texture(swapchain)?;
+
+    let render_plan = graphics
+        .new_render_plan()
+        .add_subpass(
+            SubpassBuilder::new()
+                .graphics()
+                .color_attachments([0])
+                .depth_stencil_attachment(1),
+            Some(
+                SubpassDependency::builder()
+                    .src_subpass(SUBPASS_EXTERNAL)
+                    .dst_subpass(0)
+                    .src_stage_mask(
+                        PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                            | PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+                    )
+                    .src_access_mask(AccessFlags::empty())
+                    .dst_stage_mask(
+                        PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                            | PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+                    )
+                    .dst_access_mask(
+                        AccessFlags::COLOR_ATTACHMENT_WRITE
+                            | AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                    )
+                    .build(),
+            ),
+        )
+        .add_color_attachment_present(swapchain_images[0])?
+        .set_clear_color(0, [0.1, 0.1, 0.2, 1.0])
+        .add_depth_attachment(depth_image)?
+        .set_clear_depth_stencil(1, 1.0, 0)
+        .build()?;
+
+    let pipeline = graphics.create_forward_pipeline(
+        render_plan,
+        0,
+        &descriptor_sets[..1],
+        Cursor::new(vertex_shader),
+        Cursor::new(fragment_shader),
+        model,
+    )?;
+
+    let render_targets = swapchain_images
+        .into_iter()
+        .map(|image| graphics.allocate_render_target(render_plan, &[image, depth_image]))
+        .collect::<Result<Vec<_>>>()?;
+
+    // Recorded at each frame to bind the camera of the frame in flight
+    let renderers = render_targets
+        .iter()
+        .copied()
+        .map(|render_target| {
+            let descriptor_sets = descriptor_sets.clone();
+            graphics
+                .new_renderer_from_plan(render_plan, render_target)
+                .record_each_frame(move |frame| {
+                    let descriptor_set = descriptor_sets[frame.frame_index()];
+                    frame
+                        .graphics()
+                        .bind_pipeline(pipeline)?
+                        .bind_descriptor_sets(0, &[descriptor_set])?
+                        .bind_mesh(model)?
+                        .draw_mesh(1)?;
+                    Ok(())
+                })
+                .build()
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut targets = Targets {
+        depth_image,
+        render_targets,
+        renderers,
+    };
+    let mut last_frame = Instant::now();
+    event_loop.run(move |event, _, control| {
+        *control = ControlFlow::Poll;
+
+        match event {
+            Event::MainEventsCleared => {
+                let now = Instant::now();
+                controller.update(&mut camera, (now - last_frame).as_secs_f32());
+                last_frame = now;
+
+                let (render_target_index, should_recreate_swapchain) = graphics
+                    .get_next_swapchain_render_target_index(swapchain)
+                    .unwrap();
+
+                // The previous submission of this frame is done, its UBO is free
+                let frame_index = graphics.get_swapchain_frame_index(swapchain).unwrap();
+                graphics
+                    .update_ubo(camera_ubos[frame_index], [get_camera_ubo(&camera).std140()])
+                    .unwrap();
+
+                let should_recreate_swapchain = graphics
+                    .render_and_present(
+                        targets.renderers[render_target_index as usize],
+                        swapchain,
+                        render_target_index,
+                    )
+                    .unwrap()
+                    || should_recreate_swapchain;
+
+                if should_recreate_swapchain {
+                    recreate_swapchain(
+                        &mut graphics,
+                        window.inner_size().into(),
+                        swapchain,
+                        render_plan,
+                        &mut targets,
+                    )
+                    .unwrap();
+                }
+            }
+            Event::WindowEvent {
+                event: WindowEvent::CloseRequested,
+                ..
+            } => *control = ControlFlow::Exit,
+            Event::WindowEvent {
+                event:
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(VirtualKeyCode::Escape),
+                                ..
+                            },
+                        ..
+                    },
+                ..
+            } => *control = ControlFlow::Exit,
+            Event::WindowEvent {
+                event: WindowEvent::Resized(new_size),
+                ..
+            } => {
+                recreate_swapchain(
+                    &mut graphics,
+                    new_size.into(),
+                    swapchain,
+                    render_plan,
+                    &mut targets,
+                )
+                .unwrap();
+                camera.aspect_auto(new_size.into());
+            }
+            Event::WindowEvent { event, .. } => {
+                controller.handle_window_event(&event);
+            }
+            _ => (),
+        }
+    })
+}
+
+fn recreate_swapchain(
+    graphics: &mut VkTracerApp,
+    new_size: (u32, u32),
+    swapchain: SwapchainHandle,
+    render_plan: RenderPlanHandle,
+    targets: &mut Targets,
+) -> anyhow::Result<()> {
+    // Waits for the frames in flight, nothing uses the old depth image after that
+    graphics.recreate_swapchain(swapchain, new_size)?;
+
+    let swapchain_images = graphics.get_images_from_swapchain(swapchain)?;
+    let old_depth_image = targets.depth_image;
+    targets.depth_image = graphics.create_transient_depth_texture(swapchain)?;
+    for (render_target, image) in targets
+        .render_targets
+        .iter()
+        .zip(swapchain_images.into_iter())
+    {
+        graphics.recreate_render_target(
+            render_plan,
+            new_size,
+            *render_target,
+            [image, targets.depth_image],
+        )?;
+    }
+    for (renderer, render_target) in targets
+        .renderers
+        .iter()
+        .copied()
+        .zip(targets.render_targets.iter().copied())
+    {
+        graphics.recreate_renderer(renderer, render_target)?;
+    }
+    graphics.destroy_depth_texture(old_depth_image)?;
+    Ok(())
+}
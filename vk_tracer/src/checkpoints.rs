@@ -0,0 +1,219 @@
+//! # GPU checkpoints
+//! Every debug label (passes, pipelines) is also a checkpoint written by the GPU as it executes
+//! the commands, with `VK_NV_device_diagnostic_checkpoints` or `VK_AMD_buffer_marker`. When the
+//! device is lost, the last checkpoints tell which work hung the GPU.
+
+use crate::{
+    command_recorder::QueueType, errors::Result, mem::RawBufferAllocation, setup::Adapter,
+    VkTracerApp,
+};
+use ash::{
+    version::{DeviceV1_0, InstanceV1_0},
+    vk,
+};
+use log::error;
+use parking_lot::Mutex;
+use std::{collections::HashMap, ffi::CStr, os::raw::c_void};
+
+pub(crate) fn checkpoint_extension_names() -> [&'static CStr; 2] {
+    [
+        vk::NvDeviceDiagnosticCheckpointsFn::name(),
+        vk::AmdBufferMarkerFn::name(),
+    ]
+}
+
+/// A checkpoint reached by the GPU.
+#[derive(Clone, Debug)]
+pub struct Checkpoint {
+    /// Unknown with `VK_AMD_buffer_marker`, which is shared by all the queues.
+    pub queue: Option<QueueType>,
+    /// TOP_OF_PIPE when the work after the checkpoint started, BOTTOM_OF_PIPE when the work
+    /// before it finished.
+    pub stage: vk::PipelineStageFlags,
+    pub label: String,
+}
+
+enum CheckpointWriter {
+    Nv(vk::NvDeviceDiagnosticCheckpointsFn),
+    /// Two markers: the last started and the last finished.
+    Amd(vk::AmdBufferMarkerFn, RawBufferAllocation),
+}
+
+pub(crate) struct Checkpoints {
+    writer: CheckpointWriter,
+    /// Markers are indices in there, labels are interned as passes and pipelines repeat.
+    labels: Mutex<(Vec<String>, HashMap<String, u32>)>,
+}
+
+impl Checkpoints {
+    /// None when the adapter supports none of the extensions.
+    pub(crate) fn new(
+        instance: &ash::Instance,
+        adapter: &Adapter,
+        device: &ash::Device,
+        vma: &vk_mem::Allocator,
+    ) -> Result<Option<Self>> {
+        let enabled = |name: &CStr| {
+            adapter
+                .info
+                .optional_extensions
+                .iter()
+                .any(|extension| *extension == name)
+        };
+        let load = |name: &CStr| unsafe {
+            std::mem::transmute(instance.get_device_proc_addr(device.handle(), name.as_ptr()))
+        };
+
+        let writer = if enabled(vk::NvDeviceDiagnosticCheckpointsFn::name()) {
+            CheckpointWriter::Nv(vk::NvDeviceDiagnosticCheckpointsFn::load(load))
+        } else if enabled(vk::AmdBufferMarkerFn::name()) {
+            let markers = RawBufferAllocation::new_readback_buffer(vma, 2 * 4)?;
+            CheckpointWriter::Amd(vk::AmdBufferMarkerFn::load(load), markers)
+        } else {
+            return Ok(None);
+        };
+
+        Ok(Some(Self {
+            writer,
+            labels: Mutex::new((Vec::new(), HashMap::new())),
+        }))
+    }
+
+    fn marker(&self, label: String) -> u32 {
+        let mut labels = self.labels.lock();
+        let (names, indices) = &mut *labels;
+        if let Some(marker) = indices.get(&label) {
+            return *marker;
+        }
+        let marker = names.len() as u32;
+        names.push(label.clone());
+        indices.insert(label, marker);
+        marker
+    }
+
+    pub(crate) fn write(&self, commands: vk::CommandBuffer, label: String) {
+        let marker = self.marker(label);
+        unsafe {
+            match &self.writer {
+                CheckpointWriter::Nv(fns) => {
+                    fns.cmd_set_checkpoint_nv(commands, marker as usize as *const c_void);
+                }
+                CheckpointWriter::Amd(fns, markers) => {
+                    fns.cmd_write_buffer_marker_amd(
+                        commands,
+                        vk::PipelineStageFlags::TOP_OF_PIPE,
+                        markers.buffer,
+                        0,
+                        marker,
+                    );
+                    fns.cmd_write_buffer_marker_amd(
+                        commands,
+                        vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                        markers.buffer,
+                        4,
+                        marker,
+                    );
+                }
+            }
+        }
+    }
+
+    pub(crate) fn destroy(self, vma: &vk_mem::Allocator) -> Result<()> {
+        match self.writer {
+            CheckpointWriter::Nv(_) => Ok(()),
+            CheckpointWriter::Amd(_, markers) => markers.destroy(vma),
+        }
+    }
+}
+
+impl VkTracerApp {
+    /// The last checkpoints reached by the GPU, meaningful after
+    /// [crate::errors::VkTracerError::DeviceLost]. Empty when the device supports none of the
+    /// extensions.
+    pub fn last_checkpoints(&self) -> Result<Vec<Checkpoint>> {
+        let checkpoints = match self.checkpoints.as_ref() {
+            Some(checkpoints) => checkpoints,
+            None => return Ok(Vec::new()),
+        };
+        let labels = checkpoints.labels.lock();
+        let label = |marker: u32| {
+            labels
+                .0
+                .get(marker as usize)
+                .cloned()
+                .unwrap_or_else(|| format!("Unknown checkpoint {}", marker))
+        };
+
+        match &checkpoints.writer {
+            CheckpointWriter::Nv(fns) => {
+                let mut result = Vec::new();
+                // Queues of the same family are the same queue
+                let mut visited = Vec::new();
+                for (ty, (queue, _)) in self.command_pools.iter() {
+                    if visited.contains(queue) {
+                        continue;
+                    }
+                    visited.push(*queue);
+
+                    unsafe {
+                        let mut count = 0;
+                        fns.get_queue_checkpoint_data_nv(*queue, &mut count, std::ptr::null_mut());
+                        let mut data = vec![vk::CheckpointDataNV::default(); count as usize];
+                        fns.get_queue_checkpoint_data_nv(*queue, &mut count, data.as_mut_ptr());
+                        data.truncate(count as usize);
+
+                        result.extend(data.iter().map(|data| Checkpoint {
+                            queue: Some(*ty),
+                            stage: data.stage,
+                            label: label(data.p_checkpoint_marker as usize as u32),
+                        }));
+                    }
+                }
+                Ok(result)
+            }
+            CheckpointWriter::Amd(_, markers) => {
+                let (need_to_unmap, mapped) = markers.ensure_mapped(&self.vma)?;
+                let (started, finished) = unsafe {
+                    let mapped = mapped as *const u32;
+                    (
+                        std::ptr::read_volatile(mapped),
+                        std::ptr::read_volatile(mapped.add(1)),
+                    )
+                };
+                if need_to_unmap {
+                    self.vma.unmap_memory(&markers.allocation)?;
+                }
+
+                Ok(vec![
+                    Checkpoint {
+                        queue: None,
+                        stage: vk::PipelineStageFlags::TOP_OF_PIPE,
+                        label: label(started),
+                    },
+                    Checkpoint {
+                        queue: None,
+                        stage: vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                        label: label(finished),
+                    },
+                ])
+            }
+        }
+    }
+
+    /// Log the last checkpoints, see [VkTracerApp::last_checkpoints].
+    pub(crate) fn dump_checkpoints(&self) {
+        match self.last_checkpoints() {
+            Ok(checkpoints) if !checkpoints.is_empty() => {
+                error!("Last GPU checkpoints:");
+                for checkpoint in checkpoints {
+                    error!(
+                        " - {:?} {:?}: {}",
+                        checkpoint.queue, checkpoint.stage, checkpoint.label
+                    );
+                }
+            }
+            Ok(_) => {}
+            Err(err) => error!("Can't read the GPU checkpoints ({})", err),
+        }
+    }
+}
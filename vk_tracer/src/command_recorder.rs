@@ -1,5 +1,1391 @@
+use crate::{
+    errors::{HandleType, Result, VkTracerError},
+    mem::{format_aspect, is_format_supported, BufferHandle, ImageViewFatHandle},
+    raytracing::RAYTRACING_STAGES,
+    render::{push_constants_as_bytes, PushConstants},
+    submission::DroppedCommands,
+    synchronization2::Submission,
+    ComputePipelineHandle, DescriptorSetHandle, RaytracingPipelineHandle, VkTracerApp,
+};
+use ash::{version::DeviceV1_0, vk};
+use log::warn;
+use std::slice::from_ref;
+
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
 pub enum QueueType {
     Graphics,
     Transfer,
+    /// The graphics queue when the device has no dedicated compute queue.
+    Compute,
+    /// The graphics queue when it can present to the surface, otherwise the first queue
+    /// family that can.
+    Present,
+}
+
+impl VkTracerApp {
+    pub(crate) fn queue_and_pool(&self, ty: QueueType) -> Result<(vk::Queue, vk::CommandPool)> {
+        self.command_pools
+            .get(&ty)
+            .copied()
+            .ok_or(VkTracerError::NoQueueAvailable(ty))
+    }
+
+    pub(crate) fn queue_family(&self, ty: QueueType) -> u32 {
+        let info = &self.adapter.info;
+        match ty {
+            QueueType::Graphics => info.graphics_queue.index,
+            QueueType::Transfer => info.transfer_queue.index,
+            QueueType::Compute => info.compute_queue.index,
+            QueueType::Present => info.present_queue.index,
+        }
+    }
+
+    /// Whether [QueueType::Compute] is a dedicated queue family that runs asynchronously
+    /// from graphics, instead of the graphics queue itself.
+    pub fn has_dedicated_compute_queue(&self) -> bool {
+        let info = &self.adapter.info;
+        info.compute_queue.index != info.graphics_queue.index
+    }
+
+    /// Record compute work to submit on `queue`, usually [QueueType::Compute]. Resources are
+    /// owned by one queue family at a time, see [ComputeRecorder::acquire_buffer].
+    pub fn new_compute_recorder(&mut self, queue: QueueType) -> Result<ComputeRecorder> {
+        let commands = OneTimeCommands::begin(self, queue)?;
+        Ok(ComputeRecorder {
+            app: self,
+            commands,
+            bound_layout: None,
+            bound_shader_binding_table: None,
+            submitted: false,
+        })
+    }
+}
+
+impl VkTracerApp {
+    /// Record copies to submit on `queue`. Images used by the graphics queue should be copied
+    /// on [QueueType::Graphics] as no queue ownership transfer is done.
+    pub fn new_transfer_recorder(&mut self, queue: QueueType) -> Result<TransferRecorder> {
+        let commands = OneTimeCommands::begin(self, queue)?;
+        Ok(TransferRecorder {
+            app: self,
+            queue,
+            commands,
+            submitted: false,
+        })
+    }
+}
+
+/// A command buffer recorded once, submitted and waited on.
+pub(crate) struct OneTimeCommands {
+    queue: vk::Queue,
+    pool: vk::CommandPool,
+    pub(crate) family: u32,
+    pub(crate) buffer: vk::CommandBuffer,
+}
+
+impl OneTimeCommands {
+    pub(crate) fn begin(app: &mut VkTracerApp, queue: QueueType) -> Result<Self> {
+        let family = app.queue_family(queue);
+        let (queue, pool) = app.queue_and_pool(queue)?;
+        let buffer = app.get_command_buffer(pool)?;
+
+        unsafe {
+            app.device.begin_command_buffer(
+                buffer,
+                &vk::CommandBufferBeginInfo::builder()
+                    .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT),
+            )?;
+        }
+
+        Ok(Self {
+            queue,
+            pool,
+            family,
+            buffer,
+        })
+    }
+
+    /// Submitted later in a batch, see [VkTracerApp::flush_submissions].
+    pub(crate) fn defer(&self, app: &mut VkTracerApp) -> Result<()> {
+        unsafe {
+            app.device.end_command_buffer(self.buffer)?;
+        }
+        app.defer_commands(self.queue, self.pool, self.buffer);
+        Ok(())
+    }
+
+    pub(crate) fn submit(&self, app: &mut VkTracerApp) -> Result<()> {
+        unsafe {
+            app.device.end_command_buffer(self.buffer)?;
+        }
+        submit_and_wait(app, self.queue, self.pool, vec![self.buffer])
+    }
+
+    /// Submitted alone without waiting, the caller polls the returned commands.
+    fn submit_unwaited(&self, app: &mut VkTracerApp) -> Result<SubmittedCommands> {
+        let fence = app.get_fence()?;
+        unsafe {
+            app.device.end_command_buffer(self.buffer)?;
+            app.queue_submit(
+                self.queue,
+                from_ref(&Submission {
+                    commands: from_ref(&self.buffer),
+                    ..Default::default()
+                }),
+                fence,
+            )?;
+        }
+        Ok(SubmittedCommands {
+            fence,
+            pool: self.pool,
+            buffer: self.buffer,
+        })
+    }
+
+    /// Never submitted, the command buffer is reset to be recorded again.
+    pub(crate) fn discard(&self, app: &mut VkTracerApp) {
+        let reset = unsafe {
+            app.device
+                .reset_command_buffer(self.buffer, vk::CommandBufferResetFlags::empty())
+        };
+        match reset {
+            Ok(()) => app.recycle_command_buffers(self.pool, Some(self.buffer)),
+            Err(err) => warn!("Failed to reset an unsubmitted command buffer ({})", err),
+        }
+    }
+}
+
+/// Commands running on the device, nothing waits on them.
+pub(crate) struct SubmittedCommands {
+    fence: vk::Fence,
+    pool: vk::CommandPool,
+    buffer: vk::CommandBuffer,
+}
+
+impl SubmittedCommands {
+    pub(crate) fn is_complete(&self, app: &VkTracerApp) -> Result<bool> {
+        Ok(unsafe { app.device.get_fence_status(self.fence)? })
+    }
+
+    pub(crate) fn wait(&self, app: &VkTracerApp) -> Result<()> {
+        unsafe {
+            app.device
+                .wait_for_fences(from_ref(&self.fence), true, u64::MAX)?;
+        }
+        Ok(())
+    }
+
+    /// The commands must be complete.
+    pub(crate) fn recycle(self, app: &mut VkTracerApp) -> Result<()> {
+        app.recycle_fence(self.fence)?;
+        app.recycle_command_buffers(self.pool, Some(self.buffer));
+        Ok(())
+    }
+}
+
+/// Submit command buffers of the same queue at once and wait for them, they are recycled after.
+fn submit_and_wait(
+    app: &mut VkTracerApp,
+    queue: vk::Queue,
+    pool: vk::CommandPool,
+    buffers: Vec<vk::CommandBuffer>,
+) -> Result<()> {
+    let fence = app.get_fence()?;
+    unsafe {
+        app.queue_submit(
+            queue,
+            from_ref(&Submission {
+                commands: &buffers,
+                ..Default::default()
+            }),
+            fence,
+        )?;
+        app.device
+            .wait_for_fences(from_ref(&fence), true, u64::MAX)?;
+    }
+
+    app.recycle_fence(fence)?;
+    app.recycle_command_buffers(pool, buffers);
+    Ok(())
+}
+
+pub struct ComputeRecorder<'app> {
+    app: &'app mut VkTracerApp,
+    commands: OneTimeCommands,
+    bound_layout: Option<vk::PipelineLayout>,
+    /// When the bound pipeline is a raytracing pipeline.
+    bound_shader_binding_table: Option<[vk::StridedDeviceAddressRegionKHR; 3]>,
+    submitted: bool,
+}
+
+impl Drop for ComputeRecorder<'_> {
+    fn drop(&mut self) {
+        // Dropped on an error or without submitting
+        if !self.submitted {
+            self.commands.discard(self.app);
+        }
+    }
+}
+
+impl ComputeRecorder<'_> {
+    /// Bind the pipeline along with its descriptor sets and push constants.
+    pub fn bind_pipeline(mut self, pipeline: ComputePipelineHandle) -> Result<Self> {
+        // Each pipeline gets its own label region
+        if self.bound_layout.is_some() {
+            self.app.end_debug_label(self.commands.buffer);
+        }
+        self.app
+            .begin_debug_label(self.commands.buffer, format_args!("{:?}", pipeline));
+
+        let pipeline = storage_access!(
+            self.app.compute_pipeline_storage,
+            pipeline,
+            HandleType::ComputePipeline
+        );
+        let device = &self.app.device;
+        let commands = self.commands.buffer;
+
+        unsafe {
+            device.cmd_bind_pipeline(commands, vk::PipelineBindPoint::COMPUTE, pipeline.pipeline);
+
+            if !pipeline.descriptor_sets.is_empty() {
+                device.cmd_bind_descriptor_sets(
+                    commands,
+                    vk::PipelineBindPoint::COMPUTE,
+                    pipeline.pipeline_layout,
+                    0,
+                    &pipeline.descriptor_sets,
+                    &pipeline.dynamic_offsets,
+                );
+            }
+
+            if let Some(data) = pipeline.push_constants.as_ref() {
+                device.cmd_push_constants(
+                    commands,
+                    pipeline.pipeline_layout,
+                    vk::ShaderStageFlags::COMPUTE,
+                    0,
+                    data,
+                );
+            }
+        }
+
+        self.bound_layout = Some(pipeline.pipeline_layout);
+        self.bound_shader_binding_table = None;
+        Ok(self)
+    }
+
+    /// Bind a raytracing pipeline along with its descriptor sets and push constants, the
+    /// next traces use its shader binding table.
+    pub fn bind_raytracing_pipeline(mut self, pipeline: RaytracingPipelineHandle) -> Result<Self> {
+        if self.bound_layout.is_some() {
+            self.app.end_debug_label(self.commands.buffer);
+        }
+        self.app
+            .begin_debug_label(self.commands.buffer, format_args!("{:?}", pipeline));
+
+        let pipeline = storage_access!(
+            self.app.raytracing_pipeline_storage,
+            pipeline,
+            HandleType::RaytracingPipeline
+        );
+        let device = &self.app.device;
+        let commands = self.commands.buffer;
+
+        unsafe {
+            device.cmd_bind_pipeline(
+                commands,
+                vk::PipelineBindPoint::RAY_TRACING_KHR,
+                pipeline.pipeline,
+            );
+
+            if !pipeline.descriptor_sets.is_empty() {
+                device.cmd_bind_descriptor_sets(
+                    commands,
+                    vk::PipelineBindPoint::RAY_TRACING_KHR,
+                    pipeline.pipeline_layout,
+                    0,
+                    &pipeline.descriptor_sets,
+                    &[],
+                );
+            }
+
+            if let Some(data) = pipeline.push_constants.as_ref() {
+                device.cmd_push_constants(
+                    commands,
+                    pipeline.pipeline_layout,
+                    RAYTRACING_STAGES,
+                    0,
+                    data,
+                );
+            }
+        }
+
+        self.bound_layout = Some(pipeline.pipeline_layout);
+        self.bound_shader_binding_table = Some(pipeline.shader_binding_table.regions);
+        Ok(self)
+    }
+
+    /// The bind point and push constant stages of the bound pipeline.
+    fn bound_stages(&self) -> (vk::PipelineBindPoint, vk::ShaderStageFlags) {
+        match self.bound_shader_binding_table {
+            Some(_) => (vk::PipelineBindPoint::RAY_TRACING_KHR, RAYTRACING_STAGES),
+            None => (
+                vk::PipelineBindPoint::COMPUTE,
+                vk::ShaderStageFlags::COMPUTE,
+            ),
+        }
+    }
+
+    /// Replace the descriptor sets of the bound pipeline, starting at set `first_set`.
+    /// The sets must have the same layouts as the ones given to the pipeline.
+    pub fn bind_descriptor_sets(
+        self,
+        first_set: u32,
+        descriptor_sets: &[DescriptorSetHandle],
+    ) -> Result<Self> {
+        self.bind_descriptor_sets_with_offsets(first_set, descriptor_sets, &[])
+    }
+
+    /// Same as [ComputeRecorder::bind_descriptor_sets], with one offset per dynamic buffer of
+    /// the sets.
+    pub fn bind_descriptor_sets_with_offsets(
+        self,
+        first_set: u32,
+        descriptor_sets: &[DescriptorSetHandle],
+        dynamic_offsets: &[u32],
+    ) -> Result<Self> {
+        let layout = self.bound_layout.ok_or(VkTracerError::NoPipelineBound)?;
+        let (bind_point, _) = self.bound_stages();
+        let dynamic_types = self.app.dynamic_descriptor_types(descriptor_sets)?;
+        self.app
+            .check_dynamic_offsets(&dynamic_types, dynamic_offsets)?;
+
+        let mut sets = Vec::with_capacity(descriptor_sets.len());
+        for handle in descriptor_sets.iter().copied() {
+            sets.push(
+                storage_access!(
+                    self.app.descriptor_set_storage,
+                    handle,
+                    HandleType::DescriptorSet
+                )
+                .handle,
+            );
+        }
+
+        unsafe {
+            self.app.device.cmd_bind_descriptor_sets(
+                self.commands.buffer,
+                bind_point,
+                layout,
+                first_set,
+                &sets,
+                dynamic_offsets,
+            );
+        }
+        Ok(self)
+    }
+
+    /// Replace the push constants of the bound pipeline for the next dispatches.
+    pub fn push_constants<P: PushConstants>(self, data: &P) -> Result<Self> {
+        let layout = self.bound_layout.ok_or(VkTracerError::NoPipelineBound)?;
+        let (_, stages) = self.bound_stages();
+
+        unsafe {
+            self.app.device.cmd_push_constants(
+                self.commands.buffer,
+                layout,
+                stages,
+                0,
+                push_constants_as_bytes(data)?,
+            );
+        }
+        Ok(self)
+    }
+
+    /// Insert a global memory barrier, typically between two dispatches where the second reads
+    /// what the first wrote.
+    pub fn barrier(
+        self,
+        src_stage: vk::PipelineStageFlags,
+        src_access: vk::AccessFlags,
+        dst_stage: vk::PipelineStageFlags,
+        dst_access: vk::AccessFlags,
+    ) -> Self {
+        let barrier = vk::MemoryBarrier::builder()
+            .src_access_mask(src_access)
+            .dst_access_mask(dst_access);
+
+        unsafe {
+            self.app.cmd_pipeline_barrier(
+                self.commands.buffer,
+                src_stage,
+                dst_stage,
+                from_ref(&barrier),
+                &[],
+                &[],
+            );
+        }
+        self
+    }
+
+    /// Set `size` bytes from `offset` to the repeated `data`, or up to the end of the buffer
+    /// when `None`, both multiples of 4. Typically to reset counters before a dispatch,
+    /// followed by [ComputeRecorder::barrier].
+    pub fn fill_buffer(
+        self,
+        dst: impl Into<BufferHandle>,
+        offset: vk::DeviceSize,
+        size: Option<vk::DeviceSize>,
+        data: u32,
+    ) -> Result<Self> {
+        cmd_fill_buffer(
+            self.app,
+            self.commands.buffer,
+            dst.into(),
+            offset,
+            size,
+            data,
+        )?;
+        Ok(self)
+    }
+
+    /// Write a small amount of data (a multiple of 4, at most 65536 bytes) inline in the
+    /// command buffer, at an offset multiple of 4.
+    pub fn update_buffer<T: Copy>(
+        self,
+        dst: impl Into<BufferHandle>,
+        offset: vk::DeviceSize,
+        data: &[T],
+    ) -> Result<Self> {
+        cmd_update_buffer(self.app, self.commands.buffer, dst.into(), offset, data)?;
+        Ok(self)
+    }
+
+    /// Full barrier changing the layout of the whole image, before or after a dispatch.
+    pub fn image_barrier(
+        self,
+        image: ImageViewFatHandle,
+        old_layout: vk::ImageLayout,
+        new_layout: vk::ImageLayout,
+    ) -> Self {
+        let barrier = vk::ImageMemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::MEMORY_WRITE)
+            .dst_access_mask(vk::AccessFlags::MEMORY_READ | vk::AccessFlags::MEMORY_WRITE)
+            .old_layout(old_layout)
+            .new_layout(new_layout)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(image.handle)
+            .subresource_range(
+                vk::ImageSubresourceRange::builder()
+                    .aspect_mask(format_aspect(image.format))
+                    .level_count(1)
+                    .layer_count(1)
+                    .build(),
+            );
+
+        unsafe {
+            self.app.cmd_pipeline_barrier(
+                self.commands.buffer,
+                vk::PipelineStageFlags::ALL_COMMANDS,
+                vk::PipelineStageFlags::ALL_COMMANDS,
+                &[],
+                &[],
+                from_ref(&barrier),
+            );
+        }
+        self
+    }
+
+    /// Take the ownership of a buffer last used on `from`, which released it with
+    /// [ComputeRecorder::release_buffer] or [TransferRecorder::release_buffer]. Needed with a
+    /// dedicated compute queue, nothing is recorded when both queues share a family.
+    pub fn acquire_buffer(self, buffer: impl Into<BufferHandle>, from: QueueType) -> Result<Self> {
+        let src_family = self.app.queue_family(from);
+        cmd_buffer_ownership(
+            self.app,
+            self.commands.buffer,
+            buffer.into(),
+            src_family,
+            self.commands.family,
+            false,
+        )?;
+        Ok(self)
+    }
+
+    /// Give the ownership of a buffer to `to`, where it must then be acquired before its use.
+    pub fn release_buffer(self, buffer: impl Into<BufferHandle>, to: QueueType) -> Result<Self> {
+        let dst_family = self.app.queue_family(to);
+        cmd_buffer_ownership(
+            self.app,
+            self.commands.buffer,
+            buffer.into(),
+            self.commands.family,
+            dst_family,
+            true,
+        )?;
+        Ok(self)
+    }
+
+    /// Same as [ComputeRecorder::acquire_buffer] for an image, the layouts must be the same as
+    /// the ones of the release. When both queues share a family, the release alone transitions
+    /// the layout.
+    pub fn acquire_image(
+        self,
+        image: ImageViewFatHandle,
+        old_layout: vk::ImageLayout,
+        new_layout: vk::ImageLayout,
+        from: QueueType,
+    ) -> Self {
+        let src_family = self.app.queue_family(from);
+        cmd_image_ownership(
+            self.app,
+            self.commands.buffer,
+            image,
+            (old_layout, new_layout),
+            src_family,
+            self.commands.family,
+            false,
+        );
+        self
+    }
+
+    /// Same as [ComputeRecorder::release_buffer] for an image, the layout can change at the
+    /// same time.
+    pub fn release_image(
+        self,
+        image: ImageViewFatHandle,
+        old_layout: vk::ImageLayout,
+        new_layout: vk::ImageLayout,
+        to: QueueType,
+    ) -> Self {
+        let dst_family = self.app.queue_family(to);
+        cmd_image_ownership(
+            self.app,
+            self.commands.buffer,
+            image,
+            (old_layout, new_layout),
+            self.commands.family,
+            dst_family,
+            true,
+        );
+        self
+    }
+
+    /// Barrier between two dispatches of this recorder.
+    pub fn compute_barrier(self) -> Self {
+        self.barrier(
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::AccessFlags::SHADER_WRITE,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+        )
+    }
+
+    pub fn dispatch(
+        self,
+        group_count_x: u32,
+        group_count_y: u32,
+        group_count_z: u32,
+    ) -> Result<Self> {
+        if self.bound_layout.is_none() {
+            return Err(VkTracerError::NoPipelineBound);
+        }
+
+        unsafe {
+            self.app.device.cmd_dispatch(
+                self.commands.buffer,
+                group_count_x,
+                group_count_y,
+                group_count_z,
+            );
+        }
+        Ok(self)
+    }
+
+    /// Launch `width * height * depth` raygen invocations of the bound raytracing pipeline.
+    pub fn trace_rays(self, width: u32, height: u32, depth: u32) -> Result<Self> {
+        let regions = self
+            .bound_shader_binding_table
+            .ok_or(VkTracerError::NoPipelineBound)?;
+        let (loader, _) = self.app.raytracing_pipeline_fns()?;
+
+        unsafe {
+            loader.cmd_trace_rays(
+                self.commands.buffer,
+                from_ref(&regions[0]),
+                from_ref(&regions[1]),
+                from_ref(&regions[2]),
+                &[vk::StridedDeviceAddressRegionKHR::default()],
+                width,
+                height,
+                depth,
+            );
+        }
+        Ok(self)
+    }
+
+    /// Submit the recorded work and wait for it to complete.
+    pub fn submit(mut self) -> Result<()> {
+        if self.bound_layout.is_some() {
+            self.app.end_debug_label(self.commands.buffer);
+        }
+        self.submitted = true;
+        self.commands.submit(self.app)
+    }
+
+    /// Submit the recorded work along with other deferred work, without waiting.
+    /// Graphics work submitted later waits for it.
+    pub fn submit_deferred(mut self) -> Result<()> {
+        if self.bound_layout.is_some() {
+            self.app.end_debug_label(self.commands.buffer);
+        }
+        self.submitted = true;
+        self.commands.defer(self.app)
+    }
+}
+
+pub struct TransferRecorder<'app> {
+    app: &'app mut VkTracerApp,
+    queue: QueueType,
+    commands: OneTimeCommands,
+    submitted: bool,
+}
+
+impl Drop for TransferRecorder<'_> {
+    fn drop(&mut self) {
+        // Dropped on an error or without submitting
+        if !self.submitted {
+            self.commands.discard(self.app);
+        }
+    }
+}
+
+impl TransferRecorder<'_> {
+    /// Copy `size` bytes, which must fit in both buffers, or as much as fits when `None`.
+    pub fn copy_buffer(
+        self,
+        src: impl Into<BufferHandle>,
+        dst: impl Into<BufferHandle>,
+        size: Option<vk::DeviceSize>,
+    ) -> Result<Self> {
+        let src = self.app.get_buffer(src.into())?;
+        let dst = self.app.get_buffer(dst.into())?;
+        let size = size.unwrap_or_else(|| src.real_size.min(dst.real_size));
+        check_buffer_bounds(0, size, src.real_size)?;
+        check_buffer_bounds(0, size, dst.real_size)?;
+
+        let region = vk::BufferCopy::builder().size(size);
+        unsafe {
+            self.app.device.cmd_copy_buffer(
+                self.commands.buffer,
+                src.buffer,
+                dst.buffer,
+                from_ref(&region),
+            );
+        }
+        Ok(self)
+    }
+
+    /// Copy between buffers that aren't behind a handle, like the ones of a mesh.
+    pub(crate) fn copy_raw_buffer(
+        self,
+        src: vk::Buffer,
+        dst: vk::Buffer,
+        size: vk::DeviceSize,
+    ) -> Self {
+        let region = vk::BufferCopy::builder().size(size);
+        unsafe {
+            self.app
+                .device
+                .cmd_copy_buffer(self.commands.buffer, src, dst, from_ref(&region));
+        }
+        self
+    }
+
+    /// Set `size` bytes from `offset` to the repeated `data`, or up to the end of the buffer
+    /// when `None`, both multiples of 4.
+    pub fn fill_buffer(
+        self,
+        dst: impl Into<BufferHandle>,
+        offset: vk::DeviceSize,
+        size: Option<vk::DeviceSize>,
+        data: u32,
+    ) -> Result<Self> {
+        cmd_fill_buffer(
+            self.app,
+            self.commands.buffer,
+            dst.into(),
+            offset,
+            size,
+            data,
+        )?;
+        Ok(self)
+    }
+
+    /// Write a small amount of data (a multiple of 4, at most 65536 bytes) inline in the
+    /// command buffer without a staging buffer, at an offset multiple of 4.
+    pub fn update_buffer<T: Copy>(
+        self,
+        dst: impl Into<BufferHandle>,
+        offset: vk::DeviceSize,
+        data: &[T],
+    ) -> Result<Self> {
+        cmd_update_buffer(self.app, self.commands.buffer, dst.into(), offset, data)?;
+        Ok(self)
+    }
+
+    /// Copy a buffer into a region of an image in `old_layout` (can be UNDEFINED to discard its
+    /// content), the image is left in `final_layout`.
+    pub fn copy_buffer_to_image(
+        self,
+        src: impl Into<BufferHandle>,
+        dst: ImageViewFatHandle,
+        region: vk::BufferImageCopy,
+        old_layout: vk::ImageLayout,
+        final_layout: vk::ImageLayout,
+    ) -> Result<Self> {
+        let src = self.app.get_buffer(src.into())?;
+
+        self.transition(
+            dst,
+            &region.image_subresource,
+            old_layout,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        );
+        unsafe {
+            self.app.device.cmd_copy_buffer_to_image(
+                self.commands.buffer,
+                src.buffer,
+                dst.handle,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                from_ref(&region),
+            );
+        }
+        self.transition(
+            dst,
+            &region.image_subresource,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            final_layout,
+        );
+
+        Ok(self)
+    }
+
+    /// Copy a region of an image in `old_layout` into a buffer, the image is left in
+    /// `final_layout`.
+    pub fn copy_image_to_buffer(
+        self,
+        src: ImageViewFatHandle,
+        dst: impl Into<BufferHandle>,
+        region: vk::BufferImageCopy,
+        old_layout: vk::ImageLayout,
+        final_layout: vk::ImageLayout,
+    ) -> Result<Self> {
+        let dst = self.app.get_buffer(dst.into())?;
+
+        self.transition(
+            src,
+            &region.image_subresource,
+            old_layout,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        );
+        unsafe {
+            self.app.device.cmd_copy_image_to_buffer(
+                self.commands.buffer,
+                src.handle,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                dst.buffer,
+                from_ref(&region),
+            );
+        }
+        self.transition(
+            src,
+            &region.image_subresource,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            final_layout,
+        );
+
+        Ok(self)
+    }
+
+    /// Scaled copy between two images, for example to generate mip levels with a linear filter.
+    /// Empty aspect masks in `region` are deduced from the formats, depth and stencil formats
+    /// are always blitted with a nearest filter. Only available on a queue with graphics support,
+    /// typically [QueueType::Graphics].
+    pub fn blit_image(
+        self,
+        src: ImageViewFatHandle,
+        src_layouts: (vk::ImageLayout, vk::ImageLayout),
+        dst: ImageViewFatHandle,
+        dst_layouts: (vk::ImageLayout, vk::ImageLayout),
+        mut region: vk::ImageBlit,
+        filter: vk::Filter,
+    ) -> Result<Self> {
+        self.check_graphics_queue()?;
+
+        let aspect = format_aspect(src.format);
+        fill_aspect(&mut region.src_subresource, aspect);
+        fill_aspect(&mut region.dst_subresource, format_aspect(dst.format));
+        if region.src_subresource.aspect_mask != region.dst_subresource.aspect_mask {
+            return Err(VkTracerError::MismatchedBlitAspects(
+                region.src_subresource.aspect_mask,
+                region.dst_subresource.aspect_mask,
+            ));
+        }
+
+        let filter = if aspect == vk::ImageAspectFlags::COLOR {
+            filter
+        } else {
+            vk::Filter::NEAREST
+        };
+
+        let mut src_features = vk::FormatFeatureFlags::BLIT_SRC;
+        if filter == vk::Filter::LINEAR {
+            src_features |= vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR;
+        }
+        self.check_format(src.format, src_features)?;
+        self.check_format(dst.format, vk::FormatFeatureFlags::BLIT_DST)?;
+
+        self.before_two_images(
+            src,
+            &region.src_subresource,
+            src_layouts.0,
+            dst,
+            &region.dst_subresource,
+            dst_layouts.0,
+        );
+        unsafe {
+            self.app.device.cmd_blit_image(
+                self.commands.buffer,
+                src.handle,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                dst.handle,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                from_ref(&region),
+                filter,
+            );
+        }
+        self.after_two_images(
+            src,
+            &region.src_subresource,
+            src_layouts.1,
+            dst,
+            &region.dst_subresource,
+            dst_layouts.1,
+        );
+
+        Ok(self)
+    }
+
+    /// Resolve a multisampled color image into a single sampled one, outside of a render pass.
+    /// Only available on a queue with graphics support.
+    pub fn resolve_image(
+        self,
+        src: ImageViewFatHandle,
+        src_layouts: (vk::ImageLayout, vk::ImageLayout),
+        dst: ImageViewFatHandle,
+        dst_layouts: (vk::ImageLayout, vk::ImageLayout),
+        mut region: vk::ImageResolve,
+    ) -> Result<Self> {
+        self.check_graphics_queue()?;
+
+        fill_aspect(&mut region.src_subresource, vk::ImageAspectFlags::COLOR);
+        fill_aspect(&mut region.dst_subresource, vk::ImageAspectFlags::COLOR);
+
+        self.before_two_images(
+            src,
+            &region.src_subresource,
+            src_layouts.0,
+            dst,
+            &region.dst_subresource,
+            dst_layouts.0,
+        );
+        unsafe {
+            self.app.device.cmd_resolve_image(
+                self.commands.buffer,
+                src.handle,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                dst.handle,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                from_ref(&region),
+            );
+        }
+        self.after_two_images(
+            src,
+            &region.src_subresource,
+            src_layouts.1,
+            dst,
+            &region.dst_subresource,
+            dst_layouts.1,
+        );
+
+        Ok(self)
+    }
+
+    /// Blits and resolves need a queue family with graphics support.
+    fn check_graphics_queue(&self) -> Result<()> {
+        let family = &self.app.adapter.info.physical_device_info.queue_families
+            [self.commands.family as usize];
+        if family.queue_flags.contains(vk::QueueFlags::GRAPHICS) {
+            Ok(())
+        } else {
+            Err(VkTracerError::NoGraphicsSupport(self.queue))
+        }
+    }
+
+    fn check_format(&self, format: vk::Format, features: vk::FormatFeatureFlags) -> Result<()> {
+        if is_format_supported(self.app, format, vk::ImageTiling::OPTIMAL, features) {
+            Ok(())
+        } else {
+            Err(VkTracerError::UnsupportedFormatFeatures(format, features))
+        }
+    }
+
+    fn before_two_images(
+        &self,
+        src: ImageViewFatHandle,
+        src_subresource: &vk::ImageSubresourceLayers,
+        src_layout: vk::ImageLayout,
+        dst: ImageViewFatHandle,
+        dst_subresource: &vk::ImageSubresourceLayers,
+        dst_layout: vk::ImageLayout,
+    ) {
+        self.transition(
+            src,
+            src_subresource,
+            src_layout,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        );
+        self.transition(
+            dst,
+            dst_subresource,
+            dst_layout,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        );
+    }
+
+    fn after_two_images(
+        &self,
+        src: ImageViewFatHandle,
+        src_subresource: &vk::ImageSubresourceLayers,
+        src_layout: vk::ImageLayout,
+        dst: ImageViewFatHandle,
+        dst_subresource: &vk::ImageSubresourceLayers,
+        dst_layout: vk::ImageLayout,
+    ) {
+        self.transition(
+            src,
+            src_subresource,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            src_layout,
+        );
+        self.transition(
+            dst,
+            dst_subresource,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            dst_layout,
+        );
+    }
+
+    /// Full barrier, what comes before and after the copy isn't known.
+    fn transition(
+        &self,
+        image: ImageViewFatHandle,
+        subresource: &vk::ImageSubresourceLayers,
+        old_layout: vk::ImageLayout,
+        new_layout: vk::ImageLayout,
+    ) {
+        let barrier = vk::ImageMemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::MEMORY_WRITE)
+            .dst_access_mask(vk::AccessFlags::MEMORY_READ | vk::AccessFlags::MEMORY_WRITE)
+            .old_layout(old_layout)
+            .new_layout(new_layout)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(image.handle)
+            .subresource_range(
+                vk::ImageSubresourceRange::builder()
+                    .aspect_mask(subresource.aspect_mask)
+                    .base_mip_level(subresource.mip_level)
+                    .level_count(1)
+                    .base_array_layer(subresource.base_array_layer)
+                    .layer_count(subresource.layer_count)
+                    .build(),
+            );
+
+        unsafe {
+            self.app.cmd_pipeline_barrier(
+                self.commands.buffer,
+                vk::PipelineStageFlags::ALL_COMMANDS,
+                vk::PipelineStageFlags::ALL_COMMANDS,
+                &[],
+                &[],
+                from_ref(&barrier),
+            );
+        }
+    }
+
+    /// Take the ownership of a buffer last used on `from`, see [ComputeRecorder::acquire_buffer].
+    pub fn acquire_buffer(self, buffer: impl Into<BufferHandle>, from: QueueType) -> Result<Self> {
+        let src_family = self.app.queue_family(from);
+        cmd_buffer_ownership(
+            self.app,
+            self.commands.buffer,
+            buffer.into(),
+            src_family,
+            self.commands.family,
+            false,
+        )?;
+        Ok(self)
+    }
+
+    /// Give the ownership of a buffer to `to`, see [ComputeRecorder::release_buffer].
+    pub fn release_buffer(self, buffer: impl Into<BufferHandle>, to: QueueType) -> Result<Self> {
+        let dst_family = self.app.queue_family(to);
+        cmd_buffer_ownership(
+            self.app,
+            self.commands.buffer,
+            buffer.into(),
+            self.commands.family,
+            dst_family,
+            true,
+        )?;
+        Ok(self)
+    }
+
+    /// Take the ownership of an image last used on `from`, see [ComputeRecorder::acquire_image].
+    pub fn acquire_image(
+        self,
+        image: ImageViewFatHandle,
+        old_layout: vk::ImageLayout,
+        new_layout: vk::ImageLayout,
+        from: QueueType,
+    ) -> Self {
+        let src_family = self.app.queue_family(from);
+        cmd_image_ownership(
+            self.app,
+            self.commands.buffer,
+            image,
+            (old_layout, new_layout),
+            src_family,
+            self.commands.family,
+            false,
+        );
+        self
+    }
+
+    /// Give the ownership of an image to `to`, see [ComputeRecorder::release_image].
+    pub fn release_image(
+        self,
+        image: ImageViewFatHandle,
+        old_layout: vk::ImageLayout,
+        new_layout: vk::ImageLayout,
+        to: QueueType,
+    ) -> Self {
+        let dst_family = self.app.queue_family(to);
+        cmd_image_ownership(
+            self.app,
+            self.commands.buffer,
+            image,
+            (old_layout, new_layout),
+            self.commands.family,
+            dst_family,
+            true,
+        );
+        self
+    }
+
+    /// Submit the copies and wait for them to complete.
+    pub fn submit(mut self) -> Result<()> {
+        self.submitted = true;
+        self.commands.submit(self.app)
+    }
+
+    /// Submit the copies along with other deferred work, without waiting.
+    /// Graphics work submitted later waits for them.
+    pub fn submit_deferred(mut self) -> Result<()> {
+        self.submitted = true;
+        self.commands.defer(self.app)
+    }
+
+    /// Submit the copies on their own without waiting for them.
+    pub(crate) fn submit_unwaited(mut self) -> Result<SubmittedCommands> {
+        self.submitted = true;
+        self.commands.submit_unwaited(self.app)
+    }
+
+    /// Stop recording, the copies are submitted with others by [VkTracerApp::submit_transfers].
+    pub fn finish(mut self) -> Result<RecordedTransfer> {
+        unsafe {
+            self.app.device.end_command_buffer(self.commands.buffer)?;
+        }
+        // The recorded transfer owns the command buffer from now on
+        self.submitted = true;
+        Ok(RecordedTransfer {
+            commands: OneTimeCommands { ..self.commands },
+            dropped: self.app.submissions.dropped_commands.clone(),
+            submitted: false,
+        })
+    }
+}
+
+/// Copies waiting to be submitted, see [TransferRecorder::finish]. Dropping it without
+/// submitting gives its command buffer back to the app.
+pub struct RecordedTransfer {
+    commands: OneTimeCommands,
+    dropped: DroppedCommands,
+    submitted: bool,
+}
+
+impl Drop for RecordedTransfer {
+    fn drop(&mut self) {
+        if !self.submitted {
+            self.dropped
+                .lock()
+                .push((self.commands.pool, self.commands.buffer));
+        }
+    }
+}
+
+impl VkTracerApp {
+    /// Submit several recorded transfers at once, one submission per queue, and wait for all
+    /// of them.
+    pub fn submit_transfers(
+        &mut self,
+        transfers: impl IntoIterator<Item = RecordedTransfer>,
+    ) -> Result<()> {
+        let mut batches: Vec<(vk::Queue, vk::CommandPool, Vec<vk::CommandBuffer>)> = Vec::new();
+        for mut transfer in transfers {
+            transfer.submitted = true;
+            let commands = &transfer.commands;
+            match batches
+                .iter_mut()
+                .find(|(queue, _, _)| *queue == commands.queue)
+            {
+                Some((_, _, buffers)) => buffers.push(commands.buffer),
+                None => batches.push((commands.queue, commands.pool, vec![commands.buffer])),
+            }
+        }
+
+        let mut fences = Vec::with_capacity(batches.len());
+        for (queue, _, buffers) in batches.iter() {
+            let fence = self.get_fence()?;
+            unsafe {
+                self.queue_submit(
+                    *queue,
+                    from_ref(&Submission {
+                        commands: buffers,
+                        ..Default::default()
+                    }),
+                    fence,
+                )?;
+            }
+            fences.push(fence);
+        }
+
+        if !fences.is_empty() {
+            unsafe { self.device.wait_for_fences(&fences, true, u64::MAX)? };
+        }
+        for fence in fences {
+            self.recycle_fence(fence)?;
+        }
+        for (_, pool, buffers) in batches {
+            self.recycle_command_buffers(pool, buffers);
+        }
+        Ok(())
+    }
+}
+
+fn fill_aspect(subresource: &mut vk::ImageSubresourceLayers, aspect: vk::ImageAspectFlags) {
+    if subresource.aspect_mask.is_empty() {
+        subresource.aspect_mask = aspect;
+    }
+}
+
+/// The stages and accesses of one half of a queue family ownership transfer, the release
+/// makes the writes available and the acquire makes them visible.
+fn ownership_masks(
+    release: bool,
+) -> (
+    vk::PipelineStageFlags,
+    vk::AccessFlags,
+    vk::PipelineStageFlags,
+    vk::AccessFlags,
+) {
+    if release {
+        (
+            vk::PipelineStageFlags::ALL_COMMANDS,
+            vk::AccessFlags::MEMORY_WRITE,
+            vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+            vk::AccessFlags::empty(),
+        )
+    } else {
+        (
+            vk::PipelineStageFlags::TOP_OF_PIPE,
+            vk::AccessFlags::empty(),
+            vk::PipelineStageFlags::ALL_COMMANDS,
+            vk::AccessFlags::MEMORY_READ | vk::AccessFlags::MEMORY_WRITE,
+        )
+    }
+}
+
+/// Record the release or the acquire of a whole buffer, the resources are all EXCLUSIVE.
+fn cmd_buffer_ownership(
+    app: &VkTracerApp,
+    commands: vk::CommandBuffer,
+    buffer: BufferHandle,
+    src_family: u32,
+    dst_family: u32,
+    release: bool,
+) -> Result<()> {
+    let buffer = app.get_buffer(buffer)?;
+    if src_family == dst_family {
+        return Ok(());
+    }
+
+    let (src_stage, src_access, dst_stage, dst_access) = ownership_masks(release);
+    let barrier = vk::BufferMemoryBarrier::builder()
+        .src_access_mask(src_access)
+        .dst_access_mask(dst_access)
+        .src_queue_family_index(src_family)
+        .dst_queue_family_index(dst_family)
+        .buffer(buffer.buffer)
+        .size(vk::WHOLE_SIZE);
+    unsafe {
+        app.cmd_pipeline_barrier(commands, src_stage, dst_stage, &[], from_ref(&barrier), &[]);
+    }
+    Ok(())
+}
+
+/// Same as [cmd_buffer_ownership] for the first mip level and layer of an image.
+fn cmd_image_ownership(
+    app: &VkTracerApp,
+    commands: vk::CommandBuffer,
+    image: ImageViewFatHandle,
+    (old_layout, new_layout): (vk::ImageLayout, vk::ImageLayout),
+    src_family: u32,
+    dst_family: u32,
+    release: bool,
+) {
+    let (src_family, dst_family, (src_stage, src_access, dst_stage, dst_access)) =
+        if src_family != dst_family {
+            (src_family, dst_family, ownership_masks(release))
+        } else if release {
+            // Only the layout transition, done once by the release
+            (
+                vk::QUEUE_FAMILY_IGNORED,
+                vk::QUEUE_FAMILY_IGNORED,
+                (
+                    vk::PipelineStageFlags::ALL_COMMANDS,
+                    vk::AccessFlags::MEMORY_WRITE,
+                    vk::PipelineStageFlags::ALL_COMMANDS,
+                    vk::AccessFlags::MEMORY_READ | vk::AccessFlags::MEMORY_WRITE,
+                ),
+            )
+        } else {
+            return;
+        };
+
+    let barrier = vk::ImageMemoryBarrier::builder()
+        .src_access_mask(src_access)
+        .dst_access_mask(dst_access)
+        .old_layout(old_layout)
+        .new_layout(new_layout)
+        .src_queue_family_index(src_family)
+        .dst_queue_family_index(dst_family)
+        .image(image.handle)
+        .subresource_range(
+            vk::ImageSubresourceRange::builder()
+                .aspect_mask(format_aspect(image.format))
+                .level_count(1)
+                .layer_count(1)
+                .build(),
+        );
+    unsafe {
+        app.cmd_pipeline_barrier(commands, src_stage, dst_stage, &[], &[], from_ref(&barrier));
+    }
+}
+
+fn cmd_fill_buffer(
+    app: &VkTracerApp,
+    commands: vk::CommandBuffer,
+    dst: BufferHandle,
+    offset: vk::DeviceSize,
+    size: Option<vk::DeviceSize>,
+    data: u32,
+) -> Result<()> {
+    let dst = app.get_buffer(dst)?;
+    // Up to the end is rounded down to a multiple of 4 by the device
+    let checked_size = size.unwrap_or_else(|| dst.real_size.saturating_sub(offset) & !3);
+    check_buffer_range(offset, checked_size, dst.real_size)?;
+    unsafe {
+        app.device.cmd_fill_buffer(
+            commands,
+            dst.buffer,
+            offset,
+            size.unwrap_or(vk::WHOLE_SIZE),
+            data,
+        );
+    }
+    Ok(())
+}
+
+fn cmd_update_buffer<T: Copy>(
+    app: &VkTracerApp,
+    commands: vk::CommandBuffer,
+    dst: BufferHandle,
+    offset: vk::DeviceSize,
+    data: &[T],
+) -> Result<()> {
+    let size = std::mem::size_of_val(data);
+    if size % 4 != 0 || size > 65536 {
+        return Err(VkTracerError::InvalidBufferUpdateSize(size));
+    }
+
+    let dst = app.get_buffer(dst)?;
+    check_buffer_range(offset, size as vk::DeviceSize, dst.real_size)?;
+    let data = unsafe { std::slice::from_raw_parts(data.as_ptr() as *const u8, size) };
+    unsafe {
+        app.device
+            .cmd_update_buffer(commands, dst.buffer, offset, data);
+    }
+    Ok(())
+}
+
+/// Fills and updates write a non-empty range of whole words inside the buffer.
+/// Fills and updates also need an offset and size multiple of 4.
+fn check_buffer_range(
+    offset: vk::DeviceSize,
+    size: vk::DeviceSize,
+    buffer_size: vk::DeviceSize,
+) -> Result<()> {
+    check_buffer_bounds(offset, size, buffer_size)?;
+    if offset % 4 != 0 || size % 4 != 0 {
+        return Err(VkTracerError::InvalidBufferRange {
+            offset,
+            size,
+            buffer_size,
+        });
+    }
+    Ok(())
+}
+
+fn check_buffer_bounds(
+    offset: vk::DeviceSize,
+    size: vk::DeviceSize,
+    buffer_size: vk::DeviceSize,
+) -> Result<()> {
+    let in_bounds = offset
+        .checked_add(size)
+        .map_or(false, |end| end <= buffer_size);
+    if size == 0 || !in_bounds {
+        return Err(VkTracerError::InvalidBufferRange {
+            offset,
+            size,
+            buffer_size,
+        });
+    }
+    Ok(())
 }
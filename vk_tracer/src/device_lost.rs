@@ -0,0 +1,153 @@
+use crate::{
+    errors::{HandleType, Result, VkTracerError},
+    setup::{create_device, DeviceObjects},
+    BlasHandle, HostBufferHandle, IndirectBufferHandle, MeshHandle, StorageBufferHandle,
+    SurfaceHandle, SwapchainHandle, TexelBufferHandle, TextureHandle, UboHandle, VkTracerApp,
+};
+use ash::version::DeviceV1_0;
+use log::{info, warn};
+
+/// What [VkTracerApp::try_recover] destroyed. Every handle created before the recovery is
+/// invalid except surfaces, the content of these resources has to be uploaded again.
+#[derive(Debug, Default)]
+pub struct LostResources {
+    pub meshes: Vec<MeshHandle>,
+    pub textures: Vec<TextureHandle>,
+    pub ubos: Vec<UboHandle>,
+    pub indirect_buffers: Vec<IndirectBufferHandle>,
+    pub host_buffers: Vec<HostBufferHandle>,
+    pub storage_buffers: Vec<StorageBufferHandle>,
+    pub texel_buffers: Vec<TexelBufferHandle>,
+    pub blas: Vec<BlasHandle>,
+    /// The swapchains to create again, the surfaces are still valid.
+    pub swapchains: Vec<(SwapchainHandle, SurfaceHandle)>,
+    /// How many objects without content were destroyed, ex: pipelines.
+    pub others: Vec<(HandleType, usize)>,
+}
+
+impl LostResources {
+    fn collect(app: &VkTracerApp) -> Self {
+        let mut others = vec![
+            (HandleType::Sampler, app.sampler_storage.len()),
+            (
+                HandleType::ExternalMemory,
+                app.external_memory_storage.len(),
+            ),
+            (HandleType::RenderPlan, app.render_plan_storage.len()),
+            (HandleType::RenderTarget, app.render_target_storage.len()),
+            (
+                HandleType::OffscreenTarget,
+                app.offscreen_target_storage.len(),
+            ),
+            (HandleType::DepthTexture, app.depth_texture_storage.len()),
+            (
+                HandleType::ForwardPipeline,
+                app.forward_pipeline_storage.len(),
+            ),
+            (
+                HandleType::ComputePipeline,
+                app.compute_pipeline_storage.len(),
+            ),
+            (
+                HandleType::RaytracingPipeline,
+                app.raytracing_pipeline_storage.len(),
+            ),
+            (
+                HandleType::PipelineLibrary,
+                app.pipeline_library_storage.len(),
+            ),
+            (HandleType::Renderer, app.renderer_storage.len()),
+            (HandleType::GpuProfiler, app.gpu_profiler_storage.len()),
+            (HandleType::Fence, app.fence_storage.len()),
+            (HandleType::Semaphore, app.semaphore_storage.len()),
+            (
+                HandleType::DescriptorPool,
+                app.descriptor_pool_storage.len(),
+            ),
+            (HandleType::DescriptorSet, app.descriptor_set_storage.len()),
+            #[cfg(feature = "bindless")]
+            (HandleType::BindlessTable, app.bindless_table_storage.len()),
+            #[cfg(feature = "math")]
+            (HandleType::Tlas, app.tlas_storage.len()),
+        ];
+        others.retain(|(_, count)| *count > 0);
+
+        Self {
+            meshes: app.mesh_storage.keys().collect(),
+            textures: app.texture_storage.keys().collect(),
+            ubos: app.ubo_storage.keys().collect(),
+            indirect_buffers: app.indirect_buffer_storage.keys().collect(),
+            host_buffers: app.host_buffer_storage.keys().collect(),
+            storage_buffers: app.storage_buffer_storage.keys().collect(),
+            texel_buffers: app.texel_buffer_storage.keys().collect(),
+            blas: app.blas_storage.keys().collect(),
+            swapchains: app
+                .swapchain_storage
+                .iter()
+                .map(|(handle, swapchain)| (handle, swapchain.surface))
+                .collect(),
+            others,
+        }
+    }
+}
+
+impl VkTracerApp {
+    /// Create a new device after [VkTracerError::DeviceLost] and destroy everything created on
+    /// the lost one. The application then creates its objects again from the report. On
+    /// failure, nothing is destroyed and the recovery can be attempted again.
+    /// The device of [VkTracerApp::from_raw] belongs to the application and can't be recovered.
+    pub fn try_recover(&mut self) -> Result<LostResources> {
+        if self.external_handles {
+            return Err(VkTracerError::DeviceLost);
+        }
+
+        // Created next to the lost device first so that a failure leaves the app untouched
+        let device = create_device(
+            &self.instance,
+            &self.adapter,
+            self.device_group.as_ref(),
+            &self.extensions,
+        )?;
+        let objects =
+            match DeviceObjects::create(&self.instance, &self.adapter, &device, &self.extensions) {
+                Ok(objects) => objects,
+                Err(err) => {
+                    unsafe {
+                        device.destroy_device(None);
+                    }
+                    return Err(err);
+                }
+            };
+
+        let lost = LostResources::collect(self);
+        #[cfg(feature = "checkpoints")]
+        self.dump_checkpoints();
+        unsafe {
+            if let Err(err) = self.device.device_wait_idle() {
+                warn!("Destroying the objects of the previous device ({})", err);
+            }
+            self.destroy_device_objects();
+            self.device.destroy_device(None);
+        }
+
+        self.device = device;
+        self.full_screen_exclusive = objects.full_screen_exclusive;
+        self.display_timing = objects.display_timing;
+        self.calibrated_timestamps = objects.calibrated_timestamps;
+        self.external_memory = objects.external_memory;
+        self.raytracing = objects.raytracing;
+        self.dynamic_rendering = objects.dynamic_rendering;
+        self.synchronization2 = objects.synchronization2;
+        self.vma = objects.vma;
+        self.command_pools = objects.command_pools;
+        self.pipeline_cache = objects.pipeline_cache;
+        #[cfg(feature = "checkpoints")]
+        {
+            self.checkpoints = objects.checkpoints;
+        }
+        self.submissions = Default::default();
+
+        info!("Device recreated");
+        Ok(lost)
+    }
+}
@@ -1,7 +1,7 @@
 use crate::{
     command_recorder::QueueType,
     mesh::Mesh,
-    render::{ForwardPipeline, Renderer},
+    render::{ComputePipeline, ForwardPipeline, GpuProfiler, Renderer},
     setup::DebugUtils,
 };
 use ash::{
@@ -9,10 +9,15 @@ use ash::{
     vk,
 };
 use present::{Surface, Swapchain};
-use render::{RenderPlan, RenderTarget};
-use setup::Adapter;
+#[cfg(feature = "math")]
+use raytracing::Tlas;
+use raytracing::{Blas, PipelineLibrary, RaytracingFns, RaytracingPipeline};
+use render::{DynamicRenderingFns, RenderPlan, RenderTarget};
+use setup::{Adapter, DeviceGroup, VkTracerExtensions};
 use slotmap::{new_key_type, SlotMap};
-use std::{collections::HashMap, slice::from_ref};
+use std::collections::{HashMap, HashSet};
+use submission::SubmissionScheduler;
+use synchronization2::Synchronization2Fns;
 
 #[macro_use]
 macro_rules! storage_access {
@@ -43,22 +48,45 @@ macro_rules! storage_access_mut {
     };
 }
 
+#[cfg(feature = "checkpoints")]
+mod checkpoints;
 pub mod command_recorder;
+mod device_lost;
 pub mod mem;
 pub mod mesh;
 pub mod present;
+pub mod raytracing;
 pub mod render;
 pub mod setup;
+mod submission;
+pub mod sync;
+mod synchronization2;
 pub mod utils;
 
-use crate::mem::{DescriptorPool, DescriptorSet, RawBufferAllocation};
+use crate::mem::{
+    DescriptorLayoutKey, DescriptorPool, DescriptorSet, ExternalMemory, ExternalMemoryFns,
+    PipelineLayoutKey, RawBufferAllocation, TexelBuffer, Texture,
+};
 #[cfg(feature = "shaderc")]
 pub use ::shaderc;
 pub use ash;
+#[cfg(feature = "checkpoints")]
+pub use checkpoints::Checkpoint;
+pub use device_lost::LostResources;
 pub use glsl_layout;
 #[cfg(feature = "math")]
 pub use nalgebra_glm as glm;
 
+// So that the derives can refer to this crate as `::vk_tracer` inside of it too
+extern crate self as vk_tracer;
+
+#[doc(hidden)]
+pub mod __private {
+    pub use ash::vk;
+    pub use field_offset::offset_of;
+    pub use lazy_static::lazy_static;
+}
+
 pub const VULKAN_VERSION: u32 = ash::vk::API_VERSION_1_2;
 pub const VULKAN_VERSION_STR: &str = "1.2.0";
 
@@ -76,7 +104,10 @@ pub mod errors {
         #[error("Shaderc error: {0}")]
         ShaderCError(#[from] shaderc::Error),
         #[error("Vulkan error: {0}")]
-        Vulkan(#[from] ash::vk::Result),
+        Vulkan(ash::vk::Result),
+        /// See [crate::VkTracerApp::try_recover].
+        #[error("The device was lost")]
+        DeviceLost,
         #[error("Loading error: {0}")]
         LoadingError(#[from] ash::LoadingError),
         #[error("Instance error: {0}")]
@@ -85,149 +116,517 @@ pub mod errors {
         VmaError(#[from] vk_mem::Error),
         #[error("IO Error: {0}")]
         IoError(#[from] std::io::Error),
+        #[error("Extension {0} is not enabled")]
+        ExtensionNotEnabled(&'static str),
+        #[error("No {0:?} queue available")]
+        NoQueueAvailable(crate::command_recorder::QueueType),
+        #[error("The {0:?} queue doesn't support graphics commands")]
+        NoGraphicsSupport(crate::command_recorder::QueueType),
+        #[error("The pipeline has no push constants")]
+        NoPushConstants,
+        #[error("No pipeline is bound")]
+        NoPipelineBound,
+        #[error("{0} vertices can't be indexed with u16")]
+        TooManyVerticesForU16(usize),
+        #[error("A mesh needs at least one vertex stream, all of the same length")]
+        InvalidVertexStreams,
+        #[error("The vertex streams of a mesh can't be empty")]
+        EmptyVertexStreams,
+        #[error("No mesh nor index buffer is bound")]
+        NoMeshBound,
+        #[error("The renderer is already being recorded")]
+        RendererRecordingReentered,
+        #[error("{draw_count} indirect draws don't fit in a buffer of {size} bytes")]
+        IndirectBufferTooSmall { draw_count: u32, size: u64 },
+        #[error("Push constants are {expected} bytes, got {actual} bytes")]
+        PushConstantsSizeMismatch { expected: usize, actual: usize },
+        #[error("Push constants must be a multiple of 4 bytes, got {0} bytes")]
+        UnalignedPushConstants(usize),
+        #[error("Push constants of {size} bytes exceed the device limit of {max} bytes")]
+        PushConstantsTooLarge { size: usize, max: u32 },
+        #[error("Buffer updates must be a multiple of 4 and at most 65536 bytes, got {0} bytes")]
+        InvalidBufferUpdateSize(usize),
+        #[error("Buffer range of {size} bytes at {offset} must be non-empty and fit in the buffer ({buffer_size} bytes), fills and updates also need multiples of 4")]
+        InvalidBufferRange {
+            offset: u64,
+            size: u64,
+            buffer_size: u64,
+        },
+        #[error("The graphics queue can't present to this surface")]
+        PresentNotSupported,
         #[error("No surface available")]
         NoSurfaceAvailable,
+        #[error("The surface is still used by a swapchain")]
+        SurfaceInUse,
+        #[error("Protected swapchains aren't supported")]
+        ProtectedSwapchainNotSupported,
         #[error("No suitable adapter")]
         NoSuitableAdapterError,
+        #[error("No adapter matches the selector {0}")]
+        NoMatchingAdapter(String),
+        #[error("Missing instance extensions {extensions:?} and layers {layers:?}")]
+        MissingInstanceExtensions {
+            extensions: Vec<String>,
+            layers: Vec<String>,
+        },
+        #[error("The surface doesn't support the swapchain usages {0:?}")]
+        UnsupportedSwapchainUsage(ash::vk::ImageUsageFlags),
         #[error("No suitable format can be found")]
         NoSuitableImageFormat,
+        #[error("Expected {expected} bytes of pixels, got {actual}")]
+        TextureSizeMismatch { expected: usize, actual: usize },
+        #[error("No attachment {index} in a render plan of {count} attachments")]
+        InvalidAttachmentIndex { index: usize, count: usize },
+        #[error(
+            "Format {format:?} doesn't support {features:?}, supported alternatives: {fallbacks:?}"
+        )]
+        UnsupportedAttachmentFormat {
+            format: ash::vk::Format,
+            features: ash::vk::FormatFeatureFlags,
+            fallbacks: Vec<ash::vk::Format>,
+        },
+        #[error("The device doesn't support {0}")]
+        FeatureNotSupported(&'static str),
+        #[error("More than {0} overlay vertices in a frame")]
+        TooManyOverlayVertices(usize),
+        #[error("More than {0} profiler scopes in a frame")]
+        TooManyProfilerScopes(u32),
+        #[error("Prerecorded renderers can't be profiled, record them each frame")]
+        ProfilerNeedsEachFrame,
+        #[error("Blits can't change the aspects, from {0:?} to {1:?}")]
+        MismatchedBlitAspects(ash::vk::ImageAspectFlags, ash::vk::ImageAspectFlags),
+        #[error("Format {0:?} doesn't support {1:?}")]
+        UnsupportedFormatFeatures(ash::vk::Format, ash::vk::FormatFeatureFlags),
+        #[error("No suitable memory type")]
+        NoSuitableMemoryType,
+        #[error("The bindless table is full ({0} textures)")]
+        BindlessTableFull(u32),
+        #[error("The descriptor set has no binding {0}")]
+        NoSuchBinding(u32),
+        #[error("Binding {binding} is a {expected:?} descriptor, not {actual:?}")]
+        DescriptorTypeMismatch {
+            binding: u32,
+            expected: ash::vk::DescriptorType,
+            actual: ash::vk::DescriptorType,
+        },
+        #[error("Index {index} is out of binding {binding} of {count} descriptors")]
+        DescriptorIndexOutOfBounds {
+            binding: u32,
+            index: u32,
+            count: u32,
+        },
+        #[error("Descriptor range of {range} bytes is larger than the buffer ({size} bytes)")]
+        DescriptorRangeOutOfBounds { range: u64, size: u64 },
+        #[error("The descriptor sets have {expected} dynamic descriptors, got {actual} offsets")]
+        DynamicOffsetCountMismatch { expected: usize, actual: usize },
+        #[error("Dynamic offset {offset} isn't a multiple of {alignment}")]
+        UnalignedDynamicOffset { offset: u32, alignment: u64 },
         #[error("Invalid {0:?} handle")]
         InvalidHandle(HandleType),
+        #[error("The TLAS was created with {expected} instances, got {actual}")]
+        TlasInstanceCountMismatch { expected: usize, actual: usize },
+        #[error("The TLAS has room for {capacity} instances, got {actual}")]
+        TlasCapacityExceeded { capacity: usize, actual: usize },
+        #[error("Positions must be R32G32B32_SFLOAT to be path traced or optimized, got {0:?}")]
+        UnsupportedPositionFormat(ash::vk::Format),
+        #[error("A raytracing pipeline needs a raygen shader")]
+        NoRaygenShader,
+        #[error("Pipeline libraries can only contain hit groups")]
+        InvalidPipelineLibrary,
+        #[error("The library was built with another layout or interface than the pipeline")]
+        PipelineLibraryMismatch,
+        #[error("Unsupported asset {0:?}")]
+        UnsupportedAsset(std::path::PathBuf),
+        #[error("The upload of the asset failed")]
+        AssetUploadFailed,
         #[cfg(feature = "gltf")]
         #[error("Gltf error: {0}")]
         GltfError(#[from] gltf::Error),
+        #[cfg(feature = "mesh_optimizer")]
+        #[error("Meshoptimizer error: {0}")]
+        MeshOptimizerError(#[from] meshopt::Error),
+        #[cfg(feature = "asset_loader")]
+        #[error("Image error: {0}")]
+        ImageError(#[from] image::ImageError),
     }
 
-    #[derive(Debug)]
+    impl From<ash::vk::Result> for VkTracerError {
+        fn from(result: ash::vk::Result) -> Self {
+            match result {
+                ash::vk::Result::ERROR_DEVICE_LOST => Self::DeviceLost,
+                result => Self::Vulkan(result),
+            }
+        }
+    }
+
+    #[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
     pub enum HandleType {
         // Higher level objects
         Mesh,
         Ubo,
+        IndirectBuffer,
+        HostBuffer,
+        StorageBuffer,
+        TexelBuffer,
+        Sampler,
+        Texture,
+        ExternalMemory,
 
+        Surface,
         Swapchain,
         RenderPlan,
         RenderTarget,
+        OffscreenTarget,
+        DepthTexture,
         ForwardPipeline,
+        ComputePipeline,
+        RaytracingPipeline,
+        PipelineLibrary,
         Renderer,
+        GpuProfiler,
+        Fence,
+        Semaphore,
         DescriptorPool,
         DescriptorSet,
+        BindlessTable,
+        Blas,
+        Tlas,
+        RtInstance,
     }
 }
 
 pub mod prelude {
     #[cfg(feature = "math")]
-    pub use crate::mesh::{VertexXyz, VertexXyzUv, VertexXyzUvNorm};
+    pub use crate::mesh::{
+        VertexQuantized, VertexXyz, VertexXyzUv, VertexXyzUvNorm, VertexXyzUvNormSkin,
+        VertexXyzUvNormTan,
+    };
+    #[cfg(feature = "math")]
+    pub use crate::raytracing::{RtInstance, RtScene};
+    #[cfg(feature = "math")]
+    pub use crate::render::{Scene, SceneEntity};
     pub use crate::{
-        errors::Result, glsl_layout::Uniform, mem::DescriptorSetBuilder, mesh::MeshIndex,
-        render::SubpassBuilder, setup::VkTracerExtensions, ForwardPipelineHandle, MeshHandle,
-        RenderPlanHandle, RenderTargetHandle, RendererHandle, SwapchainHandle, VkTracerApp,
+        errors::Result,
+        glsl_layout::Uniform,
+        mem::DescriptorSetBuilder,
+        mesh::{IndexFormat, MeshIndex, MeshLoadOptions, VertexLayout},
+        render::{DepthStencilAccess, PushConstants, SubpassBuilder},
+        setup::VkTracerExtensions,
+        BlasHandle, ComputePipelineHandle, EntityHandle, ExternalMemoryHandle, FenceHandle,
+        ForwardPipelineHandle, GpuProfilerHandle, HostBufferHandle, IndirectBufferHandle,
+        MeshHandle, OffscreenTargetHandle, PipelineLibraryHandle, RaytracingPipelineHandle,
+        RenderPlanHandle, RenderTargetHandle, RendererHandle, RtInstanceHandle, SamplerHandle,
+        SemaphoreHandle, StorageBufferHandle, SurfaceHandle, SwapchainHandle, TexelBufferHandle,
+        TextureHandle, TlasHandle, VkTracerApp,
     };
     pub use ash::vk::{
-        AccessFlags, PipelineStageFlags, SubpassDependency2 as SubpassDependency, SUBPASS_EXTERNAL,
+        AccessFlags, AttachmentLoadOp, AttachmentStoreOp, PipelineStageFlags,
+        SubpassDependency2 as SubpassDependency, SUBPASS_EXTERNAL,
     };
 }
 
 new_key_type! {
     // Higher level objects
     pub struct MeshHandle;
+    pub struct EntityHandle;
     pub struct UboHandle;
+    pub struct IndirectBufferHandle;
+    pub struct HostBufferHandle;
+    pub struct StorageBufferHandle;
+    pub struct TexelBufferHandle;
+    pub struct SamplerHandle;
+    pub struct TextureHandle;
+    pub struct ExternalMemoryHandle;
 
+    pub struct SurfaceHandle;
     pub struct SwapchainHandle;
     pub struct RenderPlanHandle;
     pub struct RenderTargetHandle;
+    pub struct OffscreenTargetHandle;
     pub struct ForwardPipelineHandle;
+    pub struct ComputePipelineHandle;
+    pub struct RaytracingPipelineHandle;
+    pub struct PipelineLibraryHandle;
     pub struct RendererHandle;
+    pub struct GpuProfilerHandle;
+    pub struct FenceHandle;
+    pub struct SemaphoreHandle;
     pub struct DescriptorPoolHandle;
     pub struct DescriptorSetHandle;
+    pub struct BindlessTableHandle;
+    pub struct BlasHandle;
+    pub struct TlasHandle;
+    pub struct RtInstanceHandle;
 }
 
 pub struct VkTracerApp {
     pub(crate) entry: ash::Entry,
     pub(crate) instance: ash::Instance,
     pub(crate) debug_utils: Option<DebugUtils>,
-    pub(crate) surface: Option<Surface>,
+    pub(crate) main_surface: Option<SurfaceHandle>,
     pub(crate) adapter: Adapter,
     pub(crate) device: ash::Device,
+    pub(crate) device_group: Option<DeviceGroup>,
+    pub(crate) display: Option<ash::extensions::khr::Display>,
+    pub(crate) full_screen_exclusive: Option<vk::ExtFullScreenExclusiveFn>,
+    pub(crate) display_timing: Option<vk::GoogleDisplayTimingFn>,
+    pub(crate) calibrated_timestamps: Option<vk::ExtCalibratedTimestampsFn>,
+    pub(crate) external_memory: Option<ExternalMemoryFns>,
+    pub(crate) raytracing: Option<RaytracingFns>,
+    pub(crate) dynamic_rendering: Option<DynamicRenderingFns>,
+    pub(crate) synchronization2: Option<Synchronization2Fns>,
+    /// The instance and device belong to the application, see [VkTracerApp::from_raw].
+    pub(crate) external_handles: bool,
+    /// Kept to recreate the device, see [VkTracerApp::try_recover].
+    pub(crate) extensions: HashSet<VkTracerExtensions>,
+    #[cfg(feature = "checkpoints")]
+    pub(crate) checkpoints: Option<checkpoints::Checkpoints>,
     pub(crate) vma: vk_mem::Allocator,
     pub(crate) command_pools: HashMap<QueueType, (vk::Queue, vk::CommandPool)>,
+    /// Used by every pipeline, see [VkTracerApp::save_pipeline_cache].
+    pub(crate) pipeline_cache: vk::PipelineCache,
+    pub(crate) submissions: SubmissionScheduler,
+    /// Counts the frames recorded with [render::FrameRecorder], to tell them apart.
+    pub(crate) recorded_frames: std::cell::Cell<u64>,
 
     // Higher level objects
     pub(crate) mesh_storage: SlotMap<MeshHandle, Mesh>,
     pub(crate) ubo_storage: SlotMap<UboHandle, RawBufferAllocation>,
+    pub(crate) indirect_buffer_storage: SlotMap<IndirectBufferHandle, RawBufferAllocation>,
+    pub(crate) host_buffer_storage: SlotMap<HostBufferHandle, RawBufferAllocation>,
+    pub(crate) storage_buffer_storage: SlotMap<StorageBufferHandle, RawBufferAllocation>,
+    pub(crate) texel_buffer_storage: SlotMap<TexelBufferHandle, TexelBuffer>,
+    pub(crate) sampler_storage: SlotMap<SamplerHandle, vk::Sampler>,
+    pub(crate) texture_storage: SlotMap<TextureHandle, Texture>,
+    pub(crate) external_memory_storage: SlotMap<ExternalMemoryHandle, ExternalMemory>,
 
+    pub(crate) surface_storage: SlotMap<SurfaceHandle, Surface>,
     pub(crate) swapchain_storage: SlotMap<SwapchainHandle, Swapchain>,
     pub(crate) render_plan_storage: SlotMap<RenderPlanHandle, RenderPlan>,
     pub(crate) render_target_storage: SlotMap<RenderTargetHandle, RenderTarget>,
+    pub(crate) offscreen_target_storage: SlotMap<OffscreenTargetHandle, Texture>,
+    /// Depth attachments of the swapchains, by image.
+    pub(crate) depth_texture_storage: HashMap<vk::Image, Texture>,
     pub(crate) forward_pipeline_storage: SlotMap<ForwardPipelineHandle, ForwardPipeline>,
+    pub(crate) compute_pipeline_storage: SlotMap<ComputePipelineHandle, ComputePipeline>,
+    pub(crate) raytracing_pipeline_storage: SlotMap<RaytracingPipelineHandle, RaytracingPipeline>,
+    pub(crate) pipeline_library_storage: SlotMap<PipelineLibraryHandle, PipelineLibrary>,
     pub(crate) renderer_storage: SlotMap<RendererHandle, Renderer>,
+    pub(crate) gpu_profiler_storage: SlotMap<GpuProfilerHandle, GpuProfiler>,
+    pub(crate) fence_storage: SlotMap<FenceHandle, vk::Fence>,
+    pub(crate) semaphore_storage: SlotMap<SemaphoreHandle, vk::Semaphore>,
     pub(crate) descriptor_pool_storage: SlotMap<DescriptorPoolHandle, DescriptorPool>,
     pub(crate) descriptor_set_storage: SlotMap<DescriptorSetHandle, DescriptorSet>,
+    pub(crate) descriptor_layout_cache: HashMap<DescriptorLayoutKey, vk::DescriptorSetLayout>,
+    pub(crate) pipeline_layout_cache: HashMap<PipelineLayoutKey, vk::PipelineLayout>,
+    #[cfg(feature = "bindless")]
+    pub(crate) bindless_table_storage: SlotMap<BindlessTableHandle, mem::BindlessTable>,
+    pub(crate) blas_storage: SlotMap<BlasHandle, Blas>,
+    #[cfg(feature = "math")]
+    pub(crate) tlas_storage: SlotMap<TlasHandle, Tlas>,
 }
 
-impl Drop for VkTracerApp {
-    fn drop(&mut self) {
+impl VkTracerApp {
+    /// Destroy everything created on the device, the device must be idle or lost. The storages
+    /// are emptied, which invalidates their handles.
+    pub(crate) unsafe fn destroy_device_objects(&mut self) {
         let device = &self.device;
         let graphics_pool = self.command_pools.get(&QueueType::Graphics).unwrap();
-        let transfer_pool = self.command_pools.get(&QueueType::Transfer).unwrap();
 
-        unsafe {
-            for (_, pool) in &self.descriptor_pool_storage {
-                device.destroy_descriptor_pool(pool.handle, None);
-            }
+        self.submissions.destroy(device);
 
-            for (_, set) in &self.descriptor_set_storage {
-                device.destroy_descriptor_set_layout(set.layout, None);
-            }
+        for (_, fence) in &self.fence_storage {
+            device.destroy_fence(*fence, None);
+        }
 
-            for (_, renderer) in &self.renderer_storage {
-                device.destroy_fence(renderer.render_fence, None);
-                device.free_command_buffers(graphics_pool.1, from_ref(&renderer.main_commands));
-            }
+        for (_, semaphore) in &self.semaphore_storage {
+            device.destroy_semaphore(*semaphore, None);
+        }
 
-            for (_, pipeline) in &self.forward_pipeline_storage {
-                device.destroy_pipeline(pipeline.pipeline, None);
-                device.destroy_pipeline_layout(pipeline.pipeline_layout, None);
-            }
+        for (_, pool) in &self.descriptor_pool_storage {
+            device.destroy_descriptor_pool(pool.handle, None);
+        }
 
-            for (_, render_target) in &self.render_target_storage {
-                device.destroy_framebuffer(render_target.framebuffer, None);
-            }
+        for layout in self.descriptor_layout_cache.values() {
+            device.destroy_descriptor_set_layout(*layout, None);
+        }
 
-            for (_, render_plan) in &self.render_plan_storage {
-                device.destroy_render_pass(render_plan.render_pass, None);
-            }
+        for (_, sampler) in &self.sampler_storage {
+            device.destroy_sampler(*sampler, None);
+        }
 
-            for (_, swapchain) in &self.swapchain_storage {
-                device.destroy_semaphore(swapchain.image_available_semaphore, None);
-                for view in &swapchain.image_views {
-                    device.destroy_image_view(*view, None);
-                }
-                swapchain.loader.destroy_swapchain(swapchain.handle, None);
-            }
+        for (_, renderer) in &self.renderer_storage {
+            renderer.commands.free(device, graphics_pool.1);
+        }
 
-            for (_, ubo) in self.ubo_storage.drain() {
-                ubo.destroy(&self.vma).unwrap();
-            }
+        for (_, profiler) in &self.gpu_profiler_storage {
+            device.destroy_query_pool(profiler.pool, None);
+        }
+
+        for (_, pipeline) in &self.forward_pipeline_storage {
+            device.destroy_pipeline(pipeline.pipeline, None);
+        }
+
+        for (_, pipeline) in &self.compute_pipeline_storage {
+            device.destroy_pipeline(pipeline.pipeline, None);
+        }
 
-            for (_, mesh) in self.mesh_storage.drain() {
-                mesh.vertices.destroy(&self.vma).unwrap();
-                mesh.indices.destroy(&self.vma).unwrap();
+        for (_, pipeline) in &self.raytracing_pipeline_storage {
+            pipeline.destroy(self);
+        }
+
+        for (_, library) in &self.pipeline_library_storage {
+            device.destroy_pipeline(library.pipeline, None);
+        }
+
+        for layout in self.pipeline_layout_cache.values() {
+            device.destroy_pipeline_layout(*layout, None);
+        }
+
+        device.destroy_pipeline_cache(self.pipeline_cache, None);
+
+        for (_, render_target) in &self.render_target_storage {
+            device.destroy_framebuffer(render_target.framebuffer, None);
+        }
+
+        for (_, render_plan) in &self.render_plan_storage {
+            device.destroy_render_pass(render_plan.render_pass, None);
+        }
+
+        for (_, swapchain) in &self.swapchain_storage {
+            swapchain.destroy(device);
+        }
+
+        for (_, ubo) in self.ubo_storage.drain() {
+            ubo.destroy(&self.vma).unwrap();
+        }
+
+        for (_, buffer) in self.indirect_buffer_storage.drain() {
+            buffer.destroy(&self.vma).unwrap();
+        }
+
+        for (_, buffer) in self.host_buffer_storage.drain() {
+            buffer.destroy(&self.vma).unwrap();
+        }
+
+        for (_, buffer) in self.storage_buffer_storage.drain() {
+            buffer.destroy(&self.vma).unwrap();
+        }
+
+        for (_, texture) in self.texture_storage.drain() {
+            device.destroy_image_view(texture.view, None);
+            texture.image.destroy(&self.vma).unwrap();
+        }
+
+        for (_, target) in self.offscreen_target_storage.drain() {
+            device.destroy_image_view(target.view, None);
+            target.image.destroy(&self.vma).unwrap();
+        }
+
+        for (_, depth) in self.depth_texture_storage.drain() {
+            device.destroy_image_view(depth.view, None);
+            depth.image.destroy(&self.vma).unwrap();
+        }
+
+        for (_, texel_buffer) in self.texel_buffer_storage.drain() {
+            device.destroy_buffer_view(texel_buffer.view, None);
+            texel_buffer.buffer.destroy(&self.vma).unwrap();
+        }
+
+        for (_, memory) in &self.external_memory_storage {
+            memory.destroy(device);
+        }
+
+        #[cfg(feature = "math")]
+        for (_, tlas) in &self.tlas_storage {
+            tlas.destroy(self);
+        }
+
+        for (_, blas) in &self.blas_storage {
+            blas.destroy(self);
+        }
+
+        // Also frees the buffers the user didn't destroy
+        if let Some(raytracing) = self.raytracing.as_ref() {
+            raytracing.memory.lock().destroy(device);
+        }
+
+        for (_, mesh) in self.mesh_storage.drain() {
+            mesh.vertices.destroy(&self.vma).unwrap();
+            for stream in mesh.extra_streams {
+                stream.destroy(&self.vma).unwrap();
             }
+            mesh.indices.destroy(&self.vma).unwrap();
+        }
 
-            self.vma.destroy();
+        #[cfg(feature = "checkpoints")]
+        if let Some(checkpoints) = self.checkpoints.take() {
+            checkpoints.destroy(&self.vma).unwrap();
+        }
 
-            device.destroy_command_pool(transfer_pool.1, None);
-            device.destroy_command_pool(graphics_pool.1, None);
+        self.vma.destroy();
+
+        // Queues of the same family share their pool
+        let pools = self
+            .command_pools
+            .values()
+            .map(|(_, pool)| *pool)
+            .collect::<HashSet<_>>();
+        for pool in pools {
+            device.destroy_command_pool(pool, None);
+        }
 
-            if let Some(surface) = self.surface.as_ref() {
+        self.command_pools.clear();
+        self.descriptor_layout_cache.clear();
+        self.pipeline_layout_cache.clear();
+        self.fence_storage.clear();
+        self.semaphore_storage.clear();
+        self.descriptor_pool_storage.clear();
+        self.descriptor_set_storage.clear();
+        self.sampler_storage.clear();
+        self.renderer_storage.clear();
+        self.gpu_profiler_storage.clear();
+        self.forward_pipeline_storage.clear();
+        self.compute_pipeline_storage.clear();
+        self.raytracing_pipeline_storage.clear();
+        self.pipeline_library_storage.clear();
+        self.render_target_storage.clear();
+        self.render_plan_storage.clear();
+        self.swapchain_storage.clear();
+        self.external_memory_storage.clear();
+        #[cfg(feature = "bindless")]
+        self.bindless_table_storage.clear();
+        #[cfg(feature = "math")]
+        self.tlas_storage.clear();
+        self.blas_storage.clear();
+    }
+}
+
+impl Drop for VkTracerApp {
+    fn drop(&mut self) {
+        unsafe {
+            // Some frames may still be in flight, fails when the device is lost
+            let wait = self.device.device_wait_idle();
+            if let Err(err) = wait {
+                log::error!("Failed to wait for the device to be idle ({})", err);
+            }
+            #[cfg(feature = "checkpoints")]
+            if wait == Err(vk::Result::ERROR_DEVICE_LOST) {
+                self.dump_checkpoints();
+            }
+            self.destroy_device_objects();
+
+            for (_, surface) in &self.surface_storage {
                 surface.loader.destroy_surface(surface.handle, None);
             }
 
-            device.destroy_device(None);
-            if let Some(debug) = self.debug_utils.take() {
-                debug.destroy();
+            if !self.external_handles {
+                self.device.destroy_device(None);
+                if let Some(debug) = self.debug_utils.take() {
+                    debug.destroy();
+                }
+                self.instance.destroy_instance(None);
             }
-            self.instance.destroy_instance(None);
         }
     }
 }
@@ -1,13 +1,105 @@
+use crate::{
+    errors::{HandleType, Result},
+    HostBufferHandle, IndirectBufferHandle, StorageBufferHandle, TexelBufferHandle, UboHandle,
+    VkTracerApp,
+};
+
 mod allocator;
+#[cfg(feature = "bindless")]
+mod bindless;
 mod buffer;
 mod descriptor_set;
+mod external;
+mod host_buffer;
 mod image;
+mod indirect;
+mod sampler;
+mod storage_buffer;
+mod texel_buffer;
+mod texture;
 mod ubo;
 
 pub(crate) use allocator::*;
+#[cfg(feature = "bindless")]
+pub(crate) use bindless::*;
 pub(crate) use buffer::*;
 pub(crate) use descriptor_set::*;
+pub(crate) use external::*;
+pub(crate) use host_buffer::*;
 pub(crate) use image::*;
+pub(crate) use indirect::*;
+pub(crate) use sampler::*;
+pub(crate) use storage_buffer::*;
+pub(crate) use texel_buffer::*;
+pub(crate) use texture::*;
 pub(crate) use ubo::*;
 
+#[cfg(feature = "bindless")]
+pub use bindless::BINDLESS_TEXTURES_BINDING;
 pub use descriptor_set::DescriptorSetBuilder;
+pub use external::ExternalHandle;
+pub use image::ImageViewFatHandle;
+
+/// Any buffer that can take part in a copy.
+#[derive(Copy, Clone, Debug)]
+pub enum BufferHandle {
+    Ubo(UboHandle),
+    Indirect(IndirectBufferHandle),
+    Host(HostBufferHandle),
+    Storage(StorageBufferHandle),
+    Texel(TexelBufferHandle),
+}
+
+impl From<UboHandle> for BufferHandle {
+    fn from(handle: UboHandle) -> Self {
+        BufferHandle::Ubo(handle)
+    }
+}
+
+impl From<IndirectBufferHandle> for BufferHandle {
+    fn from(handle: IndirectBufferHandle) -> Self {
+        BufferHandle::Indirect(handle)
+    }
+}
+
+impl From<HostBufferHandle> for BufferHandle {
+    fn from(handle: HostBufferHandle) -> Self {
+        BufferHandle::Host(handle)
+    }
+}
+
+impl From<StorageBufferHandle> for BufferHandle {
+    fn from(handle: StorageBufferHandle) -> Self {
+        BufferHandle::Storage(handle)
+    }
+}
+
+impl From<TexelBufferHandle> for BufferHandle {
+    fn from(handle: TexelBufferHandle) -> Self {
+        BufferHandle::Texel(handle)
+    }
+}
+
+impl VkTracerApp {
+    pub(crate) fn get_buffer(&self, handle: BufferHandle) -> Result<&RawBufferAllocation> {
+        Ok(match handle {
+            BufferHandle::Ubo(handle) => storage_access!(self.ubo_storage, handle, HandleType::Ubo),
+            BufferHandle::Indirect(handle) => storage_access!(
+                self.indirect_buffer_storage,
+                handle,
+                HandleType::IndirectBuffer
+            ),
+            BufferHandle::Host(handle) => {
+                storage_access!(self.host_buffer_storage, handle, HandleType::HostBuffer)
+            }
+            BufferHandle::Storage(handle) => storage_access!(
+                self.storage_buffer_storage,
+                handle,
+                HandleType::StorageBuffer
+            ),
+            BufferHandle::Texel(handle) => {
+                &storage_access!(self.texel_buffer_storage, handle, HandleType::TexelBuffer).buffer
+            }
+        })
+    }
+}
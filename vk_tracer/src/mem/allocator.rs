@@ -22,7 +22,9 @@ impl RawBufferAllocation {
             vma,
             &BufferDescription {
                 size: size as vk::DeviceSize,
-                usage: vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::VERTEX_BUFFER,
+                usage: vk::BufferUsageFlags::TRANSFER_SRC
+                    | vk::BufferUsageFlags::TRANSFER_DST
+                    | vk::BufferUsageFlags::VERTEX_BUFFER,
                 location: vk_mem::MemoryUsage::GpuOnly,
             },
         )
@@ -33,7 +35,23 @@ impl RawBufferAllocation {
             vma,
             &BufferDescription {
                 size: size as vk::DeviceSize,
-                usage: vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::INDEX_BUFFER,
+                usage: vk::BufferUsageFlags::TRANSFER_SRC
+                    | vk::BufferUsageFlags::TRANSFER_DST
+                    | vk::BufferUsageFlags::INDEX_BUFFER,
+                location: vk_mem::MemoryUsage::GpuOnly,
+            },
+        )
+    }
+
+    pub(crate) fn new_indirect_buffer(vma: &vk_mem::Allocator, size: usize) -> Result<Self> {
+        Self::new(
+            vma,
+            &BufferDescription {
+                size: size as vk::DeviceSize,
+                usage: vk::BufferUsageFlags::TRANSFER_SRC
+                    | vk::BufferUsageFlags::TRANSFER_DST
+                    | vk::BufferUsageFlags::INDIRECT_BUFFER
+                    | vk::BufferUsageFlags::STORAGE_BUFFER,
                 location: vk_mem::MemoryUsage::GpuOnly,
             },
         )
@@ -50,17 +68,57 @@ impl RawBufferAllocation {
         )
     }
 
+    pub(crate) fn new_readback_buffer(vma: &vk_mem::Allocator, size: usize) -> Result<Self> {
+        Self::new(
+            vma,
+            &BufferDescription {
+                size: size as vk::DeviceSize,
+                usage: vk::BufferUsageFlags::TRANSFER_DST,
+                location: vk_mem::MemoryUsage::GpuToCpu,
+            },
+        )
+    }
+
     pub(crate) fn new_uniform_buffer(vma: &vk_mem::Allocator, size: usize) -> Result<Self> {
         Self::new(
             vma,
             &BufferDescription {
                 size: size as vk::DeviceSize,
-                usage: vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::UNIFORM_BUFFER,
+                usage: vk::BufferUsageFlags::TRANSFER_SRC
+                    | vk::BufferUsageFlags::TRANSFER_DST
+                    | vk::BufferUsageFlags::UNIFORM_BUFFER,
                 location: vk_mem::MemoryUsage::CpuToGpu,
             },
         )
     }
 
+    pub(crate) fn new_storage_buffer(vma: &vk_mem::Allocator, size: usize) -> Result<Self> {
+        Self::new(
+            vma,
+            &BufferDescription {
+                size: size as vk::DeviceSize,
+                usage: vk::BufferUsageFlags::TRANSFER_SRC
+                    | vk::BufferUsageFlags::TRANSFER_DST
+                    | vk::BufferUsageFlags::STORAGE_BUFFER,
+                location: vk_mem::MemoryUsage::GpuOnly,
+            },
+        )
+    }
+
+    pub(crate) fn new_texel_buffer(vma: &vk_mem::Allocator, size: usize) -> Result<Self> {
+        Self::new(
+            vma,
+            &BufferDescription {
+                size: size as vk::DeviceSize,
+                usage: vk::BufferUsageFlags::TRANSFER_SRC
+                    | vk::BufferUsageFlags::TRANSFER_DST
+                    | vk::BufferUsageFlags::UNIFORM_TEXEL_BUFFER
+                    | vk::BufferUsageFlags::STORAGE_TEXEL_BUFFER,
+                location: vk_mem::MemoryUsage::GpuOnly,
+            },
+        )
+    }
+
     pub(crate) fn new(vma: &vk_mem::Allocator, desc: &BufferDescription) -> Result<Self> {
         let (buffer, allocation, info) = vma.create_buffer(
             &vk::BufferCreateInfo::builder()
@@ -117,6 +175,22 @@ impl RawBufferAllocation {
         Ok(())
     }
 
+    /// # Safety
+    /// Will fail if the buffer isn't HOST_VISIBLE
+    pub unsafe fn load(&self, vma: &vk_mem::Allocator) -> Result<Vec<u8>> {
+        let (need_to_unmap, mapped_ptr) = self.ensure_mapped(vma)?;
+
+        // Will be ignored if HOST_COHERENT
+        vma.invalidate_allocation(&self.allocation, 0, self.real_size as usize)?;
+        let data = std::slice::from_raw_parts(mapped_ptr, self.real_size as usize).to_vec();
+
+        if need_to_unmap {
+            vma.unmap_memory(&self.allocation)?;
+        }
+
+        Ok(data)
+    }
+
     pub unsafe fn copy_to(
         &self,
         device: &ash::Device,
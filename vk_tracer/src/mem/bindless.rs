@@ -0,0 +1,182 @@
+use crate::{
+    errors::{HandleType, Result, VkTracerError},
+    mem::{DescriptorPool, DescriptorSet, ImageViewFatHandle},
+    BindlessTableHandle, DescriptorSetHandle, VkTracerApp,
+};
+use ash::{version::DeviceV1_0, vk};
+use std::slice::from_ref;
+
+/// Binding of the texture array in the set of a bindless table.
+pub const BINDLESS_TEXTURES_BINDING: u32 = 0;
+
+pub(crate) struct BindlessTable {
+    set: DescriptorSetHandle,
+    capacity: u32,
+    next_slot: u32,
+    free_slots: Vec<u32>,
+}
+
+impl VkTracerApp {
+    /// Create a table of up to `capacity` sampled images, its descriptor set is given to the
+    /// pipelines and shaders index it with the slots returned by
+    /// [VkTracerApp::register_bindless_texture].
+    pub fn create_bindless_table(
+        &mut self,
+        capacity: u32,
+        stage_flags: vk::ShaderStageFlags,
+    ) -> Result<BindlessTableHandle> {
+        if !self.adapter.info.physical_device_info.descriptor_indexing {
+            return Err(VkTracerError::FeatureNotSupported("descriptor indexing"));
+        }
+
+        let binding = vk::DescriptorSetLayoutBinding::builder()
+            .binding(BINDLESS_TEXTURES_BINDING)
+            .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+            .descriptor_count(capacity)
+            .stage_flags(stage_flags)
+            .build();
+        // Slots can be left empty and changed while the set is in use
+        let binding_flags = vk::DescriptorBindingFlags::PARTIALLY_BOUND
+            | vk::DescriptorBindingFlags::VARIABLE_DESCRIPTOR_COUNT
+            | vk::DescriptorBindingFlags::UPDATE_AFTER_BIND;
+        let layout = self.descriptor_set_layout(
+            vk::DescriptorSetLayoutCreateFlags::UPDATE_AFTER_BIND_POOL,
+            from_ref(&binding),
+            from_ref(&binding_flags),
+            &[],
+        )?;
+
+        let device = &self.device;
+        let (pool, set) = unsafe {
+            let pool = device.create_descriptor_pool(
+                &vk::DescriptorPoolCreateInfo::builder()
+                    .flags(
+                        vk::DescriptorPoolCreateFlags::UPDATE_AFTER_BIND
+                            | vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET,
+                    )
+                    .max_sets(1)
+                    .pool_sizes(from_ref(
+                        &vk::DescriptorPoolSize::builder()
+                            .ty(vk::DescriptorType::SAMPLED_IMAGE)
+                            .descriptor_count(capacity),
+                    )),
+                None,
+            )?;
+
+            let mut variable_count_info =
+                vk::DescriptorSetVariableDescriptorCountAllocateInfo::builder()
+                    .descriptor_counts(from_ref(&capacity));
+            let set = device.allocate_descriptor_sets(
+                &vk::DescriptorSetAllocateInfo::builder()
+                    .descriptor_pool(pool)
+                    .set_layouts(from_ref(&layout))
+                    .push_next(&mut variable_count_info),
+            )?[0];
+
+            (pool, set)
+        };
+
+        // Destroyed along with the other descriptor sets
+        let pool = self.descriptor_pool_storage.insert(DescriptorPool {
+            handle: pool,
+            sets: Box::new([set]),
+            freed: 0,
+            update_after_bind: true,
+        });
+        let set_handle = self.descriptor_set_storage.insert(DescriptorSet {
+            handle: set,
+            layout,
+            pool,
+            bindings: Box::new([(
+                BINDLESS_TEXTURES_BINDING,
+                vk::DescriptorType::SAMPLED_IMAGE,
+                capacity,
+            )]),
+        });
+
+        Ok(self.bindless_table_storage.insert(BindlessTable {
+            set: set_handle,
+            capacity,
+            next_slot: 0,
+            free_slots: Vec::new(),
+        }))
+    }
+
+    /// The descriptor set to give to the pipelines using the table.
+    pub fn get_bindless_descriptor_set(
+        &self,
+        table: BindlessTableHandle,
+    ) -> Result<DescriptorSetHandle> {
+        Ok(storage_access!(
+            self.bindless_table_storage,
+            table,
+            HandleType::BindlessTable
+        )
+        .set)
+    }
+
+    /// Put a texture in the table, the returned slot is the index to use in shaders.
+    /// The image must be in SHADER_READ_ONLY_OPTIMAL when sampled.
+    pub fn register_bindless_texture(
+        &mut self,
+        table: BindlessTableHandle,
+        image: ImageViewFatHandle,
+    ) -> Result<u32> {
+        let table = storage_access_mut!(
+            self.bindless_table_storage,
+            table,
+            HandleType::BindlessTable
+        );
+
+        let slot = match table.free_slots.pop() {
+            Some(slot) => slot,
+            None if table.next_slot < table.capacity => {
+                table.next_slot += 1;
+                table.next_slot - 1
+            }
+            None => return Err(VkTracerError::BindlessTableFull(table.capacity)),
+        };
+
+        let set = storage_access!(
+            self.descriptor_set_storage,
+            table.set,
+            HandleType::DescriptorSet
+        );
+        let image_info = vk::DescriptorImageInfo::builder()
+            .image_view(image.view)
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+        unsafe {
+            self.device.update_descriptor_sets(
+                from_ref(
+                    &vk::WriteDescriptorSet::builder()
+                        .dst_set(set.handle)
+                        .dst_binding(BINDLESS_TEXTURES_BINDING)
+                        .dst_array_element(slot)
+                        .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                        .image_info(from_ref(&image_info)),
+                ),
+                &[],
+            );
+        }
+
+        Ok(slot)
+    }
+
+    /// Free a slot to be reused by the next registered texture, shaders must not access it
+    /// anymore.
+    pub fn unregister_bindless_texture(
+        &mut self,
+        table: BindlessTableHandle,
+        slot: u32,
+    ) -> Result<()> {
+        let table = storage_access_mut!(
+            self.bindless_table_storage,
+            table,
+            HandleType::BindlessTable
+        );
+        if slot < table.next_slot && !table.free_slots.contains(&slot) {
+            table.free_slots.push(slot);
+        }
+        Ok(())
+    }
+}
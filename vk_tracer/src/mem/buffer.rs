@@ -26,6 +26,14 @@ impl<D: Copy> TypedBuffer<D> {
         }
     }
 
+    pub(crate) fn new_indirect_buffer(vma: &vk_mem::Allocator, size: usize) -> Result<Self> {
+        unsafe {
+            Ok(TypedBuffer::from_raw(
+                RawBufferAllocation::new_indirect_buffer(vma, size * std::mem::size_of::<D>())?,
+            ))
+        }
+    }
+
     pub(crate) fn new_uniform_buffer(vma: &vk_mem::Allocator, size: usize) -> Result<Self> {
         unsafe {
             Ok(TypedBuffer::from_raw(
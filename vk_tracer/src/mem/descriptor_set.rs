@@ -1,7 +1,8 @@
 use crate::{
     ash::version::DeviceV1_0,
-    errors::{HandleType, Result},
-    DescriptorSetHandle, UboHandle, VkTracerApp,
+    errors::{HandleType, Result, VkTracerError},
+    mem::ImageViewFatHandle,
+    DescriptorPoolHandle, DescriptorSetHandle, SamplerHandle, TlasHandle, UboHandle, VkTracerApp,
 };
 use ash::vk;
 use std::{collections::HashMap, slice::from_ref};
@@ -21,6 +22,7 @@ impl VkTracerApp {
         binding: u32,
         ubo: UboHandle,
     ) -> Result<()> {
+        self.check_descriptor_write(set, binding, 0, vk::DescriptorType::UNIFORM_BUFFER, None)?;
         let buffer = storage_access!(self.ubo_storage, ubo, HandleType::Ubo);
         unsafe {
             self.device.update_descriptor_sets(
@@ -44,16 +46,448 @@ impl VkTracerApp {
         }
         Ok(())
     }
+
+    /// Bind `range` bytes of the buffer at a time, the start is given by a dynamic offset when
+    /// binding the set.
+    pub fn write_descriptor_set_ubo_dynamic(
+        &mut self,
+        set: DescriptorSetHandle,
+        binding: u32,
+        ubo: UboHandle,
+        range: vk::DeviceSize,
+    ) -> Result<()> {
+        let buffer = storage_access!(self.ubo_storage, ubo, HandleType::Ubo);
+        self.check_descriptor_write(
+            set,
+            binding,
+            0,
+            vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
+            Some((range, buffer.real_size)),
+        )?;
+        let buffer_info = vk::DescriptorBufferInfo::builder()
+            .buffer(buffer.buffer)
+            .offset(0)
+            .range(range);
+        unsafe {
+            self.device.update_descriptor_sets(
+                from_ref(
+                    &vk::WriteDescriptorSet::builder()
+                        .dst_set(
+                            storage_access!(
+                                self.descriptor_set_storage,
+                                set,
+                                HandleType::DescriptorSet
+                            )
+                            .handle,
+                        )
+                        .dst_binding(binding)
+                        .dst_array_element(0)
+                        .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC)
+                        .buffer_info(from_ref(&buffer_info)),
+                ),
+                &[],
+            )
+        }
+        Ok(())
+    }
+
+    /// Dynamic offsets of uniform buffers must be a multiple of this.
+    pub fn min_uniform_buffer_offset_alignment(&self) -> vk::DeviceSize {
+        self.adapter
+            .info
+            .physical_device_info
+            .properties
+            .limits
+            .min_uniform_buffer_offset_alignment
+    }
+
+    /// Dynamic offsets of storage buffers must be a multiple of this.
+    pub fn min_storage_buffer_offset_alignment(&self) -> vk::DeviceSize {
+        self.adapter
+            .info
+            .physical_device_info
+            .properties
+            .limits
+            .min_storage_buffer_offset_alignment
+    }
+
+    /// The type of the descriptor each dynamic offset applies to, in the order the offsets are
+    /// given: by set, then binding number, then array element.
+    pub(crate) fn dynamic_descriptor_types(
+        &self,
+        descriptor_sets: &[DescriptorSetHandle],
+    ) -> Result<Box<[vk::DescriptorType]>> {
+        let mut types = Vec::new();
+        for handle in descriptor_sets.iter().copied() {
+            let set = storage_access!(
+                self.descriptor_set_storage,
+                handle,
+                HandleType::DescriptorSet
+            );
+            let mut bindings = set.bindings.to_vec();
+            bindings.sort_unstable_by_key(|(binding, _, _)| *binding);
+            for (_, ty, count) in bindings {
+                if ty == vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC
+                    || ty == vk::DescriptorType::STORAGE_BUFFER_DYNAMIC
+                {
+                    types.extend(std::iter::repeat(ty).take(count as usize));
+                }
+            }
+        }
+        Ok(types.into_boxed_slice())
+    }
+
+    /// One offset per dynamic descriptor, aligned as the device requires for its type.
+    pub(crate) fn check_dynamic_offsets(
+        &self,
+        types: &[vk::DescriptorType],
+        dynamic_offsets: &[u32],
+    ) -> Result<()> {
+        if types.len() != dynamic_offsets.len() {
+            return Err(VkTracerError::DynamicOffsetCountMismatch {
+                expected: types.len(),
+                actual: dynamic_offsets.len(),
+            });
+        }
+        for (ty, offset) in types.iter().zip(dynamic_offsets.iter().copied()) {
+            let alignment = if *ty == vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC {
+                self.min_uniform_buffer_offset_alignment()
+            } else {
+                self.min_storage_buffer_offset_alignment()
+            };
+            if offset as vk::DeviceSize % alignment != 0 {
+                return Err(VkTracerError::UnalignedDynamicOffset { offset, alignment });
+            }
+        }
+        Ok(())
+    }
+
+    /// The image must be in `layout` when the set is used, usually GENERAL.
+    pub fn write_descriptor_set_storage_image(
+        &mut self,
+        set: DescriptorSetHandle,
+        binding: u32,
+        image: ImageViewFatHandle,
+        layout: vk::ImageLayout,
+    ) -> Result<()> {
+        self.check_descriptor_write(set, binding, 0, vk::DescriptorType::STORAGE_IMAGE, None)?;
+        let image_info = vk::DescriptorImageInfo::builder()
+            .image_view(image.view)
+            .image_layout(layout);
+        unsafe {
+            self.device.update_descriptor_sets(
+                from_ref(
+                    &vk::WriteDescriptorSet::builder()
+                        .dst_set(
+                            storage_access!(
+                                self.descriptor_set_storage,
+                                set,
+                                HandleType::DescriptorSet
+                            )
+                            .handle,
+                        )
+                        .dst_binding(binding)
+                        .dst_array_element(0)
+                        .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                        .image_info(from_ref(&image_info)),
+                ),
+                &[],
+            )
+        }
+        Ok(())
+    }
+
+    /// Write the element `index` of an array of sampled images, the image must be in
+    /// SHADER_READ_ONLY_OPTIMAL when the set is used.
+    pub fn write_descriptor_set_sampled_image(
+        &mut self,
+        set: DescriptorSetHandle,
+        binding: u32,
+        index: u32,
+        image: ImageViewFatHandle,
+    ) -> Result<()> {
+        self.check_descriptor_write(set, binding, index, vk::DescriptorType::SAMPLED_IMAGE, None)?;
+        let image_info = vk::DescriptorImageInfo::builder()
+            .image_view(image.view)
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+        unsafe {
+            self.device.update_descriptor_sets(
+                from_ref(
+                    &vk::WriteDescriptorSet::builder()
+                        .dst_set(
+                            storage_access!(
+                                self.descriptor_set_storage,
+                                set,
+                                HandleType::DescriptorSet
+                            )
+                            .handle,
+                        )
+                        .dst_binding(binding)
+                        .dst_array_element(index)
+                        .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                        .image_info(from_ref(&image_info)),
+                ),
+                &[],
+            )
+        }
+        Ok(())
+    }
+
+    /// The structure must not be destroyed while the set is in use, its updates don't need
+    /// a new write.
+    #[cfg(feature = "math")]
+    pub fn write_descriptor_set_tlas(
+        &mut self,
+        set: DescriptorSetHandle,
+        binding: u32,
+        tlas: TlasHandle,
+    ) -> Result<()> {
+        self.check_descriptor_write(
+            set,
+            binding,
+            0,
+            vk::DescriptorType::ACCELERATION_STRUCTURE_KHR,
+            None,
+        )?;
+        let tlas = storage_access!(self.tlas_storage, tlas, HandleType::Tlas);
+        let mut structure_info = vk::WriteDescriptorSetAccelerationStructureKHR::builder()
+            .acceleration_structures(from_ref(&tlas.handle));
+        let mut write = vk::WriteDescriptorSet::builder()
+            .dst_set(
+                storage_access!(self.descriptor_set_storage, set, HandleType::DescriptorSet).handle,
+            )
+            .dst_binding(binding)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::ACCELERATION_STRUCTURE_KHR)
+            .push_next(&mut structure_info)
+            .build();
+        // Not deduced from the structures in the extension
+        write.descriptor_count = 1;
+        unsafe { self.device.update_descriptor_sets(from_ref(&write), &[]) }
+        Ok(())
+    }
+
+    /// Only for sampler bindings without immutable samplers.
+    pub fn write_descriptor_set_sampler(
+        &mut self,
+        set: DescriptorSetHandle,
+        binding: u32,
+        sampler: SamplerHandle,
+    ) -> Result<()> {
+        self.check_descriptor_write(set, binding, 0, vk::DescriptorType::SAMPLER, None)?;
+        let sampler_info = vk::DescriptorImageInfo::builder().sampler(*storage_access!(
+            self.sampler_storage,
+            sampler,
+            HandleType::Sampler
+        ));
+        unsafe {
+            self.device.update_descriptor_sets(
+                from_ref(
+                    &vk::WriteDescriptorSet::builder()
+                        .dst_set(
+                            storage_access!(
+                                self.descriptor_set_storage,
+                                set,
+                                HandleType::DescriptorSet
+                            )
+                            .handle,
+                        )
+                        .dst_binding(binding)
+                        .dst_array_element(0)
+                        .descriptor_type(vk::DescriptorType::SAMPLER)
+                        .image_info(from_ref(&sampler_info)),
+                ),
+                &[],
+            )
+        }
+        Ok(())
+    }
 }
 
 pub(crate) struct DescriptorPool {
     pub(crate) handle: vk::DescriptorPool,
     pub(crate) sets: Box<[vk::DescriptorSet]>,
+    /// Amount of sets given back to the pool, that can be allocated again.
+    pub(crate) freed: u32,
+    /// Created with UPDATE_AFTER_BIND, needed by the layouts with such bindings.
+    pub(crate) update_after_bind: bool,
 }
 
 pub(crate) struct DescriptorSet {
     pub(crate) handle: vk::DescriptorSet,
+    /// Owned by the layout cache.
     pub(crate) layout: vk::DescriptorSetLayout,
+    pub(crate) pool: DescriptorPoolHandle,
+    /// Binding number, type and count of each binding, to check the writes.
+    pub(crate) bindings: Box<[(u32, vk::DescriptorType, u32)]>,
+}
+
+/// Sets with the same bindings share their layout. The immutable samplers are kept by handle,
+/// a raw sampler can be reused by a new sampler once destroyed.
+#[derive(Clone, Eq, PartialEq, Hash)]
+pub(crate) struct DescriptorLayoutKey {
+    flags: vk::DescriptorSetLayoutCreateFlags,
+    bindings: Vec<(
+        u32,
+        vk::DescriptorType,
+        u32,
+        vk::ShaderStageFlags,
+        vk::DescriptorBindingFlags,
+        Vec<SamplerHandle>,
+    )>,
+}
+
+/// Pipelines with the same descriptor set layouts and push constants share their layout.
+#[derive(Clone, Eq, PartialEq, Hash)]
+pub(crate) struct PipelineLayoutKey {
+    set_layouts: Vec<vk::DescriptorSetLayout>,
+    push_constants: Vec<(vk::ShaderStageFlags, u32, u32)>,
+}
+
+impl VkTracerApp {
+    /// Give a set back to its pool, the next sets built with [VkTracerApp::new_descriptor_sets]
+    /// can reuse its space. The set must not be used by pending commands.
+    pub fn destroy_descriptor_set(&mut self, set: DescriptorSetHandle) -> Result<()> {
+        if let Some(set) = self.descriptor_set_storage.remove(set) {
+            let pool = storage_access_mut!(
+                self.descriptor_pool_storage,
+                set.pool,
+                HandleType::DescriptorPool
+            );
+            unsafe {
+                // Always succeeds
+                let _ = self
+                    .device
+                    .free_descriptor_sets(pool.handle, from_ref(&set.handle));
+            }
+            pool.freed += 1;
+        }
+        Ok(())
+    }
+
+    /// Check that a write matches the layout of the set, `buffer` is the range written and the
+    /// size of the buffer. Only in debug builds or with the `descriptor_checks` feature.
+    pub(crate) fn check_descriptor_write(
+        &self,
+        set: DescriptorSetHandle,
+        binding: u32,
+        array_element: u32,
+        ty: vk::DescriptorType,
+        buffer: Option<(vk::DeviceSize, vk::DeviceSize)>,
+    ) -> Result<()> {
+        if !cfg!(any(feature = "descriptor_checks", debug_assertions)) {
+            return Ok(());
+        }
+
+        let set = storage_access!(self.descriptor_set_storage, set, HandleType::DescriptorSet);
+        let (_, expected, count) = set
+            .bindings
+            .iter()
+            .find(|(number, _, _)| *number == binding)
+            .ok_or(VkTracerError::NoSuchBinding(binding))?;
+        if *expected != ty {
+            return Err(VkTracerError::DescriptorTypeMismatch {
+                binding,
+                expected: *expected,
+                actual: ty,
+            });
+        }
+
+        if array_element >= *count {
+            return Err(VkTracerError::DescriptorIndexOutOfBounds {
+                binding,
+                index: array_element,
+                count: *count,
+            });
+        }
+
+        if let Some((range, size)) = buffer {
+            if range > size {
+                return Err(VkTracerError::DescriptorRangeOutOfBounds { range, size });
+            }
+        }
+        Ok(())
+    }
+
+    /// Get the layout of these bindings from the cache, or create it. The immutable samplers
+    /// of the bindings are given by index of the binding.
+    pub(crate) fn descriptor_set_layout(
+        &mut self,
+        flags: vk::DescriptorSetLayoutCreateFlags,
+        bindings: &[vk::DescriptorSetLayoutBinding],
+        binding_flags: &[vk::DescriptorBindingFlags],
+        immutable_samplers: &[(usize, Box<[SamplerHandle]>)],
+    ) -> Result<vk::DescriptorSetLayout> {
+        let key = DescriptorLayoutKey {
+            flags,
+            bindings: bindings
+                .iter()
+                .enumerate()
+                .map(|(i, binding)| {
+                    (
+                        binding.binding,
+                        binding.descriptor_type,
+                        binding.descriptor_count,
+                        binding.stage_flags,
+                        binding_flags.get(i).copied().unwrap_or_default(),
+                        immutable_samplers
+                            .iter()
+                            .find(|(index, _)| *index == i)
+                            .map(|(_, samplers)| samplers.to_vec())
+                            .unwrap_or_default(),
+                    )
+                })
+                .collect(),
+        };
+        if let Some(layout) = self.descriptor_layout_cache.get(&key) {
+            return Ok(*layout);
+        }
+
+        let mut binding_flags_info =
+            vk::DescriptorSetLayoutBindingFlagsCreateInfo::builder().binding_flags(binding_flags);
+        let mut create_info = vk::DescriptorSetLayoutCreateInfo::builder()
+            .flags(flags)
+            .bindings(bindings);
+        if !binding_flags.is_empty() {
+            create_info = create_info.push_next(&mut binding_flags_info);
+        }
+
+        let layout = unsafe {
+            self.device
+                .create_descriptor_set_layout(&create_info, None)?
+        };
+        self.descriptor_layout_cache.insert(key, layout);
+        Ok(layout)
+    }
+
+    /// Get the pipeline layout from the cache, or create it.
+    pub(crate) fn pipeline_layout(
+        &mut self,
+        set_layouts: &[vk::DescriptorSetLayout],
+        push_constant_ranges: &[vk::PushConstantRange],
+    ) -> Result<vk::PipelineLayout> {
+        let key = PipelineLayoutKey {
+            set_layouts: set_layouts.to_vec(),
+            push_constants: push_constant_ranges
+                .iter()
+                .map(|range| (range.stage_flags, range.offset, range.size))
+                .collect(),
+        };
+        if let Some(layout) = self.pipeline_layout_cache.get(&key) {
+            return Ok(*layout);
+        }
+
+        let layout = unsafe {
+            self.device.create_pipeline_layout(
+                &vk::PipelineLayoutCreateInfo::builder()
+                    .set_layouts(set_layouts)
+                    .push_constant_ranges(push_constant_ranges),
+                None,
+            )?
+        };
+        self.pipeline_layout_cache.insert(key, layout);
+        Ok(layout)
+    }
 }
 
 pub struct DescriptorPoolBuilder<'app> {
@@ -64,6 +498,9 @@ pub struct DescriptorPoolBuilder<'app> {
 
 pub struct DescriptorSetBuilder {
     bindings: Vec<vk::DescriptorSetLayoutBinding>,
+    binding_flags: Vec<vk::DescriptorBindingFlags>,
+    /// Index of the binding and its samplers.
+    immutable_samplers: Vec<(usize, Box<[SamplerHandle]>)>,
 }
 
 impl DescriptorPoolBuilder<'_> {
@@ -76,67 +513,152 @@ impl DescriptorPoolBuilder<'_> {
                         .ty(binding.descriptor_type)
                         .build()
                 })
-                .descriptor_count += 1;
+                .descriptor_count += binding.descriptor_count;
         }
         self.sets.push(set);
         self
     }
 
     pub fn build(self) -> Result<Box<[DescriptorSetHandle]>> {
-        let device = &self.app.device;
-
-        let sizes = self.sizes.values().map(|size| *size).collect::<Vec<_>>();
-
-        // Allocate pool
-        let pool = unsafe {
-            device.create_descriptor_pool(
-                &vk::DescriptorPoolCreateInfo::builder()
-                    .max_sets(self.sets.len() as u32)
-                    .pool_sizes(&sizes),
-                None,
-            )?
-        };
+        let update_after_bind = self.sets.iter().any(|set| set.is_update_after_bind());
+        let flags_needed = self
+            .sets
+            .iter()
+            .any(|set| set.binding_flags.iter().any(|flags| !flags.is_empty()));
+        if flags_needed && !self.app.adapter.info.physical_device_info.update_after_bind {
+            return Err(VkTracerError::FeatureNotSupported(
+                "descriptor update after bind",
+            ));
+        }
 
-        // Allocate set layouts
+        // Get set layouts
         let set_layouts = {
             let mut layouts = Vec::with_capacity(self.sets.len());
             for set in &self.sets {
-                layouts.push(unsafe {
-                    device.create_descriptor_set_layout(
-                        &vk::DescriptorSetLayoutCreateInfo::builder().bindings(&set.bindings),
-                        None,
-                    )?
-                });
+                // The samplers must stay alive until the layout is created
+                let mut samplers = Vec::with_capacity(set.immutable_samplers.len());
+                for (index, handles) in &set.immutable_samplers {
+                    let mut raw = Vec::with_capacity(handles.len());
+                    for handle in handles.iter().copied() {
+                        raw.push(*storage_access!(
+                            self.app.sampler_storage,
+                            handle,
+                            HandleType::Sampler
+                        ));
+                    }
+                    samplers.push((*index, raw));
+                }
+                let mut bindings = set.bindings.clone();
+                for (index, raw) in &samplers {
+                    bindings[*index].p_immutable_samplers = raw.as_ptr();
+                }
+
+                let flags = if set.is_update_after_bind() {
+                    vk::DescriptorSetLayoutCreateFlags::UPDATE_AFTER_BIND_POOL
+                } else {
+                    vk::DescriptorSetLayoutCreateFlags::empty()
+                };
+                let binding_flags = if set.binding_flags.iter().any(|flags| !flags.is_empty()) {
+                    set.binding_flags.as_slice()
+                } else {
+                    &[]
+                };
+                layouts.push(self.app.descriptor_set_layout(
+                    flags,
+                    &bindings,
+                    binding_flags,
+                    &set.immutable_samplers,
+                )?);
             }
             layouts
         };
 
-        // Allocate sets
-        let sets = unsafe {
-            device.allocate_descriptor_sets(
-                &vk::DescriptorSetAllocateInfo::builder()
-                    .descriptor_pool(pool)
-                    .set_layouts(&set_layouts),
-            )?
+        let device = &self.app.device;
+        let allocate_info = |pool| {
+            vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(pool)
+                .set_layouts(&set_layouts)
+                .build()
+        };
+
+        // Try to fit the sets in a pool where some were freed
+        let needed = set_layouts.len() as u32;
+        let mut recycled = None;
+        for (handle, pool) in self.app.descriptor_pool_storage.iter_mut() {
+            if pool.freed < needed || pool.update_after_bind != update_after_bind {
+                continue;
+            }
+            match unsafe { device.allocate_descriptor_sets(&allocate_info(pool.handle)) } {
+                Ok(sets) => {
+                    pool.freed -= needed;
+                    recycled = Some((handle, sets));
+                    break;
+                }
+                // Not enough space for these descriptor types
+                Err(vk::Result::ERROR_OUT_OF_POOL_MEMORY)
+                | Err(vk::Result::ERROR_FRAGMENTED_POOL) => continue,
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        let (pool, sets) = match recycled {
+            Some(recycled) => recycled,
+            None => {
+                let sizes = self.sizes.values().map(|size| *size).collect::<Vec<_>>();
+                let mut flags = vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET;
+                if update_after_bind {
+                    flags |= vk::DescriptorPoolCreateFlags::UPDATE_AFTER_BIND;
+                }
+
+                // Allocate pool
+                let pool = unsafe {
+                    device.create_descriptor_pool(
+                        &vk::DescriptorPoolCreateInfo::builder()
+                            .flags(flags)
+                            .max_sets(self.sets.len() as u32)
+                            .pool_sizes(&sizes),
+                        None,
+                    )?
+                };
+
+                // Allocate sets
+                let sets = unsafe { device.allocate_descriptor_sets(&allocate_info(pool))? };
+
+                let handle = self.app.descriptor_pool_storage.insert(DescriptorPool {
+                    handle: pool,
+                    sets: sets.clone().into_boxed_slice(),
+                    freed: 0,
+                    update_after_bind,
+                });
+                (handle, sets)
+            }
         };
 
         // Register all that
         let set_handles = sets
             .iter()
             .zip(set_layouts)
-            .map(|(set, layout)| {
+            .zip(&self.sets)
+            .map(|((set, layout), builder)| {
                 self.app.descriptor_set_storage.insert(DescriptorSet {
                     handle: *set,
                     layout,
+                    pool,
+                    bindings: builder
+                        .bindings
+                        .iter()
+                        .map(|binding| {
+                            (
+                                binding.binding,
+                                binding.descriptor_type,
+                                binding.descriptor_count,
+                            )
+                        })
+                        .collect(),
                 })
             })
             .collect::<Box<_>>();
 
-        self.app.descriptor_pool_storage.insert(DescriptorPool {
-            handle: pool,
-            sets: sets.into_boxed_slice(),
-        });
-
         Ok(set_handles)
     }
 }
@@ -145,9 +667,35 @@ impl DescriptorSetBuilder {
     pub fn new() -> Self {
         Self {
             bindings: Default::default(),
+            binding_flags: Default::default(),
+            immutable_samplers: Default::default(),
         }
     }
 
+    /// The last binding may be left unwritten if the shaders don't access it.
+    pub fn partially_bound(self) -> Self {
+        self.last_binding_flags(vk::DescriptorBindingFlags::PARTIALLY_BOUND)
+    }
+
+    /// The last binding can be written while the set is bound in pending commands, the
+    /// commands use the descriptors as they are at submission.
+    pub fn update_after_bind(self) -> Self {
+        self.last_binding_flags(vk::DescriptorBindingFlags::UPDATE_AFTER_BIND)
+    }
+
+    fn last_binding_flags(mut self, flags: vk::DescriptorBindingFlags) -> Self {
+        if let Some(last) = self.binding_flags.last_mut() {
+            *last |= flags;
+        }
+        self
+    }
+
+    fn is_update_after_bind(&self) -> bool {
+        self.binding_flags
+            .iter()
+            .any(|flags| flags.contains(vk::DescriptorBindingFlags::UPDATE_AFTER_BIND))
+    }
+
     pub fn raw_binding(
         mut self,
         ty: vk::DescriptorType,
@@ -163,6 +711,7 @@ impl DescriptorSetBuilder {
                 .stage_flags(stage_flags)
                 .build(),
         );
+        self.binding_flags.push(vk::DescriptorBindingFlags::empty());
         self
     }
 
@@ -171,8 +720,108 @@ impl DescriptorSetBuilder {
         self.raw_binding(vk::DescriptorType::UNIFORM_BUFFER, binding, 1, stage_flags)
     }
 
+    /// A uniform buffer bound with a dynamic offset, see
+    /// [VkTracerApp::write_descriptor_set_ubo_dynamic].
+    #[inline]
+    pub fn ubo_dynamic(self, binding: u32, stage_flags: vk::ShaderStageFlags) -> Self {
+        self.raw_binding(
+            vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
+            binding,
+            1,
+            stage_flags,
+        )
+    }
+
+    #[inline]
+    pub fn storage_buffer(self, binding: u32, stage_flags: vk::ShaderStageFlags) -> Self {
+        self.raw_binding(vk::DescriptorType::STORAGE_BUFFER, binding, 1, stage_flags)
+    }
+
+    /// A storage buffer bound with a dynamic offset, see
+    /// [VkTracerApp::write_descriptor_set_storage_buffer_dynamic].
+    #[inline]
+    pub fn storage_buffer_dynamic(self, binding: u32, stage_flags: vk::ShaderStageFlags) -> Self {
+        self.raw_binding(
+            vk::DescriptorType::STORAGE_BUFFER_DYNAMIC,
+            binding,
+            1,
+            stage_flags,
+        )
+    }
+
+    #[inline]
+    pub fn storage_image(self, binding: u32, stage_flags: vk::ShaderStageFlags) -> Self {
+        self.raw_binding(vk::DescriptorType::STORAGE_IMAGE, binding, 1, stage_flags)
+    }
+
+    #[inline]
+    pub fn uniform_texel_buffer(self, binding: u32, stage_flags: vk::ShaderStageFlags) -> Self {
+        self.raw_binding(
+            vk::DescriptorType::UNIFORM_TEXEL_BUFFER,
+            binding,
+            1,
+            stage_flags,
+        )
+    }
+
+    #[inline]
+    pub fn storage_texel_buffer(self, binding: u32, stage_flags: vk::ShaderStageFlags) -> Self {
+        self.raw_binding(
+            vk::DescriptorType::STORAGE_TEXEL_BUFFER,
+            binding,
+            1,
+            stage_flags,
+        )
+    }
+
+    /// A top-level acceleration structure, see [VkTracerApp::write_descriptor_set_tlas].
+    #[inline]
+    pub fn acceleration_structure(self, binding: u32, stage_flags: vk::ShaderStageFlags) -> Self {
+        self.raw_binding(
+            vk::DescriptorType::ACCELERATION_STRUCTURE_KHR,
+            binding,
+            1,
+            stage_flags,
+        )
+    }
+
     #[inline]
     pub fn sampler(self, binding: u32, stage_flags: vk::ShaderStageFlags) -> Self {
         self.raw_binding(vk::DescriptorType::SAMPLER, binding, 1, stage_flags)
     }
+
+    /// An array of samplers baked in the layout, they can't be written and must outlive the
+    /// sets using them.
+    pub fn immutable_samplers(
+        mut self,
+        binding: u32,
+        samplers: &[SamplerHandle],
+        stage_flags: vk::ShaderStageFlags,
+    ) -> Self {
+        self.immutable_samplers
+            .push((self.bindings.len(), Box::from(samplers)));
+        self.raw_binding(
+            vk::DescriptorType::SAMPLER,
+            binding,
+            samplers.len() as u32,
+            stage_flags,
+        )
+    }
+
+    /// An array of `count` images, written one at a time with
+    /// [VkTracerApp::write_descriptor_set_sampled_image].
+    #[inline]
+    pub fn sampled_images(
+        self,
+        binding: u32,
+        count: u32,
+        stage_flags: vk::ShaderStageFlags,
+    ) -> Self {
+        self.raw_binding(
+            vk::DescriptorType::SAMPLED_IMAGE,
+            binding,
+            count,
+            stage_flags,
+        )
+    }
 }
@@ -0,0 +1,488 @@
+use crate::{
+    errors::{HandleType, Result, VkTracerError},
+    mem::ImageViewFatHandle,
+    ExternalMemoryHandle, SemaphoreHandle, VkTracerApp,
+};
+use ash::{
+    version::{DeviceV1_0, InstanceV1_0, InstanceV1_1},
+    vk,
+};
+use std::ffi::CStr;
+
+/// A file descriptor, owned by the caller once exported.
+#[cfg(unix)]
+pub type ExternalHandle = std::os::raw::c_int;
+/// A NT handle, owned by the caller once exported.
+#[cfg(windows)]
+pub type ExternalHandle = vk::HANDLE;
+
+#[cfg(unix)]
+const MEMORY_HANDLE_TYPE: vk::ExternalMemoryHandleTypeFlags =
+    vk::ExternalMemoryHandleTypeFlags::EXTERNAL_MEMORY_HANDLE_TYPE_OPAQUE_FD;
+#[cfg(windows)]
+const MEMORY_HANDLE_TYPE: vk::ExternalMemoryHandleTypeFlags =
+    vk::ExternalMemoryHandleTypeFlags::EXTERNAL_MEMORY_HANDLE_TYPE_OPAQUE_WIN32;
+
+#[cfg(unix)]
+const SEMAPHORE_HANDLE_TYPE: vk::ExternalSemaphoreHandleTypeFlags =
+    vk::ExternalSemaphoreHandleTypeFlags::EXTERNAL_SEMAPHORE_HANDLE_TYPE_OPAQUE_FD;
+#[cfg(windows)]
+const SEMAPHORE_HANDLE_TYPE: vk::ExternalSemaphoreHandleTypeFlags =
+    vk::ExternalSemaphoreHandleTypeFlags::EXTERNAL_SEMAPHORE_HANDLE_TYPE_OPAQUE_WIN32;
+
+pub(crate) struct ExternalMemoryFns {
+    #[cfg(unix)]
+    memory: vk::KhrExternalMemoryFdFn,
+    #[cfg(unix)]
+    semaphore: vk::KhrExternalSemaphoreFdFn,
+    #[cfg(windows)]
+    memory: vk::KhrExternalMemoryWin32Fn,
+    #[cfg(windows)]
+    semaphore: vk::KhrExternalSemaphoreWin32Fn,
+}
+
+impl ExternalMemoryFns {
+    #[cfg(unix)]
+    pub(crate) fn extension_names() -> [&'static CStr; 2] {
+        [
+            vk::KhrExternalMemoryFdFn::name(),
+            vk::KhrExternalSemaphoreFdFn::name(),
+        ]
+    }
+
+    #[cfg(windows)]
+    pub(crate) fn extension_names() -> [&'static CStr; 2] {
+        [
+            vk::KhrExternalMemoryWin32Fn::name(),
+            vk::KhrExternalSemaphoreWin32Fn::name(),
+        ]
+    }
+
+    pub(crate) fn load(instance: &ash::Instance, device: &ash::Device) -> Self {
+        #[cfg(unix)]
+        return Self {
+            memory: vk::KhrExternalMemoryFdFn::load(|name| unsafe {
+                std::mem::transmute(instance.get_device_proc_addr(device.handle(), name.as_ptr()))
+            }),
+            semaphore: vk::KhrExternalSemaphoreFdFn::load(|name| unsafe {
+                std::mem::transmute(instance.get_device_proc_addr(device.handle(), name.as_ptr()))
+            }),
+        };
+        #[cfg(windows)]
+        return Self {
+            memory: vk::KhrExternalMemoryWin32Fn::load(|name| unsafe {
+                std::mem::transmute(instance.get_device_proc_addr(device.handle(), name.as_ptr()))
+            }),
+            semaphore: vk::KhrExternalSemaphoreWin32Fn::load(|name| unsafe {
+                std::mem::transmute(instance.get_device_proc_addr(device.handle(), name.as_ptr()))
+            }),
+        };
+    }
+}
+
+pub(crate) enum ExternalResource {
+    Buffer(vk::Buffer),
+    Image(vk::Image, vk::ImageView),
+}
+
+/// Resource with its own dedicated allocation, VMA can't allocate exportable memory.
+pub(crate) struct ExternalMemory {
+    pub(crate) memory: vk::DeviceMemory,
+    pub(crate) size: vk::DeviceSize,
+    pub(crate) resource: ExternalResource,
+}
+
+impl ExternalMemory {
+    pub(crate) unsafe fn destroy(&self, device: &ash::Device) {
+        match self.resource {
+            ExternalResource::Buffer(buffer) => device.destroy_buffer(buffer, None),
+            ExternalResource::Image(image, view) => {
+                device.destroy_image_view(view, None);
+                device.destroy_image(image, None);
+            }
+        }
+        device.free_memory(self.memory, None);
+    }
+}
+
+impl VkTracerApp {
+    /// Create a device local buffer whose memory can be exported with
+    /// [VkTracerApp::export_memory].
+    pub fn create_exportable_buffer(
+        &mut self,
+        size: vk::DeviceSize,
+        usage: vk::BufferUsageFlags,
+    ) -> Result<ExternalMemoryHandle> {
+        self.external_memory_fns()?;
+
+        let mut properties = vk::ExternalBufferProperties::default();
+        unsafe {
+            self.instance
+                .get_physical_device_external_buffer_properties(
+                    self.adapter.handle,
+                    &vk::PhysicalDeviceExternalBufferInfo::builder()
+                        .usage(usage)
+                        .handle_type(MEMORY_HANDLE_TYPE),
+                    &mut properties,
+                );
+        }
+        if !properties
+            .external_memory_properties
+            .external_memory_features
+            .contains(vk::ExternalMemoryFeatureFlags::EXPORTABLE)
+        {
+            return Err(VkTracerError::FeatureNotSupported(
+                "exportable buffers with these usages",
+            ));
+        }
+
+        let mut external_info =
+            vk::ExternalMemoryBufferCreateInfo::builder().handle_types(MEMORY_HANDLE_TYPE);
+        let buffer = unsafe {
+            self.device.create_buffer(
+                &vk::BufferCreateInfo::builder()
+                    .size(size)
+                    .usage(usage)
+                    .sharing_mode(vk::SharingMode::EXCLUSIVE)
+                    .push_next(&mut external_info),
+                None,
+            )?
+        };
+
+        let requirements = unsafe { self.device.get_buffer_memory_requirements(buffer) };
+        let dedicated_info = vk::MemoryDedicatedAllocateInfo::builder().buffer(buffer);
+        let memory = match self.allocate_exportable_memory(requirements, dedicated_info.build()) {
+            Ok(memory) => memory,
+            Err(err) => {
+                unsafe { self.device.destroy_buffer(buffer, None) };
+                return Err(err);
+            }
+        };
+
+        if let Err(err) = unsafe { self.device.bind_buffer_memory(buffer, memory, 0) } {
+            unsafe {
+                self.device.destroy_buffer(buffer, None);
+                self.device.free_memory(memory, None);
+            }
+            return Err(err.into());
+        }
+
+        Ok(self.external_memory_storage.insert(ExternalMemory {
+            memory,
+            size: requirements.size,
+            resource: ExternalResource::Buffer(buffer),
+        }))
+    }
+
+    /// Create a 2D device local image whose memory can be exported with
+    /// [VkTracerApp::export_memory].
+    pub fn create_exportable_image(
+        &mut self,
+        format: vk::Format,
+        extent: vk::Extent2D,
+        usage: vk::ImageUsageFlags,
+    ) -> Result<(ExternalMemoryHandle, ImageViewFatHandle)> {
+        self.external_memory_fns()?;
+
+        let mut external_properties = vk::ExternalImageFormatProperties::default();
+        let mut external_format_info =
+            vk::PhysicalDeviceExternalImageFormatInfo::builder().handle_type(MEMORY_HANDLE_TYPE);
+        let supported = unsafe {
+            self.instance.get_physical_device_image_format_properties2(
+                self.adapter.handle,
+                &vk::PhysicalDeviceImageFormatInfo2::builder()
+                    .format(format)
+                    .ty(vk::ImageType::TYPE_2D)
+                    .tiling(vk::ImageTiling::OPTIMAL)
+                    .usage(usage)
+                    .push_next(&mut external_format_info),
+                &mut vk::ImageFormatProperties2::builder().push_next(&mut external_properties),
+            )
+        };
+        match supported {
+            Ok(()) => {}
+            Err(vk::Result::ERROR_FORMAT_NOT_SUPPORTED) => {
+                return Err(VkTracerError::UnsupportedFormatFeatures(
+                    format,
+                    vk::FormatFeatureFlags::empty(),
+                ))
+            }
+            Err(err) => return Err(err.into()),
+        }
+        if !external_properties
+            .external_memory_properties
+            .external_memory_features
+            .contains(vk::ExternalMemoryFeatureFlags::EXPORTABLE)
+        {
+            return Err(VkTracerError::FeatureNotSupported(
+                "exportable images with this format and usages",
+            ));
+        }
+
+        let mut external_info =
+            vk::ExternalMemoryImageCreateInfo::builder().handle_types(MEMORY_HANDLE_TYPE);
+        let image = unsafe {
+            self.device.create_image(
+                &vk::ImageCreateInfo::builder()
+                    .image_type(vk::ImageType::TYPE_2D)
+                    .format(format)
+                    .extent(vk::Extent3D {
+                        width: extent.width,
+                        height: extent.height,
+                        depth: 1,
+                    })
+                    .mip_levels(1)
+                    .array_layers(1)
+                    .samples(vk::SampleCountFlags::TYPE_1)
+                    .tiling(vk::ImageTiling::OPTIMAL)
+                    .usage(usage)
+                    .sharing_mode(vk::SharingMode::EXCLUSIVE)
+                    .initial_layout(vk::ImageLayout::UNDEFINED)
+                    .push_next(&mut external_info),
+                None,
+            )?
+        };
+
+        let requirements = unsafe { self.device.get_image_memory_requirements(image) };
+        let dedicated_info = vk::MemoryDedicatedAllocateInfo::builder().image(image);
+        let memory = match self.allocate_exportable_memory(requirements, dedicated_info.build()) {
+            Ok(memory) => memory,
+            Err(err) => {
+                unsafe { self.device.destroy_image(image, None) };
+                return Err(err);
+            }
+        };
+
+        let view = unsafe {
+            self.device
+                .bind_image_memory(image, memory, 0)
+                .and_then(|_| {
+                    self.device.create_image_view(
+                        &vk::ImageViewCreateInfo::builder()
+                            .image(image)
+                            .view_type(vk::ImageViewType::TYPE_2D)
+                            .format(format)
+                            .subresource_range(
+                                vk::ImageSubresourceRange::builder()
+                                    .aspect_mask(super::format_aspect(format))
+                                    .level_count(1)
+                                    .layer_count(1)
+                                    .build(),
+                            ),
+                        None,
+                    )
+                })
+        };
+        let view = match view {
+            Ok(view) => view,
+            Err(err) => {
+                unsafe {
+                    self.device.destroy_image(image, None);
+                    self.device.free_memory(memory, None);
+                }
+                return Err(err.into());
+            }
+        };
+
+        let handle = self.external_memory_storage.insert(ExternalMemory {
+            memory,
+            size: requirements.size,
+            resource: ExternalResource::Image(image, view),
+        });
+        Ok((
+            handle,
+            ImageViewFatHandle {
+                handle: image,
+                view,
+                format,
+                extent,
+            },
+        ))
+    }
+
+    /// The raw buffer of an exportable buffer.
+    pub fn get_external_buffer(&self, handle: ExternalMemoryHandle) -> Result<vk::Buffer> {
+        let memory = storage_access!(
+            self.external_memory_storage,
+            handle,
+            HandleType::ExternalMemory
+        );
+        match memory.resource {
+            ExternalResource::Buffer(buffer) => Ok(buffer),
+            ExternalResource::Image(..) => {
+                Err(VkTracerError::InvalidHandle(HandleType::ExternalMemory))
+            }
+        }
+    }
+
+    /// Size of the allocation, needed to import it elsewhere.
+    pub fn get_external_memory_size(&self, handle: ExternalMemoryHandle) -> Result<vk::DeviceSize> {
+        Ok(storage_access!(
+            self.external_memory_storage,
+            handle,
+            HandleType::ExternalMemory
+        )
+        .size)
+    }
+
+    /// Get a new platform handle to the memory, to be imported by another API or process.
+    pub fn export_memory(&self, handle: ExternalMemoryHandle) -> Result<ExternalHandle> {
+        let fns = self.external_memory_fns()?;
+        let memory = storage_access!(
+            self.external_memory_storage,
+            handle,
+            HandleType::ExternalMemory
+        );
+
+        #[cfg(unix)]
+        let (info, mut exported) = (
+            vk::MemoryGetFdInfoKHR::builder()
+                .memory(memory.memory)
+                .handle_type(MEMORY_HANDLE_TYPE),
+            -1,
+        );
+        #[cfg(unix)]
+        let result = unsafe {
+            fns.memory
+                .get_memory_fd_khr(self.device.handle(), &*info, &mut exported)
+        };
+
+        #[cfg(windows)]
+        let (info, mut exported) = (
+            vk::MemoryGetWin32HandleInfoKHR::builder()
+                .memory(memory.memory)
+                .handle_type(MEMORY_HANDLE_TYPE),
+            std::ptr::null_mut(),
+        );
+        #[cfg(windows)]
+        let result = unsafe {
+            fns.memory
+                .get_memory_win32_handle_khr(self.device.handle(), &*info, &mut exported)
+        };
+
+        match result {
+            vk::Result::SUCCESS => Ok(exported),
+            err => Err(err.into()),
+        }
+    }
+
+    /// Create a semaphore that can be exported with [VkTracerApp::export_semaphore].
+    pub fn create_exportable_semaphore(&mut self) -> Result<SemaphoreHandle> {
+        self.external_memory_fns()?;
+
+        let mut properties = vk::ExternalSemaphoreProperties::default();
+        unsafe {
+            self.instance
+                .get_physical_device_external_semaphore_properties(
+                    self.adapter.handle,
+                    &vk::PhysicalDeviceExternalSemaphoreInfo::builder()
+                        .handle_type(SEMAPHORE_HANDLE_TYPE),
+                    &mut properties,
+                );
+        }
+        if !properties
+            .external_semaphore_features
+            .contains(vk::ExternalSemaphoreFeatureFlags::EXPORTABLE)
+        {
+            return Err(VkTracerError::FeatureNotSupported("exportable semaphores"));
+        }
+
+        let mut export_info =
+            vk::ExportSemaphoreCreateInfo::builder().handle_types(SEMAPHORE_HANDLE_TYPE);
+        let semaphore = unsafe {
+            self.device.create_semaphore(
+                &vk::SemaphoreCreateInfo::builder().push_next(&mut export_info),
+                None,
+            )?
+        };
+        Ok(self.semaphore_storage.insert(semaphore))
+    }
+
+    /// The semaphore must have been created with [VkTracerApp::create_exportable_semaphore].
+    pub fn export_semaphore(&self, semaphore: SemaphoreHandle) -> Result<ExternalHandle> {
+        let fns = self.external_memory_fns()?;
+        let semaphore = *storage_access!(self.semaphore_storage, semaphore, HandleType::Semaphore);
+
+        #[cfg(unix)]
+        let (info, mut exported) = (
+            vk::SemaphoreGetFdInfoKHR::builder()
+                .semaphore(semaphore)
+                .handle_type(SEMAPHORE_HANDLE_TYPE),
+            -1,
+        );
+        #[cfg(unix)]
+        let result = unsafe {
+            fns.semaphore
+                .get_semaphore_fd_khr(self.device.handle(), &*info, &mut exported)
+        };
+
+        #[cfg(windows)]
+        let (info, mut exported) = (
+            vk::SemaphoreGetWin32HandleInfoKHR::builder()
+                .semaphore(semaphore)
+                .handle_type(SEMAPHORE_HANDLE_TYPE),
+            std::ptr::null_mut(),
+        );
+        #[cfg(windows)]
+        let result = unsafe {
+            fns.semaphore.get_semaphore_win32_handle_khr(
+                self.device.handle(),
+                &*info,
+                &mut exported,
+            )
+        };
+
+        match result {
+            vk::Result::SUCCESS => Ok(exported),
+            err => Err(err.into()),
+        }
+    }
+
+    /// The resource must not be used by a pending submission.
+    pub fn destroy_external_memory(&mut self, handle: ExternalMemoryHandle) -> Result<()> {
+        if let Some(memory) = self.external_memory_storage.remove(handle) {
+            unsafe { memory.destroy(&self.device) };
+        }
+        Ok(())
+    }
+
+    fn external_memory_fns(&self) -> Result<&ExternalMemoryFns> {
+        self.external_memory
+            .as_ref()
+            .ok_or(VkTracerError::ExtensionNotEnabled("VK_KHR_external_memory"))
+    }
+
+    fn allocate_exportable_memory(
+        &self,
+        requirements: vk::MemoryRequirements,
+        mut dedicated_info: vk::MemoryDedicatedAllocateInfo,
+    ) -> Result<vk::DeviceMemory> {
+        let memory_properties = &self.adapter.info.physical_device_info.memory_properties;
+        let memory_type = memory_properties.memory_types
+            [..memory_properties.memory_type_count as usize]
+            .iter()
+            .enumerate()
+            .find(|(i, ty)| {
+                requirements.memory_type_bits & (1 << i) != 0
+                    && ty
+                        .property_flags
+                        .contains(vk::MemoryPropertyFlags::DEVICE_LOCAL)
+            })
+            .map(|(i, _)| i)
+            .ok_or(VkTracerError::NoSuitableMemoryType)?;
+
+        let mut export_info =
+            vk::ExportMemoryAllocateInfo::builder().handle_types(MEMORY_HANDLE_TYPE);
+        let memory = unsafe {
+            self.device.allocate_memory(
+                &vk::MemoryAllocateInfo::builder()
+                    .allocation_size(requirements.size)
+                    .memory_type_index(memory_type as u32)
+                    .push_next(&mut export_info)
+                    .push_next(&mut dedicated_info),
+                None,
+            )?
+        };
+        Ok(memory)
+    }
+}
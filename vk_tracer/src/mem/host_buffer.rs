@@ -0,0 +1,36 @@
+use crate::{
+    errors::{HandleType, Result},
+    mem::RawBufferAllocation,
+    HostBufferHandle, VkTracerApp,
+};
+
+impl VkTracerApp {
+    /// A CPU visible buffer holding `data`, to be copied elsewhere with a
+    /// [crate::command_recorder::TransferRecorder].
+    pub fn create_upload_buffer(&mut self, data: &[u8]) -> Result<HostBufferHandle> {
+        let mut buffer = RawBufferAllocation::new_staging_buffer(&self.vma, data.len())?;
+        unsafe {
+            buffer.store(&self.vma, data)?;
+        }
+        Ok(self.host_buffer_storage.insert(buffer))
+    }
+
+    /// A CPU visible buffer of `size` bytes for a [crate::command_recorder::TransferRecorder]
+    /// to copy into, see [VkTracerApp::read_host_buffer].
+    pub fn create_readback_buffer(&mut self, size: usize) -> Result<HostBufferHandle> {
+        let buffer = RawBufferAllocation::new_readback_buffer(&self.vma, size)?;
+        Ok(self.host_buffer_storage.insert(buffer))
+    }
+
+    pub fn read_host_buffer(&self, handle: HostBufferHandle) -> Result<Vec<u8>> {
+        let buffer = storage_access!(self.host_buffer_storage, handle, HandleType::HostBuffer);
+        unsafe { buffer.load(&self.vma) }
+    }
+
+    pub fn destroy_host_buffer(&mut self, handle: HostBufferHandle) -> Result<()> {
+        if let Some(buffer) = self.host_buffer_storage.remove(handle) {
+            buffer.destroy(&self.vma)?;
+        }
+        Ok(())
+    }
+}
@@ -1,6 +1,7 @@
 use crate::{
     ash::version::InstanceV1_1,
     errors::{HandleType, Result, VkTracerError},
+    mem::Texture,
     SwapchainHandle, VkTracerApp,
 };
 use ash::{version::DeviceV1_0, vk};
@@ -37,11 +38,59 @@ impl VkTracerApp {
     pub fn create_depth_texture(
         &mut self,
         swapchain: SwapchainHandle,
+    ) -> Result<ImageViewFatHandle> {
+        self.create_depth_texture_inner(swapchain, false)
+    }
+
+    /// Create a depth texture that only lives during a render plan, its content must not be
+    /// loaded or stored (which is the default for depth attachments).
+    /// On tiled GPUs it will be backed by lazily allocated memory, meaning that it may never
+    /// actually consume any memory.
+    pub fn create_transient_depth_texture(
+        &mut self,
+        swapchain: SwapchainHandle,
+    ) -> Result<ImageViewFatHandle> {
+        self.create_depth_texture_inner(swapchain, true)
+    }
+
+    /// Free a depth texture of [VkTracerApp::create_depth_texture] or
+    /// [VkTracerApp::create_transient_depth_texture], typically the one of the previous size
+    /// after recreating the swapchain. It must not be used by pending commands nor by a render
+    /// target anymore.
+    pub fn destroy_depth_texture(&mut self, depth: ImageViewFatHandle) -> Result<()> {
+        let depth = self
+            .depth_texture_storage
+            .remove(&depth.handle)
+            .ok_or(VkTracerError::InvalidHandle(HandleType::DepthTexture))?;
+        unsafe {
+            self.device.destroy_image_view(depth.view, None);
+        }
+        depth.image.destroy(&self.vma)
+    }
+
+    fn create_depth_texture_inner(
+        &mut self,
+        swapchain: SwapchainHandle,
+        transient: bool,
     ) -> Result<ImageViewFatHandle> {
         let swapchain = storage_access!(self.swapchain_storage, swapchain, HandleType::Swapchain);
 
         let format = find_depth_format(self)?;
 
+        let (usage, lazily_allocated) = if transient {
+            (
+                vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT
+                    | vk::ImageUsageFlags::TRANSIENT_ATTACHMENT,
+                has_lazily_allocated_memory(self),
+            )
+        } else {
+            // Later passes can read the depth, like the ray traced shadows
+            (
+                vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+                false,
+            )
+        };
+
         let image = RawImageAllocation::new(
             &self.vma,
             &ImageDescription {
@@ -53,15 +102,22 @@ impl VkTracerApp {
                     .build(),
                 tiling: vk::ImageTiling::OPTIMAL,
                 format,
-                usage: vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+                usage,
                 array_layers: 1,
                 mip_levels: 1,
+                lazily_allocated,
             },
         )?;
 
-        let image_view = image.fullscreen_view(&self.device, vk::ImageAspectFlags::DEPTH)?;
+        let image_view = match image.fullscreen_view(&self.device, vk::ImageAspectFlags::DEPTH) {
+            Ok(view) => view,
+            Err(err) => {
+                image.destroy(&self.vma)?;
+                return Err(err);
+            }
+        };
 
-        Ok(ImageViewFatHandle {
+        let depth = ImageViewFatHandle {
             handle: image.handle,
             view: image_view,
             format: image.format,
@@ -69,10 +125,29 @@ impl VkTracerApp {
                 .width(image.extent.width)
                 .height(image.extent.height)
                 .build(),
-        })
+        };
+        self.depth_texture_storage.insert(
+            image.handle,
+            Texture {
+                image,
+                view: image_view,
+            },
+        );
+        Ok(depth)
     }
 }
 
+/// Lazily allocated memory is usually only found on tiled (mobile) GPUs.
+fn has_lazily_allocated_memory(app: &VkTracerApp) -> bool {
+    let memory_properties = &app.adapter.info.physical_device_info.memory_properties;
+    memory_properties.memory_types[..memory_properties.memory_type_count as usize]
+        .iter()
+        .any(|ty| {
+            ty.property_flags
+                .contains(vk::MemoryPropertyFlags::LAZILY_ALLOCATED)
+        })
+}
+
 /// Needs to be kept in sync with [has_stencil].
 #[inline]
 fn find_depth_format(app: &VkTracerApp) -> Result<vk::Format> {
@@ -100,28 +175,49 @@ fn find_supported_format<const N: usize>(
     tiling: vk::ImageTiling,
     features: vk::FormatFeatureFlags,
 ) -> Result<vk::Format> {
-    for format in candidates {
-        let mut props = vk::FormatProperties2::default();
-        unsafe {
-            app.instance.get_physical_device_format_properties2(
-                app.adapter.handle,
-                format,
-                &mut props,
-            );
-        }
-
-        let available_features = match tiling {
-            vk::ImageTiling::LINEAR => props.format_properties.linear_tiling_features,
-            vk::ImageTiling::OPTIMAL => props.format_properties.optimal_tiling_features,
-            _ => unreachable!(),
-        };
+    candidates
+        .iter()
+        .copied()
+        .find(|format| is_format_supported(app, *format, tiling, features))
+        .ok_or(VkTracerError::NoSuitableImageFormat)
+}
 
-        if (available_features & features) == features {
-            return Ok(format);
+/// The aspects contained in an image of this format.
+pub(crate) fn format_aspect(format: vk::Format) -> vk::ImageAspectFlags {
+    match format {
+        vk::Format::D16_UNORM | vk::Format::X8_D24_UNORM_PACK32 | vk::Format::D32_SFLOAT => {
+            vk::ImageAspectFlags::DEPTH
+        }
+        vk::Format::S8_UINT => vk::ImageAspectFlags::STENCIL,
+        vk::Format::D16_UNORM_S8_UINT
+        | vk::Format::D24_UNORM_S8_UINT
+        | vk::Format::D32_SFLOAT_S8_UINT => {
+            vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL
         }
+        _ => vk::ImageAspectFlags::COLOR,
     }
+}
+
+/// Whether the adapter supports all the given features for this format and tiling.
+pub(crate) fn is_format_supported(
+    app: &VkTracerApp,
+    format: vk::Format,
+    tiling: vk::ImageTiling,
+    features: vk::FormatFeatureFlags,
+) -> bool {
+    let mut props = vk::FormatProperties2::default();
+    unsafe {
+        app.instance
+            .get_physical_device_format_properties2(app.adapter.handle, format, &mut props);
+    }
+
+    let available_features = match tiling {
+        vk::ImageTiling::LINEAR => props.format_properties.linear_tiling_features,
+        vk::ImageTiling::OPTIMAL => props.format_properties.optimal_tiling_features,
+        _ => unreachable!(),
+    };
 
-    Err(VkTracerError::NoSuitableImageFormat)
+    available_features.contains(features)
 }
 
 pub struct ImageDescription {
@@ -133,6 +229,9 @@ pub struct ImageDescription {
 
     pub(crate) array_layers: u32,
     pub(crate) mip_levels: u32,
+
+    /// Require LAZILY_ALLOCATED memory, only valid for TRANSIENT_ATTACHMENT images.
+    pub(crate) lazily_allocated: bool,
 }
 
 #[derive(Clone)]
@@ -160,9 +259,18 @@ impl RawImageAllocation {
                 .usage(desc.usage)
                 .sharing_mode(vk::SharingMode::EXCLUSIVE)
                 .initial_layout(vk::ImageLayout::UNDEFINED),
-            &vk_mem::AllocationCreateInfo {
-                usage: vk_mem::MemoryUsage::GpuOnly,
-                ..Default::default()
+            &if desc.lazily_allocated {
+                vk_mem::AllocationCreateInfo {
+                    usage: vk_mem::MemoryUsage::Unknown,
+                    required_flags: vk::MemoryPropertyFlags::LAZILY_ALLOCATED,
+                    preferred_flags: vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                    ..Default::default()
+                }
+            } else {
+                vk_mem::AllocationCreateInfo {
+                    usage: vk_mem::MemoryUsage::GpuOnly,
+                    ..Default::default()
+                }
             },
         )?;
 
@@ -176,6 +284,11 @@ impl RawImageAllocation {
         })
     }
 
+    pub(crate) fn destroy(self, vma: &vk_mem::Allocator) -> Result<()> {
+        vma.destroy_image(self.handle, &self.allocation)?;
+        Ok(())
+    }
+
     pub(crate) fn fullscreen_view(
         &self,
         device: &ash::Device,
@@ -0,0 +1,76 @@
+use crate::{
+    command_recorder::QueueType,
+    errors::{HandleType, Result},
+    mem::{TypedBuffer, TypedBufferWithStaging},
+    IndirectBufferHandle, VkTracerApp,
+};
+use ash::vk;
+
+impl VkTracerApp {
+    /// Create a buffer of draw commands for [crate::render::ForwardPipelineBuilder::indirect].
+    /// It can also be bound as a storage buffer to be filled by a compute shader.
+    pub fn create_indirect_buffer(
+        &mut self,
+        commands: &[vk::DrawIndexedIndirectCommand],
+    ) -> Result<IndirectBufferHandle> {
+        let mut staging = TypedBufferWithStaging::new(
+            &self.vma,
+            TypedBuffer::new_indirect_buffer(&self.vma, commands.len())?,
+        )?;
+
+        staging.store(&self.vma, commands)?;
+        let buffer = staging.commit(
+            &self.vma,
+            &self.device,
+            self.queue_and_pool(QueueType::Transfer)?,
+        )?;
+
+        Ok(self.indirect_buffer_storage.insert(buffer.into_raw()))
+    }
+
+    /// Create a buffer holding a single draw count for
+    /// [crate::render::ForwardPipelineBuilder::indirect_count], typically written by a compute
+    /// shader along with the draw commands.
+    pub fn create_indirect_count_buffer(&mut self, count: u32) -> Result<IndirectBufferHandle> {
+        let mut staging = TypedBufferWithStaging::new(
+            &self.vma,
+            TypedBuffer::new_indirect_buffer(&self.vma, 1)?,
+        )?;
+
+        staging.store(&self.vma, &[count])?;
+        let buffer = staging.commit(
+            &self.vma,
+            &self.device,
+            self.queue_and_pool(QueueType::Transfer)?,
+        )?;
+
+        Ok(self.indirect_buffer_storage.insert(buffer.into_raw()))
+    }
+
+    /// Whether [crate::render::ForwardPipelineBuilder::indirect_count] can be used.
+    pub fn supports_draw_indirect_count(&self) -> bool {
+        self.adapter.info.physical_device_info.draw_indirect_count
+    }
+
+    /// Overwrite the draw commands, the buffer keeps its original size.
+    pub fn update_indirect_buffer(
+        &mut self,
+        handle: IndirectBufferHandle,
+        commands: &[vk::DrawIndexedIndirectCommand],
+    ) -> Result<()> {
+        let buffer = storage_access!(
+            self.indirect_buffer_storage,
+            handle,
+            HandleType::IndirectBuffer
+        );
+
+        let mut staging = TypedBufferWithStaging::new_raw(&self.vma, buffer.clone())?;
+        staging.store(&self.vma, commands)?;
+        staging.commit(
+            &self.vma,
+            &self.device,
+            self.queue_and_pool(QueueType::Transfer)?,
+        )?;
+        Ok(())
+    }
+}
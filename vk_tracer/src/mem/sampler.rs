@@ -0,0 +1,44 @@
+use crate::{
+    errors::{HandleType, Result, VkTracerError},
+    SamplerHandle, VkTracerApp,
+};
+use ash::{version::DeviceV1_0, vk};
+
+impl VkTracerApp {
+    /// A sampler to write in descriptor sets or to embed in their layout with
+    /// [crate::mem::DescriptorSetBuilder::immutable_samplers].
+    pub fn create_sampler(
+        &mut self,
+        filter: vk::Filter,
+        address_mode: vk::SamplerAddressMode,
+    ) -> Result<SamplerHandle> {
+        self.create_raw_sampler(
+            &vk::SamplerCreateInfo::builder()
+                .mag_filter(filter)
+                .min_filter(filter)
+                .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
+                .address_mode_u(address_mode)
+                .address_mode_v(address_mode)
+                .address_mode_w(address_mode)
+                .max_lod(vk::LOD_CLAMP_NONE)
+                .border_color(vk::BorderColor::FLOAT_TRANSPARENT_BLACK),
+        )
+    }
+
+    pub fn create_raw_sampler(&mut self, info: &vk::SamplerCreateInfo) -> Result<SamplerHandle> {
+        let sampler = unsafe { self.device.create_sampler(info, None)? };
+        Ok(self.sampler_storage.insert(sampler))
+    }
+
+    /// The sampler must not be used by pending commands nor be an immutable sampler of a set.
+    pub fn destroy_sampler(&mut self, handle: SamplerHandle) -> Result<()> {
+        let sampler = self
+            .sampler_storage
+            .remove(handle)
+            .ok_or(VkTracerError::InvalidHandle(HandleType::Sampler))?;
+        unsafe {
+            self.device.destroy_sampler(sampler, None);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,116 @@
+use crate::{
+    command_recorder::QueueType,
+    errors::{HandleType, Result},
+    mem::RawBufferAllocation,
+    DescriptorSetHandle, StorageBufferHandle, VkTracerApp,
+};
+use ash::{version::DeviceV1_0, vk};
+use std::slice::from_ref;
+
+impl VkTracerApp {
+    /// A GPU buffer holding `data`, to be read or written by shaders.
+    pub fn create_storage_buffer<T: Copy>(&mut self, data: &[T]) -> Result<StorageBufferHandle> {
+        let size = std::mem::size_of_val(data);
+        let buffer = RawBufferAllocation::new_storage_buffer(&self.vma, size)?;
+        let handle = self.storage_buffer_storage.insert(buffer);
+
+        let bytes = unsafe { std::slice::from_raw_parts(data.as_ptr() as *const u8, size) };
+        let upload = self.create_upload_buffer(bytes)?;
+        self.new_transfer_recorder(QueueType::Transfer)?
+            .copy_buffer(upload, handle, None)?
+            .submit()?;
+        self.destroy_host_buffer(upload)?;
+
+        Ok(handle)
+    }
+
+    pub fn destroy_storage_buffer(&mut self, handle: StorageBufferHandle) -> Result<()> {
+        if let Some(buffer) = self.storage_buffer_storage.remove(handle) {
+            buffer.destroy(&self.vma)?;
+        }
+        Ok(())
+    }
+
+    pub fn write_descriptor_set_storage_buffer(
+        &mut self,
+        set: DescriptorSetHandle,
+        binding: u32,
+        buffer: StorageBufferHandle,
+    ) -> Result<()> {
+        self.check_descriptor_write(set, binding, 0, vk::DescriptorType::STORAGE_BUFFER, None)?;
+        let buffer = storage_access!(
+            self.storage_buffer_storage,
+            buffer,
+            HandleType::StorageBuffer
+        );
+        unsafe {
+            self.device.update_descriptor_sets(
+                from_ref(
+                    &vk::WriteDescriptorSet::builder()
+                        .dst_set(
+                            storage_access!(
+                                self.descriptor_set_storage,
+                                set,
+                                HandleType::DescriptorSet
+                            )
+                            .handle,
+                        )
+                        .dst_binding(binding)
+                        .dst_array_element(0)
+                        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                        .buffer_info(from_ref(&buffer.get_descriptor_buffer_info())),
+                ),
+                &[],
+            )
+        }
+        Ok(())
+    }
+
+    /// Bind `range` bytes of the buffer at a time, the start is given by a dynamic offset when
+    /// binding the set.
+    pub fn write_descriptor_set_storage_buffer_dynamic(
+        &mut self,
+        set: DescriptorSetHandle,
+        binding: u32,
+        buffer: StorageBufferHandle,
+        range: vk::DeviceSize,
+    ) -> Result<()> {
+        let buffer = storage_access!(
+            self.storage_buffer_storage,
+            buffer,
+            HandleType::StorageBuffer
+        );
+        self.check_descriptor_write(
+            set,
+            binding,
+            0,
+            vk::DescriptorType::STORAGE_BUFFER_DYNAMIC,
+            Some((range, buffer.real_size)),
+        )?;
+        let buffer_info = vk::DescriptorBufferInfo::builder()
+            .buffer(buffer.buffer)
+            .offset(0)
+            .range(range);
+        unsafe {
+            self.device.update_descriptor_sets(
+                from_ref(
+                    &vk::WriteDescriptorSet::builder()
+                        .dst_set(
+                            storage_access!(
+                                self.descriptor_set_storage,
+                                set,
+                                HandleType::DescriptorSet
+                            )
+                            .handle,
+                        )
+                        .dst_binding(binding)
+                        .dst_array_element(0)
+                        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER_DYNAMIC)
+                        .buffer_info(from_ref(&buffer_info)),
+                ),
+                &[],
+            )
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,127 @@
+use crate::{
+    errors::{HandleType, Result, VkTracerError},
+    mem::RawBufferAllocation,
+    DescriptorSetHandle, TexelBufferHandle, VkTracerApp,
+};
+use ash::{
+    version::{DeviceV1_0, InstanceV1_0},
+    vk,
+};
+use std::slice::from_ref;
+
+pub(crate) struct TexelBuffer {
+    pub(crate) buffer: RawBufferAllocation,
+    pub(crate) view: vk::BufferView,
+}
+
+impl VkTracerApp {
+    /// A GPU buffer of `size` bytes seen by shaders as an array of texels of `format`, it can
+    /// be bound as a uniform or storage texel buffer and filled with a
+    /// [crate::command_recorder::TransferRecorder].
+    pub fn create_texel_buffer(
+        &mut self,
+        size: usize,
+        format: vk::Format,
+    ) -> Result<TexelBufferHandle> {
+        let features = vk::FormatFeatureFlags::UNIFORM_TEXEL_BUFFER;
+        let supported = unsafe {
+            self.instance
+                .get_physical_device_format_properties(self.adapter.handle, format)
+                .buffer_features
+        };
+        if !supported.contains(features) {
+            return Err(VkTracerError::UnsupportedFormatFeatures(format, features));
+        }
+
+        let buffer = RawBufferAllocation::new_texel_buffer(&self.vma, size)?;
+        let view = unsafe {
+            self.device.create_buffer_view(
+                &vk::BufferViewCreateInfo::builder()
+                    .buffer(buffer.buffer)
+                    .format(format)
+                    .offset(0)
+                    .range(vk::WHOLE_SIZE),
+                None,
+            )?
+        };
+
+        Ok(self
+            .texel_buffer_storage
+            .insert(TexelBuffer { buffer, view }))
+    }
+
+    pub fn destroy_texel_buffer(&mut self, handle: TexelBufferHandle) -> Result<()> {
+        if let Some(texel_buffer) = self.texel_buffer_storage.remove(handle) {
+            unsafe {
+                self.device.destroy_buffer_view(texel_buffer.view, None);
+            }
+            texel_buffer.buffer.destroy(&self.vma)?;
+        }
+        Ok(())
+    }
+
+    pub fn write_descriptor_set_uniform_texel_buffer(
+        &mut self,
+        set: DescriptorSetHandle,
+        binding: u32,
+        texel_buffer: TexelBufferHandle,
+    ) -> Result<()> {
+        self.write_descriptor_set_texel_buffer(
+            set,
+            binding,
+            texel_buffer,
+            vk::DescriptorType::UNIFORM_TEXEL_BUFFER,
+        )
+    }
+
+    /// The format must support STORAGE_TEXEL_BUFFER.
+    pub fn write_descriptor_set_storage_texel_buffer(
+        &mut self,
+        set: DescriptorSetHandle,
+        binding: u32,
+        texel_buffer: TexelBufferHandle,
+    ) -> Result<()> {
+        self.write_descriptor_set_texel_buffer(
+            set,
+            binding,
+            texel_buffer,
+            vk::DescriptorType::STORAGE_TEXEL_BUFFER,
+        )
+    }
+
+    fn write_descriptor_set_texel_buffer(
+        &mut self,
+        set: DescriptorSetHandle,
+        binding: u32,
+        texel_buffer: TexelBufferHandle,
+        ty: vk::DescriptorType,
+    ) -> Result<()> {
+        self.check_descriptor_write(set, binding, 0, ty, None)?;
+        let texel_buffer = storage_access!(
+            self.texel_buffer_storage,
+            texel_buffer,
+            HandleType::TexelBuffer
+        );
+        unsafe {
+            self.device.update_descriptor_sets(
+                from_ref(
+                    &vk::WriteDescriptorSet::builder()
+                        .dst_set(
+                            storage_access!(
+                                self.descriptor_set_storage,
+                                set,
+                                HandleType::DescriptorSet
+                            )
+                            .handle,
+                        )
+                        .dst_binding(binding)
+                        .dst_array_element(0)
+                        .descriptor_type(ty)
+                        .texel_buffer_view(from_ref(&texel_buffer.view)),
+                ),
+                &[],
+            )
+        }
+        Ok(())
+    }
+}
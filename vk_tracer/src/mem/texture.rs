@@ -0,0 +1,179 @@
+use crate::{
+    command_recorder::QueueType,
+    errors::{HandleType, Result, VkTracerError},
+    mem::{ImageDescription, ImageViewFatHandle, RawImageAllocation},
+    TextureHandle, VkTracerApp,
+};
+use ash::{version::DeviceV1_0, vk};
+
+/// Size in bytes of a texel, only for the uncompressed formats with 1, 2 or 4 channels.
+pub(crate) fn texel_size(format: vk::Format) -> Option<usize> {
+    match format {
+        vk::Format::R8_UNORM
+        | vk::Format::R8_SNORM
+        | vk::Format::R8_UINT
+        | vk::Format::R8_SINT
+        | vk::Format::R8_SRGB => Some(1),
+        vk::Format::R8G8_UNORM
+        | vk::Format::R8G8_SNORM
+        | vk::Format::R8G8_UINT
+        | vk::Format::R8G8_SINT
+        | vk::Format::R8G8_SRGB
+        | vk::Format::R16_UNORM
+        | vk::Format::R16_SNORM
+        | vk::Format::R16_UINT
+        | vk::Format::R16_SINT
+        | vk::Format::R16_SFLOAT => Some(2),
+        vk::Format::R8G8B8A8_UNORM
+        | vk::Format::R8G8B8A8_SNORM
+        | vk::Format::R8G8B8A8_UINT
+        | vk::Format::R8G8B8A8_SINT
+        | vk::Format::R8G8B8A8_SRGB
+        | vk::Format::B8G8R8A8_UNORM
+        | vk::Format::B8G8R8A8_SRGB
+        | vk::Format::A2B10G10R10_UNORM_PACK32
+        | vk::Format::B10G11R11_UFLOAT_PACK32
+        | vk::Format::R16G16_UNORM
+        | vk::Format::R16G16_SNORM
+        | vk::Format::R16G16_SFLOAT
+        | vk::Format::R32_UINT
+        | vk::Format::R32_SINT
+        | vk::Format::R32_SFLOAT => Some(4),
+        vk::Format::R16G16B16A16_UNORM
+        | vk::Format::R16G16B16A16_SNORM
+        | vk::Format::R16G16B16A16_SFLOAT
+        | vk::Format::R32G32_UINT
+        | vk::Format::R32G32_SINT
+        | vk::Format::R32G32_SFLOAT => Some(8),
+        vk::Format::R32G32B32A32_UINT
+        | vk::Format::R32G32B32A32_SINT
+        | vk::Format::R32G32B32A32_SFLOAT => Some(16),
+        _ => None,
+    }
+}
+
+pub(crate) struct Texture {
+    pub(crate) image: RawImageAllocation,
+    pub(crate) view: vk::ImageView,
+}
+
+impl VkTracerApp {
+    /// A 2D texture filled with tightly packed `pixels`, ready to be sampled in
+    /// SHADER_READ_ONLY_OPTIMAL. Only uncompressed formats with 1, 2 or 4 channels.
+    pub fn create_texture(
+        &mut self,
+        width: u32,
+        height: u32,
+        format: vk::Format,
+        pixels: &[u8],
+    ) -> Result<TextureHandle> {
+        let texel_size = texel_size(format).ok_or(VkTracerError::NoSuitableImageFormat)?;
+        let expected = width as usize * height as usize * texel_size;
+        if pixels.len() != expected {
+            return Err(VkTracerError::TextureSizeMismatch {
+                expected,
+                actual: pixels.len(),
+            });
+        }
+
+        let texture = self.new_texture(width, height, format)?;
+        let upload = self.create_upload_buffer(pixels)?;
+        let target = self.get_texture_view(texture)?;
+        self.new_transfer_recorder(QueueType::Graphics)?
+            .copy_buffer_to_image(
+                upload,
+                target,
+                texture_upload_region(width, height),
+                vk::ImageLayout::UNDEFINED,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            )?
+            .submit()?;
+        self.destroy_host_buffer(upload)?;
+
+        Ok(texture)
+    }
+
+    /// A 2D texture with undefined content, to be filled with [texture_upload_region].
+    pub(crate) fn new_texture(
+        &mut self,
+        width: u32,
+        height: u32,
+        format: vk::Format,
+    ) -> Result<TextureHandle> {
+        self.new_texture_with_usage(
+            width,
+            height,
+            format,
+            vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED,
+        )
+    }
+
+    pub(crate) fn new_texture_with_usage(
+        &mut self,
+        width: u32,
+        height: u32,
+        format: vk::Format,
+        usage: vk::ImageUsageFlags,
+    ) -> Result<TextureHandle> {
+        let image = RawImageAllocation::new(
+            &self.vma,
+            &ImageDescription {
+                ty: vk::ImageType::TYPE_2D,
+                extent: vk::Extent3D {
+                    width,
+                    height,
+                    depth: 1,
+                },
+                tiling: vk::ImageTiling::OPTIMAL,
+                format,
+                usage,
+                array_layers: 1,
+                mip_levels: 1,
+                lazily_allocated: false,
+            },
+        )?;
+        let view = image.fullscreen_view(&self.device, vk::ImageAspectFlags::COLOR)?;
+        Ok(self.texture_storage.insert(Texture { image, view }))
+    }
+
+    /// The view to write in descriptor sets.
+    pub fn get_texture_view(&self, handle: TextureHandle) -> Result<ImageViewFatHandle> {
+        let texture = storage_access!(self.texture_storage, handle, HandleType::Texture);
+        Ok(ImageViewFatHandle {
+            handle: texture.image.handle,
+            view: texture.view,
+            format: texture.image.format,
+            extent: vk::Extent2D {
+                width: texture.image.extent.width,
+                height: texture.image.extent.height,
+            },
+        })
+    }
+
+    pub fn destroy_texture(&mut self, handle: TextureHandle) -> Result<()> {
+        if let Some(texture) = self.texture_storage.remove(handle) {
+            unsafe {
+                self.device.destroy_image_view(texture.view, None);
+            }
+            texture.image.destroy(&self.vma)?;
+        }
+        Ok(())
+    }
+}
+
+/// Copy of tightly packed pixels covering the whole texture.
+pub(crate) fn texture_upload_region(width: u32, height: u32) -> vk::BufferImageCopy {
+    vk::BufferImageCopy::builder()
+        .image_subresource(
+            vk::ImageSubresourceLayers::builder()
+                .aspect_mask(vk::ImageAspectFlags::COLOR)
+                .layer_count(1)
+                .build(),
+        )
+        .image_extent(vk::Extent3D {
+            width,
+            height,
+            depth: 1,
+        })
+        .build()
+}
@@ -17,7 +17,7 @@ impl VkTracerApp {
         let ubo = staging.commit(
             &self.vma,
             &self.device,
-            *self.command_pools.get(&QueueType::Transfer).unwrap(),
+            self.queue_and_pool(QueueType::Transfer)?,
         )?;
 
         Ok(self.ubo_storage.insert(ubo.into_raw()))
@@ -35,7 +35,7 @@ impl VkTracerApp {
         staging.commit(
             &self.vma,
             &self.device,
-            *self.command_pools.get(&QueueType::Transfer).unwrap(),
+            self.queue_and_pool(QueueType::Transfer)?,
         )?;
         Ok(())
     }
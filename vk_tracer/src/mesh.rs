@@ -1,15 +1,35 @@
 use crate::{
     command_recorder::QueueType,
-    errors::Result,
+    errors::{HandleType, Result, VkTracerError},
     mem::{RawBufferAllocation, TypedBuffer, TypedBufferWithStaging},
     MeshHandle, VkTracerApp,
 };
 use ash::vk;
-use field_offset::offset_of;
-use lazy_static::lazy_static;
 #[cfg(feature = "math")]
 use nalgebra_glm as glm;
 use std::{any::TypeId, borrow::Cow};
+pub use vk_tracer_derive::VertexLayout;
+
+#[cfg(feature = "mesh_optimizer")]
+mod optimize;
+#[cfg(feature = "mesh_optimizer")]
+pub use optimize::*;
+#[cfg(feature = "math")]
+mod bounds;
+#[cfg(feature = "math")]
+pub use bounds::*;
+#[cfg(feature = "math")]
+mod normals;
+#[cfg(feature = "math")]
+pub use normals::*;
+#[cfg(feature = "math")]
+mod quantize;
+#[cfg(feature = "math")]
+pub use quantize::*;
+#[cfg(feature = "tangents")]
+mod tangents;
+#[cfg(feature = "tangents")]
+pub use tangents::*;
 
 impl VkTracerApp {
     pub fn create_mesh_indexed<V: MeshVertex, I: MeshIndex>(
@@ -20,11 +40,333 @@ impl VkTracerApp {
         let mesh = Mesh::new(
             &self.device,
             &self.vma,
-            *self.command_pools.get(&QueueType::Transfer).unwrap(),
+            self.queue_and_pool(QueueType::Transfer)?,
             vertices,
             indices,
         )?;
 
+        Ok(self.insert_mesh(mesh))
+    }
+
+    /// Create a mesh with several levels of detail sharing the same vertices, from the most
+    /// detailed to the least. Each level is drawn up to its distance to the camera, see
+    /// [crate::render::GraphicsRecorder::draw_mesh_at_distance].
+    pub fn create_mesh_with_lods<V: MeshVertex, I: MeshIndex>(
+        &mut self,
+        vertices: &[V],
+        lods: &[(&[I], f32)],
+    ) -> Result<MeshHandle> {
+        let mut indices = Vec::new();
+        let mut mesh_lods = Vec::with_capacity(lods.len());
+        for (lod_indices, max_distance) in lods.iter().copied() {
+            mesh_lods.push(MeshLod {
+                first_index: indices.len() as u32,
+                index_count: lod_indices.len() as u32,
+                max_distance,
+            });
+            indices.extend_from_slice(lod_indices);
+        }
+
+        let handle = self.create_mesh_indexed(vertices, &indices)?;
+        let mesh = storage_access_mut!(self.mesh_storage, handle, HandleType::Mesh);
+        mesh.lods = mesh_lods.into_boxed_slice();
+        Ok(handle)
+    }
+
+    /// Pack several static meshes in a single mesh, they are drawn from the same buffers with
+    /// [crate::render::GraphicsRecorder::draw_batched] without rebinding them.
+    pub fn create_mesh_batch<V: MeshVertex, I: MeshIndex>(
+        &mut self,
+        meshes: &[(&[V], &[I])],
+    ) -> Result<MeshBatch> {
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        let mut batched = Vec::with_capacity(meshes.len());
+        for (mesh_vertices, mesh_indices) in meshes.iter().copied() {
+            batched.push(BatchedMesh {
+                first_index: indices.len() as u32,
+                index_count: mesh_indices.len() as u32,
+                vertex_offset: vertices.len() as i32,
+                #[cfg(feature = "math")]
+                bounds: MeshBounds::from_vertices(mesh_vertices),
+            });
+            vertices.extend_from_slice(mesh_vertices);
+            indices.extend_from_slice(mesh_indices);
+        }
+
+        Ok(MeshBatch {
+            mesh: self.create_mesh_indexed(&vertices, &indices)?,
+            meshes: batched,
+        })
+    }
+
+    /// Same as [VkTracerApp::create_mesh_indexed], the indices are stored in `format`.
+    pub fn create_mesh_with_index_format<V: MeshVertex>(
+        &mut self,
+        vertices: &[V],
+        indices: &[u32],
+        format: IndexFormat,
+    ) -> Result<MeshHandle> {
+        Ok(self.create_meshes_with_index_format(&[(vertices, indices)], format)?[0])
+    }
+
+    /// Same as [VkTracerApp::create_meshes_indexed], the indices of each mesh are stored in
+    /// `format`.
+    pub fn create_meshes_with_index_format<V: MeshVertex>(
+        &mut self,
+        meshes: &[(&[V], &[u32])],
+        format: IndexFormat,
+    ) -> Result<Vec<MeshHandle>> {
+        let mut narrowed = Vec::with_capacity(meshes.len());
+        for (vertices, indices) in meshes.iter().copied() {
+            narrowed.push(if format.is_u16(vertices.len())? {
+                Some(indices.iter().map(|i| *i as u16).collect::<Vec<_>>())
+            } else {
+                None
+            });
+        }
+
+        self.create_meshes_raw(
+            &meshes
+                .iter()
+                .zip(&narrowed)
+                .map(|((vertices, indices), narrowed)| {
+                    let indices = match narrowed {
+                        Some(narrowed) => RawIndices::new(narrowed),
+                        None => RawIndices::new(indices),
+                    };
+                    (*vertices, indices)
+                })
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    /// Create several meshes with a single transfer submission.
+    pub fn create_meshes_indexed<V: MeshVertex, I: MeshIndex>(
+        &mut self,
+        meshes: &[(&[V], &[I])],
+    ) -> Result<Vec<MeshHandle>> {
+        self.create_meshes_raw(
+            &meshes
+                .iter()
+                .map(|(vertices, indices)| (*vertices, RawIndices::new(indices)))
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    fn create_meshes_raw<V: MeshVertex>(
+        &mut self,
+        meshes: &[(&[V], RawIndices)],
+    ) -> Result<Vec<MeshHandle>> {
+        let mut new_meshes = Vec::with_capacity(meshes.len());
+        let mut uploads = Vec::with_capacity(2 * meshes.len());
+        let mut result = Ok(());
+        for &(vertices, ref indices) in meshes {
+            match self.new_mesh(vertices, indices) {
+                Ok(mesh) => {
+                    uploads.push((as_bytes(vertices), mesh.vertices.buffer));
+                    uploads.push((indices.bytes, mesh.indices.buffer));
+                    new_meshes.push(mesh);
+                }
+                Err(err) => {
+                    result = Err(err);
+                    break;
+                }
+            }
+        }
+
+        // Nothing is inserted until every mesh is uploaded
+        if let Err(err) = result.and_then(|_| self.upload_to_buffers(&uploads)) {
+            for mesh in new_meshes {
+                mesh.destroy(&self.vma)?;
+            }
+            return Err(err);
+        }
+
+        Ok(new_meshes
+            .into_iter()
+            .map(|mesh| self.insert_mesh(mesh))
+            .collect())
+    }
+
+    /// A mesh with empty buffers, large enough for `vertices` and `indices`.
+    pub(crate) fn new_mesh<V: MeshVertex>(
+        &self,
+        vertices: &[V],
+        indices: &RawIndices,
+    ) -> Result<Mesh> {
+        let vertex_buffer =
+            RawBufferAllocation::new_vertex_buffer(&self.vma, std::mem::size_of_val(vertices))?;
+        let index_buffer =
+            match RawBufferAllocation::new_index_buffer(&self.vma, indices.bytes.len()) {
+                Ok(buffer) => buffer,
+                Err(err) => {
+                    vertex_buffer.destroy(&self.vma)?;
+                    return Err(err);
+                }
+            };
+
+        Ok(Mesh {
+            vertices: vertex_buffer,
+            extra_streams: Vec::new(),
+            vertex_desc: (
+                TypeId::of::<V>(),
+                Cow::Borrowed(V::binding_description()),
+                Cow::Borrowed(V::attribute_description()),
+            ),
+            indices: index_buffer,
+            indices_len: indices.count,
+            index_ty: indices.ty,
+            lods: Box::new([]),
+            #[cfg(feature = "math")]
+            bounds: MeshBounds::from_vertices(vertices),
+        })
+    }
+
+    /// Create a mesh with each attribute in its own buffer, stream `i` is bound to binding `i`
+    /// and location `i`. A pass can then bind only the streams it reads with
+    /// [crate::render::GraphicsRecorder::bind_mesh_streams], like the positions for depth.
+    pub fn create_mesh_streams<I: MeshIndex>(
+        &mut self,
+        streams: &[VertexStream],
+        indices: &[I],
+    ) -> Result<MeshHandle> {
+        let vertex_count = streams
+            .first()
+            .ok_or(VkTracerError::InvalidVertexStreams)?
+            .vertex_count;
+        if streams
+            .iter()
+            .any(|stream| stream.vertex_count != vertex_count)
+        {
+            return Err(VkTracerError::InvalidVertexStreams);
+        }
+        if vertex_count == 0 {
+            return Err(VkTracerError::EmptyVertexStreams);
+        }
+
+        // The index buffer is last
+        let indices = RawIndices::new(indices);
+        let mut buffers = Vec::with_capacity(streams.len() + 1);
+        let mut result = Ok(());
+        for size in streams
+            .iter()
+            .map(|stream| stream.bytes.len())
+            .chain(std::iter::once(indices.bytes.len()))
+        {
+            let buffer = if buffers.len() < streams.len() {
+                RawBufferAllocation::new_vertex_buffer(&self.vma, size)
+            } else {
+                RawBufferAllocation::new_index_buffer(&self.vma, size)
+            };
+            match buffer {
+                Ok(buffer) => buffers.push(buffer),
+                Err(err) => {
+                    result = Err(err);
+                    break;
+                }
+            }
+        }
+
+        let result = result.and_then(|_| {
+            let uploads = streams
+                .iter()
+                .map(|stream| stream.bytes)
+                .chain(std::iter::once(indices.bytes))
+                .zip(&buffers)
+                .map(|(bytes, buffer)| (bytes, buffer.buffer))
+                .collect::<Vec<_>>();
+            self.upload_to_buffers(&uploads)
+        });
+        if let Err(err) = result {
+            for buffer in buffers {
+                buffer.destroy(&self.vma)?;
+            }
+            return Err(err);
+        }
+        let index_buffer = buffers.pop().unwrap();
+
+        let bindings = (0..streams.len() as u32)
+            .zip(streams)
+            .map(|(binding, stream)| {
+                vk::VertexInputBindingDescription::builder()
+                    .binding(binding)
+                    .stride(stream.stride)
+                    .input_rate(vk::VertexInputRate::VERTEX)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+        let attributes = (0..streams.len() as u32)
+            .zip(streams)
+            .map(|(binding, stream)| {
+                vk::VertexInputAttributeDescription::builder()
+                    .binding(binding)
+                    .location(binding)
+                    .format(stream.format)
+                    .offset(0)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+
+        let mut buffers = buffers.into_iter();
+        let mesh = Mesh {
+            vertices: buffers.next().unwrap(),
+            extra_streams: buffers.collect(),
+            vertex_desc: (
+                TypeId::of::<VertexStream<'static>>(),
+                Cow::Owned(bindings),
+                Cow::Owned(attributes),
+            ),
+            indices: index_buffer,
+            indices_len: indices.count,
+            index_ty: indices.ty,
+            lods: Box::new([]),
+            #[cfg(feature = "math")]
+            bounds: if streams[0].format == vk::Format::R32G32B32_SFLOAT {
+                MeshBounds::from_positions(streams[0].bytes, streams[0].stride as usize, 0)
+            } else {
+                None
+            },
+        };
+        Ok(self.insert_mesh(mesh))
+    }
+
+    /// Copy data to device local buffers with a single transfer submission.
+    fn upload_to_buffers(&mut self, uploads: &[(&[u8], vk::Buffer)]) -> Result<()> {
+        let mut staging = Vec::with_capacity(uploads.len());
+        let result = self.copy_from_staging(uploads, &mut staging);
+
+        // Destroyed whether the copy succeeded or not
+        for buffer in staging {
+            buffer.destroy(&self.vma)?;
+        }
+        result
+    }
+
+    /// Every staging buffer created is pushed to `staging`, even on failure.
+    fn copy_from_staging(
+        &mut self,
+        uploads: &[(&[u8], vk::Buffer)],
+        staging: &mut Vec<RawBufferAllocation>,
+    ) -> Result<()> {
+        for (data, _) in uploads.iter().copied() {
+            staging.push(RawBufferAllocation::new_staging_buffer(
+                &self.vma,
+                data.len(),
+            )?);
+            unsafe {
+                staging.last_mut().unwrap().store(&self.vma, data)?;
+            }
+        }
+
+        let mut recorder = self.new_transfer_recorder(QueueType::Transfer)?;
+        for (buffer, (data, dst)) in staging.iter().zip(uploads) {
+            recorder = recorder.copy_raw_buffer(buffer.buffer, *dst, data.len() as vk::DeviceSize);
+        }
+        recorder.submit()
+    }
+
+    pub(crate) fn insert_mesh(&mut self, mesh: Mesh) -> MeshHandle {
         if let Some(debug_utils) = self.debug_utils.as_ref() {
             debug_utils.name_object(
                 &self.device,
@@ -40,134 +382,191 @@ impl VkTracerApp {
             );
         }
 
-        Ok(self.mesh_storage.insert(mesh))
+        self.mesh_storage.insert(mesh)
+    }
+
+    /// The mesh must not be used by pending commands.
+    pub fn destroy_mesh(&mut self, handle: MeshHandle) -> Result<()> {
+        self.mesh_storage
+            .remove(handle)
+            .ok_or(VkTracerError::InvalidHandle(HandleType::Mesh))?
+            .destroy(&self.vma)
     }
 }
 
+/// Processing of the meshes when they are loaded, the optimizations require the
+/// `mesh_optimizer` feature.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct MeshLoadOptions {
+    pub index_format: IndexFormat,
+    /// Reorder the triangles and vertices for the vertex cache, overdraw and vertex fetch.
+    pub optimize: bool,
+    /// Fraction of the triangles to keep, between 0 and 1.
+    pub simplify_target: Option<f32>,
+}
+
 pub trait MeshVertex: Copy + 'static {
     fn binding_description() -> &'static [vk::VertexInputBindingDescription];
     fn attribute_description() -> &'static [vk::VertexInputAttributeDescription];
 }
 
+/// Format of a field of a vertex, for `#[derive(VertexLayout)]`.
+pub trait VertexAttribute {
+    const FORMAT: vk::Format;
+}
+
+macro_rules! vertex_attribute {
+    ($($ty:ty => $format:ident),* $(,)?) => {
+        $(impl VertexAttribute for $ty {
+            const FORMAT: vk::Format = vk::Format::$format;
+        })*
+    };
+}
+
+vertex_attribute! {
+    f32 => R32_SFLOAT,
+    [f32; 2] => R32G32_SFLOAT,
+    [f32; 3] => R32G32B32_SFLOAT,
+    [f32; 4] => R32G32B32A32_SFLOAT,
+    u32 => R32_UINT,
+    [u32; 2] => R32G32_UINT,
+    [u32; 3] => R32G32B32_UINT,
+    [u32; 4] => R32G32B32A32_UINT,
+    i32 => R32_SINT,
+    [i32; 2] => R32G32_SINT,
+    [i32; 3] => R32G32B32_SINT,
+    [i32; 4] => R32G32B32A32_SINT,
+}
+
 #[cfg(feature = "math")]
-lazy_static! {
-    static ref VERTEX_XYZ_UV_NORM_BINDING_DESC: [vk::VertexInputBindingDescription; 1] =
-        [vk::VertexInputBindingDescription::builder()
-            .binding(0)
-            .stride(std::mem::size_of::<VertexXyzUvNorm>() as u32)
-            .input_rate(vk::VertexInputRate::VERTEX)
-            .build(),];
-    static ref VERTEX_XYZ_UV_NORM_ATTRIBUTE_DESC: [vk::VertexInputAttributeDescription; 3] = [
-        vk::VertexInputAttributeDescription::builder()
-            .binding(0)
-            .location(0)
-            .format(vk::Format::R32G32B32_SFLOAT)
-            .offset(offset_of!(VertexXyzUvNorm => xyz).get_byte_offset() as u32)
-            .build(),
-        vk::VertexInputAttributeDescription::builder()
-            .binding(0)
-            .location(1)
-            .format(vk::Format::R32G32_SFLOAT)
-            .offset(offset_of!(VertexXyzUvNorm => uv).get_byte_offset() as u32)
-            .build(),
-        vk::VertexInputAttributeDescription::builder()
-            .binding(0)
-            .location(2)
-            .format(vk::Format::R32G32B32_SFLOAT)
-            .offset(offset_of!(VertexXyzUvNorm => normal).get_byte_offset() as u32)
-            .build(),
-    ];
-    static ref VERTEX_XYZ_UV_BINDING_DESC: [vk::VertexInputBindingDescription; 1] =
-        [vk::VertexInputBindingDescription::builder()
-            .binding(0)
-            .stride(std::mem::size_of::<VertexXyzUv>() as u32)
-            .input_rate(vk::VertexInputRate::VERTEX)
-            .build(),];
-    static ref VERTEX_XYZ_UV_ATTRIBUTE_DESC: [vk::VertexInputAttributeDescription; 2] = [
-        vk::VertexInputAttributeDescription::builder()
-            .binding(0)
-            .location(0)
-            .format(vk::Format::R32G32B32_SFLOAT)
-            .offset(offset_of!(VertexXyzUv => xyz).get_byte_offset() as u32)
-            .build(),
-        vk::VertexInputAttributeDescription::builder()
-            .binding(0)
-            .location(1)
-            .format(vk::Format::R32G32_SFLOAT)
-            .offset(offset_of!(VertexXyzUv => uv).get_byte_offset() as u32)
-            .build(),
-    ];
-    static ref VERTEX_XYZ_BINDING_DESC: [vk::VertexInputBindingDescription; 1] =
-        [vk::VertexInputBindingDescription::builder()
-            .binding(0)
-            .stride(std::mem::size_of::<VertexXyz>() as u32)
-            .input_rate(vk::VertexInputRate::VERTEX)
-            .build(),];
-    static ref VERTEX_XYZ_ATTRIBUTE_DESC: [vk::VertexInputAttributeDescription; 1] =
-        [vk::VertexInputAttributeDescription::builder()
-            .binding(0)
-            .location(0)
-            .format(vk::Format::R32G32B32_SFLOAT)
-            .offset(offset_of!(VertexXyz => 0).get_byte_offset() as u32)
-            .build(),];
+vertex_attribute! {
+    glm::Vec2 => R32G32_SFLOAT,
+    glm::Vec3 => R32G32B32_SFLOAT,
+    glm::Vec4 => R32G32B32A32_SFLOAT,
+    glm::UVec2 => R32G32_UINT,
+    glm::UVec3 => R32G32B32_UINT,
+    glm::UVec4 => R32G32B32A32_UINT,
+    glm::IVec2 => R32G32_SINT,
+    glm::IVec3 => R32G32B32_SINT,
+    glm::IVec4 => R32G32B32A32_SINT,
 }
 
 #[cfg(feature = "math")]
 #[repr(packed)]
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, VertexLayout)]
 pub struct VertexXyzUvNorm {
     pub xyz: glm::Vec3,
     pub uv: glm::Vec2,
     pub normal: glm::Vec3,
 }
 
+/// Vertex with a tangent frame, for normal mapping.
 #[cfg(feature = "math")]
-impl MeshVertex for VertexXyzUvNorm {
-    fn binding_description() -> &'static [vk::VertexInputBindingDescription] {
-        &*VERTEX_XYZ_UV_NORM_BINDING_DESC
-    }
+#[repr(packed)]
+#[derive(Copy, Clone, Debug, VertexLayout)]
+pub struct VertexXyzUvNormTan {
+    pub xyz: glm::Vec3,
+    pub uv: glm::Vec2,
+    pub normal: glm::Vec3,
+    /// The sign of `w` gives the handedness of the bitangent, `cross(normal, tangent.xyz) * w`.
+    pub tangent: glm::Vec4,
+}
 
-    fn attribute_description() -> &'static [vk::VertexInputAttributeDescription] {
-        &*VERTEX_XYZ_UV_NORM_ATTRIBUTE_DESC
-    }
+/// Vertex of a skinned mesh, moved by up to 4 joints of its skin.
+#[cfg(feature = "math")]
+#[repr(packed)]
+#[derive(Copy, Clone, Debug, VertexLayout)]
+pub struct VertexXyzUvNormSkin {
+    pub xyz: glm::Vec3,
+    pub uv: glm::Vec2,
+    pub normal: glm::Vec3,
+    /// Indices in the joints of the skin.
+    pub joints: [u32; 4],
+    pub weights: glm::Vec4,
 }
 
 #[cfg(feature = "math")]
 #[repr(packed)]
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, VertexLayout)]
 pub struct VertexXyzUv {
     pub xyz: glm::Vec3,
     pub uv: glm::Vec2,
 }
 
 #[cfg(feature = "math")]
-impl MeshVertex for VertexXyzUv {
-    fn binding_description() -> &'static [vk::VertexInputBindingDescription] {
-        &*VERTEX_XYZ_UV_BINDING_DESC
+#[derive(Copy, Clone, Debug, VertexLayout)]
+pub struct VertexXyz(pub glm::Vec3);
+
+pub trait MeshIndex: Copy + 'static {
+    fn ty() -> vk::IndexType;
+}
+
+/// Type of the indices stored in the index buffer of a mesh.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum IndexFormat {
+    U16,
+    U32,
+    /// u16 when the mesh has few enough vertices, halving the size of the index buffer.
+    Narrowest,
+}
+
+impl Default for IndexFormat {
+    fn default() -> Self {
+        IndexFormat::Narrowest
     }
+}
 
-    fn attribute_description() -> &'static [vk::VertexInputAttributeDescription] {
-        &*VERTEX_XYZ_UV_ATTRIBUTE_DESC
+impl IndexFormat {
+    fn is_u16(self, vertex_count: usize) -> Result<bool> {
+        let fits = vertex_count <= u16::MAX as usize + 1;
+        match self {
+            IndexFormat::U16 if !fits => Err(VkTracerError::TooManyVerticesForU16(vertex_count)),
+            IndexFormat::U16 => Ok(true),
+            IndexFormat::U32 => Ok(false),
+            IndexFormat::Narrowest => Ok(fits),
+        }
     }
 }
 
-#[cfg(feature = "math")]
+/// One attribute of all the vertices of a mesh, see [VkTracerApp::create_mesh_streams].
 #[derive(Copy, Clone, Debug)]
-pub struct VertexXyz(pub glm::Vec3);
+pub struct VertexStream<'a> {
+    bytes: &'a [u8],
+    format: vk::Format,
+    stride: u32,
+    vertex_count: usize,
+}
 
-#[cfg(feature = "math")]
-impl MeshVertex for VertexXyz {
-    fn binding_description() -> &'static [vk::VertexInputBindingDescription] {
-        &*VERTEX_XYZ_BINDING_DESC
+impl<'a> VertexStream<'a> {
+    pub fn new<A: VertexAttribute + Copy>(attributes: &'a [A]) -> Self {
+        Self {
+            bytes: as_bytes(attributes),
+            format: A::FORMAT,
+            stride: std::mem::size_of::<A>() as u32,
+            vertex_count: attributes.len(),
+        }
     }
+}
 
-    fn attribute_description() -> &'static [vk::VertexInputAttributeDescription] {
-        &*VERTEX_XYZ_ATTRIBUTE_DESC
-    }
+pub(crate) fn as_bytes<T: Copy>(data: &[T]) -> &[u8] {
+    unsafe { std::slice::from_raw_parts(data.as_ptr() as *const u8, std::mem::size_of_val(data)) }
 }
 
-pub trait MeshIndex: Copy + 'static {
-    fn ty() -> vk::IndexType;
+/// Indices of any type, to be copied in an index buffer.
+pub(crate) struct RawIndices<'a> {
+    pub(crate) bytes: &'a [u8],
+    count: u32,
+    ty: (TypeId, vk::IndexType),
+}
+
+impl<'a> RawIndices<'a> {
+    pub(crate) fn new<I: MeshIndex>(indices: &'a [I]) -> Self {
+        Self {
+            bytes: as_bytes(indices),
+            count: indices.len() as u32,
+            ty: (TypeId::of::<I>(), I::ty()),
+        }
+    }
 }
 
 impl MeshIndex for u16 {
@@ -184,14 +583,46 @@ impl MeshIndex for u32 {
 
 pub struct Mesh {
     pub(crate) vertices: RawBufferAllocation,
+    /// Buffers of the bindings after the first one, for meshes made of several streams.
+    pub(crate) extra_streams: Vec<RawBufferAllocation>,
     pub(crate) vertex_desc: (
         TypeId, // For future use
-        &'static [vk::VertexInputBindingDescription],
-        &'static [vk::VertexInputAttributeDescription],
+        Cow<'static, [vk::VertexInputBindingDescription]>,
+        Cow<'static, [vk::VertexInputAttributeDescription]>,
     ),
     pub(crate) indices: RawBufferAllocation,
     pub(crate) indices_len: u32,
     pub(crate) index_ty: (TypeId, vk::IndexType),
+    /// Empty when the mesh has a single level of detail.
+    pub(crate) lods: Box<[MeshLod]>,
+    #[cfg(feature = "math")]
+    pub(crate) bounds: Option<MeshBounds>,
+}
+
+/// Meshes sharing the vertex and index buffers of `mesh`, in the order they were given.
+#[derive(Clone, Debug)]
+pub struct MeshBatch {
+    pub mesh: MeshHandle,
+    pub meshes: Vec<BatchedMesh>,
+}
+
+/// Where a mesh is in the buffers of its batch, its indices are relative to `vertex_offset`.
+#[derive(Copy, Clone, Debug)]
+pub struct BatchedMesh {
+    pub first_index: u32,
+    pub index_count: u32,
+    pub vertex_offset: i32,
+    #[cfg(feature = "math")]
+    pub bounds: Option<MeshBounds>,
+}
+
+/// A range of the index buffer of a mesh.
+#[derive(Copy, Clone, Debug)]
+pub struct MeshLod {
+    pub first_index: u32,
+    pub index_count: u32,
+    /// Distance to the camera up to which this level is used.
+    pub max_distance: f32,
 }
 
 impl Mesh {
@@ -224,14 +655,47 @@ impl Mesh {
 
         Ok(Self {
             vertices: vertex_buffer.into_raw(),
+            extra_streams: Vec::new(),
             vertex_desc: (
                 TypeId::of::<V>(),
-                V::binding_description(),
-                V::attribute_description(),
+                Cow::Borrowed(V::binding_description()),
+                Cow::Borrowed(V::attribute_description()),
             ),
             indices: index_buffer.into_raw(),
             indices_len,
             index_ty: (TypeId::of::<I>(), I::ty()),
+            lods: Box::new([]),
+            #[cfg(feature = "math")]
+            bounds: MeshBounds::from_vertices(vertices),
         })
     }
+
+    pub(crate) fn destroy(self, vma: &vk_mem::Allocator) -> Result<()> {
+        self.vertices.destroy(vma)?;
+        for stream in self.extra_streams {
+            stream.destroy(vma)?;
+        }
+        self.indices.destroy(vma)
+    }
+
+    /// The buffers of all the bindings, in order.
+    pub(crate) fn vertex_buffers(&self) -> Vec<vk::Buffer> {
+        std::iter::once(self.vertices.buffer)
+            .chain(self.extra_streams.iter().map(|stream| stream.buffer))
+            .collect()
+    }
+
+    /// The first level that covers `distance`, or the least detailed one.
+    pub(crate) fn lod_for_distance(&self, distance: f32) -> MeshLod {
+        self.lods
+            .iter()
+            .find(|lod| distance <= lod.max_distance)
+            .or_else(|| self.lods.last())
+            .copied()
+            .unwrap_or(MeshLod {
+                first_index: 0,
+                index_count: self.indices_len,
+                max_distance: f32::INFINITY,
+            })
+    }
 }
@@ -0,0 +1,122 @@
+use crate::{
+    errors::{HandleType, Result},
+    mesh::{as_bytes, MeshVertex},
+    MeshHandle, VkTracerApp,
+};
+use ash::vk;
+use nalgebra_glm as glm;
+use std::convert::TryInto;
+
+#[derive(Copy, Clone, Debug)]
+pub struct Aabb {
+    pub min: glm::Vec3,
+    pub max: glm::Vec3,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct BoundingSphere {
+    pub center: glm::Vec3,
+    pub radius: f32,
+}
+
+/// Bounding volumes of a mesh in model space, computed when it is created.
+#[derive(Copy, Clone, Debug)]
+pub struct MeshBounds {
+    pub aabb: Aabb,
+    pub sphere: BoundingSphere,
+}
+
+impl Aabb {
+    pub fn center(&self) -> glm::Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    /// The box containing this one once transformed.
+    pub fn transform(&self, matrix: &glm::Mat4) -> Self {
+        let corners = (0..8).map(|i| {
+            let corner = glm::vec3(
+                if i & 1 == 0 { self.min.x } else { self.max.x },
+                if i & 2 == 0 { self.min.y } else { self.max.y },
+                if i & 4 == 0 { self.min.z } else { self.max.z },
+            );
+            (matrix * corner.push(1.0)).xyz()
+        });
+        Self::from_points(corners).unwrap()
+    }
+
+    fn from_points(points: impl Iterator<Item = glm::Vec3>) -> Option<Self> {
+        points.fold(None, |aabb: Option<Self>, point| {
+            Some(match aabb {
+                Some(aabb) => Self {
+                    min: glm::min2(&aabb.min, &point),
+                    max: glm::max2(&aabb.max, &point),
+                },
+                None => Self {
+                    min: point,
+                    max: point,
+                },
+            })
+        })
+    }
+}
+
+impl BoundingSphere {
+    /// The sphere containing this one once transformed, scaled by the largest axis.
+    pub fn transform(&self, matrix: &glm::Mat4) -> Self {
+        let scale = (0..3)
+            .map(|i| matrix.column(i).xyz().norm())
+            .fold(0.0, f32::max);
+        Self {
+            center: (matrix * self.center.push(1.0)).xyz(),
+            radius: self.radius * scale,
+        }
+    }
+}
+
+impl MeshBounds {
+    /// Read the positions from the attribute at location 0, `None` if it isn't a vec3 or if
+    /// there are no vertices.
+    pub(crate) fn from_vertices<V: MeshVertex>(vertices: &[V]) -> Option<Self> {
+        let position = V::attribute_description()
+            .iter()
+            .find(|attribute| attribute.location == 0)
+            .filter(|attribute| attribute.format == vk::Format::R32G32B32_SFLOAT)?;
+
+        Self::from_positions(
+            as_bytes(vertices),
+            std::mem::size_of::<V>(),
+            position.offset as usize,
+        )
+    }
+
+    /// Read a vec3 at `offset` in each vertex of `stride` bytes.
+    pub(crate) fn from_positions(bytes: &[u8], stride: usize, offset: usize) -> Option<Self> {
+        let positions = || {
+            bytes.chunks_exact(stride).map(move |vertex| {
+                let component = |i: usize| {
+                    let start = offset + 4 * i;
+                    f32::from_ne_bytes(vertex[start..start + 4].try_into().unwrap())
+                };
+                glm::vec3(component(0), component(1), component(2))
+            })
+        };
+
+        let aabb = Aabb::from_points(positions())?;
+        let center = aabb.center();
+        let radius = positions()
+            .map(|position| glm::distance(&position, &center))
+            .fold(0.0, f32::max);
+
+        Some(Self {
+            aabb,
+            sphere: BoundingSphere { center, radius },
+        })
+    }
+}
+
+impl VkTracerApp {
+    /// `None` when the positions of the vertices couldn't be read.
+    pub fn get_mesh_bounds(&self, mesh: MeshHandle) -> Result<Option<MeshBounds>> {
+        Ok(storage_access!(self.mesh_storage, mesh, HandleType::Mesh).bounds)
+    }
+}
@@ -0,0 +1,82 @@
+use crate::mesh::{VertexXyzUvNorm, VertexXyzUvNormSkin, VertexXyzUvNormTan};
+use nalgebra_glm as glm;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum NormalMode {
+    /// Average of the faces around each vertex, weighted by their area.
+    Smooth,
+    /// Normal of each face, the vertices are duplicated so that faces don't share them.
+    Flat,
+}
+
+/// Vertices that have a normal to generate.
+pub trait VertexWithNormal: Copy {
+    fn position(&self) -> glm::Vec3;
+    fn set_normal(&mut self, normal: glm::Vec3);
+}
+
+/// Compute the normals of an indexed triangle list, for data that comes without them.
+/// With [NormalMode::Flat] the vertices are replaced by one per index.
+pub fn generate_normals<V: VertexWithNormal>(
+    vertices: &mut Vec<V>,
+    indices: &mut Vec<u32>,
+    mode: NormalMode,
+) {
+    if mode == NormalMode::Flat {
+        *vertices = indices.iter().map(|i| vertices[*i as usize]).collect();
+        *indices = (0..vertices.len() as u32).collect();
+    }
+
+    let mut normals = vec![glm::Vec3::zeros(); vertices.len()];
+    for face in indices.chunks_exact(3) {
+        let [a, b, c] = [face[0] as usize, face[1] as usize, face[2] as usize];
+        let origin = vertices[a].position();
+        // Not normalized, its length is twice the area of the face
+        let normal = glm::cross(
+            &(vertices[b].position() - origin),
+            &(vertices[c].position() - origin),
+        );
+        normals[a] += normal;
+        normals[b] += normal;
+        normals[c] += normal;
+    }
+
+    for (vertex, normal) in vertices.iter_mut().zip(normals) {
+        // Vertices only part of degenerate faces
+        vertex.set_normal(
+            normal
+                .try_normalize(f32::EPSILON)
+                .unwrap_or_else(glm::Vec3::y),
+        );
+    }
+}
+
+impl VertexWithNormal for VertexXyzUvNorm {
+    fn position(&self) -> glm::Vec3 {
+        self.xyz
+    }
+
+    fn set_normal(&mut self, normal: glm::Vec3) {
+        self.normal = normal;
+    }
+}
+
+impl VertexWithNormal for VertexXyzUvNormTan {
+    fn position(&self) -> glm::Vec3 {
+        self.xyz
+    }
+
+    fn set_normal(&mut self, normal: glm::Vec3) {
+        self.normal = normal;
+    }
+}
+
+impl VertexWithNormal for VertexXyzUvNormSkin {
+    fn position(&self) -> glm::Vec3 {
+        self.xyz
+    }
+
+    fn set_normal(&mut self, normal: glm::Vec3) {
+        self.normal = normal;
+    }
+}
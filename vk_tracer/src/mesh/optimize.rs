@@ -0,0 +1,121 @@
+use crate::{
+    errors::{Result, VkTracerError},
+    mesh::MeshLoadOptions,
+    mesh::MeshVertex,
+};
+use ash::vk;
+
+/// Run [optimize_indices] then [optimize_vertex_fetch] if `options.optimize` is set.
+pub fn optimize_mesh<V: MeshVertex>(
+    vertices: &mut Vec<V>,
+    indices: &mut Vec<u32>,
+    options: &MeshLoadOptions,
+) -> Result<()> {
+    optimize_indices(vertices, indices, options)?;
+    if options.optimize {
+        optimize_vertex_fetch(vertices, indices);
+    }
+    Ok(())
+}
+
+/// Simplify the triangles and reorder them for the vertex cache and overdraw, the vertices
+/// are left untouched.
+pub fn optimize_indices<V: MeshVertex>(
+    vertices: &[V],
+    indices: &mut Vec<u32>,
+    options: &MeshLoadOptions,
+) -> Result<()> {
+    let adapter = vertex_adapter(vertices)?;
+
+    if let Some(target) = options.simplify_target {
+        let target_count = (indices.len() as f32 * target.max(0.0).min(1.0)) as usize / 3 * 3;
+        *indices = meshopt::simplify(indices, &adapter, target_count, SIMPLIFY_TARGET_ERROR);
+    }
+
+    if options.optimize {
+        meshopt::optimize_vertex_cache_in_place(indices, vertices.len());
+        meshopt::optimize_overdraw_in_place(indices, &adapter, OVERDRAW_THRESHOLD);
+    }
+    Ok(())
+}
+
+/// Simplified versions of a mesh for [crate::VkTracerApp::create_mesh_with_lods], one per
+/// fraction of the triangles to keep in `targets`.
+pub fn generate_lods<V: MeshVertex>(
+    vertices: &[V],
+    indices: &[u32],
+    targets: &[f32],
+) -> Result<Vec<Vec<u32>>> {
+    let mut lods = Vec::with_capacity(targets.len());
+    for target in targets.iter().copied() {
+        let mut lod = indices.to_vec();
+        optimize_indices(
+            vertices,
+            &mut lod,
+            &MeshLoadOptions {
+                optimize: true,
+                simplify_target: Some(target),
+                ..MeshLoadOptions::default()
+            },
+        )?;
+        lods.push(lod);
+    }
+    Ok(lods)
+}
+
+/// Reorder the vertices in the order they are first used and drop the unused ones.
+pub fn optimize_vertex_fetch<V: MeshVertex>(vertices: &mut Vec<V>, indices: &mut [u32]) {
+    // remap[old] = new, or !0 for unused vertices
+    let remap = meshopt::optimize_vertex_fetch_remap(indices, vertices.len());
+    let vertex_count = remap.iter().filter(|new| **new != !0).count();
+
+    let mut remapped = vertices[..vertex_count].to_vec();
+    for (old, new) in remap.iter().enumerate() {
+        if *new != !0 {
+            remapped[*new as usize] = vertices[old];
+        }
+    }
+    for index in indices.iter_mut() {
+        *index = remap[*index as usize];
+    }
+    *vertices = remapped;
+}
+
+/// Relative to the size of the mesh.
+const SIMPLIFY_TARGET_ERROR: f32 = 0.01;
+/// How much the vertex cache efficiency can be degraded to reduce overdraw.
+const OVERDRAW_THRESHOLD: f32 = 1.05;
+
+/// The positions are found with the attribute at location 0, meshoptimizer reads them as
+/// floats so quantized vertices must be optimized before being quantized.
+fn vertex_adapter<V: MeshVertex>(vertices: &[V]) -> Result<meshopt::VertexDataAdapter> {
+    let position = V::attribute_description()
+        .iter()
+        .find(|attribute| attribute.location == 0)
+        .copied();
+    let position_offset = match position {
+        Some(attribute)
+            if attribute.format == vk::Format::R32G32B32_SFLOAT
+                || attribute.format == vk::Format::R32G32B32A32_SFLOAT =>
+        {
+            attribute.offset as usize
+        }
+        Some(attribute) => return Err(VkTracerError::UnsupportedPositionFormat(attribute.format)),
+        None => {
+            return Err(VkTracerError::UnsupportedPositionFormat(
+                vk::Format::UNDEFINED,
+            ))
+        }
+    };
+    let data = unsafe {
+        std::slice::from_raw_parts(
+            vertices.as_ptr() as *const u8,
+            std::mem::size_of_val(vertices),
+        )
+    };
+    Ok(meshopt::VertexDataAdapter::new(
+        data,
+        std::mem::size_of::<V>(),
+        position_offset,
+    )?)
+}
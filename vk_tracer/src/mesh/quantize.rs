@@ -0,0 +1,213 @@
+use crate::{
+    errors::{HandleType, Result},
+    mesh::{Aabb, MeshBounds, MeshIndex, VertexLayout, VertexXyzUvNorm},
+    MeshHandle, VkTracerApp,
+};
+use nalgebra_glm as glm;
+
+/// Compressed [VertexXyzUvNorm], 14 bytes instead of 32. See
+/// [crate::utils::QUANTIZED_VERTEX_GLSL] to decode it in shaders.
+#[repr(packed)]
+#[derive(Copy, Clone, Debug, VertexLayout)]
+pub struct VertexQuantized {
+    /// Normalized in the AABB of the mesh, `w` is unused.
+    #[vertex(format = "R16G16B16A16_UNORM")]
+    pub xyz: [u16; 4],
+    /// Half floats.
+    #[vertex(format = "R16G16_SFLOAT")]
+    pub uv: [u16; 2],
+    /// Octahedral encoding.
+    #[vertex(format = "R8G8_SNORM")]
+    pub normal: [i8; 2],
+}
+
+impl VkTracerApp {
+    /// Quantize the vertices and create a mesh with them, the AABB to decode the positions is
+    /// the one of [VkTracerApp::get_mesh_bounds].
+    pub fn create_mesh_quantized<I: MeshIndex>(
+        &mut self,
+        vertices: &[VertexXyzUvNorm],
+        indices: &[I],
+    ) -> Result<MeshHandle> {
+        let bounds = MeshBounds::from_vertices(vertices);
+        let quantized = match bounds {
+            Some(bounds) => quantize_vertices(vertices, &bounds.aabb),
+            None => Vec::new(),
+        };
+
+        let handle = self.create_mesh_indexed(&quantized, indices)?;
+        let mesh = storage_access_mut!(self.mesh_storage, handle, HandleType::Mesh);
+        mesh.bounds = bounds;
+        Ok(handle)
+    }
+}
+
+/// Positions outside of `aabb` are clamped to it.
+pub fn quantize_vertices(vertices: &[VertexXyzUvNorm], aabb: &Aabb) -> Vec<VertexQuantized> {
+    let extent = aabb.max - aabb.min;
+    vertices
+        .iter()
+        .map(|vertex| {
+            let (xyz, uv, normal) = (vertex.xyz, vertex.uv, vertex.normal);
+            let unorm = |i: usize| {
+                let t = if extent[i] > 0.0 {
+                    (xyz[i] - aabb.min[i]) / extent[i]
+                } else {
+                    0.0
+                };
+                (t.max(0.0).min(1.0) * u16::MAX as f32).round() as u16
+            };
+            VertexQuantized {
+                xyz: [unorm(0), unorm(1), unorm(2), 0],
+                uv: [f32_to_f16(uv.x), f32_to_f16(uv.y)],
+                normal: octahedral_encode(&normal),
+            }
+        })
+        .collect()
+}
+
+/// Project a unit vector on an octahedron unfolded in a square, as two snorm8.
+pub fn octahedral_encode(normal: &glm::Vec3) -> [i8; 2] {
+    let sign = |v: f32| if v >= 0.0 { 1.0 } else { -1.0 };
+    let n = normal / (normal.x.abs() + normal.y.abs() + normal.z.abs()).max(f32::EPSILON);
+    let (x, y) = if n.z >= 0.0 {
+        (n.x, n.y)
+    } else {
+        // Fold the lower half over the diagonals
+        ((1.0 - n.y.abs()) * sign(n.x), (1.0 - n.x.abs()) * sign(n.y))
+    };
+    let snorm = |v: f32| (v.max(-1.0).min(1.0) * 127.0).round() as i8;
+    [snorm(x), snorm(y)]
+}
+
+/// Round to the nearest half float, out of range values become infinities.
+pub fn f32_to_f16(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+
+    if exponent == 0xff {
+        // Infinity or NaN
+        let nan = if mantissa != 0 { 0x200 } else { 0 };
+        return sign | 0x7c00 | nan;
+    }
+
+    let exponent = exponent - 127 + 15;
+    if exponent >= 0x1f {
+        sign | 0x7c00
+    } else if exponent <= 0 {
+        // Subnormal or zero
+        if exponent < -10 {
+            return sign;
+        }
+        let mantissa = mantissa | 0x80_0000;
+        let shift = (14 - exponent) as u32;
+        let half = mantissa >> shift;
+        let round = (mantissa >> (shift - 1)) & 1;
+        sign | (half + round) as u16
+    } else {
+        let half = ((exponent as u32) << 10) | (mantissa >> 13);
+        let round = (mantissa >> 12) & 1;
+        // A carry in the mantissa correctly bumps the exponent
+        sign | (half + round) as u16
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn f16_exact_values() {
+        assert_eq!(f32_to_f16(0.0), 0x0000);
+        assert_eq!(f32_to_f16(-0.0), 0x8000);
+        assert_eq!(f32_to_f16(1.0), 0x3c00);
+        assert_eq!(f32_to_f16(-2.0), 0xc000);
+        assert_eq!(f32_to_f16(0.5), 0x3800);
+        assert_eq!(f32_to_f16(65504.0), 0x7bff);
+    }
+
+    #[test]
+    fn f16_rounding() {
+        // Below and above the middle of 1.0 and the next half float
+        assert_eq!(f32_to_f16(1.0 + 2f32.powi(-12)), 0x3c00);
+        assert_eq!(f32_to_f16(1.0 + 2f32.powi(-11) + 2f32.powi(-13)), 0x3c01);
+        // The carry of the mantissa goes into the exponent
+        assert_eq!(f32_to_f16(2047.9), 0x6800);
+        // Rounds up to the first value that doesn't fit
+        assert_eq!(f32_to_f16(65520.0), 0x7c00);
+    }
+
+    #[test]
+    fn f16_denormals() {
+        assert_eq!(f32_to_f16(2f32.powi(-14)), 0x0400);
+        assert_eq!(f32_to_f16(1023.0 * 2f32.powi(-24)), 0x03ff);
+        assert_eq!(f32_to_f16(2f32.powi(-24)), 0x0001);
+        assert_eq!(f32_to_f16(-2f32.powi(-24)), 0x8001);
+        assert_eq!(f32_to_f16(1.75 * 2f32.powi(-24)), 0x0002);
+        // Too small even for a denormal
+        assert_eq!(f32_to_f16(2f32.powi(-26)), 0x0000);
+        assert_eq!(f32_to_f16(-f32::MIN_POSITIVE), 0x8000);
+    }
+
+    #[test]
+    fn f16_infinities_and_nan() {
+        assert_eq!(f32_to_f16(f32::INFINITY), 0x7c00);
+        assert_eq!(f32_to_f16(f32::NEG_INFINITY), 0xfc00);
+        assert_eq!(f32_to_f16(1e6), 0x7c00);
+        assert_eq!(f32_to_f16(-f32::MAX), 0xfc00);
+
+        let nan = f32_to_f16(f32::NAN);
+        assert_eq!(nan & 0x7c00, 0x7c00);
+        assert_ne!(nan & 0x03ff, 0);
+    }
+
+    #[test]
+    fn octahedral_axes() {
+        assert_eq!(octahedral_encode(&glm::vec3(1.0, 0.0, 0.0)), [127, 0]);
+        assert_eq!(octahedral_encode(&glm::vec3(-1.0, 0.0, 0.0)), [-127, 0]);
+        assert_eq!(octahedral_encode(&glm::vec3(0.0, 1.0, 0.0)), [0, 127]);
+        assert_eq!(octahedral_encode(&glm::vec3(0.0, -1.0, 0.0)), [0, -127]);
+        // The poles, the lower one is folded to a corner
+        assert_eq!(octahedral_encode(&glm::vec3(0.0, 0.0, 1.0)), [0, 0]);
+        assert_eq!(octahedral_encode(&glm::vec3(0.0, 0.0, -1.0)), [127, 127]);
+    }
+
+    #[test]
+    fn octahedral_not_normalized() {
+        assert_eq!(octahedral_encode(&glm::vec3(0.0, 0.0, 5.0)), [0, 0]);
+        assert_eq!(octahedral_encode(&glm::vec3(-0.2, 0.0, 0.0)), [-127, 0]);
+        assert_eq!(octahedral_encode(&glm::Vec3::zeros()), [0, 0]);
+    }
+
+    #[test]
+    fn octahedral_round_trip() {
+        let decode = |[x, y]: [i8; 2]| {
+            let (x, y) = (x as f32 / 127.0, y as f32 / 127.0);
+            let z = 1.0 - x.abs() - y.abs();
+            let t = (-z).max(0.0);
+            let x = if x >= 0.0 { x - t } else { x + t };
+            let y = if y >= 0.0 { y - t } else { y + t };
+            glm::normalize(&glm::vec3(x, y, z))
+        };
+
+        for normal in [
+            glm::vec3(1.0, 2.0, 3.0),
+            glm::vec3(-1.0, 0.5, -2.0),
+            glm::vec3(0.3, -0.7, -0.1),
+            glm::vec3(-0.5, -0.5, 0.5),
+        ]
+        .iter()
+        {
+            let normal = glm::normalize(normal);
+            let decoded = decode(octahedral_encode(&normal));
+            assert!(
+                glm::dot(&normal, &decoded) > 0.999,
+                "{} {}",
+                normal,
+                decoded
+            );
+        }
+    }
+}
@@ -0,0 +1,50 @@
+use crate::mesh::VertexXyzUvNormTan;
+use nalgebra_glm as glm;
+
+/// Compute the tangents of an indexed triangle list with mikktspace, as expected by glTF.
+/// Vertices shared by triangles with different tangents keep the last one, split them
+/// beforehand along UV seams to avoid it. Returns false if the generation failed.
+pub fn generate_tangents(vertices: &mut [VertexXyzUvNormTan], indices: &[u32]) -> bool {
+    mikktspace::generate_tangents(&mut TangentGeometry { vertices, indices })
+}
+
+struct TangentGeometry<'a> {
+    vertices: &'a mut [VertexXyzUvNormTan],
+    indices: &'a [u32],
+}
+
+impl TangentGeometry<'_> {
+    fn vertex(&self, face: usize, vert: usize) -> &VertexXyzUvNormTan {
+        &self.vertices[self.indices[face * 3 + vert] as usize]
+    }
+}
+
+impl mikktspace::Geometry for TangentGeometry<'_> {
+    fn num_faces(&self) -> usize {
+        self.indices.len() / 3
+    }
+
+    fn num_vertices_of_face(&self, _face: usize) -> usize {
+        3
+    }
+
+    fn position(&self, face: usize, vert: usize) -> [f32; 3] {
+        let xyz = self.vertex(face, vert).xyz;
+        [xyz.x, xyz.y, xyz.z]
+    }
+
+    fn normal(&self, face: usize, vert: usize) -> [f32; 3] {
+        let normal = self.vertex(face, vert).normal;
+        [normal.x, normal.y, normal.z]
+    }
+
+    fn tex_coord(&self, face: usize, vert: usize) -> [f32; 2] {
+        let uv = self.vertex(face, vert).uv;
+        [uv.x, uv.y]
+    }
+
+    fn set_tangent_encoded(&mut self, tangent: [f32; 4], face: usize, vert: usize) {
+        let index = self.indices[face * 3 + vert] as usize;
+        self.vertices[index].tangent = glm::make_vec4(&tangent);
+    }
+}
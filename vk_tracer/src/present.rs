@@ -1,5 +1,9 @@
+mod display;
+mod display_timing;
 mod surface;
 mod swapchain;
 
+pub use display::{DisplayInfo, DisplayModeInfo};
+pub(crate) use display_timing::*;
 pub(crate) use surface::*;
 pub(crate) use swapchain::*;
@@ -0,0 +1,114 @@
+//! # Displays
+//! Enumerate the displays and their video modes with `VK_KHR_display`, and control exclusive
+//! fullscreen with `VK_EXT_full_screen_exclusive`.
+
+use crate::{
+    errors::{HandleType, Result, VkTracerError},
+    utils::cstr_to_str,
+    SwapchainHandle, VkTracerApp,
+};
+use ash::{extensions::khr, version::DeviceV1_0, vk};
+
+#[derive(Clone, Debug)]
+pub struct DisplayInfo {
+    pub name: String,
+    pub physical_resolution: (u32, u32),
+    pub modes: Vec<DisplayModeInfo>,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct DisplayModeInfo {
+    pub resolution: (u32, u32),
+    /// In millihertz.
+    pub refresh_rate: u32,
+}
+
+impl VkTracerApp {
+    /// List the displays connected to the adapter and their video modes.
+    /// Requires [crate::setup::VkTracerAppBuilder::with_displays].
+    pub fn enumerate_displays(&self) -> Result<Vec<DisplayInfo>> {
+        let display = self
+            .display
+            .as_ref()
+            .ok_or(VkTracerError::ExtensionNotEnabled(display_extension_name()))?;
+
+        unsafe {
+            display
+                .get_physical_device_display_properties(self.adapter.handle)?
+                .iter()
+                .map(|properties| {
+                    let modes = display
+                        .get_display_mode_properties(self.adapter.handle, properties.display)?
+                        .iter()
+                        .map(|mode| DisplayModeInfo {
+                            resolution: (
+                                mode.parameters.visible_region.width,
+                                mode.parameters.visible_region.height,
+                            ),
+                            refresh_rate: mode.parameters.refresh_rate,
+                        })
+                        .collect();
+
+                    Ok(DisplayInfo {
+                        name: if properties.display_name.is_null() {
+                            String::new()
+                        } else {
+                            cstr_to_str(properties.display_name).into_owned()
+                        },
+                        physical_resolution: (
+                            properties.physical_resolution.width,
+                            properties.physical_resolution.height,
+                        ),
+                        modes,
+                    })
+                })
+                .collect()
+        }
+    }
+
+    /// Take exclusive control of the display the swapchain is presented to.
+    /// Requires [crate::setup::VkTracerExtensions::FullScreenExclusive].
+    pub fn acquire_full_screen_exclusive(&self, swapchain: SwapchainHandle) -> Result<()> {
+        let full_screen_exclusive =
+            self.full_screen_exclusive
+                .as_ref()
+                .ok_or(VkTracerError::ExtensionNotEnabled(
+                    full_screen_exclusive_extension_name(),
+                ))?;
+        let swapchain = storage_access!(self.swapchain_storage, swapchain, HandleType::Swapchain);
+
+        match unsafe {
+            full_screen_exclusive
+                .acquire_full_screen_exclusive_mode_ext(self.device.handle(), swapchain.handle)
+        } {
+            vk::Result::SUCCESS => Ok(()),
+            err => Err(err.into()),
+        }
+    }
+
+    pub fn release_full_screen_exclusive(&self, swapchain: SwapchainHandle) -> Result<()> {
+        let full_screen_exclusive =
+            self.full_screen_exclusive
+                .as_ref()
+                .ok_or(VkTracerError::ExtensionNotEnabled(
+                    full_screen_exclusive_extension_name(),
+                ))?;
+        let swapchain = storage_access!(self.swapchain_storage, swapchain, HandleType::Swapchain);
+
+        match unsafe {
+            full_screen_exclusive
+                .release_full_screen_exclusive_mode_ext(self.device.handle(), swapchain.handle)
+        } {
+            vk::Result::SUCCESS => Ok(()),
+            err => Err(err.into()),
+        }
+    }
+}
+
+fn display_extension_name() -> &'static str {
+    khr::Display::name().to_str().unwrap()
+}
+
+fn full_screen_exclusive_extension_name() -> &'static str {
+    vk::ExtFullScreenExclusiveFn::name().to_str().unwrap()
+}
@@ -0,0 +1,154 @@
+//! # Display timing
+//! `VK_GOOGLE_display_timing` lets us ask for an image to be presented at a given time and
+//! reports when the previous images were actually presented.
+//! Without it, [crate::utils::FpsLimiter] can pace the frames on the CPU.
+
+use crate::{
+    errors::{HandleType, Result, VkTracerError},
+    SwapchainHandle, VkTracerApp,
+};
+use ash::{version::DeviceV1_0, vk};
+use std::{collections::VecDeque, time::Duration};
+
+/// Timings kept until [VkTracerApp::take_presentation_timings], the oldest ones are dropped.
+const MAX_PAST_TIMINGS: usize = 64;
+/// Frames between two queries of the past timings.
+const POLL_INTERVAL: u32 = 8;
+
+#[derive(Default)]
+pub(crate) struct DisplayTimingState {
+    target_interval: Option<u64>,
+    present_id: u32,
+    /// Last known present id and when it was actually presented, in nanoseconds.
+    last_presented: Option<(u32, u64)>,
+    frames_since_poll: u32,
+    past_timings: VecDeque<vk::PastPresentationTimingGOOGLE>,
+}
+
+impl DisplayTimingState {
+    /// Query the timings of the images presented since the last poll, every few frames.
+    pub(crate) fn poll(
+        &mut self,
+        display_timing: &vk::GoogleDisplayTimingFn,
+        device: vk::Device,
+        swapchain: vk::SwapchainKHR,
+    ) -> Result<()> {
+        self.frames_since_poll += 1;
+        if self.frames_since_poll < POLL_INTERVAL {
+            return Ok(());
+        }
+        self.frames_since_poll = 0;
+
+        let mut timings = [vk::PastPresentationTimingGOOGLE::default(); MAX_PAST_TIMINGS];
+        loop {
+            let mut count = timings.len() as u32;
+            let result = unsafe {
+                display_timing.get_past_presentation_timing_google(
+                    device,
+                    swapchain,
+                    &mut count,
+                    timings.as_mut_ptr(),
+                )
+            };
+            match result {
+                vk::Result::SUCCESS | vk::Result::INCOMPLETE => {}
+                err => return Err(err.into()),
+            }
+
+            let timings = &timings[..count as usize];
+            if let Some(last) = timings.last() {
+                self.last_presented = Some((last.present_id, last.actual_present_time));
+            }
+            for timing in timings.iter().copied() {
+                if self.past_timings.len() == MAX_PAST_TIMINGS {
+                    self.past_timings.pop_front();
+                }
+                self.past_timings.push_back(timing);
+            }
+
+            // The ones that didn't fit are returned by the next call
+            if result == vk::Result::SUCCESS {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Forget about the images of the previous swapchain.
+    pub(crate) fn reset(&mut self) {
+        self.last_presented = None;
+    }
+
+    /// The present time of the next image, [None] if there is no target interval.
+    pub(crate) fn next_present_time(&mut self) -> Option<vk::PresentTimeGOOGLE> {
+        let interval = self.target_interval?;
+        self.present_id = self.present_id.wrapping_add(1);
+
+        // 0 means as soon as possible, until we know when an image was presented
+        let desired_present_time = self.last_presented.map_or(0, |(id, time)| {
+            time + interval * self.present_id.wrapping_sub(id) as u64
+        });
+
+        Some(
+            vk::PresentTimeGOOGLE::builder()
+                .present_id(self.present_id)
+                .desired_present_time(desired_present_time)
+                .build(),
+        )
+    }
+}
+
+impl VkTracerApp {
+    /// Present an image every `interval`, or as soon as possible with [None].
+    /// Requires [crate::setup::VkTracerExtensions::DisplayTiming].
+    pub fn set_target_present_interval(
+        &mut self,
+        swapchain: SwapchainHandle,
+        interval: Option<Duration>,
+    ) -> Result<()> {
+        self.display_timing
+            .as_ref()
+            .ok_or(VkTracerError::ExtensionNotEnabled(extension_name()))?;
+        let swapchain =
+            storage_access_mut!(self.swapchain_storage, swapchain, HandleType::Swapchain);
+
+        swapchain.display_timing.target_interval =
+            interval.map(|interval| interval.as_nanos() as u64);
+        Ok(())
+    }
+
+    /// The refresh rate of the display the swapchain is presented to.
+    pub fn get_refresh_cycle_duration(&self, swapchain: SwapchainHandle) -> Result<Duration> {
+        let display_timing = self
+            .display_timing
+            .as_ref()
+            .ok_or(VkTracerError::ExtensionNotEnabled(extension_name()))?;
+        let swapchain = storage_access!(self.swapchain_storage, swapchain, HandleType::Swapchain);
+
+        let mut properties = vk::RefreshCycleDurationGOOGLE::default();
+        match unsafe {
+            display_timing.get_refresh_cycle_duration_google(
+                self.device.handle(),
+                swapchain.handle,
+                &mut properties,
+            )
+        } {
+            vk::Result::SUCCESS => Ok(Duration::from_nanos(properties.refresh_duration)),
+            err => Err(err.into()),
+        }
+    }
+
+    /// The timings of the images presented since the last call, at most the last 64. The present
+    /// margin tells how early an image could have been presented.
+    pub fn take_presentation_timings(
+        &mut self,
+        swapchain: SwapchainHandle,
+    ) -> Result<Vec<vk::PastPresentationTimingGOOGLE>> {
+        let swapchain =
+            storage_access_mut!(self.swapchain_storage, swapchain, HandleType::Swapchain);
+        Ok(swapchain.display_timing.past_timings.drain(..).collect())
+    }
+}
+
+fn extension_name() -> &'static str {
+    vk::GoogleDisplayTimingFn::name().to_str().unwrap()
+}
@@ -1,9 +1,10 @@
-use ash::vk;
+use ash::{version::InstanceV1_0, vk};
 use raw_window_handle::HasRawWindowHandle;
 
 use crate::{
-    errors::Result,
+    errors::{HandleType, Result, VkTracerError},
     setup::{Adapter, AdapterRequirements},
+    SurfaceHandle, VkTracerApp,
 };
 use ash::extensions::khr;
 
@@ -30,6 +31,56 @@ pub(crate) fn choose_surface_format(
         .copied()
 }
 
+impl VkTracerApp {
+    /// Create a surface for an additional window, to be used with
+    /// [VkTracerApp::create_swapchain_for_surface].
+    /// The window must come from the same windowing system than the one given to the builder.
+    pub fn create_surface(
+        &mut self,
+        window: &impl HasRawWindowHandle,
+        window_size: (u32, u32),
+    ) -> Result<SurfaceHandle> {
+        let mut surface = Surface::create(&self.entry, &self.instance, window, window_size)?;
+        if let Err(err) = surface.complete(&self.instance, &self.adapter) {
+            unsafe {
+                surface.loader.destroy_surface(surface.handle, None);
+            }
+            return Err(err);
+        }
+
+        Ok(self.surface_storage.insert(surface))
+    }
+
+    /// The surface of the window given to the builder, if any.
+    pub fn get_main_surface(&self) -> Option<SurfaceHandle> {
+        self.main_surface
+    }
+
+    /// Destroy a surface before its window is closed, its swapchains must be destroyed first.
+    pub fn destroy_surface(&mut self, surface: SurfaceHandle) -> Result<()> {
+        if self
+            .swapchain_storage
+            .values()
+            .any(|swapchain| swapchain.surface == surface)
+        {
+            return Err(VkTracerError::SurfaceInUse);
+        }
+
+        let handle = surface;
+        let surface = self
+            .surface_storage
+            .remove(surface)
+            .ok_or(VkTracerError::InvalidHandle(HandleType::Surface))?;
+        unsafe {
+            surface.loader.destroy_surface(surface.handle, None);
+        }
+        if self.main_surface == Some(handle) {
+            self.main_surface = None;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Clone)]
 pub struct Surface {
     pub(crate) loader: ash::extensions::khr::Surface,
@@ -37,6 +88,8 @@ pub struct Surface {
     pub(crate) format: vk::Format,
     pub(crate) color_space: vk::ColorSpaceKHR,
     pub(crate) extent: vk::Extent2D,
+    pub(crate) capabilities: vk::SurfaceCapabilitiesKHR,
+    pub(crate) present_modes: Vec<vk::PresentModeKHR>,
 }
 
 impl Surface {
@@ -58,42 +111,46 @@ impl Surface {
                 .width(window_size.0)
                 .height(window_size.1)
                 .build(),
+            capabilities: vk::SurfaceCapabilitiesKHR::default(),
+            present_modes: Vec::new(),
         })
     }
 
-    pub fn complete(&mut self, adapter: &Adapter) {
-        let format = choose_surface_format(
-            &adapter
-                .info
-                .physical_device_info
-                .surface_formats
-                .as_ref()
-                .unwrap(),
-            &adapter
-                .info
-                .physical_device_info
-                .surface_format_properties
-                .as_ref()
-                .unwrap(),
-            &adapter.requirements,
-        )
-        .unwrap();
+    /// Query what the adapter supports for this surface and choose its format.
+    pub fn complete(&mut self, instance: &ash::Instance, adapter: &Adapter) -> Result<()> {
+        let formats = unsafe {
+            let support = self.loader.get_physical_device_surface_support(
+                adapter.handle,
+                adapter.info.present_queue.index,
+                self.handle,
+            )?;
+            if !support {
+                return Err(VkTracerError::PresentNotSupported);
+            }
+
+            self.present_modes = self
+                .loader
+                .get_physical_device_surface_present_modes(adapter.handle, self.handle)?;
+            self.loader
+                .get_physical_device_surface_formats(adapter.handle, self.handle)?
+        };
+        let format_properties = formats
+            .iter()
+            .map(|format| unsafe {
+                instance.get_physical_device_format_properties(adapter.handle, format.format)
+            })
+            .collect::<Vec<_>>();
+
+        let format = choose_surface_format(&formats, &format_properties, &adapter.requirements)
+            .ok_or(VkTracerError::NoSuitableImageFormat)?;
 
         self.format = format.format;
         self.color_space = format.color_space;
 
-        let min_extent = adapter
-            .info
-            .physical_device_info
-            .surface_capabilities
-            .unwrap()
-            .min_image_extent;
-        let max_extent = adapter
-            .info
-            .physical_device_info
-            .surface_capabilities
-            .unwrap()
-            .max_image_extent;
+        self.update_capabilities(adapter.handle)?;
+
+        let min_extent = self.capabilities.min_image_extent;
+        let max_extent = self.capabilities.max_image_extent;
 
         let corrected_width = self.extent.width.clamp(min_extent.width, max_extent.width);
         let corrected_height = self
@@ -105,5 +162,19 @@ impl Surface {
             .width(corrected_width)
             .height(corrected_height)
             .build();
+
+        Ok(())
+    }
+
+    /// The capabilities change with the size of the window.
+    pub(crate) fn update_capabilities(
+        &mut self,
+        physical_device: vk::PhysicalDevice,
+    ) -> Result<()> {
+        self.capabilities = unsafe {
+            self.loader
+                .get_physical_device_surface_capabilities(physical_device, self.handle)?
+        };
+        Ok(())
     }
 }
@@ -1,23 +1,34 @@
 use crate::{
     errors::{HandleType, Result, VkTracerError},
-    present::Surface,
-    setup::{Adapter, AdapterRequirements},
-    SwapchainHandle, VkTracerApp,
+    mem::ImageViewFatHandle,
+    present::{DisplayTimingState, Surface},
+    setup::{Adapter, AdapterRequirements, DeviceGroup},
+    SurfaceHandle, SwapchainHandle, VkTracerApp,
 };
 use ash::{version::DeviceV1_0, vk};
 use log::debug;
+use std::{ffi::c_void, slice::from_ref};
 
 impl VkTracerApp {
+    /// Create a swapchain for the window given to the builder.
     pub fn create_swapchain_with_surface(&mut self) -> Result<SwapchainHandle> {
-        let surface = self
-            .surface
-            .as_ref()
-            .ok_or(VkTracerError::NoSurfaceAvailable)?;
+        let surface = self.main_surface.ok_or(VkTracerError::NoSurfaceAvailable)?;
+        self.create_swapchain_for_surface(surface)
+    }
+
+    pub fn create_swapchain_for_surface(
+        &mut self,
+        surface: SurfaceHandle,
+    ) -> Result<SwapchainHandle> {
+        let surface_handle = surface;
+        let surface = storage_access!(self.surface_storage, surface, HandleType::Surface);
         let swapchain = Swapchain::new(
             &self.instance,
+            surface_handle,
             surface,
             &self.adapter,
             &self.device,
+            self.device_group.as_ref(),
             surface.extent,
         )?;
         Ok(self.swapchain_storage.insert(swapchain))
@@ -28,7 +39,63 @@ impl VkTracerApp {
         swapchain: SwapchainHandle,
     ) -> Result<(u32, bool)> {
         let swapchain = storage_access!(self.swapchain_storage, swapchain, HandleType::Swapchain);
-        swapchain.acquire_next_image()
+        swapchain.acquire_next_image(&self.device, self.device_group.as_ref())
+    }
+
+    /// Views of the swapchain images with the sRGB/non-sRGB counterpart of the swapchain format.
+    /// Only available if the swapchain was created with [vk::SwapchainCreateFlagsKHR::MUTABLE_FORMAT].
+    pub fn get_images_from_swapchain_aliased(
+        &self,
+        swapchain: SwapchainHandle,
+    ) -> Result<Vec<ImageViewFatHandle>> {
+        let swapchain = storage_access!(self.swapchain_storage, swapchain, HandleType::Swapchain);
+        let format = swapchain
+            .alias_format
+            .ok_or(VkTracerError::NoSuitableImageFormat)?;
+
+        Ok(swapchain
+            .images
+            .iter()
+            .copied()
+            .zip(swapchain.alias_image_views.iter().copied())
+            .map(|(handle, view)| ImageViewFatHandle {
+                handle,
+                view,
+                format,
+                extent: swapchain.extent,
+            })
+            .collect())
+    }
+
+    /// The transform the application must apply itself, see
+    /// [crate::setup::VkTracerAppBuilder::with_pre_rotation].
+    pub fn get_swapchain_pre_transform(
+        &self,
+        swapchain: SwapchainHandle,
+    ) -> Result<vk::SurfaceTransformFlagsKHR> {
+        let swapchain = storage_access!(self.swapchain_storage, swapchain, HandleType::Swapchain);
+        Ok(swapchain.create_info.pre_transform)
+    }
+
+    /// The real amount of images in the swapchain, use it to size per-frame resources.
+    pub fn get_swapchain_image_count(&self, swapchain: SwapchainHandle) -> Result<u32> {
+        let swapchain = storage_access!(self.swapchain_storage, swapchain, HandleType::Swapchain);
+        Ok(swapchain.images.len() as u32)
+    }
+
+    /// The frame in flight the next [VkTracerApp::render_and_present] on this swapchain
+    /// submits, see [crate::render::FrameRecorder::frame_index]. Once
+    /// [VkTracerApp::get_next_swapchain_render_target_index] returned, the previous submission
+    /// of this frame is complete and its per-frame resources can be updated.
+    pub fn get_swapchain_frame_index(&self, swapchain: SwapchainHandle) -> Result<usize> {
+        let swapchain = storage_access!(self.swapchain_storage, swapchain, HandleType::Swapchain);
+        Ok(swapchain.current_frame)
+    }
+
+    /// How many frames can be in flight, use it to size per-frame resources.
+    pub fn get_swapchain_frames_in_flight(&self, swapchain: SwapchainHandle) -> Result<usize> {
+        let swapchain = storage_access!(self.swapchain_storage, swapchain, HandleType::Swapchain);
+        Ok(swapchain.frames.len())
     }
 
     pub fn recreate_swapchain(
@@ -39,20 +106,38 @@ impl VkTracerApp {
         debug!("Recreating swapchain");
         let swapchain =
             storage_access_mut!(self.swapchain_storage, swapchain, HandleType::Swapchain);
-        self.adapter.update_surface_capabilities()?;
+        let surface =
+            storage_access_mut!(self.surface_storage, swapchain.surface, HandleType::Surface);
+        surface.update_capabilities(self.adapter.handle)?;
+
+        // Frames may still be in flight and use the old images
+        unsafe {
+            self.device.device_wait_idle()?;
+        }
 
         swapchain.recreate(
             &self.device,
-            &self.adapter,
-            self.surface
-                .as_ref()
-                .ok_or(VkTracerError::NoSurfaceAvailable)?,
+            surface,
             vk::Extent2D::builder()
                 .width(new_window_size.0)
                 .height(new_window_size.1)
                 .build(),
         )
     }
+
+    /// Wait for the frames in flight and destroy the swapchain and the views of its images,
+    /// the render targets using them must not be used anymore.
+    pub fn destroy_swapchain(&mut self, swapchain: SwapchainHandle) -> Result<()> {
+        let swapchain = self
+            .swapchain_storage
+            .remove(swapchain)
+            .ok_or(VkTracerError::InvalidHandle(HandleType::Swapchain))?;
+        unsafe {
+            self.device.device_wait_idle()?;
+            swapchain.destroy(&self.device);
+        }
+        Ok(())
+    }
 }
 
 /// Choose the present mode, will fallback to FIFO if the requirements can't be met.
@@ -69,125 +154,455 @@ pub(crate) fn choose_swapchain_present_mode(
     vk::PresentModeKHR::FIFO
 }
 
+/// Choose the amount of images of the swapchain depending on the present mode, unless a specific
+/// count is requested. The result is always within the bounds supported by the surface.
+pub(crate) fn choose_swapchain_image_count(
+    capabilities: &vk::SurfaceCapabilitiesKHR,
+    present_mode: vk::PresentModeKHR,
+    requirements: &AdapterRequirements,
+) -> u32 {
+    let image_count = requirements
+        .swapchain_image_count
+        .unwrap_or(match present_mode {
+            // Triple buffering, one image to display, one queued and one to render to
+            vk::PresentModeKHR::MAILBOX => 3,
+            _ => capabilities.min_image_count + 1,
+        });
+
+    let image_count = image_count.max(capabilities.min_image_count);
+    if capabilities.max_image_count > 0 {
+        image_count.min(capabilities.max_image_count)
+    } else {
+        image_count
+    }
+}
+
+/// With pre-rotation, the surface transform is applied by the application (see
+/// [crate::utils::Camera::set_pre_transform]) instead of the compositor, which is faster on mobile.
+/// Otherwise, prefer the identity when possible.
+pub(crate) fn choose_pre_transform(
+    capabilities: &vk::SurfaceCapabilitiesKHR,
+    pre_rotation: bool,
+) -> vk::SurfaceTransformFlagsKHR {
+    if !pre_rotation
+        && capabilities
+            .supported_transforms
+            .contains(vk::SurfaceTransformFlagsKHR::IDENTITY)
+    {
+        vk::SurfaceTransformFlagsKHR::IDENTITY
+    } else {
+        capabilities.current_transform
+    }
+}
+
+/// The swapchain images keep the native orientation of the display, so a quarter turn swaps the
+/// dimensions of the window.
+fn pre_transformed_size(
+    window_size: vk::Extent2D,
+    transform: vk::SurfaceTransformFlagsKHR,
+) -> vk::Extent2D {
+    if transform.intersects(
+        vk::SurfaceTransformFlagsKHR::ROTATE_90
+            | vk::SurfaceTransformFlagsKHR::ROTATE_270
+            | vk::SurfaceTransformFlagsKHR::HORIZONTAL_MIRROR_ROTATE_90
+            | vk::SurfaceTransformFlagsKHR::HORIZONTAL_MIRROR_ROTATE_270,
+    ) {
+        vk::Extent2D::builder()
+            .width(window_size.height)
+            .height(window_size.width)
+            .build()
+    } else {
+        window_size
+    }
+}
+
+/// The format that can be used to view the same memory with or without the sRGB conversion.
+pub(crate) fn srgb_alias_format(format: vk::Format) -> Option<vk::Format> {
+    match format {
+        vk::Format::R8G8B8A8_SRGB => Some(vk::Format::R8G8B8A8_UNORM),
+        vk::Format::R8G8B8A8_UNORM => Some(vk::Format::R8G8B8A8_SRGB),
+        vk::Format::B8G8R8A8_SRGB => Some(vk::Format::B8G8R8A8_UNORM),
+        vk::Format::B8G8R8A8_UNORM => Some(vk::Format::B8G8R8A8_SRGB),
+        vk::Format::A8B8G8R8_SRGB_PACK32 => Some(vk::Format::A8B8G8R8_UNORM_PACK32),
+        vk::Format::A8B8G8R8_UNORM_PACK32 => Some(vk::Format::A8B8G8R8_SRGB_PACK32),
+        _ => None,
+    }
+}
+
+/// Synchronization objects of one frame in flight.
+#[derive(Copy, Clone)]
+pub(crate) struct SwapchainFrameSync {
+    pub(crate) image_available: vk::Semaphore,
+    pub(crate) render_finished: vk::Semaphore,
+    pub(crate) in_flight: vk::Fence,
+}
+
+impl SwapchainFrameSync {
+    /// Nothing is left behind on failure.
+    fn new(device: &ash::Device) -> Result<Self> {
+        unsafe {
+            let image_available =
+                device.create_semaphore(&vk::SemaphoreCreateInfo::default(), None)?;
+            let render_finished =
+                match device.create_semaphore(&vk::SemaphoreCreateInfo::default(), None) {
+                    Ok(semaphore) => semaphore,
+                    Err(err) => {
+                        device.destroy_semaphore(image_available, None);
+                        return Err(err.into());
+                    }
+                };
+            // Signaled so the first wait on it doesn't block forever
+            let in_flight = match device.create_fence(
+                &vk::FenceCreateInfo::builder().flags(vk::FenceCreateFlags::SIGNALED),
+                None,
+            ) {
+                Ok(fence) => fence,
+                Err(err) => {
+                    device.destroy_semaphore(image_available, None);
+                    device.destroy_semaphore(render_finished, None);
+                    return Err(err.into());
+                }
+            };
+
+            Ok(Self {
+                image_available,
+                render_finished,
+                in_flight,
+            })
+        }
+    }
+
+    pub(crate) unsafe fn destroy(&self, device: &ash::Device) {
+        device.destroy_semaphore(self.image_available, None);
+        device.destroy_semaphore(self.render_finished, None);
+        device.destroy_fence(self.in_flight, None);
+    }
+}
+
 pub(crate) struct Swapchain {
     pub(crate) loader: ash::extensions::khr::Swapchain,
     pub(crate) create_info: vk::SwapchainCreateInfoKHR,
     pub(crate) handle: vk::SwapchainKHR,
+    pub(crate) surface: SurfaceHandle,
     pub(crate) images: Vec<vk::Image>,
     pub(crate) image_views: Vec<vk::ImageView>,
     pub(crate) extent: vk::Extent2D,
+    pre_rotation: bool,
+
+    // Only used with MUTABLE_FORMAT, boxed because the create info points to them
+    pub(crate) alias_format: Option<vk::Format>,
+    pub(crate) alias_image_views: Vec<vk::ImageView>,
+    #[allow(dead_code)]
+    view_formats: Box<[vk::Format]>,
+    #[allow(dead_code)]
+    format_list_info: Box<vk::ImageFormatListCreateInfo>,
+    // Only used with full screen exclusive, boxed for the same reason
+    #[allow(dead_code)]
+    full_screen_exclusive_info: Box<vk::SurfaceFullScreenExclusiveInfoEXT>,
+    #[allow(dead_code)]
+    full_screen_exclusive_win32_info: Box<vk::SurfaceFullScreenExclusiveWin32InfoEXT>,
+    // Shared by the graphics and present queues when they differ, boxed for the same reason
+    #[allow(dead_code)]
+    queue_family_indices: Box<[u32]>,
 
-    pub(crate) image_available_semaphore: vk::Semaphore,
+    pub(crate) frames: Box<[SwapchainFrameSync]>,
+    pub(crate) current_frame: usize,
+    /// The frame that last rendered to each image, if any.
+    pub(crate) images_in_flight: Vec<Option<usize>>,
+    /// Submitted frames, picks the physical device of a device group that renders the next one.
+    pub(crate) frame_count: u64,
+    /// How the images are presented with a device group.
+    pub(crate) device_group_mode: Option<vk::DeviceGroupPresentModeFlagsKHR>,
+    // Only used with a device group, boxed for the same reason
+    #[allow(dead_code)]
+    device_group_info: Box<vk::DeviceGroupSwapchainCreateInfoKHR>,
+    pub(crate) display_timing: DisplayTimingState,
 }
 
 impl Swapchain {
     pub(crate) fn new(
         instance: &ash::Instance,
+        surface_handle: SurfaceHandle,
         surface: &Surface,
         adapter: &Adapter,
         device: &ash::Device,
+        device_group: Option<&DeviceGroup>,
         window_size: vk::Extent2D,
     ) -> Result<Self> {
-        let capabilities = adapter
-            .info
-            .physical_device_info
-            .surface_capabilities
-            .as_ref()
-            .unwrap();
+        let capabilities = &surface.capabilities;
         let loader = ash::extensions::khr::Swapchain::new(instance, device);
 
-        let mut image_count = capabilities.min_image_count + 1;
-        if capabilities.max_image_count > 0 && image_count > capabilities.max_image_count {
-            image_count = capabilities.max_image_count
+        let present_mode =
+            choose_swapchain_present_mode(&surface.present_modes, &adapter.requirements);
+        let image_count =
+            choose_swapchain_image_count(capabilities, present_mode, &adapter.requirements);
+
+        let pre_rotation = adapter.requirements.pre_rotation;
+        let pre_transform = choose_pre_transform(capabilities, pre_rotation);
+        let extent = Self::create_clamped_extent(
+            pre_transformed_size(window_size, pre_transform),
+            capabilities,
+        );
+
+        let flags = adapter.requirements.swapchain_flags;
+
+        let usage = vk::ImageUsageFlags::COLOR_ATTACHMENT | adapter.requirements.swapchain_usage;
+        if !capabilities.supported_usage_flags.contains(usage) {
+            return Err(VkTracerError::UnsupportedSwapchainUsage(
+                usage & !capabilities.supported_usage_flags,
+            ));
         }
 
-        let extent = Self::create_clamped_extent(window_size, capabilities);
+        let composite_alpha = if capabilities
+            .supported_composite_alpha
+            .contains(adapter.requirements.swapchain_composite_alpha)
+        {
+            adapter.requirements.swapchain_composite_alpha
+        } else {
+            vk::CompositeAlphaFlagsKHR::OPAQUE
+        };
+        let alias_format = if flags.contains(vk::SwapchainCreateFlagsKHR::MUTABLE_FORMAT) {
+            Some(srgb_alias_format(surface.format).ok_or(VkTracerError::NoSuitableImageFormat)?)
+        } else {
+            None
+        };
 
-        let create_info = vk::SwapchainCreateInfoKHR::builder()
+        let view_formats = std::iter::once(surface.format)
+            .chain(alias_format)
+            .collect::<Box<[_]>>();
+        let mut format_list_info = Box::new(
+            vk::ImageFormatListCreateInfo::builder()
+                .view_formats(&view_formats)
+                .build(),
+        );
+
+        let full_screen_exclusive = adapter
+            .requirements
+            .required_extensions
+            .contains(&vk::ExtFullScreenExclusiveFn::name());
+        let mut full_screen_exclusive_info = Box::new(
+            vk::SurfaceFullScreenExclusiveInfoEXT::builder()
+                .full_screen_exclusive(vk::FullScreenExclusiveEXT::APPLICATION_CONTROLLED)
+                .build(),
+        );
+        let mut full_screen_exclusive_win32_info = Box::new(
+            vk::SurfaceFullScreenExclusiveWin32InfoEXT::builder()
+                .hmonitor(
+                    adapter
+                        .requirements
+                        .full_screen_exclusive_monitor
+                        .unwrap_or(std::ptr::null_mut()),
+                )
+                .build(),
+        );
+
+        let device_group_mode = match device_group {
+            Some(group) => Some(group.choose_present_mode(
+                loader.fp(),
+                device,
+                surface.handle,
+                &adapter.info.physical_device_info.memory_properties,
+            )?),
+            None => None,
+        };
+        let mut device_group_info = Box::new(
+            vk::DeviceGroupSwapchainCreateInfoKHR::builder()
+                .modes(device_group_mode.unwrap_or_default())
+                .build(),
+        );
+
+        // Concurrent access saves the ownership transfers to the present queue
+        let graphics_family = adapter.info.graphics_queue.index;
+        let present_family = adapter.info.present_queue.index;
+        let (sharing_mode, queue_family_indices) = if graphics_family == present_family {
+            (vk::SharingMode::EXCLUSIVE, Box::default())
+        } else {
+            (
+                vk::SharingMode::CONCURRENT,
+                vec![graphics_family, present_family].into_boxed_slice(),
+            )
+        };
+
+        let mut create_info = vk::SwapchainCreateInfoKHR::builder()
+            .flags(flags)
             .surface(surface.handle)
             .min_image_count(image_count)
             .image_format(surface.format)
             .image_color_space(surface.color_space)
             .image_extent(extent)
             .image_array_layers(1)
-            .image_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT)
-            .image_sharing_mode(vk::SharingMode::EXCLUSIVE)
-            .queue_family_indices(&[])
-            .pre_transform(capabilities.current_transform)
-            .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE)
-            .present_mode(choose_swapchain_present_mode(
-                adapter
-                    .info
-                    .physical_device_info
-                    .surface_present_modes
-                    .as_ref()
-                    .unwrap(),
-                &adapter.requirements,
-            ))
+            .image_usage(usage)
+            .image_sharing_mode(sharing_mode)
+            .pre_transform(pre_transform)
+            .composite_alpha(composite_alpha)
+            .present_mode(present_mode)
             .clipped(true)
-            .old_swapchain(vk::SwapchainKHR::null());
+            .old_swapchain(vk::SwapchainKHR::null())
+            .build();
+
+        // Chain the optional structures by hand, they are boxed so the create info stays valid
+        // for the recreations
+        let mut p_next: *const c_void = std::ptr::null();
+        if full_screen_exclusive {
+            if adapter.requirements.full_screen_exclusive_monitor.is_some() {
+                full_screen_exclusive_win32_info.p_next = p_next;
+                p_next = &*full_screen_exclusive_win32_info as *const _ as *const c_void;
+            }
+            full_screen_exclusive_info.p_next = p_next as *mut c_void;
+            p_next = &*full_screen_exclusive_info as *const _ as *const c_void;
+        }
+        if device_group_mode.is_some() {
+            device_group_info.p_next = p_next;
+            p_next = &*device_group_info as *const _ as *const c_void;
+        }
+        // A mutable format swapchain needs to know in advance the formats of the views
+        if alias_format.is_some() {
+            format_list_info.p_next = p_next;
+            p_next = &*format_list_info as *const _ as *const c_void;
+        }
+        create_info.p_next = p_next;
+        create_info.queue_family_index_count = queue_family_indices.len() as u32;
+        create_info.p_queue_family_indices = queue_family_indices.as_ptr();
 
         let swapchain = unsafe { loader.create_swapchain(&create_info, None)? };
 
         let images = unsafe { loader.get_swapchain_images(swapchain)? };
-        let image_views = Self::create_image_views(device, &surface, &images)?;
+        let image_views = Self::create_image_views(device, surface.format, &images)?;
+        let alias_image_views = if let Some(format) = alias_format {
+            Self::create_image_views(device, format, &images)?
+        } else {
+            Vec::new()
+        };
 
-        let image_available_semaphore =
-            unsafe { device.create_semaphore(&vk::SemaphoreCreateInfo::default(), None)? };
+        let mut frames = Vec::with_capacity(adapter.requirements.frames_in_flight as usize);
+        for _ in 0..adapter.requirements.frames_in_flight {
+            match SwapchainFrameSync::new(device) {
+                Ok(frame) => frames.push(frame),
+                Err(err) => {
+                    for frame in frames {
+                        unsafe { frame.destroy(device) };
+                    }
+                    return Err(err);
+                }
+            }
+        }
+        let frames = frames.into_boxed_slice();
+        let images_in_flight = vec![None; images.len()];
 
         Ok(Self {
             loader,
-            create_info: create_info.build(),
+            create_info,
             handle: swapchain,
+            surface: surface_handle,
             images,
             image_views,
             extent,
-            image_available_semaphore,
+            pre_rotation,
+            alias_format,
+            alias_image_views,
+            view_formats,
+            format_list_info,
+            full_screen_exclusive_info,
+            full_screen_exclusive_win32_info,
+            queue_family_indices,
+            frames,
+            current_frame: 0,
+            images_in_flight,
+            frame_count: 0,
+            device_group_mode,
+            device_group_info,
+            display_timing: DisplayTimingState::default(),
         })
     }
 
+    pub(crate) unsafe fn destroy(&self, device: &ash::Device) {
+        for frame in self.frames.iter() {
+            frame.destroy(device);
+        }
+        for view in self.image_views.iter().chain(self.alias_image_views.iter()) {
+            device.destroy_image_view(*view, None);
+        }
+        self.loader.destroy_swapchain(self.handle, None);
+    }
+
     pub(crate) fn recreate(
         &mut self,
         device: &ash::Device,
-        adapter: &Adapter,
         surface: &Surface,
         window_size: vk::Extent2D,
     ) -> Result<()> {
         unsafe {
             // Destroy previous swapchain images
-            for image_view in self.image_views.iter().copied() {
+            for image_view in self
+                .image_views
+                .drain(..)
+                .chain(self.alias_image_views.drain(..))
+            {
                 device.destroy_image_view(image_view, None);
             }
         }
 
+        // The device may have been rotated
+        let pre_transform = choose_pre_transform(&surface.capabilities, self.pre_rotation);
         self.extent = Self::create_clamped_extent(
-            window_size,
-            adapter
-                .info
-                .physical_device_info
-                .surface_capabilities
-                .as_ref()
-                .unwrap(),
+            pre_transformed_size(window_size, pre_transform),
+            &surface.capabilities,
         );
+        self.create_info.pre_transform = pre_transform;
         self.create_info.image_extent = self.extent;
         self.create_info.old_swapchain = self.handle;
 
         self.handle = unsafe { self.loader.create_swapchain(&self.create_info, None)? };
 
         self.images = unsafe { self.loader.get_swapchain_images(self.handle)? };
-        self.image_views = Self::create_image_views(device, surface, &self.images)?;
+        self.image_views = Self::create_image_views(device, surface.format, &self.images)?;
+        if let Some(format) = self.alias_format {
+            self.alias_image_views = Self::create_image_views(device, format, &self.images)?;
+        }
+        self.images_in_flight = vec![None; self.images.len()];
+        self.display_timing.reset();
 
         Ok(())
     }
 
-    pub(crate) fn acquire_next_image(&self) -> Result<(u32, bool)> {
+    /// With a device group, the image is acquired for the device that renders the next frame.
+    pub(crate) fn acquire_next_image(
+        &self,
+        device: &ash::Device,
+        device_group: Option<&DeviceGroup>,
+    ) -> Result<(u32, bool)> {
+        let frame = &self.frames[self.current_frame];
         unsafe {
-            Ok(self.loader.acquire_next_image(
-                self.handle,
-                u64::MAX,
-                self.image_available_semaphore,
-                vk::Fence::null(),
-            )?)
+            // The semaphore of this frame can only be reused once its last submission is done
+            device.wait_for_fences(from_ref(&frame.in_flight), true, u64::MAX)?;
+
+            let group = match device_group {
+                Some(group) => group,
+                None => {
+                    return Ok(self.loader.acquire_next_image(
+                        self.handle,
+                        u64::MAX,
+                        frame.image_available,
+                        vk::Fence::null(),
+                    )?)
+                }
+            };
+
+            let acquire_info = vk::AcquireNextImageInfoKHR::builder()
+                .swapchain(self.handle)
+                .timeout(u64::MAX)
+                .semaphore(frame.image_available)
+                .device_mask(1 << group.afr_device_index(self.frame_count));
+            let mut index = 0;
+            match self.loader.fp().acquire_next_image2_khr(
+                device.handle(),
+                &*acquire_info,
+                &mut index,
+            ) {
+                vk::Result::SUCCESS => Ok((index, false)),
+                vk::Result::SUBOPTIMAL_KHR => Ok((index, true)),
+                err => Err(err.into()),
+            }
         }
     }
 
@@ -209,7 +624,7 @@ impl Swapchain {
 
     fn create_image_views(
         device: &ash::Device,
-        surface: &Surface,
+        format: vk::Format,
         images: &[vk::Image],
     ) -> Result<Vec<vk::ImageView>> {
         Ok(images
@@ -219,7 +634,7 @@ impl Swapchain {
                 device.create_image_view(
                     &vk::ImageViewCreateInfo::builder()
                         .image(image)
-                        .format(surface.format)
+                        .format(format)
                         .view_type(vk::ImageViewType::TYPE_2D)
                         .components(
                             vk::ComponentMapping::builder()
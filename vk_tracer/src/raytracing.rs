@@ -0,0 +1,346 @@
+use crate::{
+    errors::{Result, VkTracerError},
+    VkTracerApp,
+};
+use ash::{
+    extensions::khr,
+    version::{DeviceV1_0, DeviceV1_2, InstanceV1_1},
+    vk,
+};
+use parking_lot::Mutex;
+
+mod blas;
+#[cfg(all(feature = "camera", feature = "shaderc"))]
+mod denoiser;
+#[cfg(all(feature = "camera", feature = "shaderc"))]
+mod path_tracer;
+mod pipeline;
+#[cfg(feature = "math")]
+mod rt_scene;
+#[cfg(all(feature = "math", feature = "shaderc"))]
+mod shadows;
+#[cfg(feature = "math")]
+mod tlas;
+
+pub(crate) use blas::*;
+#[cfg(all(feature = "camera", feature = "shaderc"))]
+pub use denoiser::*;
+#[cfg(all(feature = "camera", feature = "shaderc"))]
+pub use path_tracer::*;
+pub use pipeline::*;
+#[cfg(feature = "math")]
+pub use rt_scene::*;
+#[cfg(all(feature = "math", feature = "shaderc"))]
+pub use shadows::*;
+#[cfg(feature = "math")]
+pub(crate) use tlas::*;
+
+/// Loaded when [crate::setup::VkTracerExtensions::PipelineRaytracing] or
+/// [crate::setup::VkTracerExtensions::RayQuery] is enabled.
+pub(crate) struct RaytracingFns {
+    pub(crate) acceleration_structure: khr::AccelerationStructure,
+    pub(crate) scratch_alignment: vk::DeviceSize,
+    /// Only with [crate::setup::VkTracerExtensions::PipelineRaytracing].
+    pub(crate) pipeline: Option<(
+        khr::RayTracingPipeline,
+        vk::PhysicalDeviceRayTracingPipelinePropertiesKHR,
+    )>,
+    /// With [crate::setup::VkTracerExtensions::PipelineLibrary].
+    pub(crate) pipeline_library: bool,
+    /// Where the [AddressableBuffer]s are bound.
+    pub(crate) memory: Mutex<AddressableMemory>,
+}
+
+impl RaytracingFns {
+    pub(crate) fn load(
+        instance: &ash::Instance,
+        device: &ash::Device,
+        physical_device: vk::PhysicalDevice,
+        pipeline: bool,
+        pipeline_library: bool,
+    ) -> Self {
+        let mut acceleration_structure_properties =
+            vk::PhysicalDeviceAccelerationStructurePropertiesKHR::default();
+        let mut pipeline_properties = vk::PhysicalDeviceRayTracingPipelinePropertiesKHR::default();
+        let mut properties = vk::PhysicalDeviceProperties2::builder()
+            .push_next(&mut acceleration_structure_properties);
+        if pipeline {
+            properties = properties.push_next(&mut pipeline_properties);
+        }
+        unsafe { instance.get_physical_device_properties2(physical_device, &mut properties) };
+
+        Self {
+            acceleration_structure: khr::AccelerationStructure::new(instance, device),
+            scratch_alignment: acceleration_structure_properties
+                .min_acceleration_structure_scratch_offset_alignment
+                as vk::DeviceSize,
+            pipeline: if pipeline {
+                Some((
+                    khr::RayTracingPipeline::new(instance, device),
+                    pipeline_properties,
+                ))
+            } else {
+                None
+            },
+            pipeline_library,
+            memory: Mutex::default(),
+        }
+    }
+}
+
+/// Size of the memory blocks the [AddressableBuffer]s are sub-allocated from, bigger buffers get
+/// a block of their own.
+const ADDRESSABLE_BLOCK_SIZE: vk::DeviceSize = 64 << 20;
+
+/// Device memory with device addresses, shared by many buffers to stay far from
+/// `maxMemoryAllocationCount`.
+#[derive(Default)]
+pub(crate) struct AddressableMemory {
+    blocks: Vec<MemoryBlock>,
+}
+
+struct MemoryBlock {
+    memory: vk::DeviceMemory,
+    memory_type: u32,
+    size: vk::DeviceSize,
+    /// Offset and size of the free ranges, sorted and never adjacent.
+    free: Vec<(vk::DeviceSize, vk::DeviceSize)>,
+}
+
+impl AddressableMemory {
+    /// Returns the memory and the offset to bind the buffer at, and the range to give back to
+    /// [AddressableMemory::free].
+    fn allocate(
+        &mut self,
+        app: &VkTracerApp,
+        requirements: vk::MemoryRequirements,
+    ) -> Result<(
+        vk::DeviceMemory,
+        vk::DeviceSize,
+        (vk::DeviceSize, vk::DeviceSize),
+    )> {
+        let memory_properties = &app.adapter.info.physical_device_info.memory_properties;
+        let memory_type = memory_properties.memory_types
+            [..memory_properties.memory_type_count as usize]
+            .iter()
+            .enumerate()
+            .find(|(i, ty)| {
+                requirements.memory_type_bits & (1 << i) != 0
+                    && ty
+                        .property_flags
+                        .contains(vk::MemoryPropertyFlags::DEVICE_LOCAL)
+            })
+            .map(|(i, _)| i as u32)
+            .ok_or(VkTracerError::NoSuitableMemoryType)?;
+
+        for block in self
+            .blocks
+            .iter_mut()
+            .filter(|block| block.memory_type == memory_type)
+        {
+            if let Some((offset, range)) = block.take(requirements.size, requirements.alignment) {
+                return Ok((block.memory, offset, range));
+            }
+        }
+
+        let size = requirements.size.max(ADDRESSABLE_BLOCK_SIZE);
+        let mut flags_info =
+            vk::MemoryAllocateFlagsInfo::builder().flags(vk::MemoryAllocateFlags::DEVICE_ADDRESS);
+        let memory = unsafe {
+            app.device.allocate_memory(
+                &vk::MemoryAllocateInfo::builder()
+                    .allocation_size(size)
+                    .memory_type_index(memory_type)
+                    .push_next(&mut flags_info),
+                None,
+            )?
+        };
+        let mut block = MemoryBlock {
+            memory,
+            memory_type,
+            size,
+            free: vec![(0, size)],
+        };
+        // Always fits at the start of a new block
+        let (offset, range) = block
+            .take(requirements.size, requirements.alignment)
+            .unwrap();
+        self.blocks.push(block);
+        Ok((memory, offset, range))
+    }
+
+    /// Give a range back, the block is freed once it's empty.
+    fn free(
+        &mut self,
+        device: &ash::Device,
+        memory: vk::DeviceMemory,
+        range: (vk::DeviceSize, vk::DeviceSize),
+    ) {
+        if let Some(i) = self.blocks.iter().position(|block| block.memory == memory) {
+            let block = &mut self.blocks[i];
+            block.give_back(range);
+            if block.free == [(0, block.size)] {
+                unsafe { device.free_memory(block.memory, None) };
+                self.blocks.swap_remove(i);
+            }
+        }
+    }
+
+    /// The buffers bound in the blocks must be destroyed.
+    pub(crate) unsafe fn destroy(&mut self, device: &ash::Device) {
+        for block in self.blocks.drain(..) {
+            device.free_memory(block.memory, None);
+        }
+    }
+}
+
+impl MemoryBlock {
+    /// First fit, returns the aligned offset and the range taken from the free list.
+    fn take(
+        &mut self,
+        size: vk::DeviceSize,
+        alignment: vk::DeviceSize,
+    ) -> Option<(vk::DeviceSize, (vk::DeviceSize, vk::DeviceSize))> {
+        let (i, offset) = self
+            .free
+            .iter()
+            .enumerate()
+            .find_map(|(i, (start, length))| {
+                let offset = (start + alignment - 1) / alignment * alignment;
+                Some((i, offset)).filter(|_| offset + size <= start + length)
+            })?;
+
+        let (start, length) = self.free.remove(i);
+        let end = offset + size;
+        // Kept in order, after the padding before the aligned offset
+        if start + length > end {
+            self.free.insert(i, (end, start + length - end));
+        }
+        if offset > start {
+            self.free.insert(i, (start, offset - start));
+        }
+        Some((offset, (offset, size)))
+    }
+
+    fn give_back(&mut self, (offset, size): (vk::DeviceSize, vk::DeviceSize)) {
+        let i = match self.free.binary_search_by_key(&offset, |(start, _)| *start) {
+            Ok(i) | Err(i) => i,
+        };
+        self.free.insert(i, (offset, size));
+        // Merge with the next range, then with the previous one
+        if i + 1 < self.free.len() && offset + size == self.free[i + 1].0 {
+            self.free[i].1 += self.free.remove(i + 1).1;
+        }
+        if i > 0 && self.free[i - 1].0 + self.free[i - 1].1 == offset {
+            self.free[i - 1].1 += self.free.remove(i).1;
+        }
+    }
+}
+
+/// A device local buffer whose address can be given to acceleration structure builds, VMA
+/// doesn't allocate memory with device addresses so it's bound in an [AddressableMemory].
+#[derive(Default)]
+pub(crate) struct AddressableBuffer {
+    pub(crate) buffer: vk::Buffer,
+    pub(crate) memory: vk::DeviceMemory,
+    pub(crate) address: vk::DeviceAddress,
+    pub(crate) size: vk::DeviceSize,
+    /// Taken from the memory.
+    range: (vk::DeviceSize, vk::DeviceSize),
+}
+
+impl AddressableBuffer {
+    pub(crate) fn new(
+        app: &VkTracerApp,
+        size: vk::DeviceSize,
+        usage: vk::BufferUsageFlags,
+    ) -> Result<Self> {
+        let pool = &app.raytracing_fns()?.memory;
+        let device = &app.device;
+        let buffer = unsafe {
+            device.create_buffer(
+                &vk::BufferCreateInfo::builder()
+                    .size(size)
+                    .usage(usage | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS)
+                    .sharing_mode(vk::SharingMode::EXCLUSIVE),
+                None,
+            )?
+        };
+
+        let requirements = unsafe { device.get_buffer_memory_requirements(buffer) };
+        let (memory, offset, range) = match pool.lock().allocate(app, requirements) {
+            Ok(allocation) => allocation,
+            Err(err) => {
+                unsafe { device.destroy_buffer(buffer, None) };
+                return Err(err);
+            }
+        };
+
+        unsafe {
+            if let Err(err) = device.bind_buffer_memory(buffer, memory, offset) {
+                device.destroy_buffer(buffer, None);
+                pool.lock().free(device, memory, range);
+                return Err(err.into());
+            }
+            let address = device
+                .get_buffer_device_address(&vk::BufferDeviceAddressInfo::builder().buffer(buffer));
+            Ok(Self {
+                buffer,
+                memory,
+                address,
+                size,
+                range,
+            })
+        }
+    }
+
+    pub(crate) unsafe fn destroy(&self, app: &VkTracerApp) {
+        if self.memory == vk::DeviceMemory::null() {
+            return;
+        }
+        app.device.destroy_buffer(self.buffer, None);
+        if let Some(raytracing) = app.raytracing.as_ref() {
+            raytracing
+                .memory
+                .lock()
+                .free(&app.device, self.memory, self.range);
+        }
+    }
+}
+
+impl VkTracerApp {
+    pub(crate) fn raytracing_fns(&self) -> Result<&RaytracingFns> {
+        self.raytracing
+            .as_ref()
+            .ok_or(VkTracerError::ExtensionNotEnabled(
+                "VK_KHR_acceleration_structure",
+            ))
+    }
+
+    pub(crate) fn raytracing_pipeline_fns(
+        &self,
+    ) -> Result<&(
+        khr::RayTracingPipeline,
+        vk::PhysicalDeviceRayTracingPipelinePropertiesKHR,
+    )> {
+        self.raytracing
+            .as_ref()
+            .and_then(|raytracing| raytracing.pipeline.as_ref())
+            .ok_or(VkTracerError::ExtensionNotEnabled(
+                "VK_KHR_ray_tracing_pipeline",
+            ))
+    }
+}
+
+/// Write `data` inline in the command buffer, updates are limited to 65536 bytes each.
+pub(crate) unsafe fn cmd_update_raw_buffer<T: Copy>(
+    device: &ash::Device,
+    commands: vk::CommandBuffer,
+    buffer: vk::Buffer,
+    data: &[T],
+) {
+    let bytes = std::slice::from_raw_parts(data.as_ptr() as *const u8, std::mem::size_of_val(data));
+    for (i, chunk) in bytes.chunks(65536).enumerate() {
+        device.cmd_update_buffer(commands, buffer, (i * 65536) as vk::DeviceSize, chunk);
+    }
+}
@@ -0,0 +1,482 @@
+use crate::{
+    command_recorder::{OneTimeCommands, QueueType},
+    errors::{HandleType, Result, VkTracerError},
+    raytracing::AddressableBuffer,
+    BlasHandle, MeshHandle, VkTracerApp,
+};
+use ash::{version::DeviceV1_0, vk};
+use std::slice::from_ref;
+
+pub(crate) struct Blas {
+    pub(crate) handle: vk::AccelerationStructureKHR,
+    pub(crate) buffer: AddressableBuffer,
+    pub(crate) address: vk::DeviceAddress,
+}
+
+impl Blas {
+    pub(crate) unsafe fn destroy(&self, app: &VkTracerApp) {
+        if let Some(raytracing) = app.raytracing.as_ref() {
+            raytracing
+                .acceleration_structure
+                .destroy_acceleration_structure(self.handle, None);
+        }
+        self.buffer.destroy(app);
+    }
+}
+
+/// Positions and indices of a mesh copied next to each other, only needed during the build
+/// unless the shaders read the triangles.
+pub(crate) struct BlasInput {
+    pub(crate) buffer: AddressableBuffer,
+    pub(crate) vertex_format: vk::Format,
+    pub(crate) vertex_stride: vk::DeviceSize,
+    vertex_count: u32,
+    pub(crate) index_type: vk::IndexType,
+    pub(crate) index_offset: vk::DeviceSize,
+    triangle_count: u32,
+}
+
+impl VkTracerApp {
+    /// Build one bottom-level acceleration structure per mesh, from the attribute at location 0
+    /// of the meshes. The structures are compacted before being returned, the builds are
+    /// waited on.
+    pub fn create_blas(&mut self, meshes: &[MeshHandle]) -> Result<Vec<BlasHandle>> {
+        let (handles, inputs) = self.create_blas_with_inputs(meshes)?;
+        unsafe { destroy_buffers(self, inputs.iter().map(|input| &input.buffer)) };
+        Ok(handles)
+    }
+
+    /// Like [VkTracerApp::create_blas] but the input buffers are given back, to be destroyed
+    /// by the caller.
+    pub(crate) fn create_blas_with_inputs(
+        &mut self,
+        meshes: &[MeshHandle],
+    ) -> Result<(Vec<BlasHandle>, Vec<BlasInput>)> {
+        self.raytracing_fns()?;
+        if meshes.is_empty() {
+            return Ok((Vec::new(), Vec::new()));
+        }
+
+        let mut inputs = Vec::with_capacity(meshes.len());
+        let mut copies = Vec::with_capacity(meshes.len());
+        for handle in meshes.iter().copied() {
+            match self.blas_input(handle) {
+                Ok((input, copy)) => {
+                    inputs.push(input);
+                    copies.push(copy);
+                }
+                Err(err) => {
+                    unsafe { destroy_buffers(self, inputs.iter().map(|i| &i.buffer)) };
+                    return Err(err);
+                }
+            }
+        }
+
+        match self.build_blas(&inputs, &copies) {
+            Ok(handles) => Ok((handles, inputs)),
+            Err(err) => {
+                unsafe { destroy_buffers(self, inputs.iter().map(|input| &input.buffer)) };
+                Err(err)
+            }
+        }
+    }
+
+    pub fn destroy_blas(&mut self, handle: BlasHandle) -> Result<()> {
+        if let Some(blas) = self.blas_storage.remove(handle) {
+            unsafe { blas.destroy(self) };
+        }
+        Ok(())
+    }
+
+    /// To reference the structure in the instances of a top-level acceleration structure.
+    pub fn get_blas_address(&self, handle: BlasHandle) -> Result<vk::DeviceAddress> {
+        Ok(storage_access!(self.blas_storage, handle, HandleType::Blas).address)
+    }
+
+    /// The input buffer of a mesh and the copies from the buffers of the mesh that fill it.
+    fn blas_input(
+        &self,
+        handle: MeshHandle,
+    ) -> Result<(BlasInput, Vec<(vk::Buffer, vk::BufferCopy)>)> {
+        let mesh = storage_access!(self.mesh_storage, handle, HandleType::Mesh);
+        let (_, bindings, attributes) = &mesh.vertex_desc;
+        let position = attributes
+            .iter()
+            .find(|attribute| attribute.location == 0)
+            .ok_or(VkTracerError::InvalidVertexStreams)?;
+        let binding = bindings
+            .iter()
+            .find(|binding| binding.binding == position.binding)
+            .ok_or(VkTracerError::InvalidVertexStreams)?;
+        let vertices = match position.binding {
+            0 => &mesh.vertices,
+            stream => mesh
+                .extra_streams
+                .get(stream as usize - 1)
+                .ok_or(VkTracerError::InvalidVertexStreams)?,
+        };
+
+        // Index data must be aligned to the size of an index
+        let index_offset = (vertices.real_size + 3) / 4 * 4;
+        let buffer = AddressableBuffer::new(
+            self,
+            index_offset + mesh.indices.real_size,
+            vk::BufferUsageFlags::TRANSFER_DST
+                | vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR,
+        )?;
+        let copies = vec![
+            (
+                vertices.buffer,
+                vk::BufferCopy::builder()
+                    .src_offset(position.offset as vk::DeviceSize)
+                    .size(vertices.real_size - position.offset as vk::DeviceSize)
+                    .build(),
+            ),
+            (
+                mesh.indices.buffer,
+                vk::BufferCopy::builder()
+                    .dst_offset(index_offset)
+                    .size(mesh.indices.real_size)
+                    .build(),
+            ),
+        ];
+
+        Ok((
+            BlasInput {
+                buffer,
+                vertex_format: position.format,
+                vertex_stride: binding.stride as vk::DeviceSize,
+                vertex_count: (vertices.real_size / binding.stride as vk::DeviceSize) as u32,
+                index_type: mesh.index_ty.1,
+                index_offset,
+                triangle_count: mesh.indices_len / 3,
+            },
+            copies,
+        ))
+    }
+
+    fn build_blas(
+        &mut self,
+        inputs: &[BlasInput],
+        copies: &[Vec<(vk::Buffer, vk::BufferCopy)>],
+    ) -> Result<Vec<BlasHandle>> {
+        let raytracing = self.raytracing_fns()?;
+        let loader = &raytracing.acceleration_structure;
+        let scratch_alignment = raytracing.scratch_alignment.max(1);
+
+        let geometries = inputs
+            .iter()
+            .map(|input| {
+                vk::AccelerationStructureGeometryKHR::builder()
+                    .geometry_type(vk::GeometryTypeKHR::TRIANGLES)
+                    .flags(vk::GeometryFlagsKHR::OPAQUE)
+                    .geometry(vk::AccelerationStructureGeometryDataKHR {
+                        triangles: vk::AccelerationStructureGeometryTrianglesDataKHR::builder()
+                            .vertex_format(input.vertex_format)
+                            .vertex_data(vk::DeviceOrHostAddressConstKHR {
+                                device_address: input.buffer.address,
+                            })
+                            .vertex_stride(input.vertex_stride)
+                            .max_vertex(input.vertex_count.saturating_sub(1))
+                            .index_type(input.index_type)
+                            .index_data(vk::DeviceOrHostAddressConstKHR {
+                                device_address: input.buffer.address + input.index_offset,
+                            })
+                            .build(),
+                    })
+                    .build()
+            })
+            .collect::<Vec<_>>();
+
+        let mut build_infos = geometries
+            .iter()
+            .map(|geometry| {
+                vk::AccelerationStructureBuildGeometryInfoKHR::builder()
+                    .ty(vk::AccelerationStructureTypeKHR::BOTTOM_LEVEL)
+                    .flags(
+                        vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_TRACE
+                            | vk::BuildAccelerationStructureFlagsKHR::ALLOW_COMPACTION,
+                    )
+                    .mode(vk::BuildAccelerationStructureModeKHR::BUILD)
+                    .geometries(from_ref(geometry))
+                    .build()
+            })
+            .collect::<Vec<_>>();
+
+        // Everything created is kept here to be destroyed on errors
+        let mut structures = Vec::with_capacity(inputs.len());
+        let mut scratch_buffers = Vec::with_capacity(inputs.len());
+        let result = (|| -> Result<()> {
+            for (build_info, input) in build_infos.iter_mut().zip(inputs) {
+                let sizes = unsafe {
+                    loader.get_acceleration_structure_build_sizes(
+                        vk::AccelerationStructureBuildTypeKHR::DEVICE,
+                        build_info,
+                        from_ref(&input.triangle_count),
+                    )
+                };
+
+                let buffer = AddressableBuffer::new(
+                    self,
+                    sizes.acceleration_structure_size,
+                    vk::BufferUsageFlags::ACCELERATION_STRUCTURE_STORAGE_KHR,
+                )?;
+                let handle = unsafe {
+                    loader.create_acceleration_structure(
+                        &vk::AccelerationStructureCreateInfoKHR::builder()
+                            .buffer(buffer.buffer)
+                            .size(sizes.acceleration_structure_size)
+                            .ty(vk::AccelerationStructureTypeKHR::BOTTOM_LEVEL),
+                        None,
+                    )
+                };
+                let handle = match handle {
+                    Ok(handle) => handle,
+                    Err(err) => {
+                        unsafe { buffer.destroy(self) };
+                        return Err(err.into());
+                    }
+                };
+                structures.push((handle, buffer));
+
+                // Scratch addresses have their own alignment
+                let scratch = AddressableBuffer::new(
+                    self,
+                    sizes.build_scratch_size + scratch_alignment,
+                    vk::BufferUsageFlags::STORAGE_BUFFER,
+                )?;
+                build_info.dst_acceleration_structure = handle;
+                build_info.scratch_data = vk::DeviceOrHostAddressKHR {
+                    device_address: (scratch.address + scratch_alignment - 1) / scratch_alignment
+                        * scratch_alignment,
+                };
+                scratch_buffers.push(scratch);
+            }
+            Ok(())
+        })();
+        if let Err(err) = result {
+            unsafe {
+                destroy_structures(self, &structures);
+                destroy_buffers(self, &scratch_buffers);
+            }
+            return Err(err);
+        }
+
+        let result = self.record_blas_builds(inputs, copies, &build_infos, &structures);
+        unsafe { destroy_buffers(self, &scratch_buffers) };
+        let compacted_sizes = match result {
+            Ok(sizes) => sizes,
+            Err(err) => {
+                unsafe { destroy_structures(self, &structures) };
+                return Err(err);
+            }
+        };
+
+        let result = self.compact_blas(&structures, &compacted_sizes);
+        unsafe { destroy_structures(self, &structures) };
+        Ok(result?
+            .into_iter()
+            .map(|blas| self.blas_storage.insert(blas))
+            .collect())
+    }
+
+    /// Copy the inputs, build the structures and read back their compacted sizes.
+    fn record_blas_builds(
+        &mut self,
+        inputs: &[BlasInput],
+        copies: &[Vec<(vk::Buffer, vk::BufferCopy)>],
+        build_infos: &[vk::AccelerationStructureBuildGeometryInfoKHR],
+        structures: &[(vk::AccelerationStructureKHR, AddressableBuffer)],
+    ) -> Result<Vec<vk::DeviceSize>> {
+        let query_pool = unsafe {
+            self.device.create_query_pool(
+                &vk::QueryPoolCreateInfo::builder()
+                    .query_type(vk::QueryType::ACCELERATION_STRUCTURE_COMPACTED_SIZE_KHR)
+                    .query_count(structures.len() as u32),
+                None,
+            )?
+        };
+
+        let result = (|| -> Result<Vec<vk::DeviceSize>> {
+            let commands = OneTimeCommands::begin(self, QueueType::Compute)?;
+            let loader = &self.raytracing_fns()?.acceleration_structure;
+            let device = &self.device;
+            let range_infos = inputs
+                .iter()
+                .map(|input| {
+                    vk::AccelerationStructureBuildRangeInfoKHR::builder()
+                        .primitive_count(input.triangle_count)
+                        .build()
+                })
+                .collect::<Vec<_>>();
+
+            unsafe {
+                for (input, copies) in inputs.iter().zip(copies) {
+                    for (src, region) in copies {
+                        device.cmd_copy_buffer(
+                            commands.buffer,
+                            *src,
+                            input.buffer.buffer,
+                            from_ref(region),
+                        );
+                    }
+                }
+                self.cmd_memory_barrier(
+                    commands.buffer,
+                    (
+                        vk::PipelineStageFlags::TRANSFER,
+                        vk::AccessFlags::TRANSFER_WRITE,
+                    ),
+                    (
+                        vk::PipelineStageFlags::ACCELERATION_STRUCTURE_BUILD_KHR,
+                        vk::AccessFlags::SHADER_READ,
+                    ),
+                );
+
+                loader.cmd_build_acceleration_structures(
+                    commands.buffer,
+                    build_infos,
+                    &range_infos.iter().map(from_ref).collect::<Vec<_>>(),
+                );
+                self.cmd_memory_barrier(
+                    commands.buffer,
+                    (
+                        vk::PipelineStageFlags::ACCELERATION_STRUCTURE_BUILD_KHR,
+                        vk::AccessFlags::ACCELERATION_STRUCTURE_WRITE_KHR,
+                    ),
+                    (
+                        vk::PipelineStageFlags::ACCELERATION_STRUCTURE_BUILD_KHR,
+                        vk::AccessFlags::ACCELERATION_STRUCTURE_READ_KHR,
+                    ),
+                );
+
+                device.cmd_reset_query_pool(
+                    commands.buffer,
+                    query_pool,
+                    0,
+                    structures.len() as u32,
+                );
+                loader.cmd_write_acceleration_structures_properties(
+                    commands.buffer,
+                    &structures
+                        .iter()
+                        .map(|(handle, _)| *handle)
+                        .collect::<Vec<_>>(),
+                    vk::QueryType::ACCELERATION_STRUCTURE_COMPACTED_SIZE_KHR,
+                    query_pool,
+                    0,
+                );
+            }
+            commands.submit(self)?;
+
+            let mut sizes = vec![0u64; structures.len()];
+            unsafe {
+                self.device.get_query_pool_results(
+                    query_pool,
+                    0,
+                    structures.len() as u32,
+                    &mut sizes,
+                    vk::QueryResultFlags::TYPE_64 | vk::QueryResultFlags::WAIT,
+                )?;
+            }
+            Ok(sizes)
+        })();
+
+        unsafe { self.device.destroy_query_pool(query_pool, None) };
+        result
+    }
+
+    /// Copy the structures into new ones of their compacted size.
+    fn compact_blas(
+        &mut self,
+        structures: &[(vk::AccelerationStructureKHR, AddressableBuffer)],
+        compacted_sizes: &[vk::DeviceSize],
+    ) -> Result<Vec<Blas>> {
+        let mut compacted = Vec::with_capacity(structures.len());
+        let result = (|| -> Result<()> {
+            let loader = &self.raytracing_fns()?.acceleration_structure;
+            for size in compacted_sizes.iter().copied() {
+                let buffer = AddressableBuffer::new(
+                    self,
+                    size,
+                    vk::BufferUsageFlags::ACCELERATION_STRUCTURE_STORAGE_KHR,
+                )?;
+                let handle = unsafe {
+                    loader.create_acceleration_structure(
+                        &vk::AccelerationStructureCreateInfoKHR::builder()
+                            .buffer(buffer.buffer)
+                            .size(size)
+                            .ty(vk::AccelerationStructureTypeKHR::BOTTOM_LEVEL),
+                        None,
+                    )
+                };
+                let handle = match handle {
+                    Ok(handle) => handle,
+                    Err(err) => {
+                        unsafe { buffer.destroy(self) };
+                        return Err(err.into());
+                    }
+                };
+                let address = unsafe {
+                    loader.get_acceleration_structure_device_address(
+                        &vk::AccelerationStructureDeviceAddressInfoKHR::builder()
+                            .acceleration_structure(handle),
+                    )
+                };
+                compacted.push(Blas {
+                    handle,
+                    buffer,
+                    address,
+                });
+            }
+
+            let commands = OneTimeCommands::begin(self, QueueType::Compute)?;
+            let loader = &self.raytracing_fns()?.acceleration_structure;
+            for ((src, _), dst) in structures.iter().zip(&compacted) {
+                unsafe {
+                    loader.cmd_copy_acceleration_structure(
+                        commands.buffer,
+                        &vk::CopyAccelerationStructureInfoKHR::builder()
+                            .src(*src)
+                            .dst(dst.handle)
+                            .mode(vk::CopyAccelerationStructureModeKHR::COMPACT),
+                    );
+                }
+            }
+            commands.submit(self)
+        })();
+
+        match result {
+            Ok(()) => Ok(compacted),
+            Err(err) => {
+                for blas in compacted {
+                    unsafe { blas.destroy(self) };
+                }
+                Err(err)
+            }
+        }
+    }
+}
+
+unsafe fn destroy_buffers<'a>(
+    app: &VkTracerApp,
+    buffers: impl IntoIterator<Item = &'a AddressableBuffer>,
+) {
+    for buffer in buffers {
+        buffer.destroy(app);
+    }
+}
+
+unsafe fn destroy_structures(
+    app: &VkTracerApp,
+    structures: &[(vk::AccelerationStructureKHR, AddressableBuffer)],
+) {
+    if let Ok(raytracing) = app.raytracing_fns() {
+        for (handle, buffer) in structures {
+            raytracing
+                .acceleration_structure
+                .destroy_acceleration_structure(*handle, None);
+            buffer.destroy(app);
+        }
+    }
+}
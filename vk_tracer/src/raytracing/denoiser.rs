@@ -0,0 +1,273 @@
+use crate::{
+    command_recorder::{ComputeRecorder, OneTimeCommands, QueueType},
+    errors::Result,
+    mem::ImageViewFatHandle,
+    TextureHandle, VkTracerApp,
+};
+use ash::{version::DeviceV1_0, vk};
+use nalgebra_glm as glm;
+use std::slice::from_ref;
+
+/// A denoise pass between the output of a [crate::raytracing::PathTracer] and its composition,
+/// ex: an SVGF-style compute pass or an external library, see
+/// [VkTracerApp::set_path_tracer_denoiser].
+pub trait Denoiser {
+    /// Called once when attached, the images don't change until the denoiser is removed so they
+    /// can be written in descriptor sets here.
+    fn init(&mut self, app: &mut VkTracerApp, images: &DenoiserImages) -> Result<()>;
+
+    /// Record the pass writing [DenoiserImages::output], submitted right after the path tracing
+    /// of the same render. Every image is in GENERAL layout.
+    fn denoise<'app>(
+        &mut self,
+        recorder: ComputeRecorder<'app>,
+        frame: &DenoiserFrame,
+    ) -> Result<ComputeRecorder<'app>>;
+
+    /// Destroy what [Denoiser::init] created, nothing uses it anymore.
+    fn destroy(&mut self, _app: &mut VkTracerApp) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// The images of a [Denoiser], all the size of the path tracer output.
+#[derive(Copy, Clone)]
+pub struct DenoiserImages {
+    /// RGBA32F radiance averaged by the path tracer.
+    pub noisy: ImageViewFatHandle,
+    /// RGBA16F albedo of the primary hit, the sky color on misses.
+    pub albedo: ImageViewFatHandle,
+    /// RGBA16F world space normal of the primary hit, facing the camera, and its distance in w.
+    /// The distance is negative on misses.
+    pub normal_depth: ImageViewFatHandle,
+    /// RGBA32F denoised image of the previous render, owned by the app.
+    pub history: ImageViewFatHandle,
+    /// RGBA32F denoised image to write, copied to the history after the pass.
+    pub output: ImageViewFatHandle,
+}
+
+/// What changes at each render.
+#[derive(Copy, Clone, Debug)]
+pub struct DenoiserFrame {
+    pub inverse_view_proj: glm::Mat4,
+    /// Of the previous render, to reproject the history.
+    pub previous_view_proj: glm::Mat4,
+    /// Samples per pixel averaged in the noisy image.
+    pub sample_count: u32,
+    /// False when the history holds nothing yet, on the first render.
+    pub history_valid: bool,
+}
+
+/// A denoiser attached to a path tracer with the images it owns.
+pub(crate) struct DenoiseState {
+    pub(crate) denoiser: Box<dyn Denoiser>,
+    pub(crate) output: TextureHandle,
+    pub(crate) history: TextureHandle,
+    pub(crate) images: DenoiserImages,
+    pub(crate) previous_view_proj: Option<glm::Mat4>,
+}
+
+impl DenoiseState {
+    pub(crate) fn new(
+        app: &mut VkTracerApp,
+        mut denoiser: Box<dyn Denoiser>,
+        noisy: ImageViewFatHandle,
+        albedo: ImageViewFatHandle,
+        normal_depth: ImageViewFatHandle,
+    ) -> Result<Self> {
+        let usage = vk::ImageUsageFlags::STORAGE
+            | vk::ImageUsageFlags::SAMPLED
+            | vk::ImageUsageFlags::TRANSFER_SRC
+            | vk::ImageUsageFlags::TRANSFER_DST;
+        let (width, height) = (noisy.extent.width, noisy.extent.height);
+        let output = app.new_texture_with_usage(width, height, noisy.format, usage)?;
+        let history = match app.new_texture_with_usage(width, height, noisy.format, usage) {
+            Ok(history) => history,
+            Err(err) => {
+                app.destroy_texture(output)?;
+                return Err(err);
+            }
+        };
+
+        let images = DenoiserImages {
+            noisy,
+            albedo,
+            normal_depth,
+            history: app.get_texture_view(history)?,
+            output: app.get_texture_view(output)?,
+        };
+        if let Err(err) = denoiser.init(app, &images) {
+            app.destroy_texture(output)?;
+            app.destroy_texture(history)?;
+            return Err(err);
+        }
+
+        Ok(Self {
+            denoiser,
+            output,
+            history,
+            images,
+            previous_view_proj: None,
+        })
+    }
+
+    /// Nothing must use it anymore.
+    pub(crate) fn destroy(mut self, app: &mut VkTracerApp) -> Result<()> {
+        self.denoiser.destroy(app)?;
+        app.destroy_texture(self.output)?;
+        app.destroy_texture(self.history)
+    }
+
+    /// Once the path tracing commands are deferred, with the noisy and auxiliary images left in
+    /// GENERAL, record the denoiser and keep its output as the history of the next render.
+    /// Every image ends up in SHADER_READ_ONLY_OPTIMAL.
+    pub(crate) fn denoise(
+        &mut self,
+        app: &mut VkTracerApp,
+        inverse_view_proj: &glm::Mat4,
+        sample_count: u32,
+    ) -> Result<()> {
+        let view_proj = glm::inverse(inverse_view_proj);
+        let frame = DenoiserFrame {
+            inverse_view_proj: *inverse_view_proj,
+            previous_view_proj: self.previous_view_proj.unwrap_or(view_proj),
+            sample_count,
+            history_valid: self.previous_view_proj.is_some(),
+        };
+
+        // The previous output is overwritten and the history discarded the first time
+        let commands = OneTimeCommands::begin(app, QueueType::Graphics)?;
+        unsafe {
+            image_barrier(
+                app,
+                commands.buffer,
+                self.images.output,
+                vk::ImageLayout::UNDEFINED,
+                vk::ImageLayout::GENERAL,
+            );
+            image_barrier(
+                app,
+                commands.buffer,
+                self.images.history,
+                if frame.history_valid {
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
+                } else {
+                    vk::ImageLayout::UNDEFINED
+                },
+                vk::ImageLayout::GENERAL,
+            );
+        }
+        commands.defer(app)?;
+
+        let recorder = app.new_compute_recorder(QueueType::Graphics)?;
+        self.denoiser.denoise(recorder, &frame)?.submit_deferred()?;
+
+        let commands = OneTimeCommands::begin(app, QueueType::Graphics)?;
+        unsafe {
+            for image in [
+                self.images.noisy,
+                self.images.albedo,
+                self.images.normal_depth,
+            ]
+            .iter()
+            {
+                image_barrier(
+                    app,
+                    commands.buffer,
+                    *image,
+                    vk::ImageLayout::GENERAL,
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                );
+            }
+            image_barrier(
+                app,
+                commands.buffer,
+                self.images.output,
+                vk::ImageLayout::GENERAL,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            );
+            image_barrier(
+                app,
+                commands.buffer,
+                self.images.history,
+                vk::ImageLayout::GENERAL,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            );
+
+            let subresource = vk::ImageSubresourceLayers::builder()
+                .aspect_mask(vk::ImageAspectFlags::COLOR)
+                .layer_count(1)
+                .build();
+            let extent = self.images.output.extent;
+            app.device.cmd_copy_image(
+                commands.buffer,
+                self.images.output.handle,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                self.images.history.handle,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                from_ref(
+                    &vk::ImageCopy::builder()
+                        .src_subresource(subresource)
+                        .dst_subresource(subresource)
+                        .extent(vk::Extent3D {
+                            width: extent.width,
+                            height: extent.height,
+                            depth: 1,
+                        }),
+                ),
+            );
+
+            image_barrier(
+                app,
+                commands.buffer,
+                self.images.output,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            );
+            image_barrier(
+                app,
+                commands.buffer,
+                self.images.history,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            );
+        }
+        commands.defer(app)?;
+
+        self.previous_view_proj = Some(view_proj);
+        Ok(())
+    }
+}
+
+/// Full barrier, the denoiser can use the images from any stage.
+pub(crate) unsafe fn image_barrier(
+    app: &VkTracerApp,
+    commands: vk::CommandBuffer,
+    image: ImageViewFatHandle,
+    old_layout: vk::ImageLayout,
+    new_layout: vk::ImageLayout,
+) {
+    let barrier = vk::ImageMemoryBarrier::builder()
+        .src_access_mask(vk::AccessFlags::MEMORY_WRITE)
+        .dst_access_mask(vk::AccessFlags::MEMORY_READ | vk::AccessFlags::MEMORY_WRITE)
+        .old_layout(old_layout)
+        .new_layout(new_layout)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .image(image.handle)
+        .subresource_range(
+            vk::ImageSubresourceRange::builder()
+                .aspect_mask(vk::ImageAspectFlags::COLOR)
+                .level_count(1)
+                .layer_count(1)
+                .build(),
+        );
+    app.cmd_pipeline_barrier(
+        commands,
+        vk::PipelineStageFlags::ALL_COMMANDS,
+        vk::PipelineStageFlags::ALL_COMMANDS,
+        &[],
+        &[],
+        from_ref(&barrier),
+    );
+}
@@ -0,0 +1,593 @@
+use crate::{
+    command_recorder::{OneTimeCommands, QueueType},
+    errors::{HandleType, Result, VkTracerError},
+    mem::{DescriptorSetBuilder, ImageViewFatHandle},
+    raytracing::{
+        cmd_update_raw_buffer, image_barrier, AddressableBuffer, DenoiseState, Denoiser,
+        ShaderBindingTable, RAYTRACING_STAGES,
+    },
+    render::{push_constants_as_bytes, PushConstants, Scene},
+    utils::{Camera, ShaderCompiler},
+    BlasHandle, DescriptorSetHandle, MeshHandle, TextureHandle, TlasHandle, VkTracerApp,
+};
+use ash::{version::DeviceV1_0, vk};
+use nalgebra_glm as glm;
+use std::{collections::HashMap, io::Cursor, slice::from_ref};
+
+const COMMON_GLSL: &str = include_str!("../utils/shaders/path_tracer_common.glsl");
+const RAYGEN_GLSL: &str = include_str!("../utils/shaders/path_tracer.rgen");
+const MISS_GLSL: &str = include_str!("../utils/shaders/path_tracer.rmiss");
+const CLOSEST_HIT_GLSL: &str = include_str!("../utils/shaders/path_tracer.rchit");
+
+/// Progressive reference path tracer of the entities of a [Scene], every surface is a grey
+/// diffuse lit by the sky. Each call to [VkTracerApp::render_path_tracer] adds a sample per
+/// pixel to [PathTracer::output], the accumulation restarts when the camera moves. A
+/// [Denoiser] can be plugged between the output and its composition.
+/// Needs [crate::setup::VkTracerExtensions::PipelineRaytracing] and `shaderInt64`.
+pub struct PathTracer {
+    /// RGBA32F radiance averaged over the frames, in SHADER_READ_ONLY_OPTIMAL between renders.
+    pub output: TextureHandle,
+    /// RGBA16F albedo of the primary hits of the last render, for a [Denoiser].
+    pub albedo: TextureHandle,
+    /// RGBA16F normal and distance of the primary hits of the last render.
+    pub normal_depth: TextureHandle,
+    /// Bounces after the primary hit.
+    pub max_bounces: u32,
+    pub sky_color: glm::Vec3,
+    output_view: Option<ImageViewFatHandle>,
+    albedo_view: Option<ImageViewFatHandle>,
+    normal_depth_view: Option<ImageViewFatHandle>,
+    denoise: Option<DenoiseState>,
+    mesh_indices: HashMap<MeshHandle, u32>,
+    blas: Vec<BlasHandle>,
+    /// Positions and indices of each mesh, read by the closest hit shader.
+    geometry: Vec<AddressableBuffer>,
+    geometry_table: AddressableBuffer,
+    tlas: TlasHandle,
+    descriptor_set: DescriptorSetHandle,
+    pipeline: vk::Pipeline,
+    pipeline_layout: vk::PipelineLayout,
+    shader_binding_table: ShaderBindingTable,
+    frame: u32,
+    last_inverse_view_proj: glm::Mat4,
+}
+
+impl PathTracer {
+    /// Discard the samples accumulated so far, needed when the scene changes without
+    /// [VkTracerApp::update_path_tracer_scene].
+    pub fn reset_accumulation(&mut self) {
+        self.frame = 0;
+    }
+
+    /// Samples per pixel accumulated in the output.
+    pub fn sample_count(&self) -> u32 {
+        self.frame
+    }
+
+    fn views(&self) -> Result<(ImageViewFatHandle, ImageViewFatHandle, ImageViewFatHandle)> {
+        match (self.output_view, self.albedo_view, self.normal_depth_view) {
+            (Some(output), Some(albedo), Some(normal_depth)) => Ok((output, albedo, normal_depth)),
+            _ => Err(VkTracerError::InvalidHandle(HandleType::Texture)),
+        }
+    }
+
+    /// The image to compose, the output of the denoiser when there is one.
+    pub fn final_output(&self) -> TextureHandle {
+        self.denoise
+            .as_ref()
+            .map_or(self.output, |denoise| denoise.output)
+    }
+}
+
+/// Push constants of the stages, see `path_tracer_common.glsl`.
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct PathTracerParams {
+    inverse_view_proj: glm::Mat4,
+    geometries: vk::DeviceAddress,
+    frame: u32,
+    max_bounces: u32,
+    sky_color: glm::Vec4,
+}
+
+unsafe impl PushConstants for PathTracerParams {}
+
+/// Where the closest hit shader finds the triangles of a mesh.
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct GeometryRecord {
+    vertices: vk::DeviceAddress,
+    indices: vk::DeviceAddress,
+    vertex_stride: u32,
+    u16_indices: u32,
+}
+
+impl VkTracerApp {
+    /// Build the acceleration structures of the entities of the scene and an output of
+    /// `extent`. The positions of the meshes must be R32G32B32_SFLOAT.
+    pub fn create_path_tracer(
+        &mut self,
+        scene: &Scene,
+        extent: vk::Extent2D,
+    ) -> Result<PathTracer> {
+        self.raytracing_pipeline_fns()?;
+        if self.adapter.info.physical_device_info.features.shader_int64 != vk::TRUE {
+            return Err(VkTracerError::FeatureNotSupported("shaderInt64"));
+        }
+
+        let mut tracer = PathTracer {
+            output: TextureHandle::default(),
+            albedo: TextureHandle::default(),
+            normal_depth: TextureHandle::default(),
+            max_bounces: 4,
+            sky_color: glm::vec3(1.0, 1.0, 1.0),
+            output_view: None,
+            albedo_view: None,
+            normal_depth_view: None,
+            denoise: None,
+            mesh_indices: HashMap::new(),
+            blas: Vec::new(),
+            geometry: Vec::new(),
+            geometry_table: AddressableBuffer::default(),
+            tlas: TlasHandle::default(),
+            descriptor_set: DescriptorSetHandle::default(),
+            pipeline: vk::Pipeline::null(),
+            pipeline_layout: vk::PipelineLayout::null(),
+            shader_binding_table: ShaderBindingTable::default(),
+            frame: 0,
+            last_inverse_view_proj: glm::identity(),
+        };
+        match self.init_path_tracer(&mut tracer, scene, extent) {
+            Ok(()) => Ok(tracer),
+            Err(err) => {
+                self.destroy_path_tracer(tracer)?;
+                Err(err)
+            }
+        }
+    }
+
+    /// Refit the structures to the new transforms of the entities, which must be the same as
+    /// when the path tracer was created. The accumulation restarts.
+    pub fn update_path_tracer_scene(
+        &mut self,
+        tracer: &mut PathTracer,
+        scene: &Scene,
+    ) -> Result<()> {
+        let instances = path_tracer_instances(tracer, scene)?;
+        self.update_tlas(tracer.tlas, &instances)?;
+        tracer.reset_accumulation();
+        Ok(())
+    }
+
+    /// Trace a sample per pixel on the graphics queue, submitted with the next frame. The
+    /// denoiser of the path tracer, if any, runs right after.
+    pub fn render_path_tracer(&mut self, tracer: &mut PathTracer, camera: &Camera) -> Result<()> {
+        let inverse_view_proj = camera.inverse_view_projection();
+        if inverse_view_proj != tracer.last_inverse_view_proj {
+            tracer.last_inverse_view_proj = inverse_view_proj;
+            tracer.reset_accumulation();
+        }
+        let (output, albedo, normal_depth) = tracer.views()?;
+        let descriptor_set = storage_access!(
+            self.descriptor_set_storage,
+            tracer.descriptor_set,
+            HandleType::DescriptorSet
+        )
+        .handle;
+        let params = PathTracerParams {
+            inverse_view_proj,
+            geometries: tracer.geometry_table.address,
+            frame: tracer.frame,
+            max_bounces: tracer.max_bounces,
+            sky_color: glm::vec3_to_vec4(&tracer.sky_color),
+        };
+        let push_constants = push_constants_as_bytes(&params)?;
+
+        let commands = OneTimeCommands::begin(self, QueueType::Graphics)?;
+        let (loader, _) = self.raytracing_pipeline_fns()?;
+        let device = &self.device;
+        unsafe {
+            // The first frame discards the content, the primary hits are written every time
+            image_barrier(
+                self,
+                commands.buffer,
+                output,
+                if tracer.frame == 0 {
+                    vk::ImageLayout::UNDEFINED
+                } else {
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
+                },
+                vk::ImageLayout::GENERAL,
+            );
+            for image in [albedo, normal_depth].iter() {
+                image_barrier(
+                    self,
+                    commands.buffer,
+                    *image,
+                    vk::ImageLayout::UNDEFINED,
+                    vk::ImageLayout::GENERAL,
+                );
+            }
+            self.cmd_memory_barrier(
+                commands.buffer,
+                (
+                    vk::PipelineStageFlags::ACCELERATION_STRUCTURE_BUILD_KHR,
+                    vk::AccessFlags::ACCELERATION_STRUCTURE_WRITE_KHR,
+                ),
+                (
+                    vk::PipelineStageFlags::RAY_TRACING_SHADER_KHR,
+                    vk::AccessFlags::ACCELERATION_STRUCTURE_READ_KHR,
+                ),
+            );
+
+            device.cmd_bind_pipeline(
+                commands.buffer,
+                vk::PipelineBindPoint::RAY_TRACING_KHR,
+                tracer.pipeline,
+            );
+            device.cmd_bind_descriptor_sets(
+                commands.buffer,
+                vk::PipelineBindPoint::RAY_TRACING_KHR,
+                tracer.pipeline_layout,
+                0,
+                from_ref(&descriptor_set),
+                &[],
+            );
+            device.cmd_push_constants(
+                commands.buffer,
+                tracer.pipeline_layout,
+                RAYTRACING_STAGES,
+                0,
+                push_constants,
+            );
+            loader.cmd_trace_rays(
+                commands.buffer,
+                from_ref(&tracer.shader_binding_table.regions[0]),
+                from_ref(&tracer.shader_binding_table.regions[1]),
+                from_ref(&tracer.shader_binding_table.regions[2]),
+                &[vk::StridedDeviceAddressRegionKHR::default()],
+                output.extent.width,
+                output.extent.height,
+                1,
+            );
+
+            // The denoiser reads them in GENERAL and transitions them after
+            if tracer.denoise.is_some() {
+                self.cmd_memory_barrier(
+                    commands.buffer,
+                    (
+                        vk::PipelineStageFlags::RAY_TRACING_SHADER_KHR,
+                        vk::AccessFlags::SHADER_WRITE,
+                    ),
+                    (
+                        vk::PipelineStageFlags::ALL_COMMANDS,
+                        vk::AccessFlags::MEMORY_READ,
+                    ),
+                );
+            } else {
+                for image in [output, albedo, normal_depth].iter() {
+                    image_barrier(
+                        self,
+                        commands.buffer,
+                        *image,
+                        vk::ImageLayout::GENERAL,
+                        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    );
+                }
+            }
+        }
+        commands.defer(self)?;
+        tracer.frame += 1;
+
+        if let Some(denoise) = tracer.denoise.as_mut() {
+            if let Err(err) = denoise.denoise(self, &inverse_view_proj, tracer.frame) {
+                // The layouts of the images are unknown, start over
+                denoise.previous_view_proj = None;
+                tracer.reset_accumulation();
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    /// Denoise each render of the path tracer with `denoiser`, which replaces the previous one.
+    /// The app creates the output and history images it needs, the path tracer must not be used
+    /// by pending commands.
+    pub fn set_path_tracer_denoiser(
+        &mut self,
+        tracer: &mut PathTracer,
+        denoiser: Box<dyn Denoiser>,
+    ) -> Result<()> {
+        self.remove_path_tracer_denoiser(tracer)?;
+        let (output, albedo, normal_depth) = tracer.views()?;
+        tracer.denoise = Some(DenoiseState::new(
+            self,
+            denoiser,
+            output,
+            albedo,
+            normal_depth,
+        )?);
+        Ok(())
+    }
+
+    /// Destroy the denoiser of the path tracer and its images, if any. The path tracer must not
+    /// be used by pending commands.
+    pub fn remove_path_tracer_denoiser(&mut self, tracer: &mut PathTracer) -> Result<()> {
+        match tracer.denoise.take() {
+            Some(denoise) => denoise.destroy(self),
+            None => Ok(()),
+        }
+    }
+
+    /// The path tracer must not be used by pending commands.
+    pub fn destroy_path_tracer(&mut self, mut tracer: PathTracer) -> Result<()> {
+        self.remove_path_tracer_denoiser(&mut tracer)?;
+        unsafe {
+            self.device.destroy_pipeline(tracer.pipeline, None);
+            tracer.shader_binding_table.destroy(self);
+            tracer.geometry_table.destroy(self);
+            for buffer in &tracer.geometry {
+                buffer.destroy(self);
+            }
+        }
+        self.destroy_descriptor_set(tracer.descriptor_set)?;
+        self.destroy_texture(tracer.output)?;
+        self.destroy_texture(tracer.albedo)?;
+        self.destroy_texture(tracer.normal_depth)?;
+        self.destroy_tlas(tracer.tlas)?;
+        for blas in tracer.blas {
+            self.destroy_blas(blas)?;
+        }
+        Ok(())
+    }
+
+    /// Everything created is stored in the tracer right away to be destroyed on errors.
+    fn init_path_tracer(
+        &mut self,
+        tracer: &mut PathTracer,
+        scene: &Scene,
+        extent: vk::Extent2D,
+    ) -> Result<()> {
+        let mut meshes = Vec::new();
+        for (_, entity) in scene.iter() {
+            if !tracer.mesh_indices.contains_key(&entity.mesh) {
+                tracer.mesh_indices.insert(entity.mesh, meshes.len() as u32);
+                meshes.push(entity.mesh);
+            }
+        }
+
+        let (blas, inputs) = self.create_blas_with_inputs(&meshes)?;
+        tracer.blas = blas;
+        let records = inputs
+            .iter()
+            .map(|input| GeometryRecord {
+                vertices: input.buffer.address,
+                indices: input.buffer.address + input.index_offset,
+                vertex_stride: input.vertex_stride as u32,
+                u16_indices: (input.index_type == vk::IndexType::UINT16) as u32,
+            })
+            .collect::<Vec<_>>();
+        let position_format = inputs
+            .iter()
+            .map(|input| input.vertex_format)
+            .find(|format| *format != vk::Format::R32G32B32_SFLOAT);
+        tracer.geometry = inputs.into_iter().map(|input| input.buffer).collect();
+        if let Some(format) = position_format {
+            return Err(VkTracerError::UnsupportedPositionFormat(format));
+        }
+        tracer.geometry_table = AddressableBuffer::new(
+            self,
+            (records.len().max(1) * std::mem::size_of::<GeometryRecord>()) as vk::DeviceSize,
+            vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+        )?;
+
+        let instances = path_tracer_instances(tracer, scene)?;
+        tracer.tlas = self.create_tlas(&instances)?;
+
+        tracer.output = self.new_texture_with_usage(
+            extent.width,
+            extent.height,
+            vk::Format::R32G32B32A32_SFLOAT,
+            vk::ImageUsageFlags::STORAGE
+                | vk::ImageUsageFlags::SAMPLED
+                | vk::ImageUsageFlags::TRANSFER_SRC,
+        )?;
+        let output = self.get_texture_view(tracer.output)?;
+        tracer.output_view = Some(output);
+        let aux_usage = vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED;
+        tracer.albedo = self.new_texture_with_usage(
+            extent.width,
+            extent.height,
+            vk::Format::R16G16B16A16_SFLOAT,
+            aux_usage,
+        )?;
+        let albedo = self.get_texture_view(tracer.albedo)?;
+        tracer.albedo_view = Some(albedo);
+        tracer.normal_depth = self.new_texture_with_usage(
+            extent.width,
+            extent.height,
+            vk::Format::R16G16B16A16_SFLOAT,
+            aux_usage,
+        )?;
+        let normal_depth = self.get_texture_view(tracer.normal_depth)?;
+        tracer.normal_depth_view = Some(normal_depth);
+
+        tracer.descriptor_set = self
+            .new_descriptor_sets()
+            .new_set(
+                DescriptorSetBuilder::new()
+                    .acceleration_structure(0, vk::ShaderStageFlags::RAYGEN_KHR)
+                    .storage_image(1, vk::ShaderStageFlags::RAYGEN_KHR)
+                    .storage_image(2, vk::ShaderStageFlags::RAYGEN_KHR)
+                    .storage_image(3, vk::ShaderStageFlags::RAYGEN_KHR),
+            )
+            .build()?[0];
+        self.write_descriptor_set_tlas(tracer.descriptor_set, 0, tracer.tlas)?;
+        for (binding, image) in [output, albedo, normal_depth].iter().enumerate() {
+            self.write_descriptor_set_storage_image(
+                tracer.descriptor_set,
+                binding as u32 + 1,
+                *image,
+                vk::ImageLayout::GENERAL,
+            )?;
+        }
+
+        self.create_path_tracer_pipeline(tracer)?;
+        tracer.shader_binding_table = ShaderBindingTable::new(self, tracer.pipeline, 1, 1)?;
+
+        let commands = OneTimeCommands::begin(self, QueueType::Graphics)?;
+        unsafe {
+            cmd_update_raw_buffer(
+                &self.device,
+                commands.buffer,
+                tracer.geometry_table.buffer,
+                &records,
+            );
+            self.cmd_memory_barrier(
+                commands.buffer,
+                (
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::AccessFlags::TRANSFER_WRITE,
+                ),
+                (
+                    vk::PipelineStageFlags::RAY_TRACING_SHADER_KHR,
+                    vk::AccessFlags::SHADER_READ,
+                ),
+            );
+        }
+        commands.submit(self)
+    }
+
+    fn create_path_tracer_pipeline(&mut self, tracer: &mut PathTracer) -> Result<()> {
+        let set_layout = storage_access!(
+            self.descriptor_set_storage,
+            tracer.descriptor_set,
+            HandleType::DescriptorSet
+        )
+        .layout;
+        let push_constant_range = vk::PushConstantRange::builder()
+            .stage_flags(RAYTRACING_STAGES)
+            .size(std::mem::size_of::<PathTracerParams>() as u32)
+            .build();
+        tracer.pipeline_layout =
+            self.pipeline_layout(from_ref(&set_layout), from_ref(&push_constant_range))?;
+
+        let mut compiler = ShaderCompiler::new()?;
+        let mut modules = Vec::with_capacity(3);
+        let result = (|| -> Result<vk::Pipeline> {
+            for (src, name, kind) in [
+                (
+                    RAYGEN_GLSL,
+                    "path_tracer.rgen",
+                    crate::shaderc::ShaderKind::RayGeneration,
+                ),
+                (
+                    MISS_GLSL,
+                    "path_tracer.rmiss",
+                    crate::shaderc::ShaderKind::Miss,
+                ),
+                (
+                    CLOSEST_HIT_GLSL,
+                    "path_tracer.rchit",
+                    crate::shaderc::ShaderKind::ClosestHit,
+                ),
+            ]
+            .iter()
+            .copied()
+            {
+                let spv = compiler.compile_source(
+                    &format!("{}\n{}", COMMON_GLSL, src),
+                    name,
+                    kind,
+                    "main",
+                )?;
+                let spv = ash::util::read_spv(&mut Cursor::new(spv))?;
+                modules.push(unsafe {
+                    self.device.create_shader_module(
+                        &vk::ShaderModuleCreateInfo::builder().code(&spv),
+                        None,
+                    )?
+                });
+            }
+
+            let stages = [
+                vk::ShaderStageFlags::RAYGEN_KHR,
+                vk::ShaderStageFlags::MISS_KHR,
+                vk::ShaderStageFlags::CLOSEST_HIT_KHR,
+            ]
+            .iter()
+            .zip(&modules)
+            .map(|(stage, module)| {
+                vk::PipelineShaderStageCreateInfo::builder()
+                    .stage(*stage)
+                    .module(*module)
+                    .name(crate::utils::str_to_cstr("main\0"))
+                    .build()
+            })
+            .collect::<Vec<_>>();
+            let general_group = |shader| {
+                vk::RayTracingShaderGroupCreateInfoKHR::builder()
+                    .ty(vk::RayTracingShaderGroupTypeKHR::GENERAL)
+                    .general_shader(shader)
+                    .closest_hit_shader(vk::SHADER_UNUSED_KHR)
+                    .any_hit_shader(vk::SHADER_UNUSED_KHR)
+                    .intersection_shader(vk::SHADER_UNUSED_KHR)
+                    .build()
+            };
+            let groups = [
+                general_group(0),
+                general_group(1),
+                vk::RayTracingShaderGroupCreateInfoKHR::builder()
+                    .ty(vk::RayTracingShaderGroupTypeKHR::TRIANGLES_HIT_GROUP)
+                    .general_shader(vk::SHADER_UNUSED_KHR)
+                    .closest_hit_shader(2)
+                    .any_hit_shader(vk::SHADER_UNUSED_KHR)
+                    .intersection_shader(vk::SHADER_UNUSED_KHR)
+                    .build(),
+            ];
+
+            let (loader, _) = self.raytracing_pipeline_fns()?;
+            let pipelines = unsafe {
+                loader.create_ray_tracing_pipelines(
+                    vk::DeferredOperationKHR::null(),
+                    self.pipeline_cache,
+                    from_ref(
+                        &vk::RayTracingPipelineCreateInfoKHR::builder()
+                            .stages(&stages)
+                            .groups(&groups)
+                            // The bounces are iterated in the raygen shader
+                            .max_pipeline_ray_recursion_depth(1)
+                            .layout(tracer.pipeline_layout),
+                    ),
+                    None,
+                )?
+            };
+            Ok(pipelines[0])
+        })();
+
+        unsafe {
+            for module in modules {
+                self.device.destroy_shader_module(module, None);
+            }
+        }
+        tracer.pipeline = result?;
+        Ok(())
+    }
+}
+
+/// An instance per entity, with the index of the geometry record of its mesh.
+fn path_tracer_instances(
+    tracer: &PathTracer,
+    scene: &Scene,
+) -> Result<Vec<(BlasHandle, glm::Mat4, u32)>> {
+    scene
+        .iter()
+        .map(|(_, entity)| {
+            let index = *tracer
+                .mesh_indices
+                .get(&entity.mesh)
+                .ok_or(VkTracerError::InvalidHandle(HandleType::Mesh))?;
+            Ok((tracer.blas[index as usize], entity.transform, index))
+        })
+        .collect()
+}
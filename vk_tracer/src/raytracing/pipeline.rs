@@ -0,0 +1,488 @@
+use crate::{
+    command_recorder::{OneTimeCommands, QueueType},
+    errors::{HandleType, Result, VkTracerError},
+    raytracing::{cmd_update_raw_buffer, AddressableBuffer},
+    render::{push_constants_as_bytes, PushConstants},
+    utils::str_to_cstr,
+    DescriptorSetHandle, PipelineLibraryHandle, RaytracingPipelineHandle, VkTracerApp,
+};
+use ash::{version::DeviceV1_0, vk};
+use std::{io::Cursor, slice::from_ref};
+
+/// Every stage of a raytracing pipeline, the push constants are visible to all of them.
+pub(crate) const RAYTRACING_STAGES: vk::ShaderStageFlags = vk::ShaderStageFlags::from_raw(
+    vk::ShaderStageFlags::RAYGEN_KHR.as_raw()
+        | vk::ShaderStageFlags::MISS_KHR.as_raw()
+        | vk::ShaderStageFlags::CLOSEST_HIT_KHR.as_raw()
+        | vk::ShaderStageFlags::ANY_HIT_KHR.as_raw()
+        | vk::ShaderStageFlags::INTERSECTION_KHR.as_raw(),
+);
+
+/// The SPIR-V of the shaders of a hit group, it is a triangles hit group unless it has an
+/// intersection shader.
+#[derive(Clone, Default)]
+pub struct HitGroup {
+    pub closest_hit: Option<Vec<u8>>,
+    pub any_hit: Option<Vec<u8>>,
+    pub intersection: Option<Vec<u8>>,
+}
+
+pub(crate) struct RaytracingPipeline {
+    pub(crate) pipeline: vk::Pipeline,
+    /// Owned by the layout cache.
+    pub(crate) pipeline_layout: vk::PipelineLayout,
+    pub(crate) descriptor_sets: Box<[vk::DescriptorSet]>,
+    pub(crate) push_constants: Option<Box<[u8]>>,
+    pub(crate) shader_binding_table: ShaderBindingTable,
+}
+
+impl RaytracingPipeline {
+    pub(crate) unsafe fn destroy(&self, app: &VkTracerApp) {
+        app.device.destroy_pipeline(self.pipeline, None);
+        self.shader_binding_table.destroy(app);
+    }
+}
+
+/// Hit groups compiled once, linked into the pipelines built with
+/// [RaytracingPipelineBuilder::library].
+pub(crate) struct PipelineLibrary {
+    pub(crate) pipeline: vk::Pipeline,
+    pub(crate) pipeline_layout: vk::PipelineLayout,
+    pub(crate) hit_group_count: u32,
+    pub(crate) interface: (u32, u32),
+}
+
+/// One region per kind of group, the groups of the pipeline must be ordered as the raygen
+/// shader, then the miss shaders and the hit groups.
+#[derive(Default)]
+pub(crate) struct ShaderBindingTable {
+    buffer: AddressableBuffer,
+    /// Raygen, miss and hit regions.
+    pub(crate) regions: [vk::StridedDeviceAddressRegionKHR; 3],
+}
+
+impl ShaderBindingTable {
+    pub(crate) fn new(
+        app: &mut VkTracerApp,
+        pipeline: vk::Pipeline,
+        miss_count: u32,
+        hit_count: u32,
+    ) -> Result<Self> {
+        let (loader, properties) = app.raytracing_pipeline_fns()?;
+        let handle_size = properties.shader_group_handle_size as vk::DeviceSize;
+        let handle_alignment = properties.shader_group_handle_alignment.max(1) as vk::DeviceSize;
+        let base_alignment = properties.shader_group_base_alignment.max(1) as vk::DeviceSize;
+        let stride = align(handle_size, handle_alignment);
+
+        let group_count = 1 + miss_count + hit_count;
+        let handles = unsafe {
+            loader.get_ray_tracing_shader_group_handles(
+                pipeline,
+                0,
+                group_count,
+                group_count as usize * handle_size as usize,
+            )?
+        };
+
+        // Offset and record count of each region
+        let counts = [1, miss_count, hit_count];
+        let mut offsets = [0; 3];
+        let mut size = 0;
+        for (offset, count) in offsets.iter_mut().zip(counts.iter()) {
+            *offset = size;
+            size += align(*count as vk::DeviceSize * stride, base_alignment);
+        }
+
+        let mut data = vec![0u8; size as usize];
+        let mut group = 0;
+        for (offset, count) in offsets.iter().zip(counts.iter()) {
+            for i in 0..*count as vk::DeviceSize {
+                let start = (offset + i * stride) as usize;
+                let handle = group * handle_size as usize;
+                data[start..start + handle_size as usize]
+                    .copy_from_slice(&handles[handle..handle + handle_size as usize]);
+                group += 1;
+            }
+        }
+
+        // The regions must start on the base alignment
+        let buffer = AddressableBuffer::new(
+            app,
+            size + base_alignment,
+            vk::BufferUsageFlags::SHADER_BINDING_TABLE_KHR | vk::BufferUsageFlags::TRANSFER_DST,
+        )?;
+        let start = align(buffer.address, base_alignment);
+        let mut regions = [vk::StridedDeviceAddressRegionKHR::default(); 3];
+        for (i, region) in regions.iter_mut().enumerate() {
+            if counts[i] > 0 {
+                *region = vk::StridedDeviceAddressRegionKHR {
+                    device_address: start + offsets[i],
+                    stride,
+                    size: counts[i] as vk::DeviceSize * stride,
+                };
+            }
+        }
+
+        // Written from the start of the buffer, padded up to the aligned regions
+        let mut padded = vec![0u8; (start - buffer.address) as usize];
+        padded.extend_from_slice(&data);
+        let result = OneTimeCommands::begin(app, QueueType::Graphics).and_then(|commands| {
+            unsafe {
+                cmd_update_raw_buffer(&app.device, commands.buffer, buffer.buffer, &padded);
+                app.cmd_memory_barrier(
+                    commands.buffer,
+                    (
+                        vk::PipelineStageFlags::TRANSFER,
+                        vk::AccessFlags::TRANSFER_WRITE,
+                    ),
+                    (
+                        vk::PipelineStageFlags::RAY_TRACING_SHADER_KHR,
+                        vk::AccessFlags::SHADER_READ,
+                    ),
+                );
+            }
+            commands.submit(app)
+        });
+        match result {
+            Ok(()) => Ok(Self { buffer, regions }),
+            Err(err) => {
+                unsafe { buffer.destroy(app) };
+                Err(err)
+            }
+        }
+    }
+
+    pub(crate) unsafe fn destroy(&self, app: &VkTracerApp) {
+        self.buffer.destroy(app);
+    }
+}
+
+fn align(value: vk::DeviceSize, alignment: vk::DeviceSize) -> vk::DeviceSize {
+    (value + alignment - 1) / alignment * alignment
+}
+
+impl VkTracerApp {
+    pub fn new_raytracing_pipeline(&mut self) -> RaytracingPipelineBuilder {
+        RaytracingPipelineBuilder {
+            app: self,
+            descriptor_sets: Vec::new(),
+            push_constants: None,
+            raygen: None,
+            misses: Vec::new(),
+            hit_groups: Vec::new(),
+            libraries: Vec::new(),
+            max_recursion_depth: 1,
+            interface: (64, 8),
+        }
+    }
+
+    pub fn destroy_raytracing_pipeline(&mut self, handle: RaytracingPipelineHandle) -> Result<()> {
+        if let Some(pipeline) = self.raytracing_pipeline_storage.remove(handle) {
+            unsafe { pipeline.destroy(self) };
+        }
+        Ok(())
+    }
+
+    /// The pipelines linked with the library stay valid.
+    pub fn destroy_pipeline_library(&mut self, handle: PipelineLibraryHandle) -> Result<()> {
+        if let Some(library) = self.pipeline_library_storage.remove(handle) {
+            unsafe { self.device.destroy_pipeline(library.pipeline, None) };
+        }
+        Ok(())
+    }
+}
+
+pub struct RaytracingPipelineBuilder<'app> {
+    app: &'app mut VkTracerApp,
+    descriptor_sets: Vec<DescriptorSetHandle>,
+    push_constants: Option<Box<[u8]>>,
+    raygen: Option<Vec<u8>>,
+    misses: Vec<Vec<u8>>,
+    hit_groups: Vec<HitGroup>,
+    libraries: Vec<PipelineLibraryHandle>,
+    max_recursion_depth: u32,
+    interface: (u32, u32),
+}
+
+impl RaytracingPipelineBuilder<'_> {
+    /// Optional, bound along with the pipeline. Libraries must have the same sets and push
+    /// constants size as the pipelines they are linked into.
+    pub fn descriptor_sets(mut self, descriptor_sets: &[DescriptorSetHandle]) -> Self {
+        self.descriptor_sets.extend_from_slice(descriptor_sets);
+        self
+    }
+
+    /// Optional, pushed to every stage when binding the pipeline, they can be changed before
+    /// each trace with [crate::command_recorder::ComputeRecorder::push_constants].
+    pub fn push_constants<P: PushConstants>(mut self, data: &P) -> Result<Self> {
+        self.push_constants = Some(Box::from(push_constants_as_bytes(data)?));
+        Ok(self)
+    }
+
+    /// The SPIR-V of the raygen shader, required unless building a library.
+    pub fn raygen(mut self, spv: &[u8]) -> Self {
+        self.raygen = Some(spv.to_vec());
+        self
+    }
+
+    /// Miss shaders are indexed in the order they are added.
+    pub fn miss(mut self, spv: &[u8]) -> Self {
+        self.misses.push(spv.to_vec());
+        self
+    }
+
+    /// Hit groups are indexed in the order they are added, followed by the ones of the
+    /// libraries.
+    pub fn hit_group(mut self, group: HitGroup) -> Self {
+        self.hit_groups.push(group);
+        self
+    }
+
+    /// Link the hit groups of a library, needs
+    /// [crate::setup::VkTracerExtensions::PipelineLibrary].
+    pub fn library(mut self, library: PipelineLibraryHandle) -> Self {
+        self.libraries.push(library);
+        self
+    }
+
+    /// Defaults to 1, when only the raygen shader traces rays.
+    pub fn max_recursion_depth(mut self, depth: u32) -> Self {
+        self.max_recursion_depth = depth;
+        self
+    }
+
+    /// Max size in bytes of the ray payloads and hit attributes, must be the same for the
+    /// libraries and the pipelines using them. Defaults to 64 and 8 bytes.
+    pub fn interface(mut self, max_payload_size: u32, max_attribute_size: u32) -> Self {
+        self.interface = (max_payload_size, max_attribute_size);
+        self
+    }
+
+    pub fn build(mut self) -> Result<RaytracingPipelineHandle> {
+        if self.raygen.is_none() {
+            return Err(VkTracerError::NoRaygenShader);
+        }
+        let (layout, descriptor_sets) = self.layout()?;
+        let app = &*self.app;
+
+        let mut libraries = Vec::with_capacity(self.libraries.len());
+        let mut hit_count = self.hit_groups.len() as u32;
+        for handle in self.libraries.iter().copied() {
+            let library = storage_access!(
+                app.pipeline_library_storage,
+                handle,
+                HandleType::PipelineLibrary
+            );
+            if library.pipeline_layout != layout || library.interface != self.interface {
+                return Err(VkTracerError::PipelineLibraryMismatch);
+            }
+            libraries.push(library.pipeline);
+            hit_count += library.hit_group_count;
+        }
+
+        let raygen = self.raygen.as_deref().unwrap_or_default();
+        let mut shaders = vec![(vk::ShaderStageFlags::RAYGEN_KHR, raygen)];
+        shaders.extend(
+            self.misses
+                .iter()
+                .map(|spv| (vk::ShaderStageFlags::MISS_KHR, spv.as_slice())),
+        );
+        let miss_count = self.misses.len() as u32;
+        let pipeline = self.create_pipeline(
+            layout,
+            shaders,
+            &libraries,
+            vk::PipelineCreateFlags::empty(),
+        )?;
+
+        let app = self.app;
+        let shader_binding_table =
+            match ShaderBindingTable::new(app, pipeline, miss_count, hit_count) {
+                Ok(table) => table,
+                Err(err) => {
+                    unsafe { app.device.destroy_pipeline(pipeline, None) };
+                    return Err(err);
+                }
+            };
+        Ok(app.raytracing_pipeline_storage.insert(RaytracingPipeline {
+            pipeline,
+            pipeline_layout: layout,
+            descriptor_sets,
+            push_constants: self.push_constants,
+            shader_binding_table,
+        }))
+    }
+
+    /// Compile only the hit groups, to be linked into pipelines with the same descriptor sets,
+    /// push constants size and interface.
+    pub fn build_library(mut self) -> Result<PipelineLibraryHandle> {
+        self.app.raytracing_pipeline_fns()?;
+        let enabled = self
+            .app
+            .raytracing
+            .as_ref()
+            .map_or(false, |raytracing| raytracing.pipeline_library);
+        if !enabled {
+            return Err(VkTracerError::ExtensionNotEnabled(
+                "VK_KHR_pipeline_library",
+            ));
+        }
+        if self.raygen.is_some() || !self.misses.is_empty() || !self.libraries.is_empty() {
+            return Err(VkTracerError::InvalidPipelineLibrary);
+        }
+
+        let (layout, _) = self.layout()?;
+        let pipeline = self.create_pipeline(
+            layout,
+            Vec::new(),
+            &[],
+            vk::PipelineCreateFlags::LIBRARY_KHR,
+        )?;
+        Ok(self.app.pipeline_library_storage.insert(PipelineLibrary {
+            pipeline,
+            pipeline_layout: layout,
+            hit_group_count: self.hit_groups.len() as u32,
+            interface: self.interface,
+        }))
+    }
+
+    /// Layouts with the same sets and push constants size are the same, from the layout cache.
+    fn layout(&mut self) -> Result<(vk::PipelineLayout, Box<[vk::DescriptorSet]>)> {
+        let app = &mut *self.app;
+        let mut descriptor_layouts = Vec::with_capacity(self.descriptor_sets.len());
+        let mut descriptor_sets = Vec::with_capacity(self.descriptor_sets.len());
+        for handle in self.descriptor_sets.iter().copied() {
+            let set = storage_access!(
+                app.descriptor_set_storage,
+                handle,
+                HandleType::DescriptorSet
+            );
+            descriptor_layouts.push(set.layout);
+            descriptor_sets.push(set.handle);
+        }
+        // Bound without offsets, sets with dynamic buffers go through the recorder instead
+        let dynamic_types = app.dynamic_descriptor_types(&self.descriptor_sets)?;
+        app.check_dynamic_offsets(&dynamic_types, &[])?;
+
+        let push_constant_ranges = self
+            .push_constants
+            .iter()
+            .map(|data| {
+                vk::PushConstantRange::builder()
+                    .stage_flags(RAYTRACING_STAGES)
+                    .size(data.len() as u32)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+        let layout = app.pipeline_layout(&descriptor_layouts, &push_constant_ranges)?;
+        Ok((layout, descriptor_sets.into_boxed_slice()))
+    }
+
+    /// `shaders` are the general shaders, each in its own group, before the hit groups.
+    fn create_pipeline(
+        &self,
+        layout: vk::PipelineLayout,
+        mut shaders: Vec<(vk::ShaderStageFlags, &[u8])>,
+        libraries: &[vk::Pipeline],
+        flags: vk::PipelineCreateFlags,
+    ) -> Result<vk::Pipeline> {
+        let general_count = shaders.len() as u32;
+        let mut groups = (0..general_count)
+            .map(|shader| {
+                vk::RayTracingShaderGroupCreateInfoKHR::builder()
+                    .ty(vk::RayTracingShaderGroupTypeKHR::GENERAL)
+                    .general_shader(shader)
+                    .closest_hit_shader(vk::SHADER_UNUSED_KHR)
+                    .any_hit_shader(vk::SHADER_UNUSED_KHR)
+                    .intersection_shader(vk::SHADER_UNUSED_KHR)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+        for group in &self.hit_groups {
+            let mut add_shader = |stage, spv: &Option<Vec<u8>>| match spv {
+                Some(spv) => {
+                    shaders.push((stage, spv.as_slice()));
+                    shaders.len() as u32 - 1
+                }
+                None => vk::SHADER_UNUSED_KHR,
+            };
+            let closest_hit = add_shader(vk::ShaderStageFlags::CLOSEST_HIT_KHR, &group.closest_hit);
+            let any_hit = add_shader(vk::ShaderStageFlags::ANY_HIT_KHR, &group.any_hit);
+            let intersection =
+                add_shader(vk::ShaderStageFlags::INTERSECTION_KHR, &group.intersection);
+            groups.push(
+                vk::RayTracingShaderGroupCreateInfoKHR::builder()
+                    .ty(if group.intersection.is_some() {
+                        vk::RayTracingShaderGroupTypeKHR::PROCEDURAL_HIT_GROUP
+                    } else {
+                        vk::RayTracingShaderGroupTypeKHR::TRIANGLES_HIT_GROUP
+                    })
+                    .general_shader(vk::SHADER_UNUSED_KHR)
+                    .closest_hit_shader(closest_hit)
+                    .any_hit_shader(any_hit)
+                    .intersection_shader(intersection)
+                    .build(),
+            );
+        }
+
+        let device = &self.app.device;
+        let mut modules = Vec::with_capacity(shaders.len());
+        let result = (|| -> Result<vk::Pipeline> {
+            for (_, spv) in shaders.iter() {
+                let code = ash::util::read_spv(&mut Cursor::new(spv))?;
+                modules.push(unsafe {
+                    device.create_shader_module(
+                        &vk::ShaderModuleCreateInfo::builder().code(&code),
+                        None,
+                    )?
+                });
+            }
+            let stages = shaders
+                .iter()
+                .zip(&modules)
+                .map(|((stage, _), module)| {
+                    vk::PipelineShaderStageCreateInfo::builder()
+                        .stage(*stage)
+                        .module(*module)
+                        .name(str_to_cstr("main\0"))
+                        .build()
+                })
+                .collect::<Vec<_>>();
+
+            let library_info = vk::PipelineLibraryCreateInfoKHR::builder().libraries(libraries);
+            let interface = vk::RayTracingPipelineInterfaceCreateInfoKHR::builder()
+                .max_pipeline_ray_payload_size(self.interface.0)
+                .max_pipeline_ray_hit_attribute_size(self.interface.1);
+            let mut create_info = vk::RayTracingPipelineCreateInfoKHR::builder()
+                .flags(flags)
+                .stages(&stages)
+                .groups(&groups)
+                .max_pipeline_ray_recursion_depth(self.max_recursion_depth)
+                .layout(layout);
+            // The interface is only needed when libraries are involved
+            if !libraries.is_empty() || flags.contains(vk::PipelineCreateFlags::LIBRARY_KHR) {
+                create_info = create_info
+                    .library_info(&library_info)
+                    .library_interface(&interface);
+            }
+
+            let (loader, _) = self.app.raytracing_pipeline_fns()?;
+            let pipelines = unsafe {
+                loader.create_ray_tracing_pipelines(
+                    vk::DeferredOperationKHR::null(),
+                    self.app.pipeline_cache,
+                    from_ref(&create_info),
+                    None,
+                )?
+            };
+            Ok(pipelines[0])
+        })();
+
+        unsafe {
+            for module in modules {
+                device.destroy_shader_module(module, None);
+            }
+        }
+        result
+    }
+}
@@ -0,0 +1,170 @@
+use crate::{
+    errors::{HandleType, Result},
+    BlasHandle, DescriptorSetHandle, RtInstanceHandle, TlasHandle, VkTracerApp,
+};
+use ash::version::DeviceV1_0;
+use nalgebra_glm as glm;
+use slotmap::SlotMap;
+
+/// An instance of a BLAS in a [RtScene].
+#[derive(Copy, Clone, Debug)]
+pub struct RtInstance {
+    pub blas: BlasHandle,
+    pub transform: glm::Mat4,
+    /// Read by the shaders as `gl_InstanceCustomIndexEXT` (24 bits).
+    pub custom_index: u32,
+}
+
+/// Retained set of instances behind a single TLAS. Changes are only recorded, the structure
+/// is brought up to date once per frame by [VkTracerApp::update_rt_scene]: a refit when only
+/// transforms changed and a rebuild when instances were added or removed.
+pub struct RtScene {
+    instances: SlotMap<RtInstanceHandle, RtInstance>,
+    tlas: Option<TlasHandle>,
+    capacity: usize,
+    /// Instances were added or removed since the last update.
+    structure_dirty: bool,
+    /// Transforms changed since the last update.
+    transforms_dirty: bool,
+    /// Rewritten when the structure is reallocated.
+    bindings: Vec<(DescriptorSetHandle, u32)>,
+}
+
+impl RtScene {
+    pub fn new() -> Self {
+        Self::with_capacity(16)
+    }
+
+    /// Room for `capacity` instances before the TLAS needs to be reallocated.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            instances: SlotMap::with_key(),
+            tlas: None,
+            capacity: capacity.max(1),
+            structure_dirty: true,
+            transforms_dirty: false,
+            bindings: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, instance: RtInstance) -> RtInstanceHandle {
+        self.structure_dirty = true;
+        self.instances.insert(instance)
+    }
+
+    pub fn remove(&mut self, handle: RtInstanceHandle) -> Option<RtInstance> {
+        let instance = self.instances.remove(handle);
+        self.structure_dirty |= instance.is_some();
+        instance
+    }
+
+    pub fn set_transform(&mut self, handle: RtInstanceHandle, transform: glm::Mat4) -> Result<()> {
+        storage_access_mut!(self.instances, handle, HandleType::RtInstance).transform = transform;
+        self.transforms_dirty = true;
+        Ok(())
+    }
+
+    pub fn get(&self, handle: RtInstanceHandle) -> Result<&RtInstance> {
+        Ok(storage_access!(
+            self.instances,
+            handle,
+            HandleType::RtInstance
+        ))
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.instances.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.instances.is_empty()
+    }
+
+    /// The structure to trace rays against, none before the first update.
+    #[inline]
+    pub fn tlas(&self) -> Option<TlasHandle> {
+        self.tlas
+    }
+
+    fn instance_list(&self) -> Vec<(BlasHandle, glm::Mat4, u32)> {
+        self.instances
+            .values()
+            .map(|instance| (instance.blas, instance.transform, instance.custom_index))
+            .collect()
+    }
+}
+
+impl Default for RtScene {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VkTracerApp {
+    /// Bring the TLAS of the scene up to date, to be called once per frame before recording
+    /// commands tracing rays against it. The work is submitted with the next frame, except
+    /// for the first build and when the instances outgrew the structure: the device is then
+    /// waited on and the structure reallocated with twice the room, the handle changes and
+    /// the bindings registered with [VkTracerApp::bind_rt_scene] are rewritten.
+    pub fn update_rt_scene(&mut self, scene: &mut RtScene) -> Result<TlasHandle> {
+        let tlas = match scene.tlas {
+            Some(tlas) if scene.instances.len() <= scene.capacity => {
+                if scene.structure_dirty {
+                    self.rebuild_tlas(tlas, &scene.instance_list())?;
+                } else if scene.transforms_dirty {
+                    self.update_tlas(tlas, &scene.instance_list())?;
+                }
+                tlas
+            }
+            previous => {
+                while scene.capacity < scene.instances.len() {
+                    scene.capacity *= 2;
+                }
+                let tlas =
+                    self.create_tlas_with_capacity(&scene.instance_list(), scene.capacity)?;
+                if let Some(previous) = previous {
+                    unsafe {
+                        self.device.device_wait_idle()?;
+                    }
+                    self.destroy_tlas(previous)?;
+                }
+                scene.tlas = Some(tlas);
+                for &(set, binding) in &scene.bindings {
+                    self.write_descriptor_set_tlas(set, binding, tlas)?;
+                }
+                tlas
+            }
+        };
+
+        scene.structure_dirty = false;
+        scene.transforms_dirty = false;
+        Ok(tlas)
+    }
+
+    /// Write the TLAS of the scene in an acceleration structure binding and keep it up to date
+    /// when the structure is reallocated. Builds the structure if needed.
+    pub fn bind_rt_scene(
+        &mut self,
+        scene: &mut RtScene,
+        set: DescriptorSetHandle,
+        binding: u32,
+    ) -> Result<()> {
+        let tlas = match scene.tlas {
+            Some(tlas) => tlas,
+            None => self.update_rt_scene(scene)?,
+        };
+        self.write_descriptor_set_tlas(set, binding, tlas)?;
+        scene.bindings.push((set, binding));
+        Ok(())
+    }
+
+    /// The structure must not be used by pending commands.
+    pub fn destroy_rt_scene(&mut self, scene: RtScene) -> Result<()> {
+        match scene.tlas {
+            Some(tlas) => self.destroy_tlas(tlas),
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,149 @@
+use crate::{
+    command_recorder::ComputeRecorder,
+    errors::Result,
+    mem::{DescriptorSetBuilder, ImageViewFatHandle},
+    render::PushConstants,
+    utils::{ShaderCompiler, RT_SHADOWS_GLSL},
+    ComputePipelineHandle, DescriptorSetHandle, SamplerHandle, TextureHandle, TlasHandle,
+    VkTracerApp,
+};
+use ash::{version::DeviceV1_0, vk};
+use nalgebra_glm as glm;
+use std::io::Cursor;
+
+/// Traces a shadow ray per pixel of the depth buffer against a TLAS and writes the result in
+/// a R32_SFLOAT mask, 1.0 for lit and 0.0 for occluded. The mask can be read as a sampled
+/// image by the lighting pipelines once [ComputeRecorder::trace_shadows] completed.
+/// Needs [crate::setup::VkTracerExtensions::RayQuery].
+pub struct ShadowPass {
+    pub mask: TextureHandle,
+    mask_view: ImageViewFatHandle,
+    depth: ImageViewFatHandle,
+    pipeline: ComputePipelineHandle,
+    descriptor_set: DescriptorSetHandle,
+    sampler: SamplerHandle,
+}
+
+/// Push constants of [crate::utils::RT_SHADOWS_GLSL].
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct ShadowPassParams {
+    /// Inverse of the view projection used to render the depth buffer.
+    pub inverse_view_proj: glm::Mat4,
+    /// xyz is the direction towards the light and w the max length of the rays.
+    pub light_direction: glm::Vec4,
+}
+
+unsafe impl PushConstants for ShadowPassParams {}
+
+impl VkTracerApp {
+    /// The depth must be the one of a non transient depth attachment, of the size of the mask.
+    pub fn create_shadow_pass(
+        &mut self,
+        depth: ImageViewFatHandle,
+        tlas: TlasHandle,
+    ) -> Result<ShadowPass> {
+        // Fails early when no acceleration structure can exist
+        self.raytracing_fns()?;
+
+        let mask = self.new_texture_with_usage(
+            depth.extent.width,
+            depth.extent.height,
+            vk::Format::R32_SFLOAT,
+            vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED,
+        )?;
+        let mask_view = self.get_texture_view(mask)?;
+        let sampler =
+            self.create_sampler(vk::Filter::NEAREST, vk::SamplerAddressMode::CLAMP_TO_EDGE)?;
+
+        let descriptor_set = self
+            .new_descriptor_sets()
+            .new_set(
+                DescriptorSetBuilder::new()
+                    .acceleration_structure(0, vk::ShaderStageFlags::COMPUTE)
+                    .sampled_images(1, 1, vk::ShaderStageFlags::COMPUTE)
+                    .immutable_samplers(2, &[sampler], vk::ShaderStageFlags::COMPUTE)
+                    .storage_image(3, vk::ShaderStageFlags::COMPUTE),
+            )
+            .build()?[0];
+        self.write_descriptor_set_tlas(descriptor_set, 0, tlas)?;
+        self.write_descriptor_set_sampled_image(descriptor_set, 1, 0, depth)?;
+        self.write_descriptor_set_storage_image(
+            descriptor_set,
+            3,
+            mask_view,
+            vk::ImageLayout::GENERAL,
+        )?;
+
+        let spv = ShaderCompiler::new()?.compile_source(
+            RT_SHADOWS_GLSL,
+            "rt_shadows.comp",
+            crate::shaderc::ShaderKind::Compute,
+            "main",
+        )?;
+        let pipeline = self
+            .new_compute_pipeline()
+            .descriptor_sets(&[descriptor_set])
+            .push_constants(&ShadowPassParams {
+                inverse_view_proj: glm::identity(),
+                light_direction: glm::vec4(0.0, 1.0, 0.0, 0.0),
+            })?
+            .build(Cursor::new(spv))?;
+
+        Ok(ShadowPass {
+            mask,
+            mask_view,
+            depth,
+            pipeline,
+            descriptor_set,
+            sampler,
+        })
+    }
+
+    /// The pass must not be used by pending commands.
+    pub fn destroy_shadow_pass(&mut self, pass: ShadowPass) -> Result<()> {
+        if let Some(pipeline) = self.compute_pipeline_storage.remove(pass.pipeline) {
+            unsafe {
+                self.device.destroy_pipeline(pipeline.pipeline, None);
+            }
+        }
+        // The sampler is embedded in the layout of the set
+        self.destroy_descriptor_set(pass.descriptor_set)?;
+        self.destroy_sampler(pass.sampler)?;
+        self.destroy_texture(pass.mask)
+    }
+}
+
+impl ComputeRecorder<'_> {
+    /// Trace the shadows of the depth written by the previous graphics work, the recorder must
+    /// be on [crate::command_recorder::QueueType::Graphics]. The depth goes back to
+    /// DEPTH_STENCIL_ATTACHMENT_OPTIMAL and the mask is left in SHADER_READ_ONLY_OPTIMAL.
+    pub fn trace_shadows(self, pass: &ShadowPass, params: &ShadowPassParams) -> Result<Self> {
+        let mask = pass.mask_view;
+        let extent = mask.extent;
+
+        self.image_barrier(
+            pass.depth,
+            vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        )
+        // Every pixel is overwritten
+        .image_barrier(mask, vk::ImageLayout::UNDEFINED, vk::ImageLayout::GENERAL)
+        .bind_pipeline(pass.pipeline)?
+        .push_constants(params)?
+        .dispatch((extent.width + 7) / 8, (extent.height + 7) / 8, 1)
+        .map(|recorder| {
+            recorder
+                .image_barrier(
+                    mask,
+                    vk::ImageLayout::GENERAL,
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                )
+                .image_barrier(
+                    pass.depth,
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                )
+        })
+    }
+}
@@ -0,0 +1,326 @@
+use crate::{
+    command_recorder::{OneTimeCommands, QueueType},
+    errors::{HandleType, Result, VkTracerError},
+    raytracing::{cmd_update_raw_buffer, AddressableBuffer},
+    BlasHandle, TlasHandle, VkTracerApp,
+};
+use ash::{version::DeviceV1_0, vk};
+use nalgebra_glm as glm;
+use std::slice::from_ref;
+
+pub(crate) struct Tlas {
+    pub(crate) handle: vk::AccelerationStructureKHR,
+    pub(crate) buffer: AddressableBuffer,
+    /// Kept to update the instances.
+    instances: AddressableBuffer,
+    instance_count: u32,
+    /// Max instance count of the rebuilds.
+    pub(crate) capacity: u32,
+    scratch: AddressableBuffer,
+    scratch_address: vk::DeviceAddress,
+}
+
+impl Tlas {
+    pub(crate) unsafe fn destroy(&self, app: &VkTracerApp) {
+        if let Some(raytracing) = app.raytracing.as_ref() {
+            raytracing
+                .acceleration_structure
+                .destroy_acceleration_structure(self.handle, None);
+        }
+        self.buffer.destroy(app);
+        self.instances.destroy(app);
+        self.scratch.destroy(app);
+    }
+}
+
+/// Same layout as `VkAccelerationStructureInstanceKHR`.
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct InstanceData {
+    /// Row major 3x4 matrix.
+    transform: [f32; 12],
+    custom_index_and_mask: u32,
+    sbt_offset_and_flags: u32,
+    blas_address: vk::DeviceAddress,
+}
+
+const TLAS_FLAGS: vk::BuildAccelerationStructureFlagsKHR =
+    vk::BuildAccelerationStructureFlagsKHR::from_raw(
+        vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_TRACE.as_raw()
+            | vk::BuildAccelerationStructureFlagsKHR::ALLOW_UPDATE.as_raw(),
+    );
+
+impl VkTracerApp {
+    /// Build a top-level acceleration structure with an instance of a BLAS per entry, along
+    /// with its transform and the custom index read by the shaders (24 bits). The build is
+    /// waited on.
+    pub fn create_tlas(
+        &mut self,
+        instances: &[(BlasHandle, glm::Mat4, u32)],
+    ) -> Result<TlasHandle> {
+        self.create_tlas_with_capacity(instances, instances.len())
+    }
+
+    /// Like [VkTracerApp::create_tlas] with room for `capacity` instances, to be rebuilt with
+    /// [VkTracerApp::rebuild_tlas] when instances are added or removed.
+    pub fn create_tlas_with_capacity(
+        &mut self,
+        instances: &[(BlasHandle, glm::Mat4, u32)],
+        capacity: usize,
+    ) -> Result<TlasHandle> {
+        let capacity = capacity.max(instances.len()) as u32;
+        let raytracing = self.raytracing_fns()?;
+        let loader = &raytracing.acceleration_structure;
+        let scratch_alignment = raytracing.scratch_alignment.max(1);
+        let instance_count = instances.len() as u32;
+
+        let instance_buffer = AddressableBuffer::new(
+            self,
+            (capacity.max(1) as usize * std::mem::size_of::<InstanceData>()) as vk::DeviceSize,
+            vk::BufferUsageFlags::TRANSFER_DST
+                | vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR,
+        )?;
+        let geometry = tlas_geometry(instance_buffer.address);
+        let sizes = unsafe {
+            loader.get_acceleration_structure_build_sizes(
+                vk::AccelerationStructureBuildTypeKHR::DEVICE,
+                &vk::AccelerationStructureBuildGeometryInfoKHR::builder()
+                    .ty(vk::AccelerationStructureTypeKHR::TOP_LEVEL)
+                    .flags(TLAS_FLAGS)
+                    .mode(vk::BuildAccelerationStructureModeKHR::BUILD)
+                    .geometries(from_ref(&geometry)),
+                from_ref(&capacity),
+            )
+        };
+
+        // Everything created is destroyed together on errors
+        let mut buffers = vec![instance_buffer];
+        let result = (|| -> Result<(vk::AccelerationStructureKHR, AddressableBuffer)> {
+            buffers.push(AddressableBuffer::new(
+                self,
+                sizes.acceleration_structure_size,
+                vk::BufferUsageFlags::ACCELERATION_STRUCTURE_STORAGE_KHR,
+            )?);
+            // The updates reuse the scratch buffer
+            let scratch = AddressableBuffer::new(
+                self,
+                sizes.build_scratch_size.max(sizes.update_scratch_size) + scratch_alignment,
+                vk::BufferUsageFlags::STORAGE_BUFFER,
+            )?;
+            let handle = unsafe {
+                loader.create_acceleration_structure(
+                    &vk::AccelerationStructureCreateInfoKHR::builder()
+                        .buffer(buffers[1].buffer)
+                        .size(sizes.acceleration_structure_size)
+                        .ty(vk::AccelerationStructureTypeKHR::TOP_LEVEL),
+                    None,
+                )
+            };
+            match handle {
+                Ok(handle) => Ok((handle, scratch)),
+                Err(err) => {
+                    buffers.push(scratch);
+                    Err(err.into())
+                }
+            }
+        })();
+        let (handle, scratch) = match result {
+            Ok(result) => result,
+            Err(err) => {
+                unsafe {
+                    for buffer in &buffers {
+                        buffer.destroy(self);
+                    }
+                }
+                return Err(err);
+            }
+        };
+
+        let mut buffers = buffers.into_iter();
+        let tlas = Tlas {
+            handle,
+            instances: buffers.next().unwrap(),
+            buffer: buffers.next().unwrap(),
+            instance_count,
+            capacity,
+            scratch_address: (scratch.address + scratch_alignment - 1) / scratch_alignment
+                * scratch_alignment,
+            scratch,
+        };
+
+        let result = OneTimeCommands::begin(self, QueueType::Graphics).and_then(|commands| {
+            self.record_tlas_build(&commands, &tlas, instances, false)?;
+            commands.submit(self)
+        });
+        match result {
+            Ok(()) => Ok(self.tlas_storage.insert(tlas)),
+            Err(err) => {
+                unsafe { tlas.destroy(self) };
+                Err(err)
+            }
+        }
+    }
+
+    /// Refit the structure to new transforms, there must be as many instances as when it was
+    /// created. The update is submitted with the next frame, before its commands.
+    pub fn update_tlas(
+        &mut self,
+        handle: TlasHandle,
+        instances: &[(BlasHandle, glm::Mat4, u32)],
+    ) -> Result<()> {
+        let instance_count =
+            storage_access!(self.tlas_storage, handle, HandleType::Tlas).instance_count;
+        if instances.len() != instance_count as usize {
+            return Err(VkTracerError::TlasInstanceCountMismatch {
+                expected: instance_count as usize,
+                actual: instances.len(),
+            });
+        }
+
+        let commands = OneTimeCommands::begin(self, QueueType::Graphics)?;
+        let tlas = storage_access!(self.tlas_storage, handle, HandleType::Tlas);
+        self.record_tlas_build(&commands, tlas, instances, true)?;
+        commands.defer(self)
+    }
+
+    /// Build the structure again with other instances, at most as many as its capacity.
+    /// Submitted with the next frame like [VkTracerApp::update_tlas].
+    pub fn rebuild_tlas(
+        &mut self,
+        handle: TlasHandle,
+        instances: &[(BlasHandle, glm::Mat4, u32)],
+    ) -> Result<()> {
+        let capacity = storage_access!(self.tlas_storage, handle, HandleType::Tlas).capacity;
+        if instances.len() > capacity as usize {
+            return Err(VkTracerError::TlasCapacityExceeded {
+                capacity: capacity as usize,
+                actual: instances.len(),
+            });
+        }
+
+        let commands = OneTimeCommands::begin(self, QueueType::Graphics)?;
+        let tlas = storage_access!(self.tlas_storage, handle, HandleType::Tlas);
+        self.record_tlas_build(&commands, tlas, instances, false)?;
+        commands.defer(self)?;
+        storage_access_mut!(self.tlas_storage, handle, HandleType::Tlas).instance_count =
+            instances.len() as u32;
+        Ok(())
+    }
+
+    pub fn destroy_tlas(&mut self, handle: TlasHandle) -> Result<()> {
+        if let Some(tlas) = self.tlas_storage.remove(handle) {
+            unsafe { tlas.destroy(self) };
+        }
+        Ok(())
+    }
+
+    /// Write the instances and build the structure, on the graphics queue to be ordered with
+    /// the frames tracing rays against it.
+    fn record_tlas_build(
+        &self,
+        commands: &OneTimeCommands,
+        tlas: &Tlas,
+        instances: &[(BlasHandle, glm::Mat4, u32)],
+        update: bool,
+    ) -> Result<()> {
+        let mut data = Vec::with_capacity(instances.len());
+        for (blas, transform, custom_index) in instances.iter() {
+            let mut matrix = [0.0; 12];
+            for row in 0..3 {
+                for column in 0..4 {
+                    matrix[row * 4 + column] = transform[(row, column)];
+                }
+            }
+            data.push(InstanceData {
+                transform: matrix,
+                custom_index_and_mask: (custom_index & 0x00ff_ffff) | (0xff << 24),
+                sbt_offset_and_flags: 0,
+                blas_address: self.get_blas_address(*blas)?,
+            });
+        }
+
+        let loader = &self.raytracing_fns()?.acceleration_structure;
+        let geometry = tlas_geometry(tlas.instances.address);
+        let build_info = vk::AccelerationStructureBuildGeometryInfoKHR::builder()
+            .ty(vk::AccelerationStructureTypeKHR::TOP_LEVEL)
+            .flags(TLAS_FLAGS)
+            .mode(if update {
+                vk::BuildAccelerationStructureModeKHR::UPDATE
+            } else {
+                vk::BuildAccelerationStructureModeKHR::BUILD
+            })
+            .src_acceleration_structure(if update {
+                tlas.handle
+            } else {
+                vk::AccelerationStructureKHR::null()
+            })
+            .dst_acceleration_structure(tlas.handle)
+            .geometries(from_ref(&geometry))
+            .scratch_data(vk::DeviceOrHostAddressKHR {
+                device_address: tlas.scratch_address,
+            });
+        let range_info = vk::AccelerationStructureBuildRangeInfoKHR::builder()
+            .primitive_count(instances.len() as u32)
+            .build();
+
+        unsafe {
+            // The previous frames may still trace rays against the structure
+            self.cmd_memory_barrier(
+                commands.buffer,
+                (
+                    vk::PipelineStageFlags::ALL_COMMANDS,
+                    vk::AccessFlags::ACCELERATION_STRUCTURE_READ_KHR,
+                ),
+                (
+                    vk::PipelineStageFlags::TRANSFER
+                        | vk::PipelineStageFlags::ACCELERATION_STRUCTURE_BUILD_KHR,
+                    vk::AccessFlags::TRANSFER_WRITE
+                        | vk::AccessFlags::ACCELERATION_STRUCTURE_WRITE_KHR,
+                ),
+            );
+            cmd_update_raw_buffer(&self.device, commands.buffer, tlas.instances.buffer, &data);
+            self.cmd_memory_barrier(
+                commands.buffer,
+                (
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::AccessFlags::TRANSFER_WRITE,
+                ),
+                (
+                    vk::PipelineStageFlags::ACCELERATION_STRUCTURE_BUILD_KHR,
+                    vk::AccessFlags::SHADER_READ,
+                ),
+            );
+            loader.cmd_build_acceleration_structures(
+                commands.buffer,
+                from_ref(&build_info),
+                &[from_ref(&range_info)],
+            );
+            self.cmd_memory_barrier(
+                commands.buffer,
+                (
+                    vk::PipelineStageFlags::ACCELERATION_STRUCTURE_BUILD_KHR,
+                    vk::AccessFlags::ACCELERATION_STRUCTURE_WRITE_KHR,
+                ),
+                (
+                    vk::PipelineStageFlags::ALL_COMMANDS,
+                    vk::AccessFlags::ACCELERATION_STRUCTURE_READ_KHR,
+                ),
+            );
+        }
+        Ok(())
+    }
+}
+
+fn tlas_geometry(instances: vk::DeviceAddress) -> vk::AccelerationStructureGeometryKHR {
+    vk::AccelerationStructureGeometryKHR::builder()
+        .geometry_type(vk::GeometryTypeKHR::INSTANCES)
+        .geometry(vk::AccelerationStructureGeometryDataKHR {
+            instances: vk::AccelerationStructureGeometryInstancesDataKHR::builder()
+                .array_of_pointers(false)
+                .data(vk::DeviceOrHostAddressConstKHR {
+                    device_address: instances,
+                })
+                .build(),
+        })
+        .build()
+}
@@ -1,22 +1,49 @@
 use crate::{
     command_recorder::QueueType,
     errors::{HandleType, Result},
+    sync::FrameSync,
+    synchronization2::Submission,
     ForwardPipelineHandle, RendererHandle, SwapchainHandle, VkTracerApp,
 };
 use ash::{version::DeviceV1_0, vk};
 use std::slice::from_ref;
 
+mod compute;
+#[cfg(all(feature = "math", feature = "shaderc"))]
+mod debug_draw;
+mod dynamic_rendering;
 mod forward;
+mod graphics_recorder;
+mod offscreen;
+#[cfg(feature = "shaderc")]
+mod overlay;
+mod pipeline_cache;
+mod profiler;
 mod render_plan;
 mod render_target;
 mod renderer;
+#[cfg(feature = "math")]
+mod scene;
 
+pub use compute::ComputePipelineBuilder;
+pub(crate) use compute::*;
+#[cfg(all(feature = "math", feature = "shaderc"))]
+pub use debug_draw::*;
+pub(crate) use dynamic_rendering::*;
 pub(crate) use forward::*;
+pub use forward::{ForwardPipelineBuilder, PushConstants};
+pub use graphics_recorder::{GraphicsPipelineHandle, GraphicsRecorder};
+#[cfg(feature = "shaderc")]
+pub(crate) use overlay::*;
+pub(crate) use profiler::*;
+pub use profiler::{GpuScopeTiming, ProfilerScope};
 pub use render_plan::*;
 pub(crate) use render_target::*;
 pub use renderer::*;
+#[cfg(feature = "math")]
+pub use scene::*;
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug)]
 pub enum RenderablePipelineHandle {
     Forward(ForwardPipelineHandle),
 }
@@ -44,61 +71,231 @@ impl VkTracerApp {
         swapchain: SwapchainHandle,
         render_target_index: u32,
     ) -> Result<bool> {
-        let renderer = storage_access!(self.renderer_storage, renderer, HandleType::Renderer);
-        let swapchain = storage_access!(self.swapchain_storage, swapchain, HandleType::Swapchain);
+        self.render_and_present_with_sync(
+            renderer,
+            swapchain,
+            render_target_index,
+            FrameSync::default(),
+        )
+    }
 
-        let render_semaphore = unsafe {
-            self.device
-                .create_semaphore(&vk::SemaphoreCreateInfo::default(), None)?
+    /// Same as [VkTracerApp::render_and_present], also waiting on and signaling user semaphores.
+    pub fn render_and_present_with_sync(
+        &mut self,
+        renderer: RendererHandle,
+        swapchain: SwapchainHandle,
+        render_target_index: u32,
+        sync: FrameSync,
+    ) -> Result<bool> {
+        let graphics_queue = self.queue_and_pool(QueueType::Graphics)?.0;
+        // Waits on the render semaphore, whichever queue it is
+        let present_queue = self.queue_and_pool(QueueType::Present)?.0;
+        // The fence of this frame was already waited on when acquiring the image, so its
+        // commands can be recorded again
+        let (frame_index, frame) = {
+            let swapchain =
+                storage_access!(self.swapchain_storage, swapchain, HandleType::Swapchain);
+            let frame_index = swapchain.current_frame;
+            (frame_index, swapchain.frames[frame_index])
         };
-
-        // Reset render fence
-        unsafe {
-            // Should return immediately but its a precaution
-            self.device
-                .wait_for_fences(from_ref(&renderer.render_fence), true, u64::MAX)?;
-            self.device.reset_fences(from_ref(&renderer.render_fence))?;
+        self.retire_submissions(Some(frame.in_flight))?;
+        self.resolve_gpu_profilers(frame_index)?;
+        let commands = self.renderer_commands_for_frame(renderer, frame_index)?;
+        let mut user_waits = Vec::with_capacity(sync.wait_semaphores.len());
+        let mut user_wait_stages = Vec::with_capacity(sync.wait_semaphores.len());
+        for (semaphore, stage) in sync.wait_semaphores.iter().copied() {
+            user_waits.push(*storage_access!(
+                self.semaphore_storage,
+                semaphore,
+                HandleType::Semaphore
+            ));
+            user_wait_stages.push(stage);
         }
+        let mut user_signals = Vec::with_capacity(sync.signal_semaphores.len());
+        for semaphore in sync.signal_semaphores.iter().copied() {
+            user_signals.push(*storage_access!(
+                self.semaphore_storage,
+                semaphore,
+                HandleType::Semaphore
+            ));
+        }
+
+        // From here, the deferred work is given back to the scheduler if the frame fails
+        let batch = self.take_graphics_batch()?;
+        let (frame_count, present_time, previous_image_frame) =
+            match self.begin_frame_submission(swapchain, frame_index, render_target_index) {
+                Ok(begun) => begun,
+                Err(err) => {
+                    self.restore_graphics_batch(batch)?;
+                    return Err(err);
+                }
+            };
+
+        // Also wait on the deferred work, which is submitted just before
+        let mut wait_semaphores = vec![frame.image_available];
+        wait_semaphores.extend_from_slice(batch.frame_waits());
+        let mut wait_stages = vec![vk::PipelineStageFlags::ALL_COMMANDS; wait_semaphores.len()];
+        wait_stages[0] = vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT;
+        wait_semaphores.extend(user_waits);
+        wait_stages.extend(user_wait_stages);
 
-        let submit_info = vk::SubmitInfo::builder()
-            .wait_dst_stage_mask(from_ref(&vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT))
-            .wait_semaphores(from_ref(&swapchain.image_available_semaphore))
-            .signal_semaphores(from_ref(&render_semaphore))
-            .command_buffers(from_ref(&renderer.main_commands));
+        // The presentation only waits on the first one
+        let mut signal_semaphores = vec![frame.render_finished];
+        signal_semaphores.extend(user_signals);
 
-        let present_info = vk::PresentInfoKHR::builder()
+        let deferred_submission = Submission {
+            waits: &batch.waits,
+            wait_stages: &batch.wait_stages,
+            commands: &batch.commands,
+            signals: batch.signal.as_ref().map_or(&[][..], from_ref),
+            device_index: None,
+        };
+
+        // With a device group, only one GPU renders and presents this frame (AFR), the one the
+        // image was acquired for
+        let device_index = self
+            .device_group
+            .as_ref()
+            .map(|group| group.afr_device_index(frame_count));
+        let submission = Submission {
+            waits: &wait_semaphores,
+            wait_stages: &wait_stages,
+            commands: from_ref(&commands),
+            signals: &signal_semaphores,
+            device_index,
+        };
+        let device_mask = 1 << device_index.unwrap_or(0);
+
+        // Launch render, along with the deferred work in a single submission
+        let submitted = unsafe {
+            if batch.commands.is_empty() {
+                self.queue_submit(graphics_queue, from_ref(&submission), frame.in_flight)
+            } else {
+                self.queue_submit(
+                    graphics_queue,
+                    &[deferred_submission, submission],
+                    frame.in_flight,
+                )
+            }
+        };
+        if let Err(err) = submitted {
+            self.restore_graphics_batch(batch)?;
+            self.abort_frame_submission(
+                swapchain,
+                frame_index,
+                render_target_index,
+                previous_image_frame,
+            )?;
+            return Err(err);
+        }
+        self.submit_gpu_profilers(frame_index);
+        self.track_graphics_batch(batch, frame.in_flight)?;
+
+        let swapchain = storage_access!(self.swapchain_storage, swapchain, HandleType::Swapchain);
+        let mut present_info = vk::PresentInfoKHR::builder()
             .swapchains(from_ref(&swapchain.handle))
-            .wait_semaphores(from_ref(&render_semaphore))
+            .wait_semaphores(from_ref(&frame.render_finished))
             .image_indices(from_ref(&render_target_index));
 
-        let graphics_queue = self.command_pools.get(&QueueType::Graphics).unwrap().0;
-        let should_recreate_swapchain = unsafe {
-            // Launch render
-            self.device.queue_submit(
-                graphics_queue,
-                from_ref(&submit_info),
-                renderer.render_fence,
-            )?;
+        let mut device_group_present_info = vk::DeviceGroupPresentInfoKHR::builder()
+            .device_masks(from_ref(&device_mask))
+            .mode(swapchain.device_group_mode.unwrap_or_default());
+        if self.device_group.is_some() {
+            present_info = present_info.push_next(&mut device_group_present_info);
+        }
+
+        let mut present_times_info = vk::PresentTimesInfoGOOGLE::builder()
+            .times(present_time.as_ref().map_or(&[][..], from_ref));
+        if present_time.is_some() {
+            present_info = present_info.push_next(&mut present_times_info);
+        }
 
-            match swapchain
-                .loader
-                .queue_present(graphics_queue, &present_info)
-            {
-                Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => true,
-                err @ Err(_) => err?,
-                Ok(is_suboptimal) => is_suboptimal,
+        unsafe {
+            match swapchain.loader.queue_present(present_queue, &present_info) {
+                Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => Ok(true),
+                Err(err) => Err(err.into()),
+                Ok(is_suboptimal) => Ok(is_suboptimal),
             }
-        };
+        }
+    }
+
+    /// Wait until the frame can be submitted, then reset its fence. Returns the number of the
+    /// frame, when to present it and the frame the render target was previously used by.
+    fn begin_frame_submission(
+        &mut self,
+        swapchain: SwapchainHandle,
+        frame_index: usize,
+        render_target_index: u32,
+    ) -> Result<(u64, Option<vk::PresentTimeGOOGLE>, Option<usize>)> {
+        let swapchain =
+            storage_access_mut!(self.swapchain_storage, swapchain, HandleType::Swapchain);
+        let frame = swapchain.frames[frame_index];
 
         unsafe {
-            // Wait for the end of the render
-            self.device
-                .wait_for_fences(from_ref(&renderer.render_fence), true, u64::MAX)?;
+            // Prerecorded commands of the renderer can't be submitted again while a previous
+            // frame is still using them
+            if let Some(previous) = swapchain.images_in_flight[render_target_index as usize] {
+                if previous != frame_index {
+                    self.device.wait_for_fences(
+                        from_ref(&swapchain.frames[previous].in_flight),
+                        true,
+                        u64::MAX,
+                    )?;
+                }
+            }
+        }
 
-            // Now we can free the semaphore
-            self.device.destroy_semaphore(render_semaphore, None);
+        if let Some(display_timing) = self.display_timing.as_ref() {
+            swapchain.display_timing.poll(
+                display_timing,
+                self.device.handle(),
+                swapchain.handle,
+            )?;
         }
 
-        Ok(should_recreate_swapchain)
+        // Last, nothing would signal the fence again if the frame isn't submitted after that
+        unsafe { self.device.reset_fences(from_ref(&frame.in_flight))? };
+        let previous_image_frame =
+            swapchain.images_in_flight[render_target_index as usize].replace(frame_index);
+        swapchain.current_frame = (frame_index + 1) % swapchain.frames.len();
+        let frame_count = swapchain.frame_count;
+        swapchain.frame_count += 1;
+
+        Ok((
+            frame_count,
+            swapchain.display_timing.next_present_time(),
+            previous_image_frame,
+        ))
+    }
+
+    /// Undo [VkTracerApp::begin_frame_submission] when the frame couldn't be submitted. An empty
+    /// submission signals the fence again, otherwise waiting on this frame would never return,
+    /// and consumes the acquire semaphore to reuse it.
+    fn abort_frame_submission(
+        &mut self,
+        swapchain: SwapchainHandle,
+        frame_index: usize,
+        render_target_index: u32,
+        previous_image_frame: Option<usize>,
+    ) -> Result<()> {
+        let graphics_queue = self.queue_and_pool(QueueType::Graphics)?.0;
+        let swapchain =
+            storage_access_mut!(self.swapchain_storage, swapchain, HandleType::Swapchain);
+        let frame = swapchain.frames[frame_index];
+        swapchain.images_in_flight[render_target_index as usize] = previous_image_frame;
+        swapchain.current_frame = frame_index;
+        swapchain.frame_count -= 1;
+
+        unsafe {
+            self.queue_submit(
+                graphics_queue,
+                from_ref(&Submission {
+                    waits: from_ref(&frame.image_available),
+                    wait_stages: &[vk::PipelineStageFlags::ALL_COMMANDS],
+                    ..Default::default()
+                }),
+                frame.in_flight,
+            )
+        }
     }
 }
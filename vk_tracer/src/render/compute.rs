@@ -0,0 +1,151 @@
+use std::{
+    io::{Read, Seek},
+    slice::from_ref,
+};
+
+use ash::{version::DeviceV1_0, vk};
+
+use crate::{
+    errors::{HandleType, Result},
+    render::{push_constants_as_bytes, PushConstants},
+    utils::str_to_cstr,
+    ComputePipelineHandle, DescriptorSetHandle, VkTracerApp,
+};
+
+impl VkTracerApp {
+    pub fn new_compute_pipeline(&mut self) -> ComputePipelineBuilder {
+        ComputePipelineBuilder {
+            app: self,
+            descriptor_sets: Vec::new(),
+            dynamic_offsets: Vec::new(),
+            push_constants: None,
+        }
+    }
+}
+
+pub struct ComputePipelineBuilder<'app> {
+    app: &'app mut VkTracerApp,
+    descriptor_sets: Vec<DescriptorSetHandle>,
+    dynamic_offsets: Vec<u32>,
+    push_constants: Option<Box<[u8]>>,
+}
+
+impl ComputePipelineBuilder<'_> {
+    /// Optional, bound along with the pipeline.
+    pub fn descriptor_sets(mut self, descriptor_sets: &[DescriptorSetHandle]) -> Self {
+        self.descriptor_sets.extend_from_slice(descriptor_sets);
+        self
+    }
+
+    /// Required if the descriptor sets have dynamic buffers, one offset per dynamic descriptor
+    /// in order of set and binding, checked by `build`.
+    pub fn dynamic_offsets(mut self, dynamic_offsets: &[u32]) -> Self {
+        self.dynamic_offsets.extend_from_slice(dynamic_offsets);
+        self
+    }
+
+    /// Optional, the data will be pushed when binding the pipeline, it can be changed before each
+    /// dispatch with [crate::command_recorder::ComputeRecorder::push_constants].
+    pub fn push_constants<P: PushConstants>(mut self, data: &P) -> Result<Self> {
+        self.push_constants = Some(Box::from(push_constants_as_bytes(data)?));
+        Ok(self)
+    }
+
+    pub fn build(self, shader: impl Read + Seek) -> Result<ComputePipelineHandle> {
+        let app = self.app;
+
+        let mut descriptor_layouts = Vec::with_capacity(self.descriptor_sets.len());
+        let mut descriptor_sets = Vec::with_capacity(self.descriptor_sets.len());
+        for handle in self.descriptor_sets.iter().copied() {
+            let set = storage_access!(
+                app.descriptor_set_storage,
+                handle,
+                HandleType::DescriptorSet
+            );
+            descriptor_layouts.push(set.layout);
+            descriptor_sets.push(set.handle);
+        }
+
+        let dynamic_types = app.dynamic_descriptor_types(&self.descriptor_sets)?;
+        app.check_dynamic_offsets(&dynamic_types, &self.dynamic_offsets)?;
+
+        let push_constant_ranges = self
+            .push_constants
+            .iter()
+            .map(|data| {
+                vk::PushConstantRange::builder()
+                    .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                    .offset(0)
+                    .size(data.len() as u32)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+        let pipeline_layout = app.pipeline_layout(&descriptor_layouts, &push_constant_ranges)?;
+
+        let pipeline = ComputePipeline::new(
+            &app.device,
+            app.pipeline_cache,
+            pipeline_layout,
+            descriptor_sets.into_boxed_slice(),
+            self.dynamic_offsets.into_boxed_slice(),
+            self.push_constants,
+            shader,
+        )?;
+
+        Ok(app.compute_pipeline_storage.insert(pipeline))
+    }
+}
+
+pub(crate) struct ComputePipeline {
+    pub(crate) pipeline: vk::Pipeline,
+    /// Owned by the layout cache.
+    pub(crate) pipeline_layout: vk::PipelineLayout,
+    pub(crate) descriptor_sets: Box<[vk::DescriptorSet]>,
+    pub(crate) dynamic_offsets: Box<[u32]>,
+    pub(crate) push_constants: Option<Box<[u8]>>,
+}
+
+impl ComputePipeline {
+    pub fn new(
+        device: &ash::Device,
+        pipeline_cache: vk::PipelineCache,
+        pipeline_layout: vk::PipelineLayout,
+        descriptor_sets: Box<[vk::DescriptorSet]>,
+        dynamic_offsets: Box<[u32]>,
+        push_constants: Option<Box<[u8]>>,
+        mut shader: impl Read + Seek,
+    ) -> Result<Self> {
+        let module = unsafe {
+            let spv = ash::util::read_spv(&mut shader)?;
+            device.create_shader_module(&vk::ShaderModuleCreateInfo::builder().code(&spv), None)?
+        };
+
+        let stage = vk::PipelineShaderStageCreateInfo::builder()
+            .stage(vk::ShaderStageFlags::COMPUTE)
+            .module(module)
+            .name(str_to_cstr("main\0"));
+
+        let pipeline = unsafe {
+            let create_info = vk::ComputePipelineCreateInfo::builder()
+                .stage(stage.build())
+                .layout(pipeline_layout);
+
+            let pipelines = device
+                .create_compute_pipelines(pipeline_cache, from_ref(&create_info), None)
+                .map_err(|(_, err)| err)?;
+            pipelines[0]
+        };
+
+        unsafe {
+            device.destroy_shader_module(module, None);
+        }
+
+        Ok(Self {
+            pipeline,
+            pipeline_layout,
+            descriptor_sets,
+            dynamic_offsets,
+            push_constants,
+        })
+    }
+}
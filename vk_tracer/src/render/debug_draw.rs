@@ -0,0 +1,213 @@
+use crate::{
+    errors::Result,
+    render::{push_constants_as_bytes, GraphicsRecorder, OverlayDescription, OverlayPipeline},
+    RenderPlanHandle, VkTracerApp,
+};
+use ash::vk;
+use log::warn;
+use nalgebra_glm as glm;
+use std::mem::size_of;
+
+const VERTEX_GLSL: &str = include_str!("../utils/shaders/debug_draw.vert");
+const FRAGMENT_GLSL: &str = include_str!("../utils/shaders/debug_draw.frag");
+
+/// Segments of the circles of [DebugDraw::sphere].
+const SPHERE_SEGMENTS: usize = 24;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct DebugVertex {
+    position: glm::Vec3,
+    color: glm::Vec4,
+}
+
+/// Immediate mode lines, boxes, spheres, axes and frustums in world space. Shapes are
+/// accumulated during the frame and drawn on top of the subpass by
+/// [GraphicsRecorder::draw_debug], which also clears them.
+pub struct DebugDraw {
+    vertices: Vec<DebugVertex>,
+    overlay: OverlayPipeline,
+}
+
+impl DebugDraw {
+    pub fn line(&mut self, from: &glm::Vec3, to: &glm::Vec3, color: &glm::Vec4) -> &mut Self {
+        self.vertices.push(DebugVertex {
+            position: *from,
+            color: *color,
+        });
+        self.vertices.push(DebugVertex {
+            position: *to,
+            color: *color,
+        });
+        self
+    }
+
+    /// Axis aligned box.
+    pub fn wire_box(&mut self, min: &glm::Vec3, max: &glm::Vec3, color: &glm::Vec4) -> &mut Self {
+        let corners = (0..8)
+            .map(|i| {
+                glm::vec3(
+                    if i & 1 == 0 { min.x } else { max.x },
+                    if i & 2 == 0 { min.y } else { max.y },
+                    if i & 4 == 0 { min.z } else { max.z },
+                )
+            })
+            .collect::<Vec<_>>();
+        self.cube_edges(&corners, color)
+    }
+
+    /// Three circles, one around each axis.
+    pub fn sphere(&mut self, center: &glm::Vec3, radius: f32, color: &glm::Vec4) -> &mut Self {
+        let point = |axis: usize, angle: f32| {
+            let (sin, cos) = angle.sin_cos();
+            let mut offset = glm::Vec3::zeros();
+            offset[(axis + 1) % 3] = cos * radius;
+            offset[(axis + 2) % 3] = sin * radius;
+            center + offset
+        };
+        let step = std::f32::consts::PI * 2.0 / SPHERE_SEGMENTS as f32;
+        for axis in 0..3 {
+            for i in 0..SPHERE_SEGMENTS {
+                let from = point(axis, i as f32 * step);
+                let to = point(axis, (i + 1) as f32 * step);
+                self.line(&from, &to, color);
+            }
+        }
+        self
+    }
+
+    /// The X, Y and Z axes of the transform in red, green and blue.
+    pub fn axes(&mut self, transform: &glm::Mat4, size: f32) -> &mut Self {
+        let origin = glm::vec4_to_vec3(&(transform * glm::vec4(0.0, 0.0, 0.0, 1.0)));
+        for axis in 0..3 {
+            let mut direction = glm::Vec4::zeros();
+            direction[axis] = size;
+            direction.w = 1.0;
+            let mut color = glm::vec4(0.0, 0.0, 0.0, 1.0);
+            color[axis] = 1.0;
+
+            let end = glm::vec4_to_vec3(&(transform * direction));
+            self.line(&origin, &end, &color);
+        }
+        self
+    }
+
+    /// The volume seen through a view projection with a [0, 1] depth range.
+    pub fn frustum(&mut self, view_proj: &glm::Mat4, color: &glm::Vec4) -> &mut Self {
+        let inverse = glm::inverse(view_proj);
+        let corners = (0..8)
+            .map(|i| {
+                let ndc = glm::vec4(
+                    if i & 1 == 0 { -1.0 } else { 1.0 },
+                    if i & 2 == 0 { -1.0 } else { 1.0 },
+                    if i & 4 == 0 { 0.0 } else { 1.0 },
+                    1.0,
+                );
+                let world = inverse * ndc;
+                glm::vec4_to_vec3(&world) / world.w
+            })
+            .collect::<Vec<_>>();
+        self.cube_edges(&corners, color)
+    }
+
+    /// Corners indexed by their bits, x being the first one.
+    fn cube_edges(&mut self, corners: &[glm::Vec3], color: &glm::Vec4) -> &mut Self {
+        for i in 0..8 {
+            for bit in [1, 2, 4].iter() {
+                if i & bit == 0 {
+                    self.line(&corners[i], &corners[i | bit], color);
+                }
+            }
+        }
+        self
+    }
+
+    /// Forget the shapes of this frame without drawing them.
+    pub fn clear(&mut self) {
+        self.vertices.clear();
+    }
+}
+
+impl VkTracerApp {
+    /// The lines are drawn in the given subpass, which should be the last one of the final
+    /// image. With `depth_test`, they are hidden by the geometry already in the depth attachment
+    /// of the subpass. At most `max_vertices` (2 per line) are drawn per frame.
+    pub fn create_debug_draw(
+        &mut self,
+        render_plan: RenderPlanHandle,
+        subpass: u32,
+        max_vertices: usize,
+        depth_test: bool,
+    ) -> Result<DebugDraw> {
+        let overlay = self.create_overlay_pipeline(
+            render_plan,
+            subpass,
+            max_vertices,
+            &OverlayDescription {
+                name: "debug_draw",
+                vertex_glsl: VERTEX_GLSL,
+                fragment_glsl: FRAGMENT_GLSL,
+                stride: size_of::<DebugVertex>(),
+                attributes: &[
+                    vk::VertexInputAttributeDescription {
+                        location: 0,
+                        binding: 0,
+                        format: vk::Format::R32G32B32_SFLOAT,
+                        offset: 0,
+                    },
+                    vk::VertexInputAttributeDescription {
+                        location: 1,
+                        binding: 0,
+                        format: vk::Format::R32G32B32A32_SFLOAT,
+                        offset: size_of::<glm::Vec3>() as u32,
+                    },
+                ],
+                topology: vk::PrimitiveTopology::LINE_LIST,
+                depth_test,
+                set_layouts: &[],
+                push_constants_size: size_of::<glm::Mat4>() as u32,
+            },
+        )?;
+
+        Ok(DebugDraw {
+            vertices: Vec::with_capacity(max_vertices),
+            overlay,
+        })
+    }
+
+    /// The debug draw must not be used by pending commands.
+    pub fn destroy_debug_draw(&mut self, debug_draw: DebugDraw) -> Result<()> {
+        self.destroy_overlay_pipeline(debug_draw.overlay)
+    }
+}
+
+impl GraphicsRecorder<'_> {
+    /// Draw the shapes accumulated in the debug draw since the last call and clear them, it can
+    /// be called several times per frame. Binds the lines pipeline, a pipeline must be bound
+    /// again to draw anything else.
+    pub fn draw_debug(self, debug_draw: &mut DebugDraw, view_proj: &glm::Mat4) -> Result<Self> {
+        let max_vertices = self.overlay_vertices_left(&debug_draw.overlay);
+        if debug_draw.vertices.len() > max_vertices {
+            warn!(
+                "Too many debug vertices in this frame ({} > {}), the last ones are dropped",
+                debug_draw.vertices.len(),
+                max_vertices
+            );
+        }
+        // Lines come in pairs
+        let count = debug_draw.vertices.len().min(max_vertices) & !1;
+        if count == 0 {
+            debug_draw.clear();
+            return Ok(self);
+        }
+
+        let recorder = self.bind_overlay(
+            &debug_draw.overlay,
+            &debug_draw.vertices[..count],
+            &[],
+            push_constants_as_bytes(view_proj)?,
+        );
+        debug_draw.clear();
+        recorder?.draw(count as u32, 1, 0, 0)
+    }
+}
@@ -0,0 +1,447 @@
+//! # Dynamic rendering
+//! With [crate::setup::VkTracerExtensions::DynamicRendering], render plans don't create render
+//! passes nor framebuffers: each subpass is recorded in its own `vkCmdBeginRenderingKHR` scope
+//! and the layout transitions of the attachments are done with barriers in between.
+//! `VK_KHR_dynamic_rendering` is too recent for our version of ash, so the structures and the
+//! functions we need are declared by hand here, following the Vulkan registry.
+
+use crate::{
+    errors::{Result, VkTracerError},
+    mem::format_aspect,
+    render::{RenderPlan, RenderTarget},
+    VkTracerApp,
+};
+use ash::{
+    version::{DeviceV1_0, DeviceV1_2, InstanceV1_0},
+    vk,
+};
+use std::{ffi::CStr, os::raw::c_void, ptr, slice::from_ref};
+
+pub(crate) fn dynamic_rendering_extension_name() -> &'static CStr {
+    unsafe { CStr::from_bytes_with_nul_unchecked(b"VK_KHR_dynamic_rendering\0") }
+}
+
+const STRUCTURE_TYPE_RENDERING_INFO_KHR: vk::StructureType =
+    vk::StructureType::from_raw(1_000_044_000);
+const STRUCTURE_TYPE_RENDERING_ATTACHMENT_INFO_KHR: vk::StructureType =
+    vk::StructureType::from_raw(1_000_044_001);
+const STRUCTURE_TYPE_PIPELINE_RENDERING_CREATE_INFO_KHR: vk::StructureType =
+    vk::StructureType::from_raw(1_000_044_002);
+const STRUCTURE_TYPE_PHYSICAL_DEVICE_DYNAMIC_RENDERING_FEATURES_KHR: vk::StructureType =
+    vk::StructureType::from_raw(1_000_044_003);
+const STRUCTURE_TYPE_COMMAND_BUFFER_INHERITANCE_RENDERING_INFO_KHR: vk::StructureType =
+    vk::StructureType::from_raw(1_000_044_004);
+
+/// `VK_RENDERING_CONTENTS_SECONDARY_COMMAND_BUFFERS_BIT_KHR`
+const RENDERING_CONTENTS_SECONDARY_COMMAND_BUFFERS: u32 = 0x1;
+
+/// `VkPhysicalDeviceDynamicRenderingFeaturesKHR`
+#[repr(C)]
+pub(crate) struct PhysicalDeviceDynamicRenderingFeatures {
+    s_type: vk::StructureType,
+    p_next: *mut c_void,
+    dynamic_rendering: vk::Bool32,
+}
+
+unsafe impl vk::ExtendsDeviceCreateInfo for PhysicalDeviceDynamicRenderingFeatures {}
+
+impl PhysicalDeviceDynamicRenderingFeatures {
+    pub(crate) fn enabled() -> Self {
+        Self {
+            s_type: STRUCTURE_TYPE_PHYSICAL_DEVICE_DYNAMIC_RENDERING_FEATURES_KHR,
+            p_next: ptr::null_mut(),
+            dynamic_rendering: vk::TRUE,
+        }
+    }
+}
+
+/// `VkPipelineRenderingCreateInfoKHR`
+#[repr(C)]
+pub(crate) struct PipelineRenderingCreateInfo {
+    s_type: vk::StructureType,
+    p_next: *const c_void,
+    view_mask: u32,
+    color_attachment_count: u32,
+    p_color_attachment_formats: *const vk::Format,
+    depth_attachment_format: vk::Format,
+    stencil_attachment_format: vk::Format,
+}
+
+unsafe impl vk::ExtendsGraphicsPipelineCreateInfo for PipelineRenderingCreateInfo {}
+
+/// `VkCommandBufferInheritanceRenderingInfoKHR`
+#[repr(C)]
+pub(crate) struct CommandBufferInheritanceRenderingInfo {
+    s_type: vk::StructureType,
+    p_next: *const c_void,
+    flags: u32,
+    view_mask: u32,
+    color_attachment_count: u32,
+    p_color_attachment_formats: *const vk::Format,
+    depth_attachment_format: vk::Format,
+    stencil_attachment_format: vk::Format,
+    rasterization_samples: vk::SampleCountFlags,
+}
+
+unsafe impl vk::ExtendsCommandBufferInheritanceInfo for CommandBufferInheritanceRenderingInfo {}
+
+/// `VkRenderingAttachmentInfoKHR`
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct RenderingAttachmentInfo {
+    s_type: vk::StructureType,
+    p_next: *const c_void,
+    image_view: vk::ImageView,
+    image_layout: vk::ImageLayout,
+    resolve_mode: vk::ResolveModeFlags,
+    resolve_image_view: vk::ImageView,
+    resolve_image_layout: vk::ImageLayout,
+    load_op: vk::AttachmentLoadOp,
+    store_op: vk::AttachmentStoreOp,
+    clear_value: vk::ClearValue,
+}
+
+/// `VkRenderingInfoKHR`
+#[repr(C)]
+struct RenderingInfo {
+    s_type: vk::StructureType,
+    p_next: *const c_void,
+    flags: u32,
+    render_area: vk::Rect2D,
+    layer_count: u32,
+    view_mask: u32,
+    color_attachment_count: u32,
+    p_color_attachments: *const RenderingAttachmentInfo,
+    p_depth_attachment: *const RenderingAttachmentInfo,
+    p_stencil_attachment: *const RenderingAttachmentInfo,
+}
+
+type PfnCmdBeginRendering =
+    unsafe extern "system" fn(command_buffer: vk::CommandBuffer, info: *const RenderingInfo);
+type PfnCmdEndRendering = unsafe extern "system" fn(command_buffer: vk::CommandBuffer);
+
+pub(crate) struct DynamicRenderingFns {
+    begin_rendering: PfnCmdBeginRendering,
+    end_rendering: PfnCmdEndRendering,
+}
+
+impl DynamicRenderingFns {
+    pub(crate) fn load(instance: &ash::Instance, device: &ash::Device) -> Result<Self> {
+        let extension = dynamic_rendering_extension_name().to_str().unwrap();
+        unsafe {
+            let begin_rendering = instance
+                .get_device_proc_addr(
+                    device.handle(),
+                    b"vkCmdBeginRenderingKHR\0".as_ptr() as *const _,
+                )
+                .ok_or(VkTracerError::ExtensionNotEnabled(extension))?;
+            let end_rendering = instance
+                .get_device_proc_addr(
+                    device.handle(),
+                    b"vkCmdEndRenderingKHR\0".as_ptr() as *const _,
+                )
+                .ok_or(VkTracerError::ExtensionNotEnabled(extension))?;
+            Ok(Self {
+                begin_rendering: std::mem::transmute(begin_rendering),
+                end_rendering: std::mem::transmute(end_rendering),
+            })
+        }
+    }
+}
+
+/// Formats of the attachments of a subpass, given to the pipelines and secondary command
+/// buffers in place of a render pass.
+pub(crate) struct SubpassFormats {
+    colors: Vec<vk::Format>,
+    depth: vk::Format,
+    stencil: vk::Format,
+}
+
+impl SubpassFormats {
+    pub(crate) fn pipeline_info(&self) -> PipelineRenderingCreateInfo {
+        PipelineRenderingCreateInfo {
+            s_type: STRUCTURE_TYPE_PIPELINE_RENDERING_CREATE_INFO_KHR,
+            p_next: ptr::null(),
+            view_mask: 0,
+            color_attachment_count: self.colors.len() as u32,
+            p_color_attachment_formats: self.colors.as_ptr(),
+            depth_attachment_format: self.depth,
+            stencil_attachment_format: self.stencil,
+        }
+    }
+
+    pub(crate) fn inheritance_info(&self) -> CommandBufferInheritanceRenderingInfo {
+        CommandBufferInheritanceRenderingInfo {
+            s_type: STRUCTURE_TYPE_COMMAND_BUFFER_INHERITANCE_RENDERING_INFO_KHR,
+            p_next: ptr::null(),
+            flags: 0,
+            view_mask: 0,
+            color_attachment_count: self.colors.len() as u32,
+            p_color_attachment_formats: self.colors.as_ptr(),
+            depth_attachment_format: self.depth,
+            stencil_attachment_format: self.stencil,
+            rasterization_samples: vk::SampleCountFlags::TYPE_1,
+        }
+    }
+}
+
+impl RenderPlan {
+    /// Without render pass, the subpasses are recorded with dynamic rendering.
+    #[inline]
+    pub(crate) fn is_dynamic(&self) -> bool {
+        self.render_pass == vk::RenderPass::null()
+    }
+
+    pub(crate) fn subpass_formats(&self, subpass: usize) -> SubpassFormats {
+        let subpass = &self.subpasses[subpass];
+        let depth = subpass
+            .depth_stencil_attachment
+            .map_or(vk::Format::UNDEFINED, |i| self.attachments[i].format);
+        SubpassFormats {
+            colors: subpass
+                .color_attachments
+                .iter()
+                .map(|i| self.attachments[*i].format)
+                .collect(),
+            depth: if format_aspect(depth).contains(vk::ImageAspectFlags::DEPTH) {
+                depth
+            } else {
+                vk::Format::UNDEFINED
+            },
+            stencil: if format_aspect(depth).contains(vk::ImageAspectFlags::STENCIL) {
+                depth
+            } else {
+                vk::Format::UNDEFINED
+            },
+        }
+    }
+
+    /// Layout of an attachment during a subpass, if it uses it.
+    fn attachment_layout(&self, subpass: usize, attachment: usize) -> Option<vk::ImageLayout> {
+        let subpass = &self.subpasses[subpass];
+        if subpass.color_attachments.contains(&attachment) {
+            Some(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+        } else if subpass.depth_stencil_attachment == Some(attachment) {
+            Some(subpass.depth_stencil_access.layout())
+        } else {
+            None
+        }
+    }
+
+    /// Layout of an attachment before a subpass, from the last subpass using it.
+    fn previous_layout(&self, subpass: usize, attachment: usize) -> Option<vk::ImageLayout> {
+        (0..subpass)
+            .rev()
+            .find_map(|previous| self.attachment_layout(previous, attachment))
+    }
+
+    fn is_used_after(&self, subpass: usize, attachment: usize) -> bool {
+        (subpass + 1..self.subpasses.len())
+            .any(|next| self.attachment_layout(next, attachment).is_some())
+    }
+}
+
+impl VkTracerApp {
+    /// Begin a subpass of the render plan, the previous one must have been ended with
+    /// [VkTracerApp::cmd_end_subpass].
+    pub(crate) unsafe fn cmd_begin_subpass(
+        &self,
+        commands: vk::CommandBuffer,
+        render_plan: &RenderPlan,
+        render_target: &RenderTarget,
+        subpass: usize,
+        contents: vk::SubpassContents,
+    ) -> Result<()> {
+        let render_area = vk::Rect2D::builder()
+            .offset(vk::Offset2D::default())
+            .extent(render_target.extent)
+            .build();
+
+        if !render_plan.is_dynamic() {
+            if subpass == 0 {
+                self.device.cmd_begin_render_pass2(
+                    commands,
+                    &vk::RenderPassBeginInfo::builder()
+                        .render_pass(render_plan.render_pass)
+                        .framebuffer(render_target.framebuffer)
+                        .render_area(render_area)
+                        .clear_values(&render_plan.clear_values),
+                    &vk::SubpassBeginInfo::builder().contents(contents),
+                );
+            } else {
+                self.device.cmd_next_subpass2(
+                    commands,
+                    &vk::SubpassBeginInfo::builder().contents(contents),
+                    &vk::SubpassEndInfo::default(),
+                );
+            }
+            return Ok(());
+        }
+
+        let fns = self.dynamic_rendering_fns()?;
+        let description = &render_plan.subpasses[subpass];
+        let attachment_info = |i: usize| {
+            let attachment = &render_plan.attachments[i];
+            let previous = render_plan.previous_layout(subpass, i);
+            let layout = render_plan.attachment_layout(subpass, i).unwrap();
+            // Replaces the implicit transitions of the render pass
+            self.cmd_attachment_barrier(
+                commands,
+                render_target,
+                i,
+                previous.unwrap_or(attachment.initial_layout),
+                layout,
+            );
+
+            let stored = render_plan.is_used_after(subpass, i);
+            let info = RenderingAttachmentInfo {
+                s_type: STRUCTURE_TYPE_RENDERING_ATTACHMENT_INFO_KHR,
+                p_next: ptr::null(),
+                image_view: render_target.attachments[i].view,
+                image_layout: layout,
+                resolve_mode: vk::ResolveModeFlags::NONE,
+                resolve_image_view: vk::ImageView::null(),
+                resolve_image_layout: vk::ImageLayout::UNDEFINED,
+                load_op: if previous.is_some() {
+                    vk::AttachmentLoadOp::LOAD
+                } else {
+                    attachment.load_op
+                },
+                store_op: if stored {
+                    vk::AttachmentStoreOp::STORE
+                } else {
+                    attachment.store_op
+                },
+                clear_value: render_plan.clear_values[i],
+            };
+            let stencil_info = RenderingAttachmentInfo {
+                load_op: if previous.is_some() {
+                    vk::AttachmentLoadOp::LOAD
+                } else {
+                    attachment.stencil_load_op
+                },
+                store_op: if stored {
+                    vk::AttachmentStoreOp::STORE
+                } else {
+                    attachment.stencil_store_op
+                },
+                ..info
+            };
+            (info, stencil_info)
+        };
+
+        let color_attachments = description
+            .color_attachments
+            .iter()
+            .map(|i| attachment_info(*i).0)
+            .collect::<Vec<_>>();
+        let formats = render_plan.subpass_formats(subpass);
+        let depth_stencil = description.depth_stencil_attachment.map(attachment_info);
+        let depth = depth_stencil
+            .as_ref()
+            .filter(|_| formats.depth != vk::Format::UNDEFINED)
+            .map_or(ptr::null(), |(depth, _)| depth as *const _);
+        let stencil = depth_stencil
+            .as_ref()
+            .filter(|_| formats.stencil != vk::Format::UNDEFINED)
+            .map_or(ptr::null(), |(_, stencil)| stencil as *const _);
+
+        let info = RenderingInfo {
+            s_type: STRUCTURE_TYPE_RENDERING_INFO_KHR,
+            p_next: ptr::null(),
+            flags: if contents == vk::SubpassContents::SECONDARY_COMMAND_BUFFERS {
+                RENDERING_CONTENTS_SECONDARY_COMMAND_BUFFERS
+            } else {
+                0
+            },
+            render_area,
+            layer_count: 1,
+            view_mask: 0,
+            color_attachment_count: color_attachments.len() as u32,
+            p_color_attachments: color_attachments.as_ptr(),
+            p_depth_attachment: depth,
+            p_stencil_attachment: stencil,
+        };
+        (fns.begin_rendering)(commands, &info);
+        Ok(())
+    }
+
+    /// End a subpass of the render plan, which also ends the render pass after the last one.
+    pub(crate) unsafe fn cmd_end_subpass(
+        &self,
+        commands: vk::CommandBuffer,
+        render_plan: &RenderPlan,
+        render_target: &RenderTarget,
+        subpass: usize,
+    ) -> Result<()> {
+        let last = subpass + 1 == render_plan.subpasses.len();
+        if !render_plan.is_dynamic() {
+            // The next subpass ends this one
+            if last {
+                self.device
+                    .cmd_end_render_pass2(commands, &vk::SubpassEndInfo::default());
+            }
+            return Ok(());
+        }
+
+        (self.dynamic_rendering_fns()?.end_rendering)(commands);
+        if last {
+            for (i, attachment) in render_plan.attachments.iter().enumerate() {
+                if let Some(layout) = render_plan.previous_layout(render_plan.subpasses.len(), i) {
+                    if layout != attachment.final_layout {
+                        self.cmd_attachment_barrier(
+                            commands,
+                            render_target,
+                            i,
+                            layout,
+                            attachment.final_layout,
+                        );
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    unsafe fn cmd_attachment_barrier(
+        &self,
+        commands: vk::CommandBuffer,
+        render_target: &RenderTarget,
+        attachment: usize,
+        old_layout: vk::ImageLayout,
+        new_layout: vk::ImageLayout,
+    ) {
+        let image = render_target.attachments[attachment];
+        let barrier = vk::ImageMemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::MEMORY_WRITE)
+            .dst_access_mask(vk::AccessFlags::MEMORY_READ | vk::AccessFlags::MEMORY_WRITE)
+            .old_layout(old_layout)
+            .new_layout(new_layout)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(image.handle)
+            .subresource_range(
+                vk::ImageSubresourceRange::builder()
+                    .aspect_mask(format_aspect(image.format))
+                    .level_count(1)
+                    .layer_count(1)
+                    .build(),
+            );
+        self.cmd_pipeline_barrier(
+            commands,
+            vk::PipelineStageFlags::ALL_COMMANDS,
+            vk::PipelineStageFlags::ALL_COMMANDS,
+            &[],
+            &[],
+            from_ref(&barrier),
+        );
+    }
+
+    fn dynamic_rendering_fns(&self) -> Result<&DynamicRenderingFns> {
+        self.dynamic_rendering
+            .as_ref()
+            .ok_or(VkTracerError::ExtensionNotEnabled(
+                dynamic_rendering_extension_name().to_str().unwrap(),
+            ))
+    }
+}
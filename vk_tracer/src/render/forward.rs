@@ -3,17 +3,40 @@ use std::{
     slice::from_ref,
 };
 
-use ash::{version::DeviceV1_0, vk, vk::CommandBuffer};
+use ash::{
+    version::{DeviceV1_0, DeviceV1_2},
+    vk,
+    vk::CommandBuffer,
+};
 
 use crate::{
-    errors::{HandleType, Result},
+    errors::{HandleType, Result, VkTracerError},
     mesh::Mesh,
-    render::{RenderPlan, VkRecordable},
+    render::{RenderPlan, SubpassFormats, VkRecordable},
     utils::str_to_cstr,
-    DescriptorSetHandle, ForwardPipelineHandle, MeshHandle, RenderPlanHandle, VkTracerApp,
+    DescriptorSetHandle, ForwardPipelineHandle, IndirectBufferHandle, MeshHandle, RenderPlanHandle,
+    VkTracerApp,
 };
 
 impl VkTracerApp {
+    pub fn new_forward_pipeline(
+        &mut self,
+        render_plan: RenderPlanHandle,
+        subpass: u32,
+        mesh: MeshHandle,
+    ) -> ForwardPipelineBuilder {
+        ForwardPipelineBuilder {
+            app: self,
+            render_plan,
+            subpass,
+            mesh,
+            descriptor_sets: Vec::new(),
+            dynamic_offsets: Vec::new(),
+            push_constants: None,
+            indirect: None,
+        }
+    }
+
     pub fn create_forward_pipeline(
         &mut self,
         render_plan: RenderPlanHandle,
@@ -23,18 +46,219 @@ impl VkTracerApp {
         fragment_shader: impl Read + Seek,
         mesh_handle: MeshHandle,
     ) -> Result<ForwardPipelineHandle> {
-        let mesh = storage_access!(self.mesh_storage, mesh_handle, HandleType::Mesh);
-        let render_plan = storage_access!(
-            self.render_plan_storage,
-            render_plan,
-            HandleType::RenderPlan
+        self.new_forward_pipeline(render_plan, subpass, mesh_handle)
+            .descriptor_sets(descriptor_sets_handles)
+            .build(vertex_shader, fragment_shader)
+    }
+
+    /// Replace the push constants of a pipeline, they must have the same size as the ones declared
+    /// when building it.
+    /// The renderers using this pipeline must be recreated to see the change.
+    pub fn set_forward_pipeline_push_constants<P: PushConstants>(
+        &mut self,
+        pipeline: ForwardPipelineHandle,
+        data: &P,
+    ) -> Result<()> {
+        let data = push_constants_as_bytes(data)?;
+        let pipeline = storage_access_mut!(
+            self.forward_pipeline_storage,
+            pipeline,
+            HandleType::ForwardPipeline
+        );
+
+        let (_, push_constants) = pipeline
+            .push_constants
+            .as_mut()
+            .ok_or(VkTracerError::NoPushConstants)?;
+        if data.len() != push_constants.len() {
+            return Err(VkTracerError::PushConstantsSizeMismatch {
+                expected: push_constants.len(),
+                actual: data.len(),
+            });
+        }
+        push_constants.copy_from_slice(data);
+        Ok(())
+    }
+
+    /// Replace the dynamic offsets of the descriptor sets of a pipeline.
+    /// The renderers using this pipeline must be recreated to see the change.
+    pub fn set_forward_pipeline_dynamic_offsets(
+        &mut self,
+        pipeline: ForwardPipelineHandle,
+        dynamic_offsets: &[u32],
+    ) -> Result<()> {
+        let types = &storage_access!(
+            self.forward_pipeline_storage,
+            pipeline,
+            HandleType::ForwardPipeline
+        )
+        .dynamic_types;
+        self.check_dynamic_offsets(types, dynamic_offsets)?;
+
+        let pipeline = storage_access_mut!(
+            self.forward_pipeline_storage,
+            pipeline,
+            HandleType::ForwardPipeline
         );
+        pipeline.dynamic_offsets = Box::from(dynamic_offsets);
+        Ok(())
+    }
+}
+
+/// Plain data that can be pushed as is, its size must be a multiple of 4.
+///
+/// # Safety
+/// Every byte of the type must be initialized: it must be `#[repr(C)]` or
+/// `#[repr(transparent)]`, made of fields that are [PushConstants] too and without padding.
+pub unsafe trait PushConstants: Copy {}
+
+unsafe impl PushConstants for u32 {}
+unsafe impl PushConstants for i32 {}
+unsafe impl PushConstants for f32 {}
+unsafe impl PushConstants for u64 {}
+unsafe impl PushConstants for i64 {}
+unsafe impl PushConstants for f64 {}
+unsafe impl<P: PushConstants, const N: usize> PushConstants for [P; N] {}
+#[cfg(feature = "math")]
+unsafe impl<T: PushConstants, const R: usize, const C: usize> PushConstants
+    for crate::glm::TMat<T, R, C>
+{
+}
+
+/// Reinterpret plain data as bytes to be used as push constants.
+pub(crate) fn push_constants_as_bytes<P: PushConstants>(data: &P) -> Result<&[u8]> {
+    let size = std::mem::size_of::<P>();
+    if size % 4 != 0 {
+        return Err(VkTracerError::UnalignedPushConstants(size));
+    }
+    // No padding, every byte is initialized
+    Ok(unsafe { std::slice::from_raw_parts(data as *const P as *const u8, size) })
+}
+
+pub struct ForwardPipelineBuilder<'app> {
+    app: &'app mut VkTracerApp,
+    render_plan: RenderPlanHandle,
+    subpass: u32,
+    mesh: MeshHandle,
+    descriptor_sets: Vec<DescriptorSetHandle>,
+    dynamic_offsets: Vec<u32>,
+    push_constants: Option<(vk::ShaderStageFlags, Box<[u8]>)>,
+    indirect: Option<IndirectDraw>,
+}
+
+impl ForwardPipelineBuilder<'_> {
+    /// Optional, a pipeline can work without any descriptor set.
+    pub fn descriptor_sets(mut self, descriptor_sets: &[DescriptorSetHandle]) -> Self {
+        self.descriptor_sets.extend_from_slice(descriptor_sets);
+        self
+    }
+
+    /// Required if the descriptor sets have dynamic buffers, one offset per dynamic descriptor
+    /// in order of set and binding. Offsets must be aligned to
+    /// [VkTracerApp::min_uniform_buffer_offset_alignment] or
+    /// [VkTracerApp::min_storage_buffer_offset_alignment], this is checked by `build`.
+    pub fn dynamic_offsets(mut self, dynamic_offsets: &[u32]) -> Self {
+        self.dynamic_offsets.extend_from_slice(dynamic_offsets);
+        self
+    }
+
+    /// Optional, the data will be pushed before each draw.
+    pub fn push_constants<P: PushConstants>(
+        mut self,
+        stages: vk::ShaderStageFlags,
+        data: &P,
+    ) -> Result<Self> {
+        self.push_constants = Some((stages, Box::from(push_constants_as_bytes(data)?)));
+        Ok(self)
+    }
+
+    /// Optional, take the draw parameters from the first `draw_count` commands of a buffer
+    /// instead of drawing the whole mesh once.
+    pub fn indirect(mut self, buffer: IndirectBufferHandle, draw_count: u32) -> Self {
+        self.indirect = Some(IndirectDraw {
+            buffer,
+            count_buffer: None,
+            max_draw_count: draw_count,
+        });
+        self
+    }
 
-        let mut descriptor_layouts = Vec::with_capacity(descriptor_sets_handles.len());
-        let mut descriptor_sets = Vec::with_capacity(descriptor_sets_handles.len());
-        for handle in descriptor_sets_handles.iter().copied() {
+    /// Like [ForwardPipelineBuilder::indirect] but the amount of draws is read from
+    /// `count_buffer` on the GPU, up to `max_draw_count`.
+    /// Requires [VkTracerApp::supports_draw_indirect_count].
+    pub fn indirect_count(
+        mut self,
+        buffer: IndirectBufferHandle,
+        count_buffer: IndirectBufferHandle,
+        max_draw_count: u32,
+    ) -> Self {
+        self.indirect = Some(IndirectDraw {
+            buffer,
+            count_buffer: Some(count_buffer),
+            max_draw_count,
+        });
+        self
+    }
+
+    pub fn build(
+        self,
+        vertex_shader: impl Read + Seek,
+        fragment_shader: impl Read + Seek,
+    ) -> Result<ForwardPipelineHandle> {
+        let app = self.app;
+        if let Some(IndirectDraw {
+            count_buffer: Some(_),
+            ..
+        }) = self.indirect
+        {
+            if !app.supports_draw_indirect_count() {
+                return Err(VkTracerError::ExtensionNotEnabled(
+                    "VK_KHR_draw_indirect_count",
+                ));
+            }
+        }
+        if let Some(indirect) = self.indirect {
+            let features = &app.adapter.info.physical_device_info.features;
+            if indirect.max_draw_count > 1 && features.multi_draw_indirect != vk::TRUE {
+                return Err(VkTracerError::FeatureNotSupported("multiDrawIndirect"));
+            }
+
+            let buffer = storage_access!(
+                app.indirect_buffer_storage,
+                indirect.buffer,
+                HandleType::IndirectBuffer
+            );
+            let size = indirect.max_draw_count as vk::DeviceSize
+                * std::mem::size_of::<vk::DrawIndexedIndirectCommand>() as vk::DeviceSize;
+            if size > buffer.real_size {
+                return Err(VkTracerError::IndirectBufferTooSmall {
+                    draw_count: indirect.max_draw_count,
+                    size: buffer.real_size,
+                });
+            }
+        }
+
+        if let Some((_, data)) = self.push_constants.as_ref() {
+            let max = app
+                .adapter
+                .info
+                .physical_device_info
+                .properties
+                .limits
+                .max_push_constants_size;
+            if data.len() > max as usize {
+                return Err(VkTracerError::PushConstantsTooLarge {
+                    size: data.len(),
+                    max,
+                });
+            }
+        }
+
+        let mut descriptor_layouts = Vec::with_capacity(self.descriptor_sets.len());
+        let mut descriptor_sets = Vec::with_capacity(self.descriptor_sets.len());
+        for handle in self.descriptor_sets.iter().copied() {
             let set = storage_access!(
-                self.descriptor_set_storage,
+                app.descriptor_set_storage,
                 handle,
                 HandleType::DescriptorSet
             );
@@ -42,36 +266,82 @@ impl VkTracerApp {
             descriptor_sets.push(set.handle);
         }
 
+        let dynamic_types = app.dynamic_descriptor_types(&self.descriptor_sets)?;
+        app.check_dynamic_offsets(&dynamic_types, &self.dynamic_offsets)?;
+
+        let push_constant_ranges = self
+            .push_constants
+            .iter()
+            .map(|(stages, data)| {
+                vk::PushConstantRange::builder()
+                    .stage_flags(*stages)
+                    .offset(0)
+                    .size(data.len() as u32)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+        let pipeline_layout = app.pipeline_layout(&descriptor_layouts, &push_constant_ranges)?;
+
+        let mesh = storage_access!(app.mesh_storage, self.mesh, HandleType::Mesh);
+        let render_plan = storage_access!(
+            app.render_plan_storage,
+            self.render_plan,
+            HandleType::RenderPlan
+        );
+
         let pipeline = ForwardPipeline::new(
-            &self.device,
+            &app.device,
+            app.pipeline_cache,
             render_plan,
-            subpass,
-            &descriptor_layouts,
+            self.subpass,
+            pipeline_layout,
             descriptor_sets.into_boxed_slice(),
+            dynamic_types,
+            self.dynamic_offsets.into_boxed_slice(),
+            self.push_constants,
+            self.indirect,
             vertex_shader,
             fragment_shader,
-            mesh_handle,
+            self.mesh,
             mesh,
         )?;
 
-        Ok(self.forward_pipeline_storage.insert(pipeline))
+        Ok(app.forward_pipeline_storage.insert(pipeline))
     }
 }
 
+#[derive(Copy, Clone)]
+pub(crate) struct IndirectDraw {
+    buffer: IndirectBufferHandle,
+    count_buffer: Option<IndirectBufferHandle>,
+    max_draw_count: u32,
+}
+
 pub(crate) struct ForwardPipeline {
     pub(crate) pipeline: vk::Pipeline,
+    /// Owned by the layout cache.
     pub(crate) pipeline_layout: vk::PipelineLayout,
     pub(crate) descriptor_sets: Box<[vk::DescriptorSet]>,
+    /// Type of the descriptor of each dynamic offset.
+    pub(crate) dynamic_types: Box<[vk::DescriptorType]>,
+    pub(crate) dynamic_offsets: Box<[u32]>,
+    pub(crate) push_constants: Option<(vk::ShaderStageFlags, Box<[u8]>)>,
+    pub(crate) indirect: Option<IndirectDraw>,
     pub(crate) mesh: MeshHandle,
 }
 
 impl ForwardPipeline {
     pub fn new(
         device: &ash::Device,
+        pipeline_cache: vk::PipelineCache,
         render_plan: &RenderPlan,
         subpass: u32,
-        descriptor_layouts: &[vk::DescriptorSetLayout],
+        pipeline_layout: vk::PipelineLayout,
         descriptor_sets: Box<[vk::DescriptorSet]>,
+        dynamic_types: Box<[vk::DescriptorType]>,
+        dynamic_offsets: Box<[u32]>,
+        push_constants: Option<(vk::ShaderStageFlags, Box<[u8]>)>,
+        indirect: Option<IndirectDraw>,
         mut vertex_shader: impl Read + Seek,
         mut fragment_shader: impl Read + Seek,
         mesh_handle: MeshHandle,
@@ -100,8 +370,8 @@ impl ForwardPipeline {
         let stages = [stage_vertex.build(), stage_fragment.build()];
 
         let vertex_input_info = vk::PipelineVertexInputStateCreateInfo::builder()
-            .vertex_binding_descriptions(mesh.vertex_desc.1)
-            .vertex_attribute_descriptions(mesh.vertex_desc.2);
+            .vertex_binding_descriptions(&mesh.vertex_desc.1)
+            .vertex_attribute_descriptions(&mesh.vertex_desc.2);
 
         let input_assembly_info = vk::PipelineInputAssemblyStateCreateInfo::builder()
             .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
@@ -123,9 +393,16 @@ impl ForwardPipeline {
             .alpha_to_coverage_enable(false)
             .alpha_to_one_enable(false);
 
+        // Writing a read only depth attachment is invalid
+        let depth_write = render_plan
+            .subpasses
+            .get(subpass as usize)
+            .map_or(true, |subpass| {
+                subpass.depth_stencil_access.depth_writable()
+            });
         let depth_stencil_info = vk::PipelineDepthStencilStateCreateInfo::builder()
             .depth_test_enable(true)
-            .depth_write_enable(true)
+            .depth_write_enable(depth_write)
             .depth_compare_op(vk::CompareOp::LESS)
             .depth_bounds_test_enable(false)
             .min_depth_bounds(0.0)
@@ -150,17 +427,14 @@ impl ForwardPipeline {
         let dynamic_state = vk::PipelineDynamicStateCreateInfo::builder()
             .dynamic_states(&[vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR]);
 
-        let pipeline_layout = unsafe {
-            device.create_pipeline_layout(
-                &vk::PipelineLayoutCreateInfo::builder()
-                    .set_layouts(descriptor_layouts)
-                    .push_constant_ranges(&[]),
-                None,
-            )?
-        };
+        // Replaces the render pass with dynamic rendering
+        let subpass_formats = Some(render_plan)
+            .filter(|plan| plan.is_dynamic())
+            .map(|plan| plan.subpass_formats(subpass as usize));
+        let mut rendering_info = subpass_formats.as_ref().map(SubpassFormats::pipeline_info);
 
         let pipeline = unsafe {
-            let create_info = vk::GraphicsPipelineCreateInfo::builder()
+            let mut create_info = vk::GraphicsPipelineCreateInfo::builder()
                 .stages(&stages)
                 .vertex_input_state(&vertex_input_info)
                 .input_assembly_state(&input_assembly_info)
@@ -173,9 +447,12 @@ impl ForwardPipeline {
                 .layout(pipeline_layout)
                 .render_pass(render_plan.render_pass)
                 .subpass(subpass);
+            if let Some(rendering_info) = rendering_info.as_mut() {
+                create_info = create_info.subpass(0).push_next(rendering_info);
+            }
 
             let pipelines = device
-                .create_graphics_pipelines(vk::PipelineCache::null(), from_ref(&create_info), None)
+                .create_graphics_pipelines(pipeline_cache, from_ref(&create_info), None)
                 .map_err(|(_, err)| err)?;
             pipelines[0]
         };
@@ -189,6 +466,10 @@ impl ForwardPipeline {
             pipeline,
             pipeline_layout,
             descriptor_sets,
+            dynamic_types,
+            dynamic_offsets,
+            push_constants,
+            indirect,
             mesh: mesh_handle,
         })
     }
@@ -200,15 +481,29 @@ impl VkRecordable for ForwardPipeline {
         app: &VkTracerApp,
         viewport: vk::Extent2D,
         commands: CommandBuffer,
+    ) -> Result<()> {
+        let push_constants = self.push_constants.as_ref().map(|(_, data)| &**data);
+        self.record_commands_with_push_constants(app, viewport, commands, push_constants)
+    }
+}
+
+impl ForwardPipeline {
+    /// Same as [VkRecordable::record_commands] but with other push constants than the stored ones.
+    pub(crate) unsafe fn record_commands_with_push_constants(
+        &self,
+        app: &VkTracerApp,
+        viewport: vk::Extent2D,
+        commands: CommandBuffer,
+        push_constants: Option<&[u8]>,
     ) -> Result<()> {
         let mesh = storage_access!(app.mesh_storage, self.mesh, HandleType::Mesh);
 
+        let vertex_buffers = mesh.vertex_buffers();
         app.device.cmd_bind_vertex_buffers(
             commands,
             0,
-            from_ref(&mesh.vertices.buffer),
-            &[0],
-            //from_ref(&(mesh.vertices.info.get_offset() as vk::DeviceSize)),
+            &vertex_buffers,
+            &vec![0; vertex_buffers.len()],
         );
 
         app.device.cmd_bind_index_buffer(
@@ -226,10 +521,15 @@ impl VkRecordable for ForwardPipeline {
                 self.pipeline_layout,
                 0,
                 &self.descriptor_sets,
-                &[],
+                &self.dynamic_offsets,
             );
         }
 
+        if let (Some((stages, _)), Some(data)) = (self.push_constants.as_ref(), push_constants) {
+            app.device
+                .cmd_push_constants(commands, self.pipeline_layout, *stages, 0, data);
+        }
+
         app.device
             .cmd_bind_pipeline(commands, vk::PipelineBindPoint::GRAPHICS, self.pipeline);
 
@@ -257,8 +557,42 @@ impl VkRecordable for ForwardPipeline {
             ),
         );
 
-        app.device
-            .cmd_draw_indexed(commands, mesh.indices_len, 1, 0, 0, 1);
+        if let Some(indirect) = self.indirect {
+            let buffer = storage_access!(
+                app.indirect_buffer_storage,
+                indirect.buffer,
+                HandleType::IndirectBuffer
+            );
+            let stride = std::mem::size_of::<vk::DrawIndexedIndirectCommand>() as u32;
+
+            if let Some(count_buffer) = indirect.count_buffer {
+                let count_buffer = storage_access!(
+                    app.indirect_buffer_storage,
+                    count_buffer,
+                    HandleType::IndirectBuffer
+                );
+                app.device.cmd_draw_indexed_indirect_count(
+                    commands,
+                    buffer.buffer,
+                    0,
+                    count_buffer.buffer,
+                    0,
+                    indirect.max_draw_count,
+                    stride,
+                );
+            } else {
+                app.device.cmd_draw_indexed_indirect(
+                    commands,
+                    buffer.buffer,
+                    0,
+                    indirect.max_draw_count,
+                    stride,
+                );
+            }
+        } else {
+            app.device
+                .cmd_draw_indexed(commands, mesh.indices_len, 1, 0, 0, 1);
+        }
 
         Ok(())
     }
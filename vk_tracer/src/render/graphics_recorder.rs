@@ -0,0 +1,436 @@
+use crate::{
+    errors::{HandleType, Result, VkTracerError},
+    mesh::{BatchedMesh, Mesh},
+    render::{push_constants_as_bytes, FrameRecorder, PushConstants},
+    DescriptorSetHandle, ForwardPipelineHandle, MeshHandle, VkTracerApp,
+};
+use ash::{version::DeviceV1_0, vk};
+use std::slice::from_ref;
+
+impl FrameRecorder<'_> {
+    /// Record custom draws in the current subpass, instead of the fixed commands of
+    /// [FrameRecorder::execute_pipeline].
+    pub fn graphics(&mut self) -> GraphicsRecorder {
+        GraphicsRecorder {
+            app: self.app,
+            commands: self.commands,
+            extent: self.extent,
+            frame_index: self.frame_index,
+            recording: self.recording,
+            bound_pipeline: None,
+            bound_mesh: None,
+            indices_bound: false,
+        }
+    }
+}
+
+/// A pipeline [GraphicsRecorder::bind_pipeline] can bind.
+#[derive(Copy, Clone, Debug)]
+pub enum GraphicsPipelineHandle {
+    Forward(ForwardPipelineHandle),
+    /// Created by the application on the device of the app, compatible with the subpass being
+    /// recorded. Nothing but the pipeline itself is bound.
+    Raw {
+        pipeline: vk::Pipeline,
+        layout: vk::PipelineLayout,
+        /// The stages of the push constant range of the layout, if any.
+        push_constant_stages: Option<vk::ShaderStageFlags>,
+    },
+}
+
+impl From<ForwardPipelineHandle> for GraphicsPipelineHandle {
+    fn from(handle: ForwardPipelineHandle) -> Self {
+        GraphicsPipelineHandle::Forward(handle)
+    }
+}
+
+/// Draws inside the render pass, nothing is bound at first.
+pub struct GraphicsRecorder<'r> {
+    pub(crate) app: &'r VkTracerApp,
+    pub(crate) commands: vk::CommandBuffer,
+    pub(crate) extent: vk::Extent2D,
+    pub(crate) frame_index: usize,
+    pub(crate) recording: u64,
+    pub(crate) bound_pipeline: Option<(vk::PipelineLayout, Option<vk::ShaderStageFlags>)>,
+    pub(crate) bound_mesh: Option<&'r Mesh>,
+    pub(crate) indices_bound: bool,
+}
+
+impl GraphicsRecorder<'_> {
+    /// Bind the pipeline, along with the descriptor sets and push constants of a forward
+    /// pipeline but not its mesh. The viewport and scissor are reset to cover the whole render
+    /// target.
+    pub fn bind_pipeline(mut self, pipeline: impl Into<GraphicsPipelineHandle>) -> Result<Self> {
+        let pipeline = pipeline.into();
+
+        // Each pipeline gets its own label region
+        if self.bound_pipeline.is_some() {
+            self.app.end_debug_label(self.commands);
+        }
+        self.app
+            .begin_debug_label(self.commands, format_args!("{:?}", pipeline));
+
+        let pipeline = match pipeline {
+            GraphicsPipelineHandle::Forward(pipeline) => pipeline,
+            GraphicsPipelineHandle::Raw {
+                pipeline,
+                layout,
+                push_constant_stages,
+            } => {
+                unsafe {
+                    self.app.device.cmd_bind_pipeline(
+                        self.commands,
+                        vk::PipelineBindPoint::GRAPHICS,
+                        pipeline,
+                    );
+                }
+                self.bound_pipeline = Some((layout, push_constant_stages));
+                return Ok(self.reset_viewport_and_scissor());
+            }
+        };
+        let pipeline = storage_access!(
+            self.app.forward_pipeline_storage,
+            pipeline,
+            HandleType::ForwardPipeline
+        );
+        let device = &self.app.device;
+
+        unsafe {
+            device.cmd_bind_pipeline(
+                self.commands,
+                vk::PipelineBindPoint::GRAPHICS,
+                pipeline.pipeline,
+            );
+
+            if !pipeline.descriptor_sets.is_empty() {
+                device.cmd_bind_descriptor_sets(
+                    self.commands,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    pipeline.pipeline_layout,
+                    0,
+                    &pipeline.descriptor_sets,
+                    &pipeline.dynamic_offsets,
+                );
+            }
+
+            if let Some((stages, data)) = pipeline.push_constants.as_ref() {
+                device.cmd_push_constants(
+                    self.commands,
+                    pipeline.pipeline_layout,
+                    *stages,
+                    0,
+                    data,
+                );
+            }
+        }
+
+        self.bound_pipeline = Some((
+            pipeline.pipeline_layout,
+            pipeline.push_constants.as_ref().map(|(stages, _)| *stages),
+        ));
+
+        Ok(self.reset_viewport_and_scissor())
+    }
+
+    /// Cover the whole render target.
+    pub(crate) fn reset_viewport_and_scissor(self) -> Self {
+        let extent = self.extent;
+        self.set_viewport(
+            vk::Viewport::builder()
+                .width(extent.width as f32)
+                .height(extent.height as f32)
+                .min_depth(0.0)
+                .max_depth(1.0)
+                .build(),
+        )
+        .set_scissor(
+            vk::Rect2D::builder()
+                .offset(vk::Offset2D::default())
+                .extent(extent)
+                .build(),
+        )
+    }
+
+    /// Replace the descriptor sets of the bound pipeline, starting at set `first_set`.
+    /// The sets must have the same layouts as the ones given to the pipeline.
+    pub fn bind_descriptor_sets(
+        self,
+        first_set: u32,
+        descriptor_sets: &[DescriptorSetHandle],
+    ) -> Result<Self> {
+        self.bind_descriptor_sets_with_offsets(first_set, descriptor_sets, &[])
+    }
+
+    /// Same as [GraphicsRecorder::bind_descriptor_sets], with one offset per dynamic buffer
+    /// of the sets, typically to select the data of the next object in a larger buffer.
+    pub fn bind_descriptor_sets_with_offsets(
+        self,
+        first_set: u32,
+        descriptor_sets: &[DescriptorSetHandle],
+        dynamic_offsets: &[u32],
+    ) -> Result<Self> {
+        let (layout, _) = self.bound_pipeline.ok_or(VkTracerError::NoPipelineBound)?;
+        let dynamic_types = self.app.dynamic_descriptor_types(descriptor_sets)?;
+        self.app
+            .check_dynamic_offsets(&dynamic_types, dynamic_offsets)?;
+
+        let mut sets = Vec::with_capacity(descriptor_sets.len());
+        for handle in descriptor_sets.iter().copied() {
+            sets.push(
+                storage_access!(
+                    self.app.descriptor_set_storage,
+                    handle,
+                    HandleType::DescriptorSet
+                )
+                .handle,
+            );
+        }
+
+        unsafe {
+            self.app.device.cmd_bind_descriptor_sets(
+                self.commands,
+                vk::PipelineBindPoint::GRAPHICS,
+                layout,
+                first_set,
+                &sets,
+                dynamic_offsets,
+            );
+        }
+        Ok(self)
+    }
+
+    /// Replace the push constants of the bound pipeline for the next draws.
+    pub fn push_constants<P: PushConstants>(self, data: &P) -> Result<Self> {
+        let (layout, stages) = self.bound_pipeline.ok_or(VkTracerError::NoPipelineBound)?;
+        let stages = stages.ok_or(VkTracerError::NoPushConstants)?;
+
+        unsafe {
+            self.app.device.cmd_push_constants(
+                self.commands,
+                layout,
+                stages,
+                0,
+                push_constants_as_bytes(data)?,
+            );
+        }
+        Ok(self)
+    }
+
+    /// Bind the vertex and index buffers of a mesh, its vertex type must match the one of
+    /// the pipelines drawing it.
+    pub fn bind_mesh(mut self, handle: MeshHandle) -> Result<Self> {
+        let mesh = storage_access!(self.app.mesh_storage, handle, HandleType::Mesh);
+
+        let vertex_buffers = mesh.vertex_buffers();
+        unsafe {
+            self.app.device.cmd_bind_vertex_buffers(
+                self.commands,
+                0,
+                &vertex_buffers,
+                &vec![0; vertex_buffers.len()],
+            );
+            self.app.device.cmd_bind_index_buffer(
+                self.commands,
+                mesh.indices.buffer,
+                0,
+                mesh.index_ty.1,
+            );
+        }
+
+        self.bound_mesh = Some(mesh);
+        self.indices_bound = true;
+        Ok(self)
+    }
+
+    /// Bind only some vertex streams of a mesh created with
+    /// [VkTracerApp::create_mesh_streams], each to its own binding, along with its indices.
+    pub fn bind_mesh_streams(mut self, handle: MeshHandle, streams: &[u32]) -> Result<Self> {
+        let mesh = storage_access!(self.app.mesh_storage, handle, HandleType::Mesh);
+        let vertex_buffers = mesh.vertex_buffers();
+
+        unsafe {
+            for stream in streams.iter().copied() {
+                let buffer = vertex_buffers
+                    .get(stream as usize)
+                    .ok_or(VkTracerError::InvalidVertexStreams)?;
+                self.app.device.cmd_bind_vertex_buffers(
+                    self.commands,
+                    stream,
+                    from_ref(buffer),
+                    &[0],
+                );
+            }
+            self.app.device.cmd_bind_index_buffer(
+                self.commands,
+                mesh.indices.buffer,
+                0,
+                mesh.index_ty.1,
+            );
+        }
+
+        self.bound_mesh = Some(mesh);
+        self.indices_bound = true;
+        Ok(self)
+    }
+
+    /// Bind buffers created by the application at their offsets, from `first_binding`. They
+    /// replace the vertex buffers of the bound mesh, whose helpers can't be used anymore.
+    pub fn bind_vertex_buffers(
+        mut self,
+        first_binding: u32,
+        buffers: &[(vk::Buffer, vk::DeviceSize)],
+    ) -> Self {
+        let (buffers, offsets): (Vec<_>, Vec<_>) = buffers.iter().copied().unzip();
+        unsafe {
+            self.app.device.cmd_bind_vertex_buffers(
+                self.commands,
+                first_binding,
+                &buffers,
+                &offsets,
+            );
+        }
+        self.bound_mesh = None;
+        self
+    }
+
+    /// Bind an index buffer created by the application, for [GraphicsRecorder::draw_indexed].
+    pub fn bind_index_buffer(
+        mut self,
+        buffer: vk::Buffer,
+        offset: vk::DeviceSize,
+        index_type: vk::IndexType,
+    ) -> Self {
+        unsafe {
+            self.app
+                .device
+                .cmd_bind_index_buffer(self.commands, buffer, offset, index_type);
+        }
+        self.bound_mesh = None;
+        self.indices_bound = true;
+        self
+    }
+
+    pub fn set_viewport(self, viewport: vk::Viewport) -> Self {
+        unsafe {
+            self.app
+                .device
+                .cmd_set_viewport(self.commands, 0, from_ref(&viewport));
+        }
+        self
+    }
+
+    pub fn set_scissor(self, scissor: vk::Rect2D) -> Self {
+        unsafe {
+            self.app
+                .device
+                .cmd_set_scissor(self.commands, 0, from_ref(&scissor));
+        }
+        self
+    }
+
+    /// Draw all the indices of the bound mesh.
+    pub fn draw_mesh(self, instance_count: u32) -> Result<Self> {
+        let index_count = self
+            .bound_mesh
+            .ok_or(VkTracerError::NoMeshBound)?
+            .indices_len;
+        self.draw_indexed(index_count, instance_count, 0, 0, 0)
+    }
+
+    /// Draw the level of detail of the bound mesh for an object at `distance` from the camera,
+    /// the whole mesh if it has no levels.
+    pub fn draw_mesh_at_distance(self, distance: f32, instance_count: u32) -> Result<Self> {
+        let lod = self
+            .bound_mesh
+            .ok_or(VkTracerError::NoMeshBound)?
+            .lod_for_distance(distance);
+        self.draw_indexed(lod.index_count, instance_count, lod.first_index, 0, 0)
+    }
+
+    /// Draw one mesh of a [crate::mesh::MeshBatch], the mesh of the batch must be bound.
+    pub fn draw_batched(self, mesh: &BatchedMesh, instance_count: u32) -> Result<Self> {
+        self.draw_indexed(
+            mesh.index_count,
+            instance_count,
+            mesh.first_index,
+            mesh.vertex_offset,
+            0,
+        )
+    }
+
+    /// Draw the bound mesh only if its bounds placed by `model` are in the frustum, to skip
+    /// the objects out of view while recording.
+    #[cfg(feature = "camera")]
+    pub fn draw_mesh_if_visible(
+        self,
+        frustum: &crate::utils::Frustum,
+        model: &nalgebra_glm::Mat4,
+        instance_count: u32,
+    ) -> Result<Self> {
+        let mesh = self.bound_mesh.ok_or(VkTracerError::NoMeshBound)?;
+        match mesh.bounds {
+            Some(bounds) if !frustum.is_visible(&bounds, model) => Ok(self),
+            _ => self.draw_mesh(instance_count),
+        }
+    }
+
+    pub fn draw_indexed(
+        self,
+        index_count: u32,
+        instance_count: u32,
+        first_index: u32,
+        vertex_offset: i32,
+        first_instance: u32,
+    ) -> Result<Self> {
+        if self.bound_pipeline.is_none() {
+            return Err(VkTracerError::NoPipelineBound);
+        }
+        if !self.indices_bound {
+            return Err(VkTracerError::NoMeshBound);
+        }
+
+        unsafe {
+            self.app.device.cmd_draw_indexed(
+                self.commands,
+                index_count,
+                instance_count,
+                first_index,
+                vertex_offset,
+                first_instance,
+            );
+        }
+        Ok(self)
+    }
+
+    /// Draw without indices, the bound vertices are used in order.
+    pub fn draw(
+        self,
+        vertex_count: u32,
+        instance_count: u32,
+        first_vertex: u32,
+        first_instance: u32,
+    ) -> Result<Self> {
+        if self.bound_pipeline.is_none() {
+            return Err(VkTracerError::NoPipelineBound);
+        }
+
+        unsafe {
+            self.app.device.cmd_draw(
+                self.commands,
+                vertex_count,
+                instance_count,
+                first_vertex,
+                first_instance,
+            );
+        }
+        Ok(self)
+    }
+}
+
+impl Drop for GraphicsRecorder<'_> {
+    fn drop(&mut self) {
+        if self.bound_pipeline.is_some() {
+            self.app.end_debug_label(self.commands);
+        }
+    }
+}
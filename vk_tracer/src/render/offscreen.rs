@@ -0,0 +1,247 @@
+//! # Offscreen rendering
+//! Render targets that aren't backed by a swapchain, for headless rendering (tests, thumbnails).
+//! The color image must be the first attachment of a render plan created with
+//! [crate::render::RenderPlanBuilder::add_color_attachment_offscreen], given the image of
+//! [VkTracerApp::get_offscreen_target].
+
+use crate::{
+    command_recorder::QueueType,
+    errors::{HandleType, Result, VkTracerError},
+    mem::{
+        texel_size, ImageDescription, ImageViewFatHandle, RawBufferAllocation, RawImageAllocation,
+        Texture,
+    },
+    synchronization2::Submission,
+    OffscreenTargetHandle, RendererHandle, VkTracerApp,
+};
+use ash::{version::DeviceV1_0, vk};
+use std::slice::from_ref;
+
+impl VkTracerApp {
+    /// Create a color image that can be rendered to without any swapchain and then read back
+    /// with [VkTracerApp::read_offscreen_target].
+    pub fn create_offscreen_target(
+        &mut self,
+        extent: (u32, u32),
+        format: vk::Format,
+    ) -> Result<OffscreenTargetHandle> {
+        texel_size(format).ok_or(VkTracerError::NoSuitableImageFormat)?;
+
+        let image = RawImageAllocation::new(
+            &self.vma,
+            &ImageDescription {
+                ty: vk::ImageType::TYPE_2D,
+                extent: vk::Extent3D::builder()
+                    .width(extent.0)
+                    .height(extent.1)
+                    .depth(1)
+                    .build(),
+                tiling: vk::ImageTiling::OPTIMAL,
+                format,
+                usage: vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC,
+                array_layers: 1,
+                mip_levels: 1,
+                lazily_allocated: false,
+            },
+        )?;
+
+        let view = match image.fullscreen_view(&self.device, vk::ImageAspectFlags::COLOR) {
+            Ok(view) => view,
+            Err(err) => {
+                image.destroy(&self.vma)?;
+                return Err(err);
+            }
+        };
+
+        Ok(self
+            .offscreen_target_storage
+            .insert(Texture { image, view }))
+    }
+
+    /// The image to give to the render plan.
+    pub fn get_offscreen_target(
+        &self,
+        target: OffscreenTargetHandle,
+    ) -> Result<ImageViewFatHandle> {
+        let target = storage_access!(
+            self.offscreen_target_storage,
+            target,
+            HandleType::OffscreenTarget
+        );
+        Ok(ImageViewFatHandle {
+            handle: target.image.handle,
+            view: target.view,
+            format: target.image.format,
+            extent: vk::Extent2D::builder()
+                .width(target.image.extent.width)
+                .height(target.image.extent.height)
+                .build(),
+        })
+    }
+
+    /// The target must not be used by pending commands nor by a render target anymore.
+    pub fn destroy_offscreen_target(&mut self, target: OffscreenTargetHandle) -> Result<()> {
+        let target = self
+            .offscreen_target_storage
+            .remove(target)
+            .ok_or(VkTracerError::InvalidHandle(HandleType::OffscreenTarget))?;
+        unsafe {
+            self.device.destroy_image_view(target.view, None);
+        }
+        target.image.destroy(&self.vma)?;
+        Ok(())
+    }
+
+    /// Execute a renderer and wait for it to finish, no presentation involved.
+    pub fn render_offscreen(&mut self, renderer: RendererHandle) -> Result<()> {
+        let graphics_queue = self.queue_and_pool(QueueType::Graphics)?.0;
+        let commands = unsafe {
+            // The first frame commands may still be in use by a presented frame
+            self.device.queue_wait_idle(graphics_queue)?;
+            self.resolve_gpu_profilers(0)?;
+            self.renderer_commands_for_frame(renderer, 0)?
+        };
+
+        let batch = self.take_graphics_batch()?;
+
+        unsafe {
+            let fence = self
+                .device
+                .create_fence(&vk::FenceCreateInfo::default(), None)?;
+
+            let deferred_submission = Submission {
+                waits: &batch.waits,
+                wait_stages: &batch.wait_stages,
+                commands: &batch.commands,
+                signals: batch.signal.as_ref().map_or(&[][..], from_ref),
+                device_index: None,
+            };
+            let wait_stages = vec![vk::PipelineStageFlags::ALL_COMMANDS; batch.frame_waits().len()];
+            let submission = Submission {
+                waits: batch.frame_waits(),
+                wait_stages: &wait_stages,
+                commands: from_ref(&commands),
+                ..Default::default()
+            };
+
+            let submissions = [deferred_submission, submission];
+            let submissions = if batch.commands.is_empty() {
+                &submissions[1..]
+            } else {
+                &submissions[..]
+            };
+            if let Err(err) = self.queue_submit(graphics_queue, submissions, fence) {
+                self.device.destroy_fence(fence, None);
+                self.restore_graphics_batch(batch)?;
+                return Err(err);
+            }
+            self.submit_gpu_profilers(0);
+            self.device
+                .wait_for_fences(from_ref(&fence), true, u64::MAX)?;
+
+            self.track_graphics_batch(batch, fence)?;
+            self.retire_submissions(Some(fence))?;
+            self.device.destroy_fence(fence, None);
+        }
+
+        Ok(())
+    }
+
+    /// Copy the content of an offscreen target to the CPU, tightly packed row by row.
+    /// The target must have been rendered with [VkTracerApp::render_offscreen] before.
+    pub fn read_offscreen_target(&self, target: OffscreenTargetHandle) -> Result<Vec<u8>> {
+        let target = self.get_offscreen_target(target)?;
+        let texel_size = texel_size(target.format).ok_or(VkTracerError::NoSuitableImageFormat)?;
+        let size = target.extent.width as usize * target.extent.height as usize * texel_size;
+
+        let readback = RawBufferAllocation::new_readback_buffer(&self.vma, size)?;
+        let (queue, pool) = self.queue_and_pool(QueueType::Graphics)?;
+
+        unsafe {
+            let commands = self.device.allocate_command_buffers(
+                &vk::CommandBufferAllocateInfo::builder()
+                    .command_pool(pool)
+                    .command_buffer_count(1)
+                    .level(vk::CommandBufferLevel::PRIMARY),
+            )?[0];
+
+            self.device.begin_command_buffer(
+                commands,
+                &vk::CommandBufferBeginInfo::builder()
+                    .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT),
+            )?;
+
+            let subresource_range = vk::ImageSubresourceRange::builder()
+                .aspect_mask(vk::ImageAspectFlags::COLOR)
+                .level_count(1)
+                .layer_count(1)
+                .build();
+
+            // The render plan already left the image in TRANSFER_SRC_OPTIMAL, but its writes
+            // need to be visible to the copy
+            self.cmd_pipeline_barrier(
+                commands,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                vk::PipelineStageFlags::TRANSFER,
+                &[],
+                &[],
+                from_ref(
+                    &vk::ImageMemoryBarrier::builder()
+                        .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+                        .dst_access_mask(vk::AccessFlags::TRANSFER_READ)
+                        .old_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+                        .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+                        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                        .image(target.handle)
+                        .subresource_range(subresource_range),
+                ),
+            );
+
+            let region = vk::BufferImageCopy::builder()
+                .image_subresource(
+                    vk::ImageSubresourceLayers::builder()
+                        .aspect_mask(vk::ImageAspectFlags::COLOR)
+                        .layer_count(1)
+                        .build(),
+                )
+                .image_extent(
+                    vk::Extent3D::builder()
+                        .width(target.extent.width)
+                        .height(target.extent.height)
+                        .depth(1)
+                        .build(),
+                );
+            self.device.cmd_copy_image_to_buffer(
+                commands,
+                target.handle,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                readback.buffer,
+                from_ref(&region),
+            );
+
+            self.device.end_command_buffer(commands)?;
+
+            let fence = self
+                .device
+                .create_fence(&vk::FenceCreateInfo::default(), None)?;
+            self.queue_submit(
+                queue,
+                from_ref(&Submission {
+                    commands: from_ref(&commands),
+                    ..Default::default()
+                }),
+                fence,
+            )?;
+            self.device
+                .wait_for_fences(from_ref(&fence), true, u64::MAX)?;
+
+            self.device.destroy_fence(fence, None);
+            self.device.free_command_buffers(pool, from_ref(&commands));
+        }
+
+        let data = unsafe { readback.load(&self.vma)? };
+        readback.destroy(&self.vma)?;
+        Ok(data)
+    }
+}
@@ -0,0 +1,336 @@
+//! # Overlays
+//! Pipelines drawing vertices written by the CPU every frame on top of a subpass, used by
+//! [crate::render::DebugDraw] and [crate::utils::TextRenderer].
+
+use crate::{
+    errors::{HandleType, Result, VkTracerError},
+    mem::{BufferDescription, RawBufferAllocation},
+    render::{GraphicsRecorder, SubpassFormats},
+    utils::{str_to_cstr, ShaderCompiler},
+    RenderPlanHandle, VkTracerApp,
+};
+use ash::{version::DeviceV1_0, vk};
+use std::{cell::Cell, io::Cursor, mem::size_of, slice::from_ref};
+
+pub(crate) struct OverlayDescription<'a> {
+    /// Name of the shaders and of the debug label.
+    pub(crate) name: &'a str,
+    pub(crate) vertex_glsl: &'a str,
+    pub(crate) fragment_glsl: &'a str,
+    pub(crate) stride: usize,
+    pub(crate) attributes: &'a [vk::VertexInputAttributeDescription],
+    pub(crate) topology: vk::PrimitiveTopology,
+    pub(crate) depth_test: bool,
+    pub(crate) set_layouts: &'a [vk::DescriptorSetLayout],
+    /// Push constants of the vertex shader.
+    pub(crate) push_constants_size: u32,
+}
+
+pub(crate) struct OverlayPipeline {
+    pub(crate) name: String,
+    pub(crate) pipeline: vk::Pipeline,
+    /// Owned by the layout cache.
+    pub(crate) pipeline_layout: vk::PipelineLayout,
+    /// One region of `max_vertices` per frame in flight.
+    buffer: RawBufferAllocation,
+    /// Recording that last wrote in each region and the vertices it used, every draw of a
+    /// frame gets its own range.
+    regions_used: Box<[Cell<(u64, usize)>]>,
+    pub(crate) max_vertices: usize,
+    stride: usize,
+}
+
+impl VkTracerApp {
+    pub(crate) fn create_overlay_pipeline(
+        &mut self,
+        render_plan: RenderPlanHandle,
+        subpass: u32,
+        max_vertices: usize,
+        desc: &OverlayDescription,
+    ) -> Result<OverlayPipeline> {
+        let frames = self.adapter.requirements.frames_in_flight as usize;
+        let buffer = RawBufferAllocation::new(
+            &self.vma,
+            &BufferDescription {
+                size: (frames * max_vertices * desc.stride) as vk::DeviceSize,
+                usage: vk::BufferUsageFlags::VERTEX_BUFFER,
+                location: vk_mem::MemoryUsage::CpuToGpu,
+            },
+        )?;
+
+        let pipeline_layout = self
+            .pipeline_layout(
+                desc.set_layouts,
+                &[vk::PushConstantRange {
+                    stage_flags: vk::ShaderStageFlags::VERTEX,
+                    offset: 0,
+                    size: desc.push_constants_size,
+                }],
+            )
+            .and_then(|pipeline_layout| {
+                self.new_overlay_pipeline(render_plan, subpass, pipeline_layout, desc)
+                    .map(|pipeline| (pipeline, pipeline_layout))
+            });
+        let (pipeline, pipeline_layout) = match pipeline_layout {
+            Ok(created) => created,
+            Err(err) => {
+                buffer.destroy(&self.vma)?;
+                return Err(err);
+            }
+        };
+
+        Ok(OverlayPipeline {
+            name: desc.name.to_owned(),
+            pipeline,
+            pipeline_layout,
+            buffer,
+            regions_used: (0..frames).map(|_| Cell::new((u64::MAX, 0))).collect(),
+            max_vertices,
+            stride: desc.stride,
+        })
+    }
+
+    fn new_overlay_pipeline(
+        &self,
+        render_plan: RenderPlanHandle,
+        subpass: u32,
+        pipeline_layout: vk::PipelineLayout,
+        desc: &OverlayDescription,
+    ) -> Result<vk::Pipeline> {
+        let render_plan = storage_access!(
+            self.render_plan_storage,
+            render_plan,
+            HandleType::RenderPlan
+        );
+        let device = &self.device;
+
+        let mut compiler = ShaderCompiler::new()?;
+        let vertex_spv = compiler.compile_source(
+            desc.vertex_glsl,
+            &format!("{}.vert", desc.name),
+            crate::shaderc::ShaderKind::Vertex,
+            "main",
+        )?;
+        let fragment_spv = compiler.compile_source(
+            desc.fragment_glsl,
+            &format!("{}.frag", desc.name),
+            crate::shaderc::ShaderKind::Fragment,
+            "main",
+        )?;
+
+        let (vertex_module, fragment_module) = unsafe {
+            let spv = ash::util::read_spv(&mut Cursor::new(vertex_spv))?;
+            let vertex_module = device
+                .create_shader_module(&vk::ShaderModuleCreateInfo::builder().code(&spv), None)?;
+            let spv = ash::util::read_spv(&mut Cursor::new(fragment_spv))?;
+            let fragment_module = device
+                .create_shader_module(&vk::ShaderModuleCreateInfo::builder().code(&spv), None);
+            let fragment_module = match fragment_module {
+                Ok(fragment_module) => fragment_module,
+                Err(err) => {
+                    device.destroy_shader_module(vertex_module, None);
+                    return Err(err.into());
+                }
+            };
+            (vertex_module, fragment_module)
+        };
+
+        let stages = [
+            vk::PipelineShaderStageCreateInfo::builder()
+                .stage(vk::ShaderStageFlags::VERTEX)
+                .module(vertex_module)
+                .name(str_to_cstr("main\0"))
+                .build(),
+            vk::PipelineShaderStageCreateInfo::builder()
+                .stage(vk::ShaderStageFlags::FRAGMENT)
+                .module(fragment_module)
+                .name(str_to_cstr("main\0"))
+                .build(),
+        ];
+
+        let binding = vk::VertexInputBindingDescription {
+            binding: 0,
+            stride: desc.stride as u32,
+            input_rate: vk::VertexInputRate::VERTEX,
+        };
+        let vertex_input_info = vk::PipelineVertexInputStateCreateInfo::builder()
+            .vertex_binding_descriptions(from_ref(&binding))
+            .vertex_attribute_descriptions(desc.attributes);
+
+        let input_assembly_info = vk::PipelineInputAssemblyStateCreateInfo::builder()
+            .topology(desc.topology)
+            .primitive_restart_enable(false);
+
+        let raster_state_info = vk::PipelineRasterizationStateCreateInfo::builder()
+            .depth_clamp_enable(false)
+            .rasterizer_discard_enable(false)
+            .polygon_mode(vk::PolygonMode::FILL)
+            .cull_mode(vk::CullModeFlags::NONE)
+            .depth_bias_enable(false)
+            .line_width(1.0);
+
+        let msaa_info = vk::PipelineMultisampleStateCreateInfo::builder()
+            .rasterization_samples(vk::SampleCountFlags::TYPE_1)
+            .min_sample_shading(1.0);
+
+        // Overlays never occlude the scene
+        let depth_stencil_info = vk::PipelineDepthStencilStateCreateInfo::builder()
+            .depth_test_enable(desc.depth_test)
+            .depth_write_enable(false)
+            .depth_compare_op(vk::CompareOp::LESS_OR_EQUAL)
+            .max_depth_bounds(1.0);
+
+        let color_blend_info = vk::PipelineColorBlendAttachmentState::builder()
+            .color_write_mask(vk::ColorComponentFlags::all())
+            .blend_enable(true)
+            .src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
+            .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+            .color_blend_op(vk::BlendOp::ADD)
+            .src_alpha_blend_factor(vk::BlendFactor::ONE)
+            .dst_alpha_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+            .alpha_blend_op(vk::BlendOp::ADD);
+
+        let viewport_state_info = vk::PipelineViewportStateCreateInfo::builder()
+            .viewport_count(1)
+            .scissor_count(1);
+
+        let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+            .logic_op_enable(false)
+            .attachments(from_ref(&color_blend_info));
+
+        let dynamic_state = vk::PipelineDynamicStateCreateInfo::builder()
+            .dynamic_states(&[vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR]);
+
+        let subpass_formats = Some(render_plan)
+            .filter(|plan| plan.is_dynamic())
+            .map(|plan| plan.subpass_formats(subpass as usize));
+        let mut rendering_info = subpass_formats.as_ref().map(SubpassFormats::pipeline_info);
+
+        let pipeline = unsafe {
+            let mut create_info = vk::GraphicsPipelineCreateInfo::builder()
+                .stages(&stages)
+                .vertex_input_state(&vertex_input_info)
+                .input_assembly_state(&input_assembly_info)
+                .rasterization_state(&raster_state_info)
+                .multisample_state(&msaa_info)
+                .depth_stencil_state(&depth_stencil_info)
+                .color_blend_state(&color_blend_state)
+                .viewport_state(&viewport_state_info)
+                .dynamic_state(&dynamic_state)
+                .layout(pipeline_layout)
+                .render_pass(render_plan.render_pass)
+                .subpass(subpass);
+            if let Some(rendering_info) = rendering_info.as_mut() {
+                create_info = create_info.subpass(0).push_next(rendering_info);
+            }
+
+            let pipelines = device
+                .create_graphics_pipelines(self.pipeline_cache, from_ref(&create_info), None)
+                .map_err(|(_, err)| err);
+
+            device.destroy_shader_module(vertex_module, None);
+            device.destroy_shader_module(fragment_module, None);
+            pipelines?[0]
+        };
+
+        Ok(pipeline)
+    }
+
+    pub(crate) fn destroy_overlay_pipeline(&mut self, overlay: OverlayPipeline) -> Result<()> {
+        unsafe {
+            self.device.destroy_pipeline(overlay.pipeline, None);
+        }
+        overlay.buffer.destroy(&self.vma)
+    }
+}
+
+impl GraphicsRecorder<'_> {
+    /// Vertices that can still be drawn with the overlay in this frame.
+    pub(crate) fn overlay_vertices_left(&self, overlay: &OverlayPipeline) -> usize {
+        overlay.max_vertices - self.overlay_vertices_used(overlay)
+    }
+
+    fn overlay_vertices_used(&self, overlay: &OverlayPipeline) -> usize {
+        let region = self.frame_index % overlay.regions_used.len();
+        match overlay.regions_used[region].get() {
+            (recording, used) if recording == self.recording => used,
+            _ => 0,
+        }
+    }
+
+    /// Write the vertices after the ones already drawn in the region of the current frame and
+    /// bind the overlay with a viewport covering the render target. The recorder only exists
+    /// when the commands are recorded each frame, so the vertices are always written again.
+    pub(crate) fn bind_overlay<V: Copy>(
+        mut self,
+        overlay: &OverlayPipeline,
+        vertices: &[V],
+        descriptor_sets: &[vk::DescriptorSet],
+        push_constants: &[u8],
+    ) -> Result<Self> {
+        debug_assert_eq!(size_of::<V>(), overlay.stride);
+
+        // Each frame in flight writes its own region, and each draw its own range of it
+        let region = self.frame_index % overlay.regions_used.len();
+        let first = self.overlay_vertices_used(overlay);
+        if first + vertices.len() > overlay.max_vertices {
+            return Err(VkTracerError::TooManyOverlayVertices(overlay.max_vertices));
+        }
+        overlay.regions_used[region].set((self.recording, first + vertices.len()));
+
+        let offset = (region * overlay.max_vertices + first) * overlay.stride;
+        let size = vertices.len() * overlay.stride;
+        let (need_to_unmap, mapped) = overlay.buffer.ensure_mapped(&self.app.vma)?;
+        unsafe {
+            std::ptr::copy_nonoverlapping(vertices.as_ptr() as *const u8, mapped.add(offset), size);
+        }
+        self.app
+            .vma
+            .flush_allocation(&overlay.buffer.allocation, offset, size)?;
+        if need_to_unmap {
+            self.app.vma.unmap_memory(&overlay.buffer.allocation)?;
+        }
+
+        if self.bound_pipeline.is_some() {
+            self.app.end_debug_label(self.commands);
+        }
+        self.app
+            .begin_debug_label(self.commands, format_args!("{}", overlay.name));
+
+        let device = &self.app.device;
+        unsafe {
+            device.cmd_bind_pipeline(
+                self.commands,
+                vk::PipelineBindPoint::GRAPHICS,
+                overlay.pipeline,
+            );
+            if !descriptor_sets.is_empty() {
+                device.cmd_bind_descriptor_sets(
+                    self.commands,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    overlay.pipeline_layout,
+                    0,
+                    descriptor_sets,
+                    &[],
+                );
+            }
+            device.cmd_push_constants(
+                self.commands,
+                overlay.pipeline_layout,
+                vk::ShaderStageFlags::VERTEX,
+                0,
+                push_constants,
+            );
+            device.cmd_bind_vertex_buffers(
+                self.commands,
+                0,
+                from_ref(&overlay.buffer.buffer),
+                from_ref(&(offset as vk::DeviceSize)),
+            );
+        }
+        self.bound_pipeline = Some((overlay.pipeline_layout, Some(vk::ShaderStageFlags::VERTEX)));
+        self.bound_mesh = None;
+
+        Ok(self.reset_viewport_and_scissor())
+    }
+}
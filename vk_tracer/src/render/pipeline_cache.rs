@@ -0,0 +1,189 @@
+use crate::{errors::Result, VkTracerApp};
+use ash::{version::DeviceV1_0, vk};
+use log::info;
+use std::{convert::TryInto, fs, io, path::Path, slice::from_ref};
+
+const MAGIC: &[u8; 8] = b"VKTRPSO1";
+const HEADER_SIZE: usize = 8 + 3 * 4 + vk::UUID_SIZE + 8;
+
+/// The header of the cache files, which are only valid for the exact same device and driver.
+fn header(properties: &vk::PhysicalDeviceProperties, data_size: usize) -> Vec<u8> {
+    let mut header = Vec::with_capacity(HEADER_SIZE);
+    header.extend_from_slice(MAGIC);
+    header.extend_from_slice(&properties.vendor_id.to_le_bytes());
+    header.extend_from_slice(&properties.device_id.to_le_bytes());
+    header.extend_from_slice(&properties.driver_version.to_le_bytes());
+    header.extend_from_slice(&properties.pipeline_cache_uuid);
+    header.extend_from_slice(&(data_size as u64).to_le_bytes());
+    header
+}
+
+/// The data of the file when both our header and the one of Vulkan match the device.
+fn validate<'a>(properties: &vk::PhysicalDeviceProperties, file: &'a [u8]) -> Option<&'a [u8]> {
+    if file.len() < HEADER_SIZE {
+        return None;
+    }
+    let (file_header, data) = file.split_at(HEADER_SIZE);
+    if file_header != header(properties, data.len()).as_slice() {
+        return None;
+    }
+
+    // VkPipelineCacheHeaderVersionOne
+    let u32_at = |offset: usize| u32::from_ne_bytes(data[offset..offset + 4].try_into().unwrap());
+    let valid = data.len() >= 16 + vk::UUID_SIZE
+        && u32_at(4) == vk::PipelineCacheHeaderVersion::ONE.as_raw() as u32
+        && u32_at(8) == properties.vendor_id
+        && u32_at(12) == properties.device_id
+        && data[16..16 + vk::UUID_SIZE] == properties.pipeline_cache_uuid;
+    Some(data).filter(|_| valid)
+}
+
+impl VkTracerApp {
+    /// Add the pipelines saved by [VkTracerApp::save_pipeline_cache] to the cache, before
+    /// creating the pipelines. Caches of another device or driver version are ignored,
+    /// returns whether the cache was loaded.
+    pub fn load_pipeline_cache(&mut self, path: impl AsRef<Path>) -> Result<bool> {
+        let path = path.as_ref();
+        let file = match fs::read(path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(err) => return Err(err.into()),
+        };
+
+        let properties = &self.adapter.info.physical_device_info.properties;
+        let data = match validate(properties, &file) {
+            Some(data) => data,
+            None => {
+                info!("Discarding stale pipeline cache {:?}", path);
+                return Ok(false);
+            }
+        };
+
+        unsafe {
+            let loaded = self.device.create_pipeline_cache(
+                &vk::PipelineCacheCreateInfo::builder().initial_data(data),
+                None,
+            )?;
+            let merged = self
+                .device
+                .merge_pipeline_caches(self.pipeline_cache, from_ref(&loaded));
+            self.device.destroy_pipeline_cache(loaded, None);
+            merged?;
+        }
+
+        info!("Loaded pipeline cache {:?}", path);
+        Ok(true)
+    }
+
+    /// Persist the pipelines created so far, along with the device and driver they are valid for.
+    pub fn save_pipeline_cache(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let data = unsafe { self.device.get_pipeline_cache_data(self.pipeline_cache)? };
+
+        let mut file = header(
+            &self.adapter.info.physical_device_info.properties,
+            data.len(),
+        );
+        file.extend_from_slice(&data);
+
+        // Never leave a truncated cache behind
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, &file)?;
+        fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn properties() -> vk::PhysicalDeviceProperties {
+        vk::PhysicalDeviceProperties {
+            vendor_id: 0x10de,
+            device_id: 0x2206,
+            driver_version: 42,
+            pipeline_cache_uuid: [7; vk::UUID_SIZE],
+            ..Default::default()
+        }
+    }
+
+    /// A Vulkan cache header for `properties` followed by some pipeline data.
+    fn vulkan_data(properties: &vk::PhysicalDeviceProperties) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&(16 + vk::UUID_SIZE as u32).to_ne_bytes());
+        data.extend_from_slice(
+            &(vk::PipelineCacheHeaderVersion::ONE.as_raw() as u32).to_ne_bytes(),
+        );
+        data.extend_from_slice(&properties.vendor_id.to_ne_bytes());
+        data.extend_from_slice(&properties.device_id.to_ne_bytes());
+        data.extend_from_slice(&properties.pipeline_cache_uuid);
+        data.extend_from_slice(&[1, 2, 3, 4]);
+        data
+    }
+
+    fn cache_file(properties: &vk::PhysicalDeviceProperties, data: &[u8]) -> Vec<u8> {
+        let mut file = header(properties, data.len());
+        file.extend_from_slice(data);
+        file
+    }
+
+    #[test]
+    fn accepts_matching_cache() {
+        let properties = properties();
+        let data = vulkan_data(&properties);
+        let file = cache_file(&properties, &data);
+        assert_eq!(validate(&properties, &file), Some(data.as_slice()));
+    }
+
+    #[test]
+    fn rejects_truncated_file() {
+        let properties = properties();
+        let file = cache_file(&properties, &vulkan_data(&properties));
+        assert_eq!(validate(&properties, &file[..HEADER_SIZE - 1]), None);
+        // The size in our header doesn't match anymore
+        assert_eq!(validate(&properties, &file[..file.len() - 1]), None);
+    }
+
+    #[test]
+    fn rejects_corrupted_header() {
+        let properties = properties();
+        let mut file = cache_file(&properties, &vulkan_data(&properties));
+        file[0] = b'X';
+        assert_eq!(validate(&properties, &file), None);
+    }
+
+    #[test]
+    fn rejects_other_device_or_driver() {
+        let properties = properties();
+        let file = cache_file(&properties, &vulkan_data(&properties));
+
+        let other_driver = vk::PhysicalDeviceProperties {
+            driver_version: 43,
+            ..properties
+        };
+        assert_eq!(validate(&other_driver, &file), None);
+        let other_uuid = vk::PhysicalDeviceProperties {
+            pipeline_cache_uuid: [8; vk::UUID_SIZE],
+            ..properties
+        };
+        assert_eq!(validate(&other_uuid, &file), None);
+    }
+
+    #[test]
+    fn rejects_mismatched_vulkan_header() {
+        // Our header is right but the data was produced by another device
+        let properties = properties();
+        let other_device = vk::PhysicalDeviceProperties {
+            device_id: 0x1234,
+            ..properties
+        };
+        let file = cache_file(&properties, &vulkan_data(&other_device));
+        assert_eq!(validate(&properties, &file), None);
+
+        let mut data = vulkan_data(&properties);
+        data.truncate(16);
+        let file = cache_file(&properties, &data);
+        assert_eq!(validate(&properties, &file), None);
+    }
+}
@@ -0,0 +1,460 @@
+use crate::{
+    errors::{HandleType, Result, VkTracerError},
+    render::{FrameRecorder, RendererCommands},
+    GpuProfilerHandle, RendererHandle, VkTracerApp,
+};
+use ash::{
+    version::{DeviceV1_0, DeviceV1_2, EntryV1_0},
+    vk,
+};
+use log::warn;
+use std::{
+    cell::RefCell,
+    ops::{Deref, DerefMut},
+    ptr,
+    time::Duration,
+};
+
+#[cfg(windows)]
+const HOST_TIME_DOMAIN: vk::TimeDomainEXT = vk::TimeDomainEXT::QUERY_PERFORMANCE_COUNTER;
+#[cfg(not(windows))]
+const HOST_TIME_DOMAIN: vk::TimeDomainEXT = vk::TimeDomainEXT::CLOCK_MONOTONIC;
+
+#[cfg(windows)]
+#[link(name = "kernel32")]
+extern "system" {
+    fn QueryPerformanceFrequency(frequency: *mut i64) -> i32;
+}
+
+/// GPU duration of a profiler scope, resolved once the frame is done.
+#[derive(Debug, Clone)]
+pub struct GpuScopeTiming {
+    pub name: &'static str,
+    /// Start of the scope relative to the first scope of the frame.
+    pub offset: Duration,
+    pub duration: Duration,
+    /// Start of the scope in the host time domain (`CLOCK_MONOTONIC` nanoseconds or
+    /// `QueryPerformanceCounter` ticks), only with [crate::setup::VkTracerExtensions::CalibratedTimestamps].
+    pub host_timestamp: Option<u64>,
+}
+
+impl VkTracerApp {
+    /// Create a profiler of at most `max_scopes` scopes per frame, scopes are opened with
+    /// [FrameRecorder::profile_scope]. The renderer must be recorded each frame, see
+    /// [crate::render::RendererBuilder::record_each_frame].
+    pub fn create_gpu_profiler(
+        &mut self,
+        renderer: RendererHandle,
+        max_scopes: u32,
+    ) -> Result<GpuProfilerHandle> {
+        if let RendererCommands::Prerecorded { .. } =
+            storage_access!(self.renderer_storage, renderer, HandleType::Renderer).commands
+        {
+            return Err(VkTracerError::ProfilerNeedsEachFrame);
+        }
+
+        let host_clock = self.host_clock()?;
+        let info = &self.adapter.info;
+        if !info.physical_device_info.host_query_reset {
+            return Err(VkTracerError::FeatureNotSupported("hostQueryReset"));
+        }
+        if info.graphics_queue.properties.timestamp_valid_bits == 0 {
+            return Err(VkTracerError::FeatureNotSupported("timestamps"));
+        }
+
+        let frames = self.adapter.requirements.frames_in_flight;
+        let query_count = max_scopes * 2 * frames;
+        let pool = unsafe {
+            let pool = self.device.create_query_pool(
+                &vk::QueryPoolCreateInfo::builder()
+                    .query_type(vk::QueryType::TIMESTAMP)
+                    .query_count(query_count),
+                None,
+            )?;
+            self.device.reset_query_pool(pool, 0, query_count);
+            pool
+        };
+
+        Ok(self.gpu_profiler_storage.insert(GpuProfiler {
+            pool,
+            max_scopes,
+            timestamp_mask: timestamp_mask(info.graphics_queue.properties.timestamp_valid_bits),
+            timestamp_period: info.physical_device_info.properties.limits.timestamp_period,
+            host_clock,
+            frames: (0..frames).map(|_| RefCell::default()).collect(),
+            results: Vec::new(),
+        }))
+    }
+
+    /// Timings of the last frame resolved by this profiler.
+    pub fn get_gpu_profiler_results(
+        &self,
+        profiler: GpuProfilerHandle,
+    ) -> Result<&[GpuScopeTiming]> {
+        Ok(&storage_access!(self.gpu_profiler_storage, profiler, HandleType::GpuProfiler).results)
+    }
+
+    /// Read back the timestamps written during a previous use of this frame.
+    /// The fence of the frame must have been waited on.
+    pub(crate) fn resolve_gpu_profilers(&mut self, frame_index: usize) -> Result<()> {
+        let device = &self.device;
+        let calibration = if self
+            .gpu_profiler_storage
+            .values()
+            .any(|profiler| profiler.host_clock.is_some())
+        {
+            self.calibrate_timestamps()
+        } else {
+            None
+        };
+
+        for (_, profiler) in self.gpu_profiler_storage.iter_mut() {
+            let frame_index = frame_index % profiler.frames.len();
+            let (scopes, submitted) = {
+                let mut frame = profiler.frames[frame_index].borrow_mut();
+                (
+                    std::mem::take(&mut frame.scopes),
+                    std::mem::replace(&mut frame.submitted, false),
+                )
+            };
+            if scopes.is_empty() {
+                continue;
+            }
+
+            let first_query = profiler.first_query(frame_index);
+            let query_count = scopes.len() as u32 * 2;
+            // Waiting on queries that were never submitted would never return
+            if !submitted {
+                unsafe { device.reset_query_pool(profiler.pool, first_query, query_count) };
+                continue;
+            }
+
+            let mut timestamps = vec![0u64; query_count as usize];
+            unsafe {
+                device.get_query_pool_results(
+                    profiler.pool,
+                    first_query,
+                    query_count,
+                    &mut timestamps,
+                    vk::QueryResultFlags::TYPE_64 | vk::QueryResultFlags::WAIT,
+                )?;
+                device.reset_query_pool(profiler.pool, first_query, query_count);
+            }
+
+            let mask = profiler.timestamp_mask;
+            let period = profiler.timestamp_period as f64;
+            let frame_start = timestamps[0] & mask;
+            let host_calibration = profiler.host_clock.zip(calibration);
+
+            profiler.results.clear();
+            for (i, name) in scopes.into_iter().enumerate() {
+                let start = timestamps[i * 2] & mask;
+                let end = timestamps[i * 2 + 1] & mask;
+
+                profiler.results.push(GpuScopeTiming {
+                    name,
+                    offset: Duration::from_nanos(ticks_to_nanos(
+                        start.wrapping_sub(frame_start) & mask,
+                        period,
+                    )),
+                    duration: Duration::from_nanos(ticks_to_nanos(
+                        end.wrapping_sub(start) & mask,
+                        period,
+                    )),
+                    host_timestamp: host_calibration.map(|(clock, (device_ts, host_ts))| {
+                        host_timestamp(
+                            start,
+                            device_ts,
+                            host_ts,
+                            mask,
+                            period,
+                            clock.ticks_per_second,
+                        )
+                    }),
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The scopes recorded for this frame were submitted and can be waited on.
+    pub(crate) fn submit_gpu_profilers(&self, frame_index: usize) {
+        for (_, profiler) in &self.gpu_profiler_storage {
+            let mut frame = profiler.frames[frame_index % profiler.frames.len()].borrow_mut();
+            frame.submitted = !frame.scopes.is_empty();
+        }
+    }
+
+    /// The host clock to correlate the scopes with, if the device can calibrate it.
+    fn host_clock(&self) -> Result<Option<HostClock>> {
+        if self.calibrated_timestamps.is_none() {
+            return Ok(None);
+        }
+
+        let domains = unsafe {
+            // An instance level function, not loaded with the device ones
+            let get_domains: vk::PFN_vkGetPhysicalDeviceCalibrateableTimeDomainsEXT = {
+                let name = b"vkGetPhysicalDeviceCalibrateableTimeDomainsEXT\0";
+                let pfn = self
+                    .entry
+                    .get_instance_proc_addr(self.instance.handle(), name.as_ptr() as *const _)
+                    .ok_or(VkTracerError::ExtensionNotEnabled(
+                        vk::ExtCalibratedTimestampsFn::name().to_str().unwrap(),
+                    ))?;
+                std::mem::transmute(pfn)
+            };
+
+            let mut count = 0;
+            match get_domains(self.adapter.handle, &mut count, ptr::null_mut()) {
+                vk::Result::SUCCESS => {}
+                err => return Err(err.into()),
+            }
+            let mut domains = vec![vk::TimeDomainEXT::default(); count as usize];
+            match get_domains(self.adapter.handle, &mut count, domains.as_mut_ptr()) {
+                vk::Result::SUCCESS | vk::Result::INCOMPLETE => {}
+                err => return Err(err.into()),
+            }
+            domains.truncate(count as usize);
+            domains
+        };
+
+        if !domains.contains(&vk::TimeDomainEXT::DEVICE) || !domains.contains(&HOST_TIME_DOMAIN) {
+            warn!(
+                "Can't calibrate GPU timestamps with {:?}, only {:?}",
+                HOST_TIME_DOMAIN, domains
+            );
+            return Ok(None);
+        }
+
+        #[cfg(windows)]
+        let ticks_per_second = {
+            let mut frequency = 0;
+            unsafe { QueryPerformanceFrequency(&mut frequency) };
+            frequency as u64
+        };
+        #[cfg(not(windows))]
+        let ticks_per_second = 1_000_000_000;
+
+        Ok(Some(HostClock { ticks_per_second }))
+    }
+
+    /// A pair of device and host timestamps taken at the same time.
+    fn calibrate_timestamps(&self) -> Option<(u64, u64)> {
+        let calibrated_timestamps = self.calibrated_timestamps.as_ref()?;
+
+        let infos = [
+            vk::CalibratedTimestampInfoEXT::builder()
+                .time_domain(vk::TimeDomainEXT::DEVICE)
+                .build(),
+            vk::CalibratedTimestampInfoEXT::builder()
+                .time_domain(HOST_TIME_DOMAIN)
+                .build(),
+        ];
+        let mut timestamps = [0u64; 2];
+        let mut max_deviation = 0;
+
+        let result = unsafe {
+            calibrated_timestamps.get_calibrated_timestamps_ext(
+                self.device.handle(),
+                infos.len() as u32,
+                infos.as_ptr(),
+                timestamps.as_mut_ptr(),
+                &mut max_deviation,
+            )
+        };
+
+        if result == vk::Result::SUCCESS {
+            Some((timestamps[0], timestamps[1]))
+        } else {
+            warn!("Failed to calibrate timestamps: {}", result);
+            None
+        }
+    }
+}
+
+fn timestamp_mask(valid_bits: u32) -> u64 {
+    match valid_bits {
+        64 => u64::MAX,
+        bits => (1 << bits) - 1,
+    }
+}
+
+fn ticks_to_nanos(ticks: u64, period: f64) -> u64 {
+    (ticks as f64 * period) as u64
+}
+
+/// Where a device timestamp falls in the host clock, from a calibrated pair of timestamps.
+fn host_timestamp(
+    device_timestamp: u64,
+    calibration_device: u64,
+    calibration_host: u64,
+    mask: u64,
+    period: f64,
+    host_ticks_per_second: u64,
+) -> u64 {
+    // The difference wraps within the valid bits and is negative for earlier timestamps
+    let delta = device_timestamp.wrapping_sub(calibration_device) & mask;
+    let delta = if delta > mask / 2 {
+        (delta as i64).wrapping_sub(mask as i64).wrapping_sub(1)
+    } else {
+        delta as i64
+    };
+    let delta_nanos = delta as f64 * period;
+    let delta_host = delta_nanos * host_ticks_per_second as f64 / 1e9;
+    (calibration_host as i64).wrapping_add(delta_host.round() as i64) as u64
+}
+
+#[derive(Copy, Clone)]
+pub(crate) struct HostClock {
+    ticks_per_second: u64,
+}
+
+#[derive(Default)]
+pub(crate) struct ProfilerFrame {
+    scopes: Vec<&'static str>,
+    submitted: bool,
+}
+
+pub(crate) struct GpuProfiler {
+    pub(crate) pool: vk::QueryPool,
+    max_scopes: u32,
+    timestamp_mask: u64,
+    timestamp_period: f32,
+    host_clock: Option<HostClock>,
+    // Scopes are opened while recording, with only a shared borrow of the app
+    frames: Box<[RefCell<ProfilerFrame>]>,
+    results: Vec<GpuScopeTiming>,
+}
+
+impl GpuProfiler {
+    fn first_query(&self, frame_index: usize) -> u32 {
+        frame_index as u32 * self.max_scopes * 2
+    }
+}
+
+/// A profiled region of a frame, ends when dropped.
+pub struct ProfilerScope<'r, 'a> {
+    recorder: &'r mut FrameRecorder<'a>,
+    pool: vk::QueryPool,
+    end_query: u32,
+}
+
+impl<'a> FrameRecorder<'a> {
+    /// Measure the GPU time of the commands recorded until the returned scope is dropped.
+    /// Results are available with [VkTracerApp::get_gpu_profiler_results] once the frame is done.
+    pub fn profile_scope<'r>(
+        &'r mut self,
+        profiler: GpuProfilerHandle,
+        name: &'static str,
+    ) -> Result<ProfilerScope<'r, 'a>> {
+        let app = self.app;
+        let profiler = storage_access!(app.gpu_profiler_storage, profiler, HandleType::GpuProfiler);
+
+        let frame_index = self.frame_index % profiler.frames.len();
+        let mut frame = profiler.frames[frame_index].borrow_mut();
+        if frame.scopes.len() as u32 >= profiler.max_scopes {
+            return Err(VkTracerError::TooManyProfilerScopes(profiler.max_scopes));
+        }
+
+        let start_query = profiler.first_query(frame_index) + frame.scopes.len() as u32 * 2;
+        frame.scopes.push(name);
+
+        unsafe {
+            app.device.cmd_write_timestamp(
+                self.commands,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                profiler.pool,
+                start_query,
+            );
+        }
+
+        Ok(ProfilerScope {
+            recorder: self,
+            pool: profiler.pool,
+            end_query: start_query + 1,
+        })
+    }
+}
+
+impl<'a> Deref for ProfilerScope<'_, 'a> {
+    type Target = FrameRecorder<'a>;
+
+    fn deref(&self) -> &Self::Target {
+        self.recorder
+    }
+}
+
+impl DerefMut for ProfilerScope<'_, '_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.recorder
+    }
+}
+
+impl Drop for ProfilerScope<'_, '_> {
+    fn drop(&mut self) {
+        unsafe {
+            self.recorder.app.device.cmd_write_timestamp(
+                self.recorder.commands,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                self.pool,
+                self.end_query,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timestamp_masks() {
+        assert_eq!(timestamp_mask(64), u64::MAX);
+        assert_eq!(timestamp_mask(36), 0xf_ffff_ffff);
+        assert_eq!(timestamp_mask(1), 1);
+    }
+
+    #[test]
+    fn ticks_with_period() {
+        assert_eq!(ticks_to_nanos(1000, 1.0), 1000);
+        assert_eq!(ticks_to_nanos(1000, 52.08), 52080);
+        assert_eq!(ticks_to_nanos(0, 83.3), 0);
+    }
+
+    #[test]
+    fn host_timestamp_in_nanoseconds() {
+        let mask = u64::MAX;
+        // After and before the calibration
+        assert_eq!(
+            host_timestamp(1100, 1000, 5000, mask, 2.0, 1_000_000_000),
+            5200
+        );
+        assert_eq!(
+            host_timestamp(900, 1000, 5000, mask, 2.0, 1_000_000_000),
+            4800
+        );
+    }
+
+    #[test]
+    fn host_timestamp_in_qpc_ticks() {
+        // A 10 MHz counter ticks every 100 ns
+        let mask = u64::MAX;
+        assert_eq!(host_timestamp(2000, 1000, 500, mask, 1.0, 10_000_000), 510);
+        assert_eq!(host_timestamp(0, 1000, 500, mask, 1.0, 10_000_000), 490);
+    }
+
+    #[test]
+    fn host_timestamp_wraps_in_valid_bits() {
+        let mask = timestamp_mask(32);
+        // The device counter wrapped between the scope and the calibration
+        assert_eq!(
+            host_timestamp(mask - 9, 10, 1000, mask, 1.0, 1_000_000_000),
+            980
+        );
+        assert_eq!(
+            host_timestamp(10, mask - 9, 1000, mask, 1.0, 1_000_000_000),
+            1020
+        );
+    }
+}
@@ -1,6 +1,52 @@
-use crate::{errors::Result, mem::ImageViewFatHandle, RenderPlanHandle, VkTracerApp};
+use crate::{
+    errors::{Result, VkTracerError},
+    mem::{is_format_supported, ImageViewFatHandle},
+    RenderPlanHandle, VkTracerApp,
+};
 use ash::{version::DeviceV1_2, vk, vk::ClearColorValue};
 
+/// Suggested when a color attachment format isn't supported.
+const COLOR_ATTACHMENT_FALLBACK_FORMATS: [vk::Format; 5] = [
+    vk::Format::R8G8B8A8_SRGB,
+    vk::Format::B8G8R8A8_SRGB,
+    vk::Format::R8G8B8A8_UNORM,
+    vk::Format::B8G8R8A8_UNORM,
+    vk::Format::R16G16B16A16_SFLOAT,
+];
+
+/// Suggested when a depth attachment format isn't supported.
+const DEPTH_ATTACHMENT_FALLBACK_FORMATS: [vk::Format; 4] = [
+    vk::Format::D32_SFLOAT,
+    vk::Format::D32_SFLOAT_S8_UINT,
+    vk::Format::D24_UNORM_S8_UINT,
+    vk::Format::D16_UNORM,
+];
+
+/// Make sure the format can be used as an attachment with optimal tiling, otherwise
+/// the error will contain the formats that would work instead.
+fn validate_attachment_format(
+    app: &VkTracerApp,
+    format: vk::Format,
+    features: vk::FormatFeatureFlags,
+    fallbacks: &[vk::Format],
+) -> Result<()> {
+    if is_format_supported(app, format, vk::ImageTiling::OPTIMAL, features) {
+        Ok(())
+    } else {
+        Err(VkTracerError::UnsupportedAttachmentFormat {
+            format,
+            features,
+            fallbacks: fallbacks
+                .iter()
+                .copied()
+                .filter(|fallback| {
+                    is_format_supported(app, *fallback, vk::ImageTiling::OPTIMAL, features)
+                })
+                .collect(),
+        })
+    }
+}
+
 impl VkTracerApp {
     pub fn new_render_plan(&mut self) -> RenderPlanBuilder {
         RenderPlanBuilder {
@@ -15,6 +61,7 @@ impl VkTracerApp {
 }
 
 pub(crate) struct RenderPlan {
+    /// Null with dynamic rendering.
     pub(crate) render_pass: vk::RenderPass,
     // Data used to recreate the render pass when necessary
     pub(crate) clear_values: Vec<vk::ClearValue>,
@@ -34,16 +81,57 @@ pub struct RenderPlanBuilder<'app> {
 
 impl RenderPlanBuilder<'_> {
     /// Add a color attachment that will be used for presentation.
-    pub fn add_color_attachment_present(mut self, image: ImageViewFatHandle) -> Result<Self> {
+    pub fn add_color_attachment_present(self, image: ImageViewFatHandle) -> Result<Self> {
+        self.add_color_attachment(
+            image,
+            vk::AttachmentStoreOp::STORE,
+            vk::ImageLayout::PRESENT_SRC_KHR,
+        )
+    }
+
+    /// Add a color attachment that is only used during the render plan, ex: an intermediate target
+    /// consumed by a later subpass. Its content isn't stored which saves bandwidth on tiled GPUs.
+    pub fn add_color_attachment_transient(self, image: ImageViewFatHandle) -> Result<Self> {
+        self.add_color_attachment(
+            image,
+            vk::AttachmentStoreOp::DONT_CARE,
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        )
+    }
+
+    /// Add a color attachment that will be read back after the render, the image comes from
+    /// [VkTracerApp::get_offscreen_target].
+    pub fn add_color_attachment_offscreen(self, image: ImageViewFatHandle) -> Result<Self> {
+        self.add_color_attachment(
+            image,
+            vk::AttachmentStoreOp::STORE,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        )
+    }
+
+    /// Cleared at the start of the render plan.
+    fn add_color_attachment(
+        mut self,
+        image: ImageViewFatHandle,
+        store_op: vk::AttachmentStoreOp,
+        final_layout: vk::ImageLayout,
+    ) -> Result<Self> {
+        validate_attachment_format(
+            self.app,
+            image.format,
+            vk::FormatFeatureFlags::COLOR_ATTACHMENT,
+            &COLOR_ATTACHMENT_FALLBACK_FORMATS,
+        )?;
+
         let description = vk::AttachmentDescription2::builder()
             .format(image.format)
             .samples(vk::SampleCountFlags::TYPE_1)
             .load_op(vk::AttachmentLoadOp::CLEAR)
-            .store_op(vk::AttachmentStoreOp::STORE)
+            .store_op(store_op)
             .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
             .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
             .initial_layout(vk::ImageLayout::UNDEFINED)
-            .final_layout(vk::ImageLayout::PRESENT_SRC_KHR)
+            .final_layout(final_layout)
             .build();
 
         let reference = vk::AttachmentReference2::builder()
@@ -62,6 +150,13 @@ impl RenderPlanBuilder<'_> {
     }
 
     pub fn add_depth_attachment(mut self, image: ImageViewFatHandle) -> Result<Self> {
+        validate_attachment_format(
+            self.app,
+            image.format,
+            vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT,
+            &DEPTH_ATTACHMENT_FALLBACK_FORMATS,
+        )?;
+
         let description = vk::AttachmentDescription2::builder()
             .format(image.format)
             .samples(vk::SampleCountFlags::TYPE_1)
@@ -103,6 +198,40 @@ impl RenderPlanBuilder<'_> {
         self
     }
 
+    /// Override what happens to the content of an attachment at the start and at the end of the
+    /// render plan. The clear value is only used with [vk::AttachmentLoadOp::CLEAR].
+    pub fn set_load_store_ops(
+        mut self,
+        index: usize,
+        load: vk::AttachmentLoadOp,
+        store: vk::AttachmentStoreOp,
+    ) -> Result<Self> {
+        let attachment = self.attachment_mut(index)?;
+        attachment.load_op = load;
+        attachment.store_op = store;
+        Ok(self)
+    }
+
+    /// Same as [Self::set_load_store_ops] but for the stencil aspect.
+    pub fn set_stencil_load_store_ops(
+        mut self,
+        index: usize,
+        load: vk::AttachmentLoadOp,
+        store: vk::AttachmentStoreOp,
+    ) -> Result<Self> {
+        let attachment = self.attachment_mut(index)?;
+        attachment.stencil_load_op = load;
+        attachment.stencil_store_op = store;
+        Ok(self)
+    }
+
+    fn attachment_mut(&mut self, index: usize) -> Result<&mut vk::AttachmentDescription2> {
+        let count = self.attachments.len();
+        self.attachments
+            .get_mut(index)
+            .ok_or(VkTracerError::InvalidAttachmentIndex { index, count })
+    }
+
     pub fn add_subpass(
         mut self,
         subpass: SubpassBuilder,
@@ -119,7 +248,22 @@ impl RenderPlanBuilder<'_> {
         let mut subpasses = Vec::with_capacity(self.subpasses.len());
         let mut subpasses_references = Vec::with_capacity(self.subpasses.len());
 
-        for subpass in &self.subpasses {
+        // Depth references depend on the subpass, ex: read only after a depth prepass
+        let depth_stencil_references = self
+            .subpasses
+            .iter()
+            .map(|subpass| {
+                subpass.depth_stencil_attachment.map(|i| {
+                    let mut reference = self.references[i];
+                    reference.layout = subpass.depth_stencil_access.layout();
+                    reference
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for (subpass, depth_stencil_reference) in
+            self.subpasses.iter().zip(depth_stencil_references.iter())
+        {
             let color_attachments = subpass
                 .color_attachments
                 .iter()
@@ -132,11 +276,8 @@ impl RenderPlanBuilder<'_> {
                 .pipeline_bind_point(subpass.bind_point)
                 .color_attachments(&color_attachments);
 
-            if let Some(i) = subpass.depth_stencil_attachment {
-                subpass_description =
-                    subpass_description.depth_stencil_attachment(&self.references[i]);
-
-                subpasses_references.push(Vec::from([self.references[i]]).into_boxed_slice());
+            if let Some(reference) = depth_stencil_reference {
+                subpass_description = subpass_description.depth_stencil_attachment(reference);
             }
 
             subpasses.push(subpass_description.build());
@@ -144,14 +285,19 @@ impl RenderPlanBuilder<'_> {
             subpasses_references.push(color_attachments);
         }
 
-        let render_pass = unsafe {
-            self.app.device.create_render_pass2(
-                &vk::RenderPassCreateInfo2::builder()
-                    .attachments(&self.attachments)
-                    .dependencies(&self.dependencies)
-                    .subpasses(&subpasses),
-                None,
-            )?
+        // The subpasses are recorded one after the other, with barriers in between
+        let render_pass = if self.app.dynamic_rendering.is_some() {
+            vk::RenderPass::null()
+        } else {
+            unsafe {
+                self.app.device.create_render_pass2(
+                    &vk::RenderPassCreateInfo2::builder()
+                        .attachments(&self.attachments)
+                        .dependencies(&self.dependencies)
+                        .subpasses(&subpasses),
+                    None,
+                )?
+            }
         };
 
         Ok(self.app.render_plan_storage.insert(RenderPlan {
@@ -164,10 +310,39 @@ impl RenderPlanBuilder<'_> {
     }
 }
 
+/// How a subpass is allowed to access its depth/stencil attachment.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum DepthStencilAccess {
+    ReadWrite,
+    /// Both depth and stencil are read only, ex: after a depth prepass.
+    ReadOnly,
+    /// Depth is read only but stencil can still be written.
+    DepthReadOnly,
+    /// Stencil is read only but depth can still be written.
+    StencilReadOnly,
+}
+
+impl DepthStencilAccess {
+    pub(crate) fn layout(self) -> vk::ImageLayout {
+        match self {
+            Self::ReadWrite => vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            Self::ReadOnly => vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+            Self::DepthReadOnly => vk::ImageLayout::DEPTH_READ_ONLY_STENCIL_ATTACHMENT_OPTIMAL,
+            Self::StencilReadOnly => vk::ImageLayout::DEPTH_ATTACHMENT_STENCIL_READ_ONLY_OPTIMAL,
+        }
+    }
+
+    /// Whether pipelines of the subpass may write the depth.
+    pub(crate) fn depth_writable(self) -> bool {
+        matches!(self, Self::ReadWrite | Self::StencilReadOnly)
+    }
+}
+
 pub struct SubpassBuilder {
     bind_point: vk::PipelineBindPoint,
-    color_attachments: Box<[usize]>,
-    depth_stencil_attachment: Option<usize>,
+    pub(crate) color_attachments: Box<[usize]>,
+    pub(crate) depth_stencil_attachment: Option<usize>,
+    pub(crate) depth_stencil_access: DepthStencilAccess,
 }
 
 impl Default for SubpassBuilder {
@@ -177,6 +352,7 @@ impl Default for SubpassBuilder {
             bind_point: vk::PipelineBindPoint::GRAPHICS,
             color_attachments: Box::default(),
             depth_stencil_attachment: None,
+            depth_stencil_access: DepthStencilAccess::ReadWrite,
         }
     }
 }
@@ -201,8 +377,22 @@ impl SubpassBuilder {
         self
     }
 
-    pub fn depth_stencil_attachment(mut self, attachment: usize) -> Self {
+    pub fn depth_stencil_attachment(self, attachment: usize) -> Self {
+        self.depth_stencil_attachment_with_access(attachment, DepthStencilAccess::ReadWrite)
+    }
+
+    /// Use the depth/stencil attachment without writing to it, ex: after a depth prepass.
+    pub fn depth_stencil_attachment_read_only(self, attachment: usize) -> Self {
+        self.depth_stencil_attachment_with_access(attachment, DepthStencilAccess::ReadOnly)
+    }
+
+    pub fn depth_stencil_attachment_with_access(
+        mut self,
+        attachment: usize,
+        access: DepthStencilAccess,
+    ) -> Self {
         self.depth_stencil_attachment = Some(attachment);
+        self.depth_stencil_access = access;
         self
     }
 }
@@ -21,20 +21,25 @@ impl VkTracerApp {
 
         let attachment_views = attachments.iter().map(|a| a.view).collect::<Vec<_>>();
 
-        let framebuffer = unsafe {
-            self.device.create_framebuffer(
-                &vk::FramebufferCreateInfo::builder()
-                    .render_pass(render_plan.render_pass)
-                    .attachments(&attachment_views)
-                    .width(attachments[0].extent.width)
-                    .height(attachments[0].extent.height)
-                    .layers(1),
-                None,
-            )?
+        let framebuffer = if render_plan.is_dynamic() {
+            vk::Framebuffer::null()
+        } else {
+            unsafe {
+                self.device.create_framebuffer(
+                    &vk::FramebufferCreateInfo::builder()
+                        .render_pass(render_plan.render_pass)
+                        .attachments(&attachment_views)
+                        .width(attachments[0].extent.width)
+                        .height(attachments[0].extent.height)
+                        .layers(1),
+                    None,
+                )?
+            }
         };
 
         Ok(self.render_target_storage.insert(RenderTarget {
             framebuffer,
+            attachments: Box::from(attachments),
             extent: attachments[0].extent,
         }))
     }
@@ -67,16 +72,20 @@ impl VkTracerApp {
             attachment_views[i] = attachment.view;
         }
 
-        let framebuffer = unsafe {
-            self.device.create_framebuffer(
-                &vk::FramebufferCreateInfo::builder()
-                    .render_pass(render_plan.render_pass)
-                    .attachments(&attachment_views)
-                    .width(new_window_size.0)
-                    .height(new_window_size.1)
-                    .layers(1),
-                None,
-            )?
+        let framebuffer = if render_plan.is_dynamic() {
+            vk::Framebuffer::null()
+        } else {
+            unsafe {
+                self.device.create_framebuffer(
+                    &vk::FramebufferCreateInfo::builder()
+                        .render_pass(render_plan.render_pass)
+                        .attachments(&attachment_views)
+                        .width(new_window_size.0)
+                        .height(new_window_size.1)
+                        .layers(1),
+                    None,
+                )?
+            }
         };
 
         render_target.extent = vk::Extent2D::builder()
@@ -84,11 +93,15 @@ impl VkTracerApp {
             .height(new_window_size.1)
             .build();
         render_target.framebuffer = framebuffer;
+        render_target.attachments = Box::from(&attachments[..]);
         Ok(())
     }
 }
 
 pub(crate) struct RenderTarget {
+    /// Null with dynamic rendering.
     pub(crate) framebuffer: vk::Framebuffer,
+    /// Begin the rendering with dynamic rendering.
+    pub(crate) attachments: Box<[ImageViewFatHandle]>,
     pub(crate) extent: vk::Extent2D,
 }
@@ -1,13 +1,13 @@
 use crate::{
     command_recorder::QueueType,
-    errors::{HandleType, Result},
-    render::{RenderablePipelineHandle, VkRecordable},
-    RenderPlanHandle, RenderTargetHandle, RendererHandle, VkTracerApp,
-};
-use ash::{
-    version::{DeviceV1_0, DeviceV1_2},
-    vk,
+    errors::{HandleType, Result, VkTracerError},
+    render::{
+        push_constants_as_bytes, PushConstants, RenderPlan, RenderTarget, RenderablePipelineHandle,
+        SubpassFormats, VkRecordable,
+    },
+    ForwardPipelineHandle, RenderPlanHandle, RenderTargetHandle, RendererHandle, VkTracerApp,
 };
+use ash::{version::DeviceV1_0, vk};
 
 impl VkTracerApp {
     pub fn new_renderer_from_plan(
@@ -22,6 +22,7 @@ impl VkTracerApp {
             current_subpass: 0,
             pipelines_by_subpass: vec![Vec::with_capacity(1)],
             pipelines_amount: 0,
+            record_each_frame: None,
         }
     }
 
@@ -31,18 +32,21 @@ impl VkTracerApp {
         render_target: RenderTargetHandle,
     ) -> Result<()> {
         // We do this like that because otherwise the builder can't borrow &mut self
+        let pool = self.queue_and_pool(QueueType::Graphics)?.1;
         let (render_plan, pipelines_by_subpass, pipelines_amount) = {
             let renderer =
                 storage_access_mut!(self.renderer_storage, renderer, HandleType::Renderer);
 
-            // Destroy old
+            // Nothing is prerecorded, the next frames will just use the new target
+            if let RendererCommands::EachFrame { .. } = renderer.commands {
+                renderer.render_target = render_target;
+                return Ok(());
+            }
+
+            // Destroy old, the command buffers may still be used by frames in flight
             unsafe {
-                let pool = self.command_pools.get(&QueueType::Graphics).unwrap().1;
-                self.device
-                    .free_command_buffers(pool, &[renderer.main_commands]);
-                self.device
-                    .free_command_buffers(pool, &renderer.secondary_commands);
-                self.device.destroy_fence(renderer.render_fence, None);
+                self.device.device_wait_idle()?;
+                renderer.commands.free(&self.device, pool);
             }
 
             (
@@ -59,31 +63,304 @@ impl VkTracerApp {
             current_subpass: 0,
             pipelines_by_subpass,
             pipelines_amount,
+            record_each_frame: None,
         };
-        let ((main_commands, secondary_commands), fence) = builder.inner_build()?;
+        let (main, secondary) = builder.inner_build()?;
         let pipelines_by_subpass = builder.pipelines_by_subpass;
 
         let renderer = storage_access_mut!(self.renderer_storage, renderer, HandleType::Renderer);
         renderer.pipelines_by_subpass = pipelines_by_subpass;
-        renderer.main_commands = main_commands;
-        renderer.secondary_commands = secondary_commands;
-        renderer.render_fence = fence;
+        renderer.render_target = render_target;
+        renderer.commands = RendererCommands::Prerecorded { main, secondary };
+
+        Ok(())
+    }
+
+    /// Get the commands to submit for this frame, recording them first if the renderer
+    /// is in [RendererBuilder::record_each_frame] mode.
+    /// The caller must make sure the previous submission of this frame is finished.
+    pub(crate) fn renderer_commands_for_frame(
+        &mut self,
+        renderer: RendererHandle,
+        frame_index: usize,
+    ) -> Result<vk::CommandBuffer> {
+        let (commands, mut record, render_plan, render_target) = {
+            let renderer =
+                storage_access_mut!(self.renderer_storage, renderer, HandleType::Renderer);
+            match &mut renderer.commands {
+                RendererCommands::Prerecorded { main, .. } => return Ok(*main),
+                RendererCommands::EachFrame { per_frame, record } => (
+                    per_frame[frame_index % per_frame.len()],
+                    // Taken out so the closure can borrow the app
+                    record
+                        .take()
+                        .ok_or(VkTracerError::RendererRecordingReentered)?,
+                    renderer.render_plan,
+                    renderer.render_target,
+                ),
+            }
+        };
+
+        let result = self.record_frame(
+            renderer,
+            frame_index,
+            commands,
+            &mut record,
+            render_plan,
+            render_target,
+        );
+
+        if let RendererCommands::EachFrame { record: slot, .. } =
+            &mut storage_access_mut!(self.renderer_storage, renderer, HandleType::Renderer).commands
+        {
+            *slot = Some(record);
+        }
+
+        result.map(|_| commands)
+    }
+
+    fn record_frame(
+        &self,
+        renderer: RendererHandle,
+        frame_index: usize,
+        commands: vk::CommandBuffer,
+        record: &mut RecordFn,
+        render_plan: RenderPlanHandle,
+        render_target: RenderTargetHandle,
+    ) -> Result<()> {
+        let pipelines_by_subpass =
+            &storage_access!(self.renderer_storage, renderer, HandleType::Renderer)
+                .pipelines_by_subpass;
+        let render_plan = storage_access!(
+            self.render_plan_storage,
+            render_plan,
+            HandleType::RenderPlan
+        );
+        let render_target = storage_access!(
+            self.render_target_storage,
+            render_target,
+            HandleType::RenderTarget
+        );
+
+        unsafe {
+            self.device
+                .reset_command_buffer(commands, vk::CommandBufferResetFlags::empty())?;
+            self.device.begin_command_buffer(
+                commands,
+                &vk::CommandBufferBeginInfo::builder()
+                    .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT),
+            )?;
+            self.cmd_begin_subpass(
+                commands,
+                render_plan,
+                render_target,
+                0,
+                vk::SubpassContents::INLINE,
+            )?;
+        }
+
+        let recording = self.recorded_frames.get();
+        self.recorded_frames.set(recording + 1);
+
+        let mut recorder = FrameRecorder {
+            app: self,
+            commands,
+            frame_index,
+            recording,
+            extent: render_target.extent,
+            render_plan,
+            render_target,
+            pipelines_by_subpass,
+            current_subpass: 0,
+        };
+        self.begin_debug_label(commands, format_args!("Subpass 0"));
+        recorder.execute_prerecorded_pipelines()?;
+        record(&mut recorder)?;
+
+        // Go through the subpasses the closure didn't reach
+        while recorder.current_subpass + 1 < render_plan.subpasses.len() {
+            recorder.next_subpass()?;
+        }
+
+        self.end_debug_label(commands);
+        unsafe {
+            self.cmd_end_subpass(
+                commands,
+                render_plan,
+                render_target,
+                recorder.current_subpass,
+            )?;
+            self.device.end_command_buffer(commands)?;
+        }
 
         Ok(())
     }
 }
 
+type RecordFn = Box<dyn FnMut(&mut FrameRecorder) -> Result<()>>;
+
+pub(crate) enum RendererCommands {
+    Prerecorded {
+        main: vk::CommandBuffer,
+        secondary: Box<[vk::CommandBuffer]>,
+    },
+    EachFrame {
+        per_frame: Box<[vk::CommandBuffer]>,
+        record: Option<RecordFn>,
+    },
+}
+
+impl RendererCommands {
+    pub(crate) unsafe fn free(&self, device: &ash::Device, pool: vk::CommandPool) {
+        match self {
+            RendererCommands::Prerecorded { main, secondary } => {
+                device.free_command_buffers(pool, std::slice::from_ref(main));
+                device.free_command_buffers(pool, secondary);
+            }
+            RendererCommands::EachFrame { per_frame, .. } => {
+                device.free_command_buffers(pool, per_frame);
+            }
+        }
+    }
+}
+
 pub(crate) struct Renderer {
-    pub(crate) main_commands: vk::CommandBuffer,
-    secondary_commands: Box<[vk::CommandBuffer]>,
-    pub(crate) render_fence: vk::Fence,
+    pub(crate) commands: RendererCommands,
 
     // For recreation
     render_plan: RenderPlanHandle,
+    render_target: RenderTargetHandle,
     pipelines_by_subpass: Vec<Vec<RenderablePipelineHandle>>,
     pipelines_amount: u32,
 }
 
+/// Records the commands of a renderer in [RendererBuilder::record_each_frame] mode.
+/// The render pass is already started and the pipelines given to
+/// [RendererBuilder::execute_pipeline] are drawn at the start of their subpass.
+pub struct FrameRecorder<'a> {
+    pub(crate) app: &'a VkTracerApp,
+    pub(crate) commands: vk::CommandBuffer,
+    pub(crate) frame_index: usize,
+    /// Different for every recording, even of the same frame index.
+    pub(crate) recording: u64,
+    extent: vk::Extent2D,
+    render_plan: &'a RenderPlan,
+    render_target: &'a RenderTarget,
+    pipelines_by_subpass: &'a [Vec<RenderablePipelineHandle>],
+    current_subpass: usize,
+}
+
+impl FrameRecorder<'_> {
+    pub fn execute_pipeline(
+        &mut self,
+        pipeline: impl Into<RenderablePipelineHandle>,
+    ) -> Result<()> {
+        let pipeline = pipeline.into();
+        self.app
+            .begin_debug_label(self.commands, format_args!("{:?}", pipeline));
+        match pipeline {
+            RenderablePipelineHandle::Forward(handle) => {
+                let pipeline = storage_access!(
+                    self.app.forward_pipeline_storage,
+                    handle,
+                    HandleType::ForwardPipeline
+                );
+                unsafe { pipeline.record_commands(self.app, self.extent, self.commands)? };
+            }
+        }
+        self.app.end_debug_label(self.commands);
+        Ok(())
+    }
+
+    /// Draw a forward pipeline with push constants only valid for this frame.
+    pub fn execute_pipeline_with_push_constants<P: PushConstants>(
+        &mut self,
+        pipeline: ForwardPipelineHandle,
+        data: &P,
+    ) -> Result<()> {
+        let data = push_constants_as_bytes(data)?;
+        self.app.begin_debug_label(
+            self.commands,
+            format_args!("{:?}", RenderablePipelineHandle::Forward(pipeline)),
+        );
+        let pipeline = storage_access!(
+            self.app.forward_pipeline_storage,
+            pipeline,
+            HandleType::ForwardPipeline
+        );
+        unsafe {
+            pipeline.record_commands_with_push_constants(
+                self.app,
+                self.extent,
+                self.commands,
+                Some(data),
+            )?;
+        }
+        self.app.end_debug_label(self.commands);
+        Ok(())
+    }
+
+    pub fn next_subpass(&mut self) -> Result<()> {
+        if self.current_subpass + 1 >= self.render_plan.subpasses.len() {
+            return Ok(());
+        }
+
+        self.app.end_debug_label(self.commands);
+        unsafe {
+            self.app.cmd_end_subpass(
+                self.commands,
+                self.render_plan,
+                self.render_target,
+                self.current_subpass,
+            )?;
+            self.app.cmd_begin_subpass(
+                self.commands,
+                self.render_plan,
+                self.render_target,
+                self.current_subpass + 1,
+                vk::SubpassContents::INLINE,
+            )?;
+        }
+        self.current_subpass += 1;
+        self.app.begin_debug_label(
+            self.commands,
+            format_args!("Subpass {}", self.current_subpass),
+        );
+        self.execute_prerecorded_pipelines()
+    }
+
+    fn execute_prerecorded_pipelines(&mut self) -> Result<()> {
+        if let Some(pipelines) = self.pipelines_by_subpass.get(self.current_subpass) {
+            for pipeline in pipelines.iter().copied() {
+                self.execute_pipeline(pipeline)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// For custom drawing (UI...), the commands are inside the render pass.
+    pub fn device(&self) -> &ash::Device {
+        &self.app.device
+    }
+
+    pub fn commands(&self) -> vk::CommandBuffer {
+        self.commands
+    }
+
+    pub fn extent(&self) -> vk::Extent2D {
+        self.extent
+    }
+
+    pub fn current_subpass(&self) -> usize {
+        self.current_subpass
+    }
+
+    /// The frame in flight being recorded, to pick per-frame resources.
+    pub fn frame_index(&self) -> usize {
+        self.frame_index
+    }
+}
+
 pub struct RendererBuilder<'app> {
     app: &'app mut VkTracerApp,
     render_plan: RenderPlanHandle,
@@ -91,9 +368,10 @@ pub struct RendererBuilder<'app> {
     current_subpass: usize,
     pipelines_by_subpass: Vec<Vec<RenderablePipelineHandle>>,
     pipelines_amount: u32,
+    record_each_frame: Option<RecordFn>,
 }
 
-type RendererData = ((vk::CommandBuffer, Box<[vk::CommandBuffer]>), vk::Fence);
+type RendererData = (vk::CommandBuffer, Box<[vk::CommandBuffer]>);
 impl RendererBuilder<'_> {
     pub fn execute_pipeline(mut self, pipeline: RenderablePipelineHandle) -> Self {
         self.pipelines_by_subpass[self.current_subpass].push(pipeline);
@@ -107,7 +385,65 @@ impl RendererBuilder<'_> {
         self
     }
 
+    /// Record the commands again at each frame instead of once, the closure is called with
+    /// the render pass started and can draw a different set of objects every time.
+    /// No need to call [VkTracerApp::recreate_renderer] to see changes in push constants.
+    pub fn record_each_frame(
+        mut self,
+        record: impl FnMut(&mut FrameRecorder) -> Result<()> + 'static,
+    ) -> Self {
+        self.record_each_frame = Some(Box::new(record));
+        self
+    }
+
     fn inner_build(&self) -> Result<RendererData> {
+        let device = &self.app.device;
+        let pool = self.app.queue_and_pool(QueueType::Graphics)?.1;
+
+        unsafe {
+            // All the secondary command buffers necessary for all subpasses
+            let secondary_commands = if self.pipelines_amount == 0 {
+                Vec::new()
+            } else {
+                device.allocate_command_buffers(
+                    &vk::CommandBufferAllocateInfo::builder()
+                        .command_pool(pool)
+                        .level(vk::CommandBufferLevel::SECONDARY)
+                        .command_buffer_count(self.pipelines_amount),
+                )?
+            };
+            let top_level_commands = match device.allocate_command_buffers(
+                &vk::CommandBufferAllocateInfo::builder()
+                    .command_pool(pool)
+                    .level(vk::CommandBufferLevel::PRIMARY)
+                    .command_buffer_count(1),
+            ) {
+                Ok(commands) => commands[0],
+                Err(err) => {
+                    if !secondary_commands.is_empty() {
+                        device.free_command_buffers(pool, &secondary_commands);
+                    }
+                    return Err(err.into());
+                }
+            };
+
+            if let Err(err) = self.record_commands(top_level_commands, &secondary_commands) {
+                device.free_command_buffers(pool, std::slice::from_ref(&top_level_commands));
+                if !secondary_commands.is_empty() {
+                    device.free_command_buffers(pool, &secondary_commands);
+                }
+                return Err(err);
+            }
+            Ok((top_level_commands, secondary_commands.into_boxed_slice()))
+        }
+    }
+
+    /// Record a secondary command buffer per pipeline, executed by the top level one.
+    unsafe fn record_commands(
+        &self,
+        top_level_commands: vk::CommandBuffer,
+        secondary_commands: &[vk::CommandBuffer],
+    ) -> Result<()> {
         let render_plan = storage_access!(
             self.app.render_plan_storage,
             self.render_plan,
@@ -118,138 +454,136 @@ impl RendererBuilder<'_> {
             self.render_target,
             HandleType::RenderTarget
         );
-
         let device = &self.app.device;
-        let pool = self.app.command_pools.get(&QueueType::Graphics).unwrap();
 
-        let commands = unsafe {
-            // Record secondary command buffers
-
-            let mut secondary_commands_by_subpass = {
-                // Allocate all the command buffer necessary for all subpasses
-                let mut command_pool = device.allocate_command_buffers(
-                    &vk::CommandBufferAllocateInfo::builder()
-                        .command_pool(pool.1)
-                        .level(vk::CommandBufferLevel::SECONDARY)
-                        .command_buffer_count(self.pipelines_amount as u32),
-                )?;
-
-                let mut commands_by_subpass = Vec::with_capacity(self.pipelines_by_subpass.len());
-
-                // Iterate through each subpass and record a command buffer at a time
-                for (i, subpass) in self.pipelines_by_subpass.iter().enumerate() {
-                    let mut subpass_commands = Vec::with_capacity(subpass.len());
-
-                    for pipeline in subpass.iter().copied() {
-                        // Take a command buffer from the stash
-                        let commands = command_pool.pop().unwrap();
-
-                        device.begin_command_buffer(
-                            commands,
-                            &vk::CommandBufferBeginInfo::builder()
-                                .flags(vk::CommandBufferUsageFlags::RENDER_PASS_CONTINUE)
-                                .inheritance_info(
-                                    &vk::CommandBufferInheritanceInfo::builder()
-                                        .render_pass(render_plan.render_pass)
-                                        .subpass(i as u32)
-                                        .framebuffer(render_target.framebuffer),
-                                ),
-                        )?;
-
-                        match pipeline {
-                            RenderablePipelineHandle::Forward(handle) => {
-                                let pipeline = storage_access!(
-                                    self.app.forward_pipeline_storage,
-                                    handle,
-                                    HandleType::ForwardPipeline
-                                );
-                                pipeline.record_commands(
-                                    self.app,
-                                    render_target.extent,
-                                    commands,
-                                )?;
-                            }
-                        }
+        // Record secondary command buffers
 
-                        device.end_command_buffer(commands)?;
-                        subpass_commands.push(commands);
-                    }
-                    commands_by_subpass.push(subpass_commands);
-                }
-                commands_by_subpass
-            };
+        let secondary_commands_by_subpass = {
+            let mut command_pool = secondary_commands.iter().copied();
+            let mut commands_by_subpass = Vec::with_capacity(self.pipelines_by_subpass.len());
 
-            // Record top level command buffer
+            // Iterate through each subpass and record a command buffer at a time
+            for (i, subpass) in self.pipelines_by_subpass.iter().enumerate() {
+                let mut subpass_commands = Vec::with_capacity(subpass.len());
 
-            let top_level_commands = device.allocate_command_buffers(
-                &vk::CommandBufferAllocateInfo::builder()
-                    .command_pool(pool.1)
-                    .level(vk::CommandBufferLevel::PRIMARY)
-                    .command_buffer_count(1),
-            )?[0];
-
-            device
-                .begin_command_buffer(top_level_commands, &vk::CommandBufferBeginInfo::default())?;
-
-            device.cmd_begin_render_pass2(
-                top_level_commands,
-                &vk::RenderPassBeginInfo::builder()
+                // Replaces the render pass with dynamic rendering
+                let subpass_formats = Some(render_plan)
+                    .filter(|plan| plan.is_dynamic())
+                    .map(|plan| plan.subpass_formats(i));
+                let mut rendering_info = subpass_formats
+                    .as_ref()
+                    .map(SubpassFormats::inheritance_info);
+                let mut inheritance_info = vk::CommandBufferInheritanceInfo::builder()
                     .render_pass(render_plan.render_pass)
-                    .framebuffer(render_target.framebuffer)
-                    .render_area(
-                        vk::Rect2D::builder()
-                            .offset(vk::Offset2D::default())
-                            .extent(render_target.extent)
-                            .build(),
-                    )
-                    .clear_values(&render_plan.clear_values),
-                &vk::SubpassBeginInfo::builder()
-                    .contents(vk::SubpassContents::SECONDARY_COMMAND_BUFFERS),
-            );
-
-            let mut secondary_commands = Vec::with_capacity(self.pipelines_amount as usize);
-            loop {
-                let subpass_commands = secondary_commands_by_subpass.pop().unwrap();
-                device.cmd_execute_commands(top_level_commands, &subpass_commands);
-                secondary_commands.extend(subpass_commands);
-
-                if secondary_commands_by_subpass.is_empty() {
-                    break;
+                    .subpass(i as u32)
+                    .framebuffer(render_target.framebuffer);
+                if let Some(rendering_info) = rendering_info.as_mut() {
+                    inheritance_info = inheritance_info.subpass(0).push_next(rendering_info);
                 }
 
-                device.cmd_next_subpass2(
-                    top_level_commands,
-                    &vk::SubpassBeginInfo::builder()
-                        .contents(vk::SubpassContents::SECONDARY_COMMAND_BUFFERS),
-                    &vk::SubpassEndInfo::default(),
-                );
-            }
-
-            device.cmd_end_render_pass2(top_level_commands, &vk::SubpassEndInfo::default());
+                for pipeline in subpass.iter().copied() {
+                    // Take a command buffer from the stash
+                    let commands = command_pool.next().unwrap();
+
+                    device.begin_command_buffer(
+                        commands,
+                        &vk::CommandBufferBeginInfo::builder()
+                            .flags(
+                                vk::CommandBufferUsageFlags::RENDER_PASS_CONTINUE
+                                    | vk::CommandBufferUsageFlags::SIMULTANEOUS_USE,
+                            )
+                            .inheritance_info(&inheritance_info),
+                    )?;
+
+                    self.app
+                        .begin_debug_label(commands, format_args!("{:?}", pipeline));
+                    match pipeline {
+                        RenderablePipelineHandle::Forward(handle) => {
+                            let pipeline = storage_access!(
+                                self.app.forward_pipeline_storage,
+                                handle,
+                                HandleType::ForwardPipeline
+                            );
+                            pipeline.record_commands(self.app, render_target.extent, commands)?;
+                        }
+                    }
+                    self.app.end_debug_label(commands);
 
-            device.end_command_buffer(top_level_commands)?;
-            (top_level_commands, secondary_commands.into_boxed_slice())
+                    device.end_command_buffer(commands)?;
+                    subpass_commands.push(commands);
+                }
+                commands_by_subpass.push(subpass_commands);
+            }
+            commands_by_subpass
         };
 
-        // Create the fence already signaled because otherwise we will block infinitely when rendering for the first time
-        let render_fence = unsafe {
-            device.create_fence(
-                &vk::FenceCreateInfo::builder().flags(vk::FenceCreateFlags::SIGNALED),
-                None,
-            )?
-        };
+        // Record top level command buffer
+
+        // Submitted again by the next frames while the previous ones are still pending
+        device.begin_command_buffer(
+            top_level_commands,
+            &vk::CommandBufferBeginInfo::builder()
+                .flags(vk::CommandBufferUsageFlags::SIMULTANEOUS_USE),
+        )?;
+
+        // Every subpass of the plan is started, even the ones without pipelines
+        let mut secondary_commands_by_subpass = secondary_commands_by_subpass.into_iter();
+        for i in 0..render_plan.subpasses.len() {
+            let subpass_commands = secondary_commands_by_subpass.next().unwrap_or_default();
+            if i > 0 {
+                self.app
+                    .cmd_end_subpass(top_level_commands, render_plan, render_target, i - 1)?;
+            }
+            self.app.cmd_begin_subpass(
+                top_level_commands,
+                render_plan,
+                render_target,
+                i,
+                vk::SubpassContents::SECONDARY_COMMAND_BUFFERS,
+            )?;
+
+            self.app
+                .begin_debug_label(top_level_commands, format_args!("Subpass {}", i));
+            if !subpass_commands.is_empty() {
+                device.cmd_execute_commands(top_level_commands, &subpass_commands);
+            }
+            self.app.end_debug_label(top_level_commands);
+        }
+        self.app.cmd_end_subpass(
+            top_level_commands,
+            render_plan,
+            render_target,
+            render_plan.subpasses.len() - 1,
+        )?;
 
-        Ok((commands, render_fence))
+        device.end_command_buffer(top_level_commands)?;
+        Ok(())
     }
 
-    pub fn build(self) -> Result<RendererHandle> {
-        let (commands, render_fence) = self.inner_build()?;
+    pub fn build(mut self) -> Result<RendererHandle> {
+        let commands = if let Some(record) = self.record_each_frame.take() {
+            // One command buffer per frame in flight, reset before each recording
+            let per_frame = unsafe {
+                self.app.device.allocate_command_buffers(
+                    &vk::CommandBufferAllocateInfo::builder()
+                        .command_pool(self.app.queue_and_pool(QueueType::Graphics)?.1)
+                        .level(vk::CommandBufferLevel::PRIMARY)
+                        .command_buffer_count(self.app.adapter.requirements.frames_in_flight),
+                )?
+            };
+            RendererCommands::EachFrame {
+                per_frame: per_frame.into_boxed_slice(),
+                record: Some(record),
+            }
+        } else {
+            let (main, secondary) = self.inner_build()?;
+            RendererCommands::Prerecorded { main, secondary }
+        };
 
         Ok(self.app.renderer_storage.insert(Renderer {
-            main_commands: commands.0,
-            secondary_commands: commands.1,
-            render_fence,
+            commands,
             render_plan: self.render_plan,
+            render_target: self.render_target,
             pipelines_by_subpass: self.pipelines_by_subpass,
             pipelines_amount: self.pipelines_amount,
         }))
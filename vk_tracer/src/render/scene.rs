@@ -0,0 +1,119 @@
+use crate::{
+    errors::Result,
+    render::{GraphicsRecorder, PushConstants},
+    DescriptorSetHandle, EntityHandle, ForwardPipelineHandle, MeshHandle,
+};
+use nalgebra_glm as glm;
+use slotmap::SlotMap;
+
+/// Something to draw: a mesh, the pipeline and material it is drawn with and where.
+#[derive(Copy, Clone, Debug)]
+pub struct SceneEntity {
+    pub mesh: MeshHandle,
+    pub pipeline: ForwardPipelineHandle,
+    /// Descriptor set bound at [Scene::material_set], if any.
+    pub material: Option<DescriptorSetHandle>,
+    pub transform: glm::Mat4,
+}
+
+/// Entities to draw, their handles stay valid until they are removed.
+pub struct Scene {
+    pub(crate) entities: SlotMap<EntityHandle, SceneEntity>,
+    material_set: u32,
+}
+
+impl Scene {
+    /// The materials of the entities are bound at set `material_set` of their pipeline.
+    pub fn new(material_set: u32) -> Self {
+        Self {
+            entities: SlotMap::with_key(),
+            material_set,
+        }
+    }
+
+    pub fn material_set(&self) -> u32 {
+        self.material_set
+    }
+
+    pub fn insert(&mut self, entity: SceneEntity) -> EntityHandle {
+        self.entities.insert(entity)
+    }
+
+    pub fn remove(&mut self, handle: EntityHandle) -> Option<SceneEntity> {
+        self.entities.remove(handle)
+    }
+
+    pub fn get(&self, handle: EntityHandle) -> Option<&SceneEntity> {
+        self.entities.get(handle)
+    }
+
+    pub fn get_mut(&mut self, handle: EntityHandle) -> Option<&mut SceneEntity> {
+        self.entities.get_mut(handle)
+    }
+
+    pub fn set_transform(&mut self, handle: EntityHandle, transform: glm::Mat4) {
+        if let Some(entity) = self.entities.get_mut(handle) {
+            entity.transform = transform;
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (EntityHandle, &SceneEntity)> {
+        self.entities.iter()
+    }
+
+    /// The entities sorted by pipeline, then material, then mesh, to change as few states as
+    /// possible between draws.
+    pub fn draw_list(&self) -> Vec<EntityHandle> {
+        let mut list = self.entities.keys().collect::<Vec<_>>();
+        list.sort_by_key(|handle| {
+            let entity = &self.entities[*handle];
+            (entity.pipeline, entity.material, entity.mesh)
+        });
+        list
+    }
+}
+
+impl GraphicsRecorder<'_> {
+    /// Draw every entity of the scene following [Scene::draw_list], only binding the pipeline,
+    /// material and mesh when they change. The push constants of each draw are built from
+    /// the entity, typically with its transform.
+    pub fn draw_scene<P: PushConstants>(
+        self,
+        scene: &Scene,
+        push_constants: impl Fn(&SceneEntity) -> P,
+    ) -> Result<Self> {
+        let mut recorder = self;
+        let mut previous: Option<&SceneEntity> = None;
+
+        for handle in scene.draw_list() {
+            let entity = &scene.entities[handle];
+            let pipeline_changed = previous.map_or(true, |p| p.pipeline != entity.pipeline);
+
+            if pipeline_changed {
+                recorder = recorder.bind_pipeline(entity.pipeline)?;
+            }
+            if let Some(material) = entity.material {
+                if pipeline_changed || previous.and_then(|p| p.material) != Some(material) {
+                    recorder = recorder.bind_descriptor_sets(scene.material_set, &[material])?;
+                }
+            }
+            if pipeline_changed || previous.map_or(true, |p| p.mesh != entity.mesh) {
+                recorder = recorder.bind_mesh(entity.mesh)?;
+            }
+
+            recorder = recorder
+                .push_constants(&push_constants(entity))?
+                .draw_mesh(1)?;
+            previous = Some(entity);
+        }
+        Ok(recorder)
+    }
+}
@@ -1,13 +1,26 @@
 mod adapter;
 mod app_builder;
+mod cooperative_matrix;
 mod debug_utils;
+mod device_features;
+mod device_group;
 mod extensions;
 mod physical_device_selection;
 mod queue_indices;
+mod raw_handles;
 
 pub(crate) use adapter::*;
 pub use app_builder::*;
+pub use cooperative_matrix::CooperativeMatrixProperties;
+pub(crate) use cooperative_matrix::*;
 pub(crate) use debug_utils::*;
+pub use debug_utils::{DebugCallback, DebugMessage, ValidationFeatures};
+pub use device_features::DeviceFeatures;
+pub(crate) use device_group::*;
 pub(crate) use extensions::*;
 pub(crate) use physical_device_selection::*;
+pub use physical_device_selection::{
+    AdapterDescription, AdapterSelector, PhysicalDeviceInfo, ADAPTER_OVERRIDE_ENV,
+};
 pub(crate) use queue_indices::*;
+pub use raw_handles::RawQueues;
@@ -12,8 +12,9 @@ use crate::{
     present::Surface,
     setup::{
         required_device_extensions, required_instance_extensions,
-        required_instance_extensions_with_surface, AdapterInfo,
+        required_instance_extensions_with_surface, AdapterInfo, DeviceFeatures,
     },
+    VkTracerApp,
 };
 
 pub struct AdapterRequirements {
@@ -21,9 +22,27 @@ pub struct AdapterRequirements {
     pub instance_extensions: Vec<*const c_char>,
     pub required_extensions: Vec<&'static CStr>,
     pub optional_extensions: Vec<&'static CStr>,
+    /// Enabled on the device on top of the ones needed by vk_tracer.
+    pub features: DeviceFeatures,
+    /// Without it, [crate::command_recorder::QueueType::Graphics] is any queue able to compute.
+    pub require_graphics: bool,
     pub surface_formats: Vec<vk::Format>,
     pub surface_color_spaces: Vec<vk::ColorSpaceKHR>,
     pub present_modes: Vec<vk::PresentModeKHR>,
+    /// Override the amount of swapchain images, chosen from the present mode otherwise.
+    pub swapchain_image_count: Option<u32>,
+    /// Passed as is to the swapchain, MUTABLE_FORMAT and PROTECTED add their own requirements.
+    pub swapchain_flags: vk::SwapchainCreateFlagsKHR,
+    /// Usages of the swapchain images on top of COLOR_ATTACHMENT.
+    pub swapchain_usage: vk::ImageUsageFlags,
+    /// Used if supported by the surface, OPAQUE otherwise.
+    pub swapchain_composite_alpha: vk::CompositeAlphaFlagsKHR,
+    /// How many frames the CPU can record and submit ahead of the GPU.
+    pub frames_in_flight: u32,
+    /// Let the application handle the rotation of the surface instead of the compositor.
+    pub pre_rotation: bool,
+    /// Only used on Windows with `VK_EXT_full_screen_exclusive`.
+    pub full_screen_exclusive_monitor: Option<vk::HMONITOR>,
     pub validation_layers: Vec<&'static str>,
 }
 
@@ -47,9 +66,18 @@ impl Default for AdapterRequirements {
             instance_extensions: required_instance_extensions(false),
             required_extensions: required_device_extensions(),
             optional_extensions: Vec::new(),
+            features: DeviceFeatures::default(),
+            require_graphics: true,
             surface_formats: vec![vk::Format::R8G8B8A8_SRGB, vk::Format::B8G8R8A8_SRGB],
             surface_color_spaces: vec![vk::ColorSpaceKHR::SRGB_NONLINEAR],
             present_modes: vec![vk::PresentModeKHR::MAILBOX],
+            swapchain_image_count: None,
+            swapchain_flags: vk::SwapchainCreateFlagsKHR::empty(),
+            swapchain_usage: vk::ImageUsageFlags::empty(),
+            swapchain_composite_alpha: vk::CompositeAlphaFlagsKHR::OPAQUE,
+            frames_in_flight: 2,
+            pre_rotation: false,
+            full_screen_exclusive_monitor: None,
             validation_layers: Vec::new(),
         }
     }
@@ -62,6 +90,18 @@ pub struct Adapter {
     pub(crate) requirements: AdapterRequirements,
 }
 
+impl VkTracerApp {
+    /// Whether a device extension is enabled, either required or optional and supported.
+    pub fn is_extension_enabled(&self, name: &CStr) -> bool {
+        self.adapter
+            .requirements
+            .required_extensions
+            .iter()
+            .chain(self.adapter.info.optional_extensions.iter())
+            .any(|extension| *extension == name)
+    }
+}
+
 impl Adapter {
     pub(crate) fn new(
         handle: vk::PhysicalDevice,
@@ -74,14 +114,4 @@ impl Adapter {
             requirements,
         }
     }
-
-    pub(crate) fn update_surface_capabilities(&mut self) -> Result<()> {
-        let (loader, surface) = self.requirements.compatible_surface.as_ref().unwrap();
-
-        unsafe {
-            self.info.physical_device_info.surface_capabilities =
-                Some(loader.get_physical_device_surface_capabilities(self.handle, *surface)?);
-        }
-        Ok(())
-    }
 }
@@ -1,20 +1,37 @@
 use crate::{
     command_recorder::QueueType,
-    errors::Result,
+    errors::{Result, VkTracerError},
+    mem::ExternalMemoryFns,
     present::Surface,
+    raytracing::RaytracingFns,
+    render::{
+        dynamic_rendering_extension_name, DynamicRenderingFns,
+        PhysicalDeviceDynamicRenderingFeatures,
+    },
     setup::{
-        debug_utils::DebugUtils,
+        cooperative_matrix_extension_name,
+        debug_utils::{
+            DebugCallback, DebugMessage, DebugMessageFilter, DebugUtils, ValidationFeatures,
+            VALIDATION_LAYER,
+        },
         extensions::{required_instance_extensions, required_instance_extensions_with_surface},
-        pick_adapter, Adapter, AdapterRequirements, QueueFamilyIndices,
+        pick_adapter, Adapter, AdapterDescription, AdapterRequirements, AdapterScoring,
+        AdapterSelector, DeviceFeatures, DeviceGroup, PhysicalDeviceCooperativeMatrixFeatures,
+        PhysicalDeviceInfo, QueueFamilyIndices,
+    },
+    synchronization2::{
+        synchronization2_extension_name, synchronization2_is_core,
+        PhysicalDeviceSynchronization2Features, Synchronization2Fns,
     },
     utils::str_to_cstr,
     VkTracerApp, VULKAN_VERSION,
 };
 use ash::{
-    version::{DeviceV1_0, EntryV1_0, InstanceV1_0},
+    extensions::khr,
+    version::{DeviceV1_0, EntryV1_0, InstanceV1_0, InstanceV1_1},
     vk,
 };
-use log::debug;
+use log::{debug, warn};
 use raw_window_handle::HasRawWindowHandle;
 use slotmap::SlotMap;
 use std::{
@@ -23,34 +40,128 @@ use std::{
     ffi::{CStr, CString},
 };
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Debug)]
 enum PhysicalDevicePreference {
     Best,
+    Selected(AdapterSelector),
 }
 
 #[derive(Hash, Eq, PartialEq, Copy, Clone, Debug)]
 pub enum VkTracerExtensions {
     PipelineRaytracing,
+    /// Link hit groups compiled once into several raytracing pipelines, see
+    /// [crate::raytracing::RaytracingPipelineBuilder::build_library]. Needs
+    /// [VkTracerExtensions::PipelineRaytracing].
+    PipelineLibrary,
+    /// Trace rays from any shader with acceleration structures, without a raytracing pipeline,
+    /// see [crate::raytracing::ShadowPass].
+    RayQuery,
+    /// Matrix-accelerated operations in compute shaders, see
+    /// [VkTracerApp::cooperative_matrix_properties].
+    CooperativeMatrix,
+    /// Read the subgroup clock in shaders, see [crate::utils::SHADER_CLOCK_HEATMAP_GLSL].
+    ShaderClock,
+    /// Control exclusive fullscreen of the swapchain, see
+    /// [VkTracerApp::acquire_full_screen_exclusive].
+    FullScreenExclusive,
+    /// Schedule presentation, see [VkTracerApp::set_target_present_interval].
+    DisplayTiming,
+    /// Correlate GPU profiler scopes with the host clock, see [crate::render::GpuScopeTiming].
+    CalibratedTimestamps,
+    /// Share memory and semaphores with other APIs or processes, see
+    /// [VkTracerApp::export_memory].
+    ExternalMemory,
+    /// Record the render plans without render pass nor framebuffer objects.
+    DynamicRendering,
+    /// Submit and record the barriers with `vkQueueSubmit2` and `vkCmdPipelineBarrier2`.
+    Synchronization2,
+    /// Bounds checked buffer and image accesses and null descriptors, as far as the device
+    /// supports them. Makes partially bound sets and out of bounds accesses harmless while
+    /// developing, at a performance cost.
+    Robustness2,
 }
 
 pub struct VkTracerAppBuilder {
     physical_device_preference: PhysicalDevicePreference,
+    adapter_scoring: Option<AdapterScoring>,
     app_name: Cow<'static, str>,
     version: (u32, u32, u32),
     debug_utils: bool,
+    debug_filter: DebugMessageFilter,
+    debug_callback: Option<DebugCallback>,
+    validation_features: Option<ValidationFeatures>,
+    device_group: bool,
+    swapchain_image_count: Option<u32>,
+    swapchain_flags: vk::SwapchainCreateFlagsKHR,
+    swapchain_usage: vk::ImageUsageFlags,
+    swapchain_composite_alpha: vk::CompositeAlphaFlagsKHR,
+    swapchain_unorm: bool,
+    frames_in_flight: u32,
+    pre_rotation: bool,
+    displays: bool,
+    compute_only: bool,
+    full_screen_exclusive_monitor: Option<vk::HMONITOR>,
     extensions: HashSet<VkTracerExtensions>,
+    features: DeviceFeatures,
+    instance_extensions: Vec<CString>,
+    instance_layers: Vec<CString>,
+    optional_device_extensions: Vec<&'static CStr>,
 }
 
 impl VkTracerApp {
     pub fn builder() -> VkTracerAppBuilder {
         VkTracerAppBuilder {
             physical_device_preference: PhysicalDevicePreference::Best,
+            adapter_scoring: None,
             app_name: Cow::Borrowed("Unnamed"),
             version: (0, 0, 1),
             debug_utils: false,
+            debug_filter: DebugMessageFilter::default(),
+            debug_callback: None,
+            validation_features: None,
+            device_group: false,
+            swapchain_image_count: None,
+            swapchain_flags: vk::SwapchainCreateFlagsKHR::empty(),
+            swapchain_usage: vk::ImageUsageFlags::empty(),
+            swapchain_composite_alpha: vk::CompositeAlphaFlagsKHR::OPAQUE,
+            swapchain_unorm: false,
+            frames_in_flight: 2,
+            pre_rotation: false,
+            displays: false,
+            compute_only: false,
+            full_screen_exclusive_monitor: None,
             extensions: HashSet::new(),
+            features: DeviceFeatures::default(),
+            instance_extensions: Vec::new(),
+            instance_layers: Vec::new(),
+            optional_device_extensions: Vec::new(),
         }
     }
+
+    /// Describe the adapters of the machine, in the order used by [AdapterSelector::Index].
+    /// Creates a short-lived instance, to be called before building the app.
+    pub fn enumerate_adapters() -> Result<Vec<AdapterDescription>> {
+        let entry = unsafe { ash::Entry::new()? };
+        let app_info = vk::ApplicationInfo::builder().api_version(VULKAN_VERSION);
+        let instance = unsafe {
+            entry.create_instance(
+                &vk::InstanceCreateInfo::builder().application_info(&app_info),
+                None,
+            )?
+        };
+
+        let adapters = unsafe { instance.enumerate_physical_devices() }.map(|physical_devices| {
+            physical_devices
+                .into_iter()
+                .enumerate()
+                .map(|(index, physical_device)| unsafe {
+                    AdapterDescription::query(&instance, index, physical_device)
+                })
+                .collect()
+        });
+        unsafe { instance.destroy_instance(None) };
+        Ok(adapters?)
+    }
 }
 
 impl VkTracerAppBuilder {
@@ -59,6 +170,42 @@ impl VkTracerAppBuilder {
         self
     }
 
+    /// Only consider the adapters matching the selector, among the ones described by
+    /// [VkTracerApp::enumerate_adapters]. The best of them is still picked, building fails with
+    /// [VkTracerError::NoMatchingAdapter] when none matches. [crate::setup::ADAPTER_OVERRIDE_ENV]
+    /// takes precedence.
+    pub fn with_adapter(mut self, selector: impl Into<AdapterSelector>) -> Self {
+        self.physical_device_preference = PhysicalDevicePreference::Selected(selector.into());
+        self
+    }
+
+    /// Add a score to the adapters meeting the requirements, the highest total is picked.
+    /// Returning None rejects the adapter, ex: to require an extension or a memory size. Building
+    /// fails with [VkTracerError::NoSuitableAdapterError] when every adapter is rejected.
+    pub fn with_adapter_score_fn(
+        mut self,
+        score_fn: impl Fn(&PhysicalDeviceInfo) -> Option<u32> + 'static,
+    ) -> Self {
+        self.adapter_scoring = Some(AdapterScoring {
+            score_fn: Box::new(score_fn),
+            replace: false,
+        });
+        self
+    }
+
+    /// Like [VkTracerAppBuilder::with_adapter_score_fn] but ignore the built-in score, which
+    /// prefers discrete GPUs with more memory.
+    pub fn with_adapter_score_override(
+        mut self,
+        score_fn: impl Fn(&PhysicalDeviceInfo) -> Option<u32> + 'static,
+    ) -> Self {
+        self.adapter_scoring = Some(AdapterScoring {
+            score_fn: Box::new(score_fn),
+            replace: true,
+        });
+        self
+    }
+
     pub fn with_app_info(mut self, app_name: Cow<'static, str>, version: (u32, u32, u32)) -> Self {
         self.app_name = app_name;
         self.version = version;
@@ -70,6 +217,164 @@ impl VkTracerAppBuilder {
         self
     }
 
+    /// Only receive the messages of these severities and types, all of them by default.
+    /// Implies [VkTracerAppBuilder::with_debug_utils].
+    pub fn with_debug_message_filter(
+        mut self,
+        severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+        ty: vk::DebugUtilsMessageTypeFlagsEXT,
+    ) -> Self {
+        self.debug_utils = true;
+        self.debug_filter = DebugMessageFilter { severity, ty };
+        self
+    }
+
+    /// Route the messages to the application instead of the logger, ex: return true on errors
+    /// in tests to fail the offending call. Panicking in the callback aborts the process.
+    /// Implies [VkTracerAppBuilder::with_debug_utils].
+    pub fn with_debug_callback(
+        mut self,
+        callback: impl Fn(&DebugMessage) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.debug_utils = true;
+        self.debug_callback = Some(Box::new(callback));
+        self
+    }
+
+    /// Enable the Khronos validation layer with heavier validation modes, building fails if
+    /// the layer isn't installed. Implies [VkTracerAppBuilder::with_debug_utils].
+    pub fn with_validation_features(mut self, mut features: ValidationFeatures) -> Self {
+        if features.gpu_assisted && features.debug_printf {
+            warn!("GPU-assisted validation can't be used along with debug printf, disabling it");
+            features.gpu_assisted = false;
+        }
+        if features.debug_printf {
+            self.optional_device_extensions
+                .push(vk::KhrShaderNonSemanticInfoFn::name());
+        }
+
+        let layer = CString::new(VALIDATION_LAYER).unwrap();
+        if !self.instance_layers.contains(&layer) {
+            self.instance_layers.push(layer);
+        }
+        self.debug_utils = true;
+        self.validation_features = Some(features);
+        self
+    }
+
+    /// Drive every GPU of the device group of the chosen adapter, if there is one.
+    /// Frames will be rendered alternatively by each GPU.
+    pub fn with_device_group(mut self) -> Self {
+        self.device_group = true;
+        self
+    }
+
+    /// Request a specific amount of swapchain images instead of deriving it from the present mode.
+    /// It will still be clamped to what the surface supports.
+    pub fn with_swapchain_image_count(mut self, count: u32) -> Self {
+        self.swapchain_image_count = Some(count);
+        self
+    }
+
+    /// Flags used to create the swapchain.
+    /// - `MUTABLE_FORMAT` enables [VkTracerApp::get_images_from_swapchain_aliased] to view the
+    ///   swapchain images with or without sRGB.
+    /// - `PROTECTED` is rejected by [VkTracerAppBuilder::build], the queues, command pools and
+    ///   submissions aren't protected.
+    pub fn with_swapchain_flags(mut self, flags: vk::SwapchainCreateFlagsKHR) -> Self {
+        self.swapchain_flags = flags;
+        self
+    }
+
+    /// Additional usages of the swapchain images, ex: TRANSFER_SRC to take screenshots.
+    /// Creating the swapchain fails if the surface doesn't support them.
+    pub fn with_swapchain_usage(mut self, usage: vk::ImageUsageFlags) -> Self {
+        self.swapchain_usage = usage;
+        self
+    }
+
+    /// How the swapchain images are blended with the other windows, falls back to OPAQUE if the
+    /// surface doesn't support it.
+    pub fn with_swapchain_composite_alpha(mut self, alpha: vk::CompositeAlphaFlagsKHR) -> Self {
+        self.swapchain_composite_alpha = alpha;
+        self
+    }
+
+    /// Prefer UNORM swapchain formats over sRGB ones, when the shaders output sRGB themselves.
+    pub fn with_unorm_swapchain(mut self) -> Self {
+        self.swapchain_unorm = true;
+        self
+    }
+
+    /// How many frames can be submitted before waiting for the GPU to finish the oldest one.
+    /// Defaults to 2, 1 fully serializes the CPU and the GPU.
+    pub fn with_frames_in_flight(mut self, frames: u32) -> Self {
+        self.frames_in_flight = frames.max(1);
+        self
+    }
+
+    /// Render in the native orientation of the display and rotate in the projection (see
+    /// [crate::utils::Camera::set_pre_transform]) instead of letting the compositor rotate the
+    /// images, which avoids an extra pass on rotated mobile displays.
+    pub fn with_pre_rotation(mut self) -> Self {
+        self.pre_rotation = true;
+        self
+    }
+
+    /// Headless app for compute and transfer work only: no surface, no swapchain and no
+    /// graphics queue required. [crate::command_recorder::QueueType::Graphics] is then the
+    /// main queue, which may not support graphics.
+    pub fn compute_only(mut self) -> Self {
+        self.compute_only = true;
+        self
+    }
+
+    /// Enable [VkTracerApp::enumerate_displays].
+    pub fn with_displays(mut self) -> Self {
+        self.displays = true;
+        self
+    }
+
+    /// The monitor the window is on, required on Windows with
+    /// [VkTracerExtensions::FullScreenExclusive].
+    pub fn with_full_screen_exclusive_monitor(mut self, monitor: vk::HMONITOR) -> Self {
+        self.full_screen_exclusive_monitor = Some(monitor);
+        self
+    }
+
+    /// Require device features, the adapters not supporting all of them are skipped and building
+    /// fails with [VkTracerError::NoSuitableAdapterError] when none is left. Calls accumulate,
+    /// ex: `sampler_anisotropy` or `fill_mode_non_solid` in [DeviceFeatures::core].
+    pub fn with_features(mut self, features: &DeviceFeatures) -> Self {
+        self.features.merge(features);
+        self
+    }
+
+    /// Instance extensions to enable on top of the ones needed by vk_tracer, ex:
+    /// `VK_EXT_swapchain_colorspace` or the ones required by OpenXR. Building fails if one of
+    /// them isn't available, the extensions only provided by a layer aren't looked for.
+    pub fn with_instance_extensions(mut self, extensions: &[&str]) -> Self {
+        self.instance_extensions
+            .extend(extensions.iter().map(|name| CString::new(*name).unwrap()));
+        self
+    }
+
+    /// Instance layers to enable, ex: vendor layers. Building fails if one of them isn't
+    /// available.
+    pub fn with_instance_layers(mut self, layers: &[&str]) -> Self {
+        self.instance_layers
+            .extend(layers.iter().map(|name| CString::new(*name).unwrap()));
+        self
+    }
+
+    /// Device extensions enabled only if the chosen adapter supports them, see
+    /// [VkTracerApp::is_extension_enabled].
+    pub fn with_optional_device_extensions(mut self, extensions: &[&'static CStr]) -> Self {
+        self.optional_device_extensions
+            .extend_from_slice(extensions);
+        self
+    }
+
     pub fn with_extensions(mut self, extensions: &[VkTracerExtensions]) -> Self {
         self.extensions.extend(extensions.iter());
         self
@@ -79,6 +384,22 @@ impl VkTracerAppBuilder {
         self,
         window: Option<(&W, (u32, u32))>,
     ) -> Result<VkTracerApp> {
+        if self
+            .swapchain_flags
+            .contains(vk::SwapchainCreateFlagsKHR::PROTECTED)
+        {
+            return Err(VkTracerError::ProtectedSwapchainNotSupported);
+        }
+
+        let window = if self.compute_only {
+            if window.is_some() {
+                warn!("Compute only app, ignoring the window");
+            }
+            None
+        } else {
+            window
+        };
+
         let entry = unsafe { ash::Entry::new()? };
         debug!("Entry created");
 
@@ -102,23 +423,93 @@ impl VkTracerAppBuilder {
                 .api_version(VULKAN_VERSION);
 
             // Gather extensions, window is optional
-            let vk_extensions = if let Some((window, _)) = window {
+            let mut vk_extensions = if let Some((window, _)) = window {
                 required_instance_extensions_with_surface(self.debug_utils, window).unwrap()
             } else {
                 required_instance_extensions(self.debug_utils)
             };
+            if self.displays {
+                vk_extensions.push(khr::Display::name().as_ptr());
+            }
+            if self
+                .extensions
+                .contains(&VkTracerExtensions::FullScreenExclusive)
+            {
+                vk_extensions.push(vk::KhrGetSurfaceCapabilities2Fn::name().as_ptr());
+            }
+
+            // Then the ones of the application, all of them must be available
+            let available_extensions = entry.enumerate_instance_extension_properties()?;
+            let available_layers = entry.enumerate_instance_layer_properties()?;
+            let missing_extensions = self
+                .instance_extensions
+                .iter()
+                .filter(|name| {
+                    !available_extensions.iter().any(|extension| unsafe {
+                        CStr::from_ptr(extension.extension_name.as_ptr()) == name.as_c_str()
+                    })
+                })
+                .map(|name| name.to_string_lossy().into_owned())
+                .collect::<Vec<_>>();
+            let missing_layers = self
+                .instance_layers
+                .iter()
+                .filter(|name| {
+                    !available_layers.iter().any(|layer| unsafe {
+                        CStr::from_ptr(layer.layer_name.as_ptr()) == name.as_c_str()
+                    })
+                })
+                .map(|name| name.to_string_lossy().into_owned())
+                .collect::<Vec<_>>();
+            if !missing_extensions.is_empty() || !missing_layers.is_empty() {
+                return Err(VkTracerError::MissingInstanceExtensions {
+                    extensions: missing_extensions,
+                    layers: missing_layers,
+                });
+            }
+
+            for name in self.instance_extensions.iter() {
+                let already_enabled = vk_extensions
+                    .iter()
+                    .any(|enabled| unsafe { CStr::from_ptr(*enabled) } == name.as_c_str());
+                if !already_enabled {
+                    vk_extensions.push(name.as_ptr());
+                }
+            }
+            // Provided by the validation layer, hence not in the available extensions
+            let validation_enables = self
+                .validation_features
+                .as_ref()
+                .map(ValidationFeatures::enables);
+            let mut validation_features = validation_enables.as_ref().map(|enables| {
+                vk_extensions.push(vk::ExtValidationFeaturesFn::name().as_ptr());
+                vk::ValidationFeaturesEXT::builder().enabled_validation_features(enables)
+            });
+
+            let vk_layers = self
+                .instance_layers
+                .iter()
+                .map(|name| name.as_ptr())
+                .collect::<Vec<_>>();
 
             // Create instance
-            let info = vk::InstanceCreateInfo::builder()
+            let mut info = vk::InstanceCreateInfo::builder()
                 .application_info(&vk_app_info)
-                .enabled_extension_names(&vk_extensions);
+                .enabled_extension_names(&vk_extensions)
+                .enabled_layer_names(&vk_layers);
+            if let Some(validation_features) = validation_features.as_mut() {
+                info = info.push_next(validation_features);
+            }
 
             unsafe { entry.create_instance(&info, None)? }
         };
         debug!("Instance created");
 
         let debug_utils = if self.debug_utils {
-            Some(DebugUtils::new(&entry, &instance).unwrap())
+            Some(
+                DebugUtils::new(&entry, &instance, &self.debug_filter, self.debug_callback)
+                    .unwrap(),
+            )
         } else {
             None
         };
@@ -129,7 +520,7 @@ impl VkTracerAppBuilder {
             None
         };
 
-        let (adapter, device) = {
+        let (adapter, device, device_group) = {
             // Build adapter requirements
             let adapter_requirements = {
                 let mut requirements = if let (Some((window, _)), Some(surface)) =
@@ -145,11 +536,53 @@ impl VkTracerAppBuilder {
                     .extend(vk_tracer_extensions_to_vk_extensions(
                         self.extensions.iter(),
                     ));
+                requirements.swapchain_image_count = self.swapchain_image_count;
+                requirements.swapchain_flags = self.swapchain_flags;
+                requirements.swapchain_usage = self.swapchain_usage;
+                requirements.swapchain_composite_alpha = self.swapchain_composite_alpha;
+                if self.swapchain_unorm {
+                    requirements.surface_formats =
+                        vec![vk::Format::R8G8B8A8_UNORM, vk::Format::B8G8R8A8_UNORM];
+                }
+                requirements.features = self.features;
+                if self.compute_only {
+                    requirements.require_graphics = false;
+                    requirements
+                        .required_extensions
+                        .retain(|extension| *extension != khr::Swapchain::name());
+                }
+                requirements
+                    .optional_extensions
+                    .extend_from_slice(&self.optional_device_extensions);
+                #[cfg(feature = "checkpoints")]
+                requirements
+                    .optional_extensions
+                    .extend_from_slice(&crate::checkpoints::checkpoint_extension_names());
+                requirements.frames_in_flight = self.frames_in_flight;
+                requirements.pre_rotation = self.pre_rotation;
+                requirements.full_screen_exclusive_monitor = self.full_screen_exclusive_monitor;
+                if self
+                    .swapchain_flags
+                    .contains(vk::SwapchainCreateFlagsKHR::MUTABLE_FORMAT)
+                {
+                    requirements
+                        .required_extensions
+                        .push(vk::KhrSwapchainMutableFormatFn::name());
+                }
                 requirements
             };
 
             // Query adapter
-            let adapter_info = pick_adapter(&instance, &adapter_requirements).unwrap();
+            let selector = match &self.physical_device_preference {
+                PhysicalDevicePreference::Best => None,
+                PhysicalDevicePreference::Selected(selector) => Some(selector),
+            };
+            let adapter_info = pick_adapter(
+                &instance,
+                &adapter_requirements,
+                selector,
+                self.adapter_scoring.as_ref(),
+            )?;
             let adapter = Adapter::new(
                 adapter_info.physical_device_info.handle,
                 adapter_info,
@@ -158,39 +591,219 @@ impl VkTracerAppBuilder {
 
             debug!("Created adapter");
 
-            // Create device
-            let device = {
-                let enable_extensions = adapter
-                    .requirements
-                    .required_extensions
-                    .iter()
-                    .map(|ext| ext.as_ptr())
-                    .collect::<Vec<_>>();
-
-                // Queues create info
-                let queues_create_info =
-                    QueueFamilyIndices::from(&adapter.info).into_queue_create_info();
-
-                unsafe {
-                    instance.create_device(
-                        adapter.handle,
-                        &vk::DeviceCreateInfo::builder()
-                            .enabled_extension_names(&enable_extensions)
-                            .queue_create_infos(&queues_create_info),
-                        None,
-                    )?
-                }
+            let device_group = if self.device_group {
+                DeviceGroup::find(&instance, adapter.handle)?
+            } else {
+                None
             };
+
+            let device =
+                create_device(&instance, &adapter, device_group.as_ref(), &self.extensions)?;
             debug!("Created device");
 
-            (adapter, device)
+            if let Some(device_group) = device_group.as_ref() {
+                debug!("Device group peer memory features:");
+                device_group.log_peer_memory_features(
+                    &device,
+                    &adapter.info.physical_device_info.memory_properties,
+                );
+            }
+
+            (adapter, device, device_group)
+        };
+
+        let display = if self.displays {
+            Some(khr::Display::new(&entry, &instance))
+        } else {
+            None
         };
 
         if let Some(surface) = surface.as_mut() {
-            surface.complete(&adapter);
+            surface.complete(&instance, &adapter)?;
             debug!("Surface complete");
         }
 
+        let mut app = VkTracerApp::from_core(entry, instance, adapter, device, &self.extensions)?;
+        app.debug_utils = debug_utils;
+        app.device_group = device_group;
+        app.display = display;
+        app.main_surface = surface.map(|surface| app.surface_storage.insert(surface));
+        Ok(app)
+    }
+}
+
+impl VkTracerApp {
+    /// Everything only needed by [VkTracerAppBuilder::build] is left empty.
+    pub(crate) fn from_core(
+        entry: ash::Entry,
+        instance: ash::Instance,
+        adapter: Adapter,
+        device: ash::Device,
+        extensions: &HashSet<VkTracerExtensions>,
+    ) -> Result<Self> {
+        let DeviceObjects {
+            full_screen_exclusive,
+            display_timing,
+            calibrated_timestamps,
+            external_memory,
+            raytracing,
+            dynamic_rendering,
+            synchronization2,
+            vma,
+            command_pools,
+            pipeline_cache,
+            #[cfg(feature = "checkpoints")]
+            checkpoints,
+        } = DeviceObjects::create(&instance, &adapter, &device, extensions)?;
+
+        Ok(VkTracerApp {
+            entry,
+            instance,
+            debug_utils: None,
+            main_surface: None,
+            adapter,
+            device,
+            device_group: None,
+            display: None,
+            full_screen_exclusive,
+            display_timing,
+            calibrated_timestamps,
+            external_memory,
+            raytracing,
+            dynamic_rendering,
+            synchronization2,
+            external_handles: false,
+            extensions: extensions.clone(),
+            #[cfg(feature = "checkpoints")]
+            checkpoints,
+            vma,
+            command_pools,
+            pipeline_cache,
+            submissions: Default::default(),
+            recorded_frames: Default::default(),
+            mesh_storage: SlotMap::with_key(),
+            ubo_storage: SlotMap::with_key(),
+            indirect_buffer_storage: SlotMap::with_key(),
+            host_buffer_storage: SlotMap::with_key(),
+            storage_buffer_storage: SlotMap::with_key(),
+            texel_buffer_storage: SlotMap::with_key(),
+            sampler_storage: SlotMap::with_key(),
+            texture_storage: SlotMap::with_key(),
+            external_memory_storage: SlotMap::with_key(),
+            surface_storage: SlotMap::with_key(),
+            swapchain_storage: SlotMap::with_key(),
+            render_plan_storage: SlotMap::with_key(),
+            render_target_storage: SlotMap::with_key(),
+            offscreen_target_storage: SlotMap::with_key(),
+            depth_texture_storage: HashMap::new(),
+            forward_pipeline_storage: SlotMap::with_key(),
+            compute_pipeline_storage: SlotMap::with_key(),
+            raytracing_pipeline_storage: SlotMap::with_key(),
+            pipeline_library_storage: SlotMap::with_key(),
+            renderer_storage: SlotMap::with_key(),
+            gpu_profiler_storage: SlotMap::with_key(),
+            fence_storage: SlotMap::with_key(),
+            semaphore_storage: SlotMap::with_key(),
+            descriptor_pool_storage: SlotMap::with_key(),
+            descriptor_set_storage: SlotMap::with_key(),
+            descriptor_layout_cache: HashMap::new(),
+            pipeline_layout_cache: HashMap::new(),
+            #[cfg(feature = "bindless")]
+            bindless_table_storage: SlotMap::with_key(),
+            blas_storage: SlotMap::with_key(),
+            #[cfg(feature = "math")]
+            tlas_storage: SlotMap::with_key(),
+        })
+    }
+}
+
+/// Everything created along with the device, see [VkTracerApp::try_recover].
+pub(crate) struct DeviceObjects {
+    pub(crate) full_screen_exclusive: Option<vk::ExtFullScreenExclusiveFn>,
+    pub(crate) display_timing: Option<vk::GoogleDisplayTimingFn>,
+    pub(crate) calibrated_timestamps: Option<vk::ExtCalibratedTimestampsFn>,
+    pub(crate) external_memory: Option<ExternalMemoryFns>,
+    pub(crate) raytracing: Option<RaytracingFns>,
+    pub(crate) dynamic_rendering: Option<DynamicRenderingFns>,
+    pub(crate) synchronization2: Option<Synchronization2Fns>,
+    pub(crate) vma: vk_mem::Allocator,
+    pub(crate) command_pools: HashMap<QueueType, (vk::Queue, vk::CommandPool)>,
+    pub(crate) pipeline_cache: vk::PipelineCache,
+    #[cfg(feature = "checkpoints")]
+    pub(crate) checkpoints: Option<crate::checkpoints::Checkpoints>,
+}
+
+impl DeviceObjects {
+    /// Load the functions of the extensions, create the allocator and the command pools.
+    pub(crate) fn create(
+        instance: &ash::Instance,
+        adapter: &Adapter,
+        device: &ash::Device,
+        extensions: &HashSet<VkTracerExtensions>,
+    ) -> Result<Self> {
+        let full_screen_exclusive = if extensions.contains(&VkTracerExtensions::FullScreenExclusive)
+        {
+            Some(vk::ExtFullScreenExclusiveFn::load(|name| unsafe {
+                std::mem::transmute(instance.get_device_proc_addr(device.handle(), name.as_ptr()))
+            }))
+        } else {
+            None
+        };
+
+        let display_timing = if extensions.contains(&VkTracerExtensions::DisplayTiming) {
+            Some(vk::GoogleDisplayTimingFn::load(|name| unsafe {
+                std::mem::transmute(instance.get_device_proc_addr(device.handle(), name.as_ptr()))
+            }))
+        } else {
+            None
+        };
+
+        let calibrated_timestamps = if extensions
+            .contains(&VkTracerExtensions::CalibratedTimestamps)
+        {
+            Some(vk::ExtCalibratedTimestampsFn::load(|name| unsafe {
+                std::mem::transmute(instance.get_device_proc_addr(device.handle(), name.as_ptr()))
+            }))
+        } else {
+            None
+        };
+
+        let external_memory = if extensions.contains(&VkTracerExtensions::ExternalMemory) {
+            Some(ExternalMemoryFns::load(instance, device))
+        } else {
+            None
+        };
+
+        let raytracing = if extensions.contains(&VkTracerExtensions::PipelineRaytracing)
+            || extensions.contains(&VkTracerExtensions::RayQuery)
+        {
+            Some(RaytracingFns::load(
+                instance,
+                device,
+                adapter.handle,
+                extensions.contains(&VkTracerExtensions::PipelineRaytracing),
+                extensions.contains(&VkTracerExtensions::PipelineLibrary),
+            ))
+        } else {
+            None
+        };
+
+        let dynamic_rendering = if extensions.contains(&VkTracerExtensions::DynamicRendering) {
+            Some(DynamicRenderingFns::load(instance, device)?)
+        } else {
+            None
+        };
+
+        let synchronization2 = if extensions.contains(&VkTracerExtensions::Synchronization2) {
+            Some(Synchronization2Fns::load(
+                instance,
+                device,
+                adapter.info.physical_device_info.properties.api_version,
+            )?)
+        } else {
+            None
+        };
+
         let vma = vk_mem::Allocator::new(&vk_mem::AllocatorCreateInfo {
             physical_device: adapter.handle,
             device: device.clone(),
@@ -220,56 +833,231 @@ impl VkTracerAppBuilder {
                 if adapter.info.graphics_queue.index == adapter.info.transfer_queue.index {
                     let pool = pool_creator(
                         adapter.info.graphics_queue.index,
-                        vk::CommandPoolCreateFlags::empty(),
+                        vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER,
                     )?;
                     (pool, pool)
                 } else {
                     let graphics_pool = pool_creator(
                         adapter.info.graphics_queue.index,
-                        vk::CommandPoolCreateFlags::empty(),
+                        vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER,
                     )?;
                     let transfer_pool = pool_creator(
                         adapter.info.transfer_queue.index,
-                        vk::CommandPoolCreateFlags::TRANSIENT,
+                        vk::CommandPoolCreateFlags::TRANSIENT
+                            | vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER,
                     )?;
                     (graphics_pool, transfer_pool)
                 };
 
-            let mut command_pools = HashMap::with_capacity(2);
+            // Queues of the same family share the same pool
+            let compute_index = adapter.info.compute_queue.index;
+            let compute_pool = if compute_index == adapter.info.graphics_queue.index {
+                graphics_pool
+            } else if compute_index == adapter.info.transfer_queue.index {
+                transfer_pool
+            } else {
+                pool_creator(
+                    compute_index,
+                    vk::CommandPoolCreateFlags::TRANSIENT
+                        | vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER,
+                )?
+            };
+
+            let present_index = adapter.info.present_queue.index;
+            let present_pool = if present_index == adapter.info.graphics_queue.index {
+                graphics_pool
+            } else if present_index == adapter.info.transfer_queue.index {
+                transfer_pool
+            } else if present_index == compute_index {
+                compute_pool
+            } else {
+                pool_creator(
+                    present_index,
+                    vk::CommandPoolCreateFlags::TRANSIENT
+                        | vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER,
+                )?
+            };
+
+            let mut command_pools = HashMap::with_capacity(4);
             command_pools.insert(QueueType::Graphics, graphics_pool);
             command_pools.insert(QueueType::Transfer, transfer_pool);
+            command_pools.insert(QueueType::Compute, compute_pool);
+            command_pools.insert(QueueType::Present, present_pool);
             command_pools
         };
 
         debug!("Command pools created");
 
-        Ok(VkTracerApp {
-            entry,
-            instance,
-            debug_utils,
-            surface,
-            adapter,
-            device,
+        // Filled with VkTracerApp::load_pipeline_cache
+        let pipeline_cache =
+            unsafe { device.create_pipeline_cache(&vk::PipelineCacheCreateInfo::default(), None)? };
+
+        #[cfg(feature = "checkpoints")]
+        let checkpoints = crate::checkpoints::Checkpoints::new(instance, adapter, device, &vma)?;
+
+        Ok(Self {
+            full_screen_exclusive,
+            display_timing,
+            calibrated_timestamps,
+            external_memory,
+            raytracing,
+            dynamic_rendering,
+            synchronization2,
             vma,
             command_pools,
-            mesh_storage: SlotMap::with_key(),
-            ubo_storage: SlotMap::with_key(),
-            swapchain_storage: SlotMap::with_key(),
-            render_plan_storage: SlotMap::with_key(),
-            render_target_storage: SlotMap::with_key(),
-            forward_pipeline_storage: SlotMap::with_key(),
-            renderer_storage: SlotMap::with_key(),
-            descriptor_pool_storage: SlotMap::with_key(),
-            descriptor_set_storage: SlotMap::with_key(),
+            pipeline_cache,
+            #[cfg(feature = "checkpoints")]
+            checkpoints,
         })
     }
 }
 
-fn vk_tracer_extensions_to_vk_extensions<'a>(
+/// With the extensions and features of the adapter requirements and of `extensions`.
+pub(crate) fn create_device(
+    instance: &ash::Instance,
+    adapter: &Adapter,
+    device_group: Option<&DeviceGroup>,
+    extensions: &HashSet<VkTracerExtensions>,
+) -> Result<ash::Device> {
+    let enable_extensions = adapter
+        .requirements
+        .required_extensions
+        .iter()
+        .chain(adapter.info.optional_extensions.iter())
+        .map(|ext| ext.as_ptr())
+        .collect::<Vec<_>>();
+
+    // Queues create info
+    let queues_create_info = QueueFamilyIndices::from(&adapter.info).into_queue_create_info();
+
+    let mut device_group_info = vk::DeviceGroupDeviceCreateInfo::builder()
+        .physical_devices(device_group.map_or(&[][..], |group| &group.physical_devices));
+
+    // Whatever the device supports of robustness2
+    let robustness2 = extensions.contains(&VkTracerExtensions::Robustness2);
+    let mut robustness2_features = vk::PhysicalDeviceRobustness2FeaturesEXT::default();
+    if robustness2 {
+        let mut features2 =
+            vk::PhysicalDeviceFeatures2::builder().push_next(&mut robustness2_features);
+        unsafe { instance.get_physical_device_features2(adapter.handle, &mut features2) };
+        robustness2_features.p_next = std::ptr::null_mut();
+    }
+
+    // Indirect draws with more than one command need multi draw indirect
+    let physical_device_info = &adapter.info.physical_device_info;
+    let supported_features = &physical_device_info.features;
+    let features = vk::PhysicalDeviceFeatures::builder()
+        .multi_draw_indirect(supported_features.multi_draw_indirect == vk::TRUE)
+        // Required by robust buffer access 2
+        .robust_buffer_access(robustness2_features.robust_buffer_access2 == vk::TRUE)
+        // The path tracer reads its geometry through 64-bit addresses
+        .shader_int64(
+            extensions.contains(&VkTracerExtensions::PipelineRaytracing)
+                && supported_features.shader_int64 == vk::TRUE,
+        );
+
+    // Host query reset is used by the GPU profiler
+    let mut vulkan_12_features = vk::PhysicalDeviceVulkan12Features::builder()
+        .draw_indirect_count(physical_device_info.draw_indirect_count)
+        .host_query_reset(physical_device_info.host_query_reset);
+    if physical_device_info.update_after_bind {
+        vulkan_12_features = vulkan_12_features
+            .descriptor_binding_partially_bound(true)
+            .descriptor_binding_sampled_image_update_after_bind(true)
+            .descriptor_binding_storage_image_update_after_bind(true)
+            .descriptor_binding_storage_buffer_update_after_bind(true);
+    }
+    #[cfg(feature = "bindless")]
+    if physical_device_info.descriptor_indexing {
+        vulkan_12_features = vulkan_12_features
+            .runtime_descriptor_array(true)
+            .descriptor_binding_partially_bound(true)
+            .descriptor_binding_variable_descriptor_count(true)
+            .descriptor_binding_sampled_image_update_after_bind(true)
+            .shader_sampled_image_array_non_uniform_indexing(true);
+    }
+    let raytracing = extensions.contains(&VkTracerExtensions::PipelineRaytracing);
+    let ray_query = extensions.contains(&VkTracerExtensions::RayQuery);
+    // Acceleration structure builds read their inputs through device addresses
+    if raytracing || ray_query {
+        vulkan_12_features = vulkan_12_features.buffer_device_address(true);
+    }
+
+    // Along with the ones requested by the application
+    let mut enabled_features = DeviceFeatures {
+        core: features.build(),
+        vulkan_12: vulkan_12_features.build(),
+        acceleration_structure: vk::PhysicalDeviceAccelerationStructureFeaturesKHR::builder()
+            .acceleration_structure(true)
+            .build(),
+        ray_tracing_pipeline: vk::PhysicalDeviceRayTracingPipelineFeaturesKHR::builder()
+            .ray_tracing_pipeline(true)
+            .build(),
+        ray_query: vk::PhysicalDeviceRayQueryFeaturesKHR::builder()
+            .ray_query(true)
+            .build(),
+    };
+    enabled_features.merge(&adapter.requirements.features);
+    let DeviceFeatures {
+        core: features,
+        vulkan_12: mut vulkan_12_features,
+        acceleration_structure: mut acceleration_structure_features,
+        ray_tracing_pipeline: mut ray_tracing_pipeline_features,
+        ray_query: mut ray_query_features,
+    } = enabled_features;
+
+    let mut device_info = vk::DeviceCreateInfo::builder()
+        .enabled_extension_names(&enable_extensions)
+        .enabled_features(&features)
+        .queue_create_infos(&queues_create_info);
+
+    if device_group.is_some() {
+        device_info = device_info.push_next(&mut device_group_info);
+    }
+
+    let mut cooperative_matrix_features = PhysicalDeviceCooperativeMatrixFeatures::enabled();
+    if extensions.contains(&VkTracerExtensions::CooperativeMatrix) {
+        device_info = device_info.push_next(&mut cooperative_matrix_features);
+    }
+
+    device_info = device_info.push_next(&mut vulkan_12_features);
+    if raytracing || ray_query {
+        device_info = device_info.push_next(&mut acceleration_structure_features);
+    }
+    if raytracing {
+        device_info = device_info.push_next(&mut ray_tracing_pipeline_features);
+    }
+    if ray_query {
+        device_info = device_info.push_next(&mut ray_query_features);
+    }
+
+    let mut dynamic_rendering_features = PhysicalDeviceDynamicRenderingFeatures::enabled();
+    if extensions.contains(&VkTracerExtensions::DynamicRendering) {
+        device_info = device_info.push_next(&mut dynamic_rendering_features);
+    }
+
+    let mut synchronization2_features = PhysicalDeviceSynchronization2Features::enabled();
+    if extensions.contains(&VkTracerExtensions::Synchronization2) {
+        device_info = device_info.push_next(&mut synchronization2_features);
+    }
+
+    // The subgroup clock is always supported with the extension
+    let mut shader_clock_features =
+        vk::PhysicalDeviceShaderClockFeaturesKHR::builder().shader_subgroup_clock(true);
+    if extensions.contains(&VkTracerExtensions::ShaderClock) {
+        device_info = device_info.push_next(&mut shader_clock_features);
+    }
+
+    if robustness2 {
+        device_info = device_info.push_next(&mut robustness2_features);
+    }
+
+    Ok(unsafe { instance.create_device(adapter.handle, &device_info, None)? })
+}
+
+pub(crate) fn vk_tracer_extensions_to_vk_extensions<'a>(
     extensions: impl Iterator<Item = &'a VkTracerExtensions>,
 ) -> impl Iterator<Item = &'static CStr> {
-    use ash::extensions::khr;
-
     let mut res = HashSet::new();
 
     for extension in extensions {
@@ -282,6 +1070,44 @@ fn vk_tracer_extensions_to_vk_extensions<'a>(
                 res.insert(khr::AccelerationStructure::name());
                 res.insert(khr::RayTracingPipeline::name());
             }
+            VkTracerExtensions::PipelineLibrary => {
+                res.insert(vk::KhrPipelineLibraryFn::name());
+            }
+            VkTracerExtensions::RayQuery => {
+                res.insert(khr::DeferredHostOperations::name());
+                res.insert(khr::AccelerationStructure::name());
+                res.insert(vk::KhrRayQueryFn::name());
+            }
+            VkTracerExtensions::CooperativeMatrix => {
+                res.insert(cooperative_matrix_extension_name());
+            }
+            VkTracerExtensions::ShaderClock => {
+                res.insert(vk::KhrShaderClockFn::name());
+            }
+            VkTracerExtensions::FullScreenExclusive => {
+                res.insert(vk::ExtFullScreenExclusiveFn::name());
+            }
+            VkTracerExtensions::DisplayTiming => {
+                res.insert(vk::GoogleDisplayTimingFn::name());
+            }
+            VkTracerExtensions::CalibratedTimestamps => {
+                res.insert(vk::ExtCalibratedTimestampsFn::name());
+            }
+            VkTracerExtensions::DynamicRendering => {
+                res.insert(dynamic_rendering_extension_name());
+            }
+            VkTracerExtensions::Synchronization2 => {
+                if !synchronization2_is_core() {
+                    res.insert(synchronization2_extension_name());
+                }
+            }
+            VkTracerExtensions::Robustness2 => {
+                res.insert(vk::ExtRobustness2Fn::name());
+            }
+            VkTracerExtensions::ExternalMemory => {
+                // VK_KHR_external_memory and VK_KHR_external_semaphore promoted to vulkan 1.1
+                res.extend(ExternalMemoryFns::extension_names().iter().copied());
+            }
         }
     }
 
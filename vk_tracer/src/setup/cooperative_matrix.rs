@@ -0,0 +1,180 @@
+//! # Cooperative matrix
+//! `VK_KHR_cooperative_matrix` is too recent for our version of ash, so the few structures and
+//! the function we need are declared by hand here, following the Vulkan registry.
+
+use crate::{
+    errors::{Result, VkTracerError},
+    VkTracerApp,
+};
+use ash::{
+    version::{EntryV1_0, InstanceV1_0},
+    vk,
+};
+use std::{ffi::CStr, os::raw::c_void, ptr};
+
+pub(crate) fn cooperative_matrix_extension_name() -> &'static CStr {
+    unsafe { CStr::from_bytes_with_nul_unchecked(b"VK_KHR_cooperative_matrix\0") }
+}
+
+const STRUCTURE_TYPE_PHYSICAL_DEVICE_COOPERATIVE_MATRIX_FEATURES_KHR: vk::StructureType =
+    vk::StructureType::from_raw(1_000_506_000);
+const STRUCTURE_TYPE_COOPERATIVE_MATRIX_PROPERTIES_KHR: vk::StructureType =
+    vk::StructureType::from_raw(1_000_506_001);
+
+/// `VkPhysicalDeviceCooperativeMatrixFeaturesKHR`
+#[repr(C)]
+pub(crate) struct PhysicalDeviceCooperativeMatrixFeatures {
+    s_type: vk::StructureType,
+    p_next: *mut c_void,
+    cooperative_matrix: vk::Bool32,
+    cooperative_matrix_robust_buffer_access: vk::Bool32,
+}
+
+unsafe impl vk::ExtendsDeviceCreateInfo for PhysicalDeviceCooperativeMatrixFeatures {}
+unsafe impl vk::ExtendsPhysicalDeviceFeatures2 for PhysicalDeviceCooperativeMatrixFeatures {}
+
+impl Default for PhysicalDeviceCooperativeMatrixFeatures {
+    fn default() -> Self {
+        Self {
+            s_type: STRUCTURE_TYPE_PHYSICAL_DEVICE_COOPERATIVE_MATRIX_FEATURES_KHR,
+            p_next: ptr::null_mut(),
+            cooperative_matrix: vk::FALSE,
+            cooperative_matrix_robust_buffer_access: vk::FALSE,
+        }
+    }
+}
+
+impl PhysicalDeviceCooperativeMatrixFeatures {
+    pub(crate) fn enabled() -> Self {
+        Self {
+            cooperative_matrix: vk::TRUE,
+            ..Default::default()
+        }
+    }
+
+    /// Whether the queried device supports cooperative matrices in shaders.
+    pub(crate) fn supported(&self) -> bool {
+        self.cooperative_matrix == vk::TRUE
+    }
+}
+
+/// `VkCooperativeMatrixPropertiesKHR`
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct RawCooperativeMatrixProperties {
+    s_type: vk::StructureType,
+    p_next: *mut c_void,
+    m_size: u32,
+    n_size: u32,
+    k_size: u32,
+    a_type: vk::ComponentTypeNV,
+    b_type: vk::ComponentTypeNV,
+    c_type: vk::ComponentTypeNV,
+    result_type: vk::ComponentTypeNV,
+    saturating_accumulation: vk::Bool32,
+    scope: vk::ScopeNV,
+}
+
+impl Default for RawCooperativeMatrixProperties {
+    fn default() -> Self {
+        Self {
+            s_type: STRUCTURE_TYPE_COOPERATIVE_MATRIX_PROPERTIES_KHR,
+            p_next: ptr::null_mut(),
+            m_size: 0,
+            n_size: 0,
+            k_size: 0,
+            a_type: vk::ComponentTypeNV::FLOAT16,
+            b_type: vk::ComponentTypeNV::FLOAT16,
+            c_type: vk::ComponentTypeNV::FLOAT16,
+            result_type: vk::ComponentTypeNV::FLOAT16,
+            saturating_accumulation: vk::FALSE,
+            scope: vk::ScopeNV::SUBGROUP,
+        }
+    }
+}
+
+type PfnGetPhysicalDeviceCooperativeMatrixProperties = unsafe extern "system" fn(
+    physical_device: vk::PhysicalDevice,
+    property_count: *mut u32,
+    properties: *mut RawCooperativeMatrixProperties,
+) -> vk::Result;
+
+/// A matrix multiplication `A (MxK) * B (KxN) + C (MxN)` supported by the device.
+/// The component types share their values with the KHR extension.
+#[derive(Copy, Clone, Debug)]
+pub struct CooperativeMatrixProperties {
+    pub m_size: u32,
+    pub n_size: u32,
+    pub k_size: u32,
+    pub a_type: vk::ComponentTypeNV,
+    pub b_type: vk::ComponentTypeNV,
+    pub c_type: vk::ComponentTypeNV,
+    pub result_type: vk::ComponentTypeNV,
+    pub saturating_accumulation: bool,
+    pub scope: vk::ScopeNV,
+}
+
+impl From<&RawCooperativeMatrixProperties> for CooperativeMatrixProperties {
+    fn from(raw: &RawCooperativeMatrixProperties) -> Self {
+        Self {
+            m_size: raw.m_size,
+            n_size: raw.n_size,
+            k_size: raw.k_size,
+            a_type: raw.a_type,
+            b_type: raw.b_type,
+            c_type: raw.c_type,
+            result_type: raw.result_type,
+            saturating_accumulation: raw.saturating_accumulation == vk::TRUE,
+            scope: raw.scope,
+        }
+    }
+}
+
+impl VkTracerApp {
+    /// List the matrix sizes and types that can be used with cooperative matrices.
+    /// Requires [crate::setup::VkTracerExtensions::CooperativeMatrix].
+    pub fn cooperative_matrix_properties(&self) -> Result<Vec<CooperativeMatrixProperties>> {
+        let extension = cooperative_matrix_extension_name();
+        if !self
+            .adapter
+            .requirements
+            .required_extensions
+            .contains(&extension)
+        {
+            return Err(VkTracerError::ExtensionNotEnabled(
+                extension.to_str().unwrap(),
+            ));
+        }
+
+        unsafe {
+            let get_properties: PfnGetPhysicalDeviceCooperativeMatrixProperties = {
+                let name = b"vkGetPhysicalDeviceCooperativeMatrixPropertiesKHR\0";
+                let pfn = self
+                    .entry
+                    .get_instance_proc_addr(self.instance.handle(), name.as_ptr() as *const _)
+                    .ok_or(VkTracerError::ExtensionNotEnabled(
+                        extension.to_str().unwrap(),
+                    ))?;
+                std::mem::transmute(pfn)
+            };
+
+            let mut count = 0;
+            match get_properties(self.adapter.handle, &mut count, ptr::null_mut()) {
+                vk::Result::SUCCESS => {}
+                err => return Err(err.into()),
+            }
+
+            let mut properties = vec![RawCooperativeMatrixProperties::default(); count as usize];
+            match get_properties(self.adapter.handle, &mut count, properties.as_mut_ptr()) {
+                vk::Result::SUCCESS => {}
+                err => return Err(err.into()),
+            }
+
+            Ok(properties
+                .iter()
+                .take(count as usize)
+                .map(CooperativeMatrixProperties::from)
+                .collect())
+        }
+    }
+}
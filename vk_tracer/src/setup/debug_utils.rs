@@ -1,32 +1,137 @@
-use crate::errors::Result;
+use crate::{errors::Result, VkTracerApp};
 use ash::{extensions::ext, vk};
-use log::{info, log, Level};
+use log::{error, info, log, warn, Level};
 use std::{
     borrow::Cow,
     ffi::{CStr, CString},
+    os::raw::c_void,
+    panic::{catch_unwind, AssertUnwindSafe},
 };
 
+/// A message of the validation layers or the driver, see
+/// [crate::setup::VkTracerAppBuilder::with_debug_callback].
+#[derive(Debug)]
+pub struct DebugMessage<'a> {
+    pub severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+    pub ty: vk::DebugUtilsMessageTypeFlagsEXT,
+    pub id_name: Cow<'a, str>,
+    pub id_number: i32,
+    pub message: Cow<'a, str>,
+}
+
+/// Returning true makes the Vulkan call that triggered the message fail with
+/// `ERROR_VALIDATION_FAILED_EXT`. May be called from any thread.
+pub type DebugCallback = Box<dyn Fn(&DebugMessage) -> bool + Send + Sync>;
+
+pub(crate) const VALIDATION_LAYER: &str = "VK_LAYER_KHRONOS_validation";
+
+/// Heavier modes of the validation layer, enabled with `VK_EXT_validation_features`. Their
+/// reports go through the debug messenger.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ValidationFeatures {
+    /// Instrument the shaders to catch out of bounds descriptor and buffer accesses.
+    pub gpu_assisted: bool,
+    pub best_practices: bool,
+    /// Report the missing barriers and the hazards between submissions.
+    pub synchronization: bool,
+    /// Forward `debugPrintfEXT` from the shaders as INFO messages, incompatible with
+    /// `gpu_assisted`.
+    pub debug_printf: bool,
+}
+
+impl ValidationFeatures {
+    pub(crate) fn enables(&self) -> Vec<vk::ValidationFeatureEnableEXT> {
+        let mut enables = Vec::new();
+        if self.gpu_assisted {
+            enables.push(vk::ValidationFeatureEnableEXT::GPU_ASSISTED);
+            // Leaves a descriptor set to the layer when the application uses all of them
+            enables.push(vk::ValidationFeatureEnableEXT::GPU_ASSISTED_RESERVE_BINDING_SLOT);
+        }
+        if self.best_practices {
+            enables.push(vk::ValidationFeatureEnableEXT::BEST_PRACTICES);
+        }
+        if self.synchronization {
+            enables.push(vk::ValidationFeatureEnableEXT::SYNCHRONIZATION_VALIDATION);
+        }
+        if self.debug_printf {
+            enables.push(vk::ValidationFeatureEnableEXT::DEBUG_PRINTF);
+        }
+        enables
+    }
+}
+
+/// Which messages reach the messenger.
+pub(crate) struct DebugMessageFilter {
+    pub(crate) severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+    pub(crate) ty: vk::DebugUtilsMessageTypeFlagsEXT,
+}
+
+impl Default for DebugMessageFilter {
+    fn default() -> Self {
+        Self {
+            severity: vk::DebugUtilsMessageSeverityFlagsEXT::all(),
+            ty: vk::DebugUtilsMessageTypeFlagsEXT::all(),
+        }
+    }
+}
+
+impl VkTracerApp {
+    /// Does nothing without debug utils, also a GPU checkpoint with the `checkpoints` feature.
+    pub(crate) fn begin_debug_label(&self, commands: vk::CommandBuffer, name: std::fmt::Arguments) {
+        #[cfg(feature = "checkpoints")]
+        if let Some(checkpoints) = self.checkpoints.as_ref() {
+            checkpoints.write(commands, name.to_string());
+        }
+        if let Some(debug_utils) = self.debug_utils.as_ref() {
+            debug_utils.begin_label(commands, name);
+        }
+    }
+
+    pub(crate) fn end_debug_label(&self, commands: vk::CommandBuffer) {
+        if let Some(debug_utils) = self.debug_utils.as_ref() {
+            debug_utils.end_label(commands);
+        }
+    }
+}
+
 pub(crate) struct DebugUtils {
     pub(crate) loader: ext::DebugUtils,
     messenger: vk::DebugUtilsMessengerEXT,
+    /// Given to the messenger as user data, must outlive it.
+    callback: Option<Box<DebugCallback>>,
 }
 
 impl DebugUtils {
-    pub(crate) fn new(entry: &ash::Entry, instance: &ash::Instance) -> Result<Self> {
+    /// Without callback, the messages go to the logger.
+    pub(crate) fn new(
+        entry: &ash::Entry,
+        instance: &ash::Instance,
+        filter: &DebugMessageFilter,
+        callback: Option<DebugCallback>,
+    ) -> Result<Self> {
         let loader = ext::DebugUtils::new(entry, instance);
+        let callback = callback.map(Box::new);
+        let user_data = callback.as_ref().map_or(std::ptr::null_mut(), |callback| {
+            &**callback as *const DebugCallback as *mut c_void
+        });
         let messenger = unsafe {
             loader.create_debug_utils_messenger(
                 &vk::DebugUtilsMessengerCreateInfoEXT::builder()
-                    .message_severity(vk::DebugUtilsMessageSeverityFlagsEXT::all())
-                    .message_type(vk::DebugUtilsMessageTypeFlagsEXT::all())
-                    .pfn_user_callback(Some(vulkan_debug_callback)),
+                    .message_severity(filter.severity)
+                    .message_type(filter.ty)
+                    .pfn_user_callback(Some(vulkan_debug_callback))
+                    .user_data(user_data),
                 None,
             )?
         };
 
         info!("Debug utils setup !");
 
-        Ok(Self { loader, messenger })
+        Ok(Self {
+            loader,
+            messenger,
+            callback,
+        })
     }
 
     pub(crate) fn name_object(
@@ -36,28 +141,60 @@ impl DebugUtils {
         handle: impl vk::Handle,
         name: Cow<'static, str>,
     ) {
-        let name = CString::new(name.as_ref()).unwrap();
+        // Naming is only a debugging help, it isn't worth failing for
+        let name = match CString::new(name.as_ref()) {
+            Ok(name) => name,
+            Err(err) => {
+                warn!("Invalid object name: {}", err);
+                return;
+            }
+        };
         unsafe {
-            self.loader
-                .debug_utils_set_object_name(
-                    device.handle(),
-                    &vk::DebugUtilsObjectNameInfoEXT::builder()
-                        .object_type(ty)
-                        .object_handle(handle.as_raw())
-                        .object_name(&name),
-                )
-                .expect("Failed to name object");
+            if let Err(err) = self.loader.debug_utils_set_object_name(
+                device.handle(),
+                &vk::DebugUtilsObjectNameInfoEXT::builder()
+                    .object_type(ty)
+                    .object_handle(handle.as_raw())
+                    .object_name(&name),
+            ) {
+                warn!("Failed to name object: {}", err);
+            }
         }
 
         // Yea
         std::mem::forget(name);
     }
 
+    /// Open a labelled region in a command buffer, visible in captures.
+    pub(crate) fn begin_label(&self, commands: vk::CommandBuffer, name: std::fmt::Arguments) {
+        let name = match CString::new(name.to_string()) {
+            Ok(name) => name,
+            Err(err) => {
+                warn!("Invalid label name: {}", err);
+                CString::default()
+            }
+        };
+        unsafe {
+            self.loader.cmd_begin_debug_utils_label(
+                commands,
+                &vk::DebugUtilsLabelEXT::builder().label_name(&name),
+            );
+        }
+    }
+
+    pub(crate) fn end_label(&self, commands: vk::CommandBuffer) {
+        unsafe {
+            self.loader.cmd_end_debug_utils_label(commands);
+        }
+    }
+
     pub(crate) fn destroy(self) {
         unsafe {
             self.loader
                 .destroy_debug_utils_messenger(self.messenger, None);
         }
+        // Not called anymore
+        drop(self.callback);
     }
 }
 
@@ -65,7 +202,7 @@ unsafe extern "system" fn vulkan_debug_callback(
     message_severity: vk::DebugUtilsMessageSeverityFlagsEXT,
     message_type: vk::DebugUtilsMessageTypeFlagsEXT,
     p_callback_data: *const vk::DebugUtilsMessengerCallbackDataEXT,
-    _user_data: *mut std::os::raw::c_void,
+    user_data: *mut c_void,
 ) -> vk::Bool32 {
     let callback_data = *p_callback_data;
 
@@ -81,6 +218,26 @@ unsafe extern "system" fn vulkan_debug_callback(
         CStr::from_ptr(callback_data.p_message).to_string_lossy()
     };
 
+    if !user_data.is_null() {
+        let callback = &*(user_data as *const DebugCallback);
+        let message = DebugMessage {
+            severity: message_severity,
+            ty: message_type,
+            id_name: message_id_name,
+            id_number: message_id_number,
+            message,
+        };
+        // Unwinding into the driver is undefined behavior
+        return match catch_unwind(AssertUnwindSafe(|| callback(&message))) {
+            Ok(true) => vk::TRUE,
+            Ok(false) => vk::FALSE,
+            Err(_) => {
+                error!("Debug callback panicked on {:?}", message);
+                std::process::abort();
+            }
+        };
+    }
+
     log!(
         severity_to_level(message_severity),
         "[{:?}] {} [{} ({})]",
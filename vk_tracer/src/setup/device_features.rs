@@ -0,0 +1,257 @@
+//! # Device features
+//! Features requested by the application with [crate::setup::VkTracerAppBuilder::with_features],
+//! on top of the ones enabled by vk_tracer itself. The Vulkan structures are only made of
+//! `VkBool32` after their header, which is how they are compared and merged here.
+
+use ash::vk;
+use std::{mem::size_of, ptr};
+
+/// Every member set to TRUE is required, adapters not supporting one of them are skipped.
+/// The ray tracing structures are only enabled along with their [crate::setup::VkTracerExtensions].
+#[derive(Copy, Clone, Debug, Default)]
+pub struct DeviceFeatures {
+    pub core: vk::PhysicalDeviceFeatures,
+    pub vulkan_12: vk::PhysicalDeviceVulkan12Features,
+    pub acceleration_structure: vk::PhysicalDeviceAccelerationStructureFeaturesKHR,
+    pub ray_tracing_pipeline: vk::PhysicalDeviceRayTracingPipelineFeaturesKHR,
+    pub ray_query: vk::PhysicalDeviceRayQueryFeaturesKHR,
+}
+
+/// # Safety
+/// The implementor must be `repr(C)` and only have the members named in `FIELDS`, all of type
+/// `VkBool32`, after a header of `HEADER` bytes.
+unsafe trait FeatureStruct: Sized {
+    const NAME: &'static str;
+    const HEADER: usize = size_of::<vk::BaseOutStructure>();
+    /// The Vulkan name of each member, in order.
+    const FIELDS: &'static [&'static str];
+
+    fn bools(&self) -> &[vk::Bool32] {
+        debug_assert!(
+            Self::HEADER + Self::FIELDS.len() * size_of::<vk::Bool32>() <= size_of::<Self>()
+        );
+        unsafe {
+            std::slice::from_raw_parts(
+                (self as *const Self as *const u8).add(Self::HEADER) as *const vk::Bool32,
+                Self::FIELDS.len(),
+            )
+        }
+    }
+
+    fn bools_mut(&mut self) -> &mut [vk::Bool32] {
+        debug_assert!(
+            Self::HEADER + Self::FIELDS.len() * size_of::<vk::Bool32>() <= size_of::<Self>()
+        );
+        unsafe {
+            std::slice::from_raw_parts_mut(
+                (self as *mut Self as *mut u8).add(Self::HEADER) as *mut vk::Bool32,
+                Self::FIELDS.len(),
+            )
+        }
+    }
+}
+
+unsafe impl FeatureStruct for vk::PhysicalDeviceFeatures {
+    const NAME: &'static str = "VkPhysicalDeviceFeatures";
+    const HEADER: usize = 0;
+    const FIELDS: &'static [&'static str] = &[
+        "robustBufferAccess",
+        "fullDrawIndexUint32",
+        "imageCubeArray",
+        "independentBlend",
+        "geometryShader",
+        "tessellationShader",
+        "sampleRateShading",
+        "dualSrcBlend",
+        "logicOp",
+        "multiDrawIndirect",
+        "drawIndirectFirstInstance",
+        "depthClamp",
+        "depthBiasClamp",
+        "fillModeNonSolid",
+        "depthBounds",
+        "wideLines",
+        "largePoints",
+        "alphaToOne",
+        "multiViewport",
+        "samplerAnisotropy",
+        "textureCompressionEtc2",
+        "textureCompressionAstcLdr",
+        "textureCompressionBc",
+        "occlusionQueryPrecise",
+        "pipelineStatisticsQuery",
+        "vertexPipelineStoresAndAtomics",
+        "fragmentStoresAndAtomics",
+        "shaderTessellationAndGeometryPointSize",
+        "shaderImageGatherExtended",
+        "shaderStorageImageExtendedFormats",
+        "shaderStorageImageMultisample",
+        "shaderStorageImageReadWithoutFormat",
+        "shaderStorageImageWriteWithoutFormat",
+        "shaderUniformBufferArrayDynamicIndexing",
+        "shaderSampledImageArrayDynamicIndexing",
+        "shaderStorageBufferArrayDynamicIndexing",
+        "shaderStorageImageArrayDynamicIndexing",
+        "shaderClipDistance",
+        "shaderCullDistance",
+        "shaderFloat64",
+        "shaderInt64",
+        "shaderInt16",
+        "shaderResourceResidency",
+        "shaderResourceMinLod",
+        "sparseBinding",
+        "sparseResidencyBuffer",
+        "sparseResidencyImage2D",
+        "sparseResidencyImage3D",
+        "sparseResidency2Samples",
+        "sparseResidency4Samples",
+        "sparseResidency8Samples",
+        "sparseResidency16Samples",
+        "sparseResidencyAliased",
+        "variableMultisampleRate",
+        "inheritedQueries",
+    ];
+}
+
+unsafe impl FeatureStruct for vk::PhysicalDeviceVulkan12Features {
+    const NAME: &'static str = "VkPhysicalDeviceVulkan12Features";
+    const FIELDS: &'static [&'static str] = &[
+        "samplerMirrorClampToEdge",
+        "drawIndirectCount",
+        "storageBuffer8BitAccess",
+        "uniformAndStorageBuffer8BitAccess",
+        "storagePushConstant8",
+        "shaderBufferInt64Atomics",
+        "shaderSharedInt64Atomics",
+        "shaderFloat16",
+        "shaderInt8",
+        "descriptorIndexing",
+        "shaderInputAttachmentArrayDynamicIndexing",
+        "shaderUniformTexelBufferArrayDynamicIndexing",
+        "shaderStorageTexelBufferArrayDynamicIndexing",
+        "shaderUniformBufferArrayNonUniformIndexing",
+        "shaderSampledImageArrayNonUniformIndexing",
+        "shaderStorageBufferArrayNonUniformIndexing",
+        "shaderStorageImageArrayNonUniformIndexing",
+        "shaderInputAttachmentArrayNonUniformIndexing",
+        "shaderUniformTexelBufferArrayNonUniformIndexing",
+        "shaderStorageTexelBufferArrayNonUniformIndexing",
+        "descriptorBindingUniformBufferUpdateAfterBind",
+        "descriptorBindingSampledImageUpdateAfterBind",
+        "descriptorBindingStorageImageUpdateAfterBind",
+        "descriptorBindingStorageBufferUpdateAfterBind",
+        "descriptorBindingUniformTexelBufferUpdateAfterBind",
+        "descriptorBindingStorageTexelBufferUpdateAfterBind",
+        "descriptorBindingUpdateUnusedWhilePending",
+        "descriptorBindingPartiallyBound",
+        "descriptorBindingVariableDescriptorCount",
+        "runtimeDescriptorArray",
+        "samplerFilterMinmax",
+        "scalarBlockLayout",
+        "imagelessFramebuffer",
+        "uniformBufferStandardLayout",
+        "shaderSubgroupExtendedTypes",
+        "separateDepthStencilLayouts",
+        "hostQueryReset",
+        "timelineSemaphore",
+        "bufferDeviceAddress",
+        "bufferDeviceAddressCaptureReplay",
+        "bufferDeviceAddressMultiDevice",
+        "vulkanMemoryModel",
+        "vulkanMemoryModelDeviceScope",
+        "vulkanMemoryModelAvailabilityVisibilityChains",
+        "shaderOutputViewportIndex",
+        "shaderOutputLayer",
+        "subgroupBroadcastDynamicId",
+    ];
+}
+
+unsafe impl FeatureStruct for vk::PhysicalDeviceAccelerationStructureFeaturesKHR {
+    const NAME: &'static str = "VkPhysicalDeviceAccelerationStructureFeaturesKHR";
+    const FIELDS: &'static [&'static str] = &[
+        "accelerationStructure",
+        "accelerationStructureCaptureReplay",
+        "accelerationStructureIndirectBuild",
+        "accelerationStructureHostCommands",
+        "descriptorBindingAccelerationStructureUpdateAfterBind",
+    ];
+}
+
+unsafe impl FeatureStruct for vk::PhysicalDeviceRayTracingPipelineFeaturesKHR {
+    const NAME: &'static str = "VkPhysicalDeviceRayTracingPipelineFeaturesKHR";
+    const FIELDS: &'static [&'static str] = &[
+        "rayTracingPipeline",
+        "rayTracingPipelineShaderGroupHandleCaptureReplay",
+        "rayTracingPipelineShaderGroupHandleCaptureReplayMixed",
+        "rayTracingPipelineTraceRaysIndirect",
+        "rayTraversalPrimitiveCulling",
+    ];
+}
+
+unsafe impl FeatureStruct for vk::PhysicalDeviceRayQueryFeaturesKHR {
+    const NAME: &'static str = "VkPhysicalDeviceRayQueryFeaturesKHR";
+    const FIELDS: &'static [&'static str] = &["rayQuery"];
+}
+
+fn missing_in<T: FeatureStruct>(required: &T, supported: &T, missing: &mut Vec<String>) {
+    for ((required, supported), field) in required
+        .bools()
+        .iter()
+        .zip(supported.bools())
+        .zip(T::FIELDS)
+    {
+        if *required == vk::TRUE && *supported != vk::TRUE {
+            missing.push(format!("{}::{}", T::NAME, field));
+        }
+    }
+}
+
+fn merge_into<T: FeatureStruct>(dst: &mut T, src: &T) {
+    for (dst, src) in dst.bools_mut().iter_mut().zip(src.bools()) {
+        if *src == vk::TRUE {
+            *dst = vk::TRUE;
+        }
+    }
+}
+
+impl DeviceFeatures {
+    /// The required features `supported` lacks, empty when it supports all of them.
+    pub fn missing(&self, supported: &DeviceFeatures) -> Vec<String> {
+        let mut missing = Vec::new();
+        missing_in(&self.core, &supported.core, &mut missing);
+        missing_in(&self.vulkan_12, &supported.vulkan_12, &mut missing);
+        missing_in(
+            &self.acceleration_structure,
+            &supported.acceleration_structure,
+            &mut missing,
+        );
+        missing_in(
+            &self.ray_tracing_pipeline,
+            &supported.ray_tracing_pipeline,
+            &mut missing,
+        );
+        missing_in(&self.ray_query, &supported.ray_query, &mut missing);
+        missing
+    }
+
+    /// Enable everything `other` enables as well.
+    pub fn merge(&mut self, other: &DeviceFeatures) {
+        merge_into(&mut self.core, &other.core);
+        merge_into(&mut self.vulkan_12, &other.vulkan_12);
+        merge_into(
+            &mut self.acceleration_structure,
+            &other.acceleration_structure,
+        );
+        merge_into(&mut self.ray_tracing_pipeline, &other.ray_tracing_pipeline);
+        merge_into(&mut self.ray_query, &other.ray_query);
+    }
+
+    /// Forget the chain the structures were queried or created with.
+    pub(crate) fn unchained(mut self) -> Self {
+        self.vulkan_12.p_next = ptr::null_mut();
+        self.acceleration_structure.p_next = ptr::null_mut();
+        self.ray_tracing_pipeline.p_next = ptr::null_mut();
+        self.ray_query.p_next = ptr::null_mut();
+        self
+    }
+}
@@ -0,0 +1,182 @@
+//! # Device groups
+//! Linked multi-GPU setups are exposed by Vulkan as a group of physical devices that can be driven
+//! by a single logical device. We use them for alternate frame rendering (AFR): each frame is
+//! submitted to only one physical device of the group, in a round-robin fashion.
+
+use crate::{
+    errors::{Result, VkTracerError},
+    VkTracerApp,
+};
+use ash::{
+    version::{DeviceV1_0, DeviceV1_1, InstanceV1_1},
+    vk,
+};
+use log::debug;
+
+pub(crate) struct DeviceGroup {
+    pub(crate) physical_devices: Vec<vk::PhysicalDevice>,
+}
+
+impl DeviceGroup {
+    /// Find the group that contains the given physical device.
+    /// Returns [None] if the device isn't part of a group with more than one device.
+    pub(crate) fn find(
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+    ) -> Result<Option<Self>> {
+        let groups = unsafe {
+            let mut groups = vec![
+                vk::PhysicalDeviceGroupProperties::default();
+                instance.enumerate_physical_device_groups_len()
+            ];
+            instance.enumerate_physical_device_groups(&mut groups)?;
+            groups
+        };
+
+        let group = groups.iter().find(|group| {
+            group.physical_devices[..group.physical_device_count as usize]
+                .contains(&physical_device)
+        });
+
+        match group {
+            Some(group) if group.physical_device_count > 1 => {
+                debug!(
+                    "Found device group of {} physical devices",
+                    group.physical_device_count
+                );
+                Ok(Some(Self {
+                    physical_devices: group.physical_devices
+                        [..group.physical_device_count as usize]
+                        .to_vec(),
+                }))
+            }
+            _ => {
+                debug!("The adapter isn't part of a multi-GPU device group");
+                Ok(None)
+            }
+        }
+    }
+
+    #[inline]
+    pub(crate) fn len(&self) -> u32 {
+        self.physical_devices.len() as u32
+    }
+
+    /// The index of the physical device responsible for the given frame, counted since the
+    /// creation of the swapchain.
+    #[inline]
+    pub(crate) fn afr_device_index(&self, frame: u64) -> u32 {
+        (frame % self.len() as u64) as u32
+    }
+
+    /// How the images rendered by each physical device are presented to the surface. LOCAL
+    /// when every device can present its own images, else REMOTE when another device can
+    /// present them and read them from the memory of the one that rendered them.
+    pub(crate) fn choose_present_mode(
+        &self,
+        swapchain_fn: &vk::KhrSwapchainFn,
+        device: &ash::Device,
+        surface: vk::SurfaceKHR,
+        memory_properties: &vk::PhysicalDeviceMemoryProperties,
+    ) -> Result<vk::DeviceGroupPresentModeFlagsKHR> {
+        let mut capabilities = vk::DeviceGroupPresentCapabilitiesKHR::default();
+        let mut surface_modes = vk::DeviceGroupPresentModeFlagsKHR::empty();
+        unsafe {
+            match swapchain_fn
+                .get_device_group_present_capabilities_khr(device.handle(), &mut capabilities)
+            {
+                vk::Result::SUCCESS => {}
+                err => return Err(err.into()),
+            }
+            match swapchain_fn.get_device_group_surface_present_modes_khr(
+                device.handle(),
+                surface,
+                &mut surface_modes,
+            ) {
+                vk::Result::SUCCESS => {}
+                err => return Err(err.into()),
+            }
+        }
+        let modes = capabilities.modes & surface_modes;
+        let present_mask = &capabilities.present_mask[..self.len() as usize];
+
+        let local =
+            (0..self.len()).all(|device| present_mask[device as usize] & (1 << device) != 0);
+        if modes.contains(vk::DeviceGroupPresentModeFlagsKHR::LOCAL) && local {
+            debug!("Device group presents with LOCAL");
+            return Ok(vk::DeviceGroupPresentModeFlagsKHR::LOCAL);
+        }
+
+        // The images live in the first device local heap
+        let heap = memory_properties.memory_types[..memory_properties.memory_type_count as usize]
+            .iter()
+            .find(|ty| {
+                ty.property_flags
+                    .contains(vk::MemoryPropertyFlags::DEVICE_LOCAL)
+            })
+            .map_or(0, |ty| ty.heap_index);
+        let remote = (0..self.len()).all(|renderer| {
+            (0..self.len()).any(|presenter| {
+                present_mask[presenter as usize] & (1 << renderer) != 0
+                    && (presenter == renderer
+                        || unsafe {
+                            device.get_device_group_peer_memory_features(heap, presenter, renderer)
+                        }
+                        .contains(vk::PeerMemoryFeatureFlags::COPY_SRC))
+            })
+        });
+        if modes.contains(vk::DeviceGroupPresentModeFlagsKHR::REMOTE) && remote {
+            debug!("Device group presents with REMOTE");
+            return Ok(vk::DeviceGroupPresentModeFlagsKHR::REMOTE);
+        }
+
+        Err(VkTracerError::FeatureNotSupported(
+            "alternate frame presentation with this device group",
+        ))
+    }
+
+    /// Log how each pair of physical devices can access each other's memory for each heap.
+    pub(crate) fn log_peer_memory_features(
+        &self,
+        device: &ash::Device,
+        memory_properties: &vk::PhysicalDeviceMemoryProperties,
+    ) {
+        for heap in 0..memory_properties.memory_heap_count {
+            for local in 0..self.len() {
+                for remote in (0..self.len()).filter(|remote| *remote != local) {
+                    let features = unsafe {
+                        device.get_device_group_peer_memory_features(heap, local, remote)
+                    };
+                    debug!(
+                        " - Heap {}: device {} -> device {} [{:?}]",
+                        heap, local, remote, features
+                    );
+                }
+            }
+        }
+    }
+}
+
+impl VkTracerApp {
+    /// The amount of physical devices driven by this app, 1 if device groups aren't used.
+    pub fn device_group_size(&self) -> u32 {
+        self.device_group.as_ref().map_or(1, DeviceGroup::len)
+    }
+
+    /// How `local_device` can access memory of `heap_index` that is physically located on
+    /// `remote_device`. Returns [None] if device groups aren't used.
+    pub fn device_group_peer_memory_features(
+        &self,
+        heap_index: u32,
+        local_device: u32,
+        remote_device: u32,
+    ) -> Option<vk::PeerMemoryFeatureFlags> {
+        self.device_group.as_ref().map(|_| unsafe {
+            self.device.get_device_group_peer_memory_features(
+                heap_index,
+                local_device,
+                remote_device,
+            )
+        })
+    }
+}
@@ -1,12 +1,19 @@
 use std::{collections::HashSet, ffi::CStr};
 
-use ash::{version::InstanceV1_0, vk};
-use log::{debug, error, info};
+use ash::{
+    extensions::khr,
+    version::{InstanceV1_0, InstanceV1_1},
+    vk,
+};
+use log::{debug, error, info, warn};
 
 use crate::{
     errors::{Result, VkTracerError},
     present::choose_surface_format,
-    setup::AdapterRequirements,
+    setup::{
+        cooperative_matrix_extension_name, AdapterRequirements, DeviceFeatures,
+        PhysicalDeviceCooperativeMatrixFeatures,
+    },
     utils::cstr_to_str,
     VULKAN_VERSION, VULKAN_VERSION_STR,
 };
@@ -17,6 +24,17 @@ pub struct PhysicalDeviceInfo {
     pub properties: vk::PhysicalDeviceProperties,
     pub extensions: Vec<vk::ExtensionProperties>,
     pub features: vk::PhysicalDeviceFeatures,
+    pub protected_memory: bool,
+    pub draw_indirect_count: bool,
+    pub host_query_reset: bool,
+    /// All the descriptor indexing features needed by bindless tables.
+    pub descriptor_indexing: bool,
+    /// Partially bound bindings and update after bind of images and storage buffers.
+    pub update_after_bind: bool,
+    /// The `cooperativeMatrix` feature, false without `VK_KHR_cooperative_matrix`.
+    pub cooperative_matrix: bool,
+    /// The ray tracing structures are empty without the extensions.
+    pub supported_features: DeviceFeatures,
     pub queue_families: Vec<vk::QueueFamilyProperties>,
     pub memory_properties: vk::PhysicalDeviceMemoryProperties,
 
@@ -26,6 +44,142 @@ pub struct PhysicalDeviceInfo {
     pub surface_present_modes: Option<Vec<vk::PresentModeKHR>>,
 }
 
+impl PhysicalDeviceInfo {
+    /// Surface properties are only queried with a compatible surface in the requirements.
+    pub(crate) unsafe fn query(
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        requirements: &AdapterRequirements,
+    ) -> Self {
+        let properties = instance.get_physical_device_properties(physical_device);
+        let extensions = instance
+            .enumerate_device_extension_properties(physical_device)
+            .expect("Failed to enumerate device extensions");
+        let features = instance.get_physical_device_features(physical_device);
+        let (
+            protected_memory,
+            draw_indirect_count,
+            host_query_reset,
+            descriptor_indexing,
+            update_after_bind,
+            cooperative_matrix,
+            supported_features,
+        ) = {
+            let has_extension = |name: &CStr| {
+                extensions
+                    .iter()
+                    .any(|extension| CStr::from_ptr(extension.extension_name.as_ptr()) == name)
+            };
+            let mut protected_memory_features =
+                vk::PhysicalDeviceProtectedMemoryFeatures::default();
+            let mut cooperative_matrix_features =
+                PhysicalDeviceCooperativeMatrixFeatures::default();
+            let mut supported = DeviceFeatures {
+                core: features,
+                ..Default::default()
+            };
+            let mut features2 = vk::PhysicalDeviceFeatures2::builder()
+                .push_next(&mut protected_memory_features)
+                .push_next(&mut supported.vulkan_12);
+            // Only chained when the extensions exist
+            if has_extension(khr::AccelerationStructure::name()) {
+                features2 = features2.push_next(&mut supported.acceleration_structure);
+            }
+            if has_extension(khr::RayTracingPipeline::name()) {
+                features2 = features2.push_next(&mut supported.ray_tracing_pipeline);
+            }
+            if has_extension(vk::KhrRayQueryFn::name()) {
+                features2 = features2.push_next(&mut supported.ray_query);
+            }
+            if has_extension(cooperative_matrix_extension_name()) {
+                features2 = features2.push_next(&mut cooperative_matrix_features);
+            }
+            instance.get_physical_device_features2(physical_device, &mut features2);
+            let supported = supported.unchained();
+            let vulkan_12_features = &supported.vulkan_12;
+            (
+                protected_memory_features.protected_memory == vk::TRUE,
+                vulkan_12_features.draw_indirect_count == vk::TRUE,
+                vulkan_12_features.host_query_reset == vk::TRUE,
+                vulkan_12_features.runtime_descriptor_array == vk::TRUE
+                    && vulkan_12_features.descriptor_binding_partially_bound == vk::TRUE
+                    && vulkan_12_features.descriptor_binding_variable_descriptor_count == vk::TRUE
+                    && vulkan_12_features.descriptor_binding_sampled_image_update_after_bind
+                        == vk::TRUE
+                    && vulkan_12_features.shader_sampled_image_array_non_uniform_indexing
+                        == vk::TRUE,
+                vulkan_12_features.descriptor_binding_partially_bound == vk::TRUE
+                    && vulkan_12_features.descriptor_binding_sampled_image_update_after_bind
+                        == vk::TRUE
+                    && vulkan_12_features.descriptor_binding_storage_image_update_after_bind
+                        == vk::TRUE
+                    && vulkan_12_features.descriptor_binding_storage_buffer_update_after_bind
+                        == vk::TRUE,
+                cooperative_matrix_features.supported(),
+                supported,
+            )
+        };
+        let queue_families = instance.get_physical_device_queue_family_properties(physical_device);
+        let memory_properties = instance.get_physical_device_memory_properties(physical_device);
+
+        let surface_capabilities =
+            requirements
+                .compatible_surface
+                .as_ref()
+                .map(|(loader, surface)| {
+                    loader
+                        .get_physical_device_surface_capabilities(physical_device, *surface)
+                        .expect("Failed to get surface capabilities")
+                });
+
+        let surface_formats = requirements
+            .compatible_surface
+            .as_ref()
+            .map(|(loader, surface)| {
+                loader
+                    .get_physical_device_surface_formats(physical_device, *surface)
+                    .expect("Faild to get surface formats")
+            });
+        let surface_format_properties = surface_formats.as_ref().map(|surface_formats| {
+            surface_formats
+                .iter()
+                .map(|format| {
+                    instance.get_physical_device_format_properties(physical_device, format.format)
+                })
+                .collect::<Vec<_>>()
+        });
+        let surface_present_modes =
+            requirements
+                .compatible_surface
+                .as_ref()
+                .map(|(loader, surface)| {
+                    loader
+                        .get_physical_device_surface_present_modes(physical_device, *surface)
+                        .expect("Failed to get surface present modes")
+                });
+
+        Self {
+            handle: physical_device,
+            properties,
+            extensions,
+            features,
+            protected_memory,
+            draw_indirect_count,
+            host_query_reset,
+            descriptor_indexing,
+            update_after_bind,
+            cooperative_matrix,
+            supported_features,
+            queue_families,
+            memory_properties,
+            surface_capabilities,
+            surface_formats,
+            surface_format_properties,
+            surface_present_modes,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct QueueFamilyInfo {
     pub index: u32,
@@ -37,80 +191,200 @@ pub struct AdapterInfo {
     pub physical_device_info: PhysicalDeviceInfo,
     pub graphics_queue: QueueFamilyInfo,
     pub transfer_queue: QueueFamilyInfo,
+    pub compute_queue: QueueFamilyInfo,
+    /// The graphics queue when it can present or without surface.
+    pub present_queue: QueueFamilyInfo,
+    /// The optional extensions of the requirements supported by the device.
+    pub optional_extensions: Vec<&'static CStr>,
     pub score: u32,
 }
 
+/// What can be known of an adapter before picking it, see
+/// [crate::VkTracerApp::enumerate_adapters].
+#[derive(Clone, Debug)]
+pub struct AdapterDescription {
+    /// Position in the enumeration order, as used by [AdapterSelector::Index].
+    pub index: usize,
+    pub name: String,
+    pub device_type: vk::PhysicalDeviceType,
+    pub vendor_id: u32,
+    pub device_id: u32,
+    pub api_version: u32,
+    /// Sum of the device local heaps, in bytes.
+    pub device_local_memory: vk::DeviceSize,
+    pub queue_families: Vec<vk::QueueFamilyProperties>,
+}
+
+impl AdapterDescription {
+    pub(crate) unsafe fn query(
+        instance: &ash::Instance,
+        index: usize,
+        physical_device: vk::PhysicalDevice,
+    ) -> Self {
+        let properties = instance.get_physical_device_properties(physical_device);
+        let memory_properties = instance.get_physical_device_memory_properties(physical_device);
+
+        Self {
+            index,
+            name: cstr_to_str(properties.device_name.as_ptr()).into_owned(),
+            device_type: properties.device_type,
+            vendor_id: properties.vendor_id,
+            device_id: properties.device_id,
+            api_version: properties.api_version,
+            device_local_memory: device_local_memory(&memory_properties),
+            queue_families: instance.get_physical_device_queue_family_properties(physical_device),
+        }
+    }
+}
+
+/// Restrict the adapters considered by [crate::setup::VkTracerAppBuilder::with_adapter].
+pub enum AdapterSelector {
+    /// Index in [crate::VkTracerApp::enumerate_adapters].
+    Index(usize),
+    Predicate(Box<dyn Fn(&AdapterDescription) -> bool>),
+}
+
+impl AdapterSelector {
+    pub fn predicate(predicate: impl Fn(&AdapterDescription) -> bool + 'static) -> Self {
+        Self::Predicate(Box::new(predicate))
+    }
+
+    fn matches(&self, description: &AdapterDescription) -> bool {
+        match self {
+            Self::Index(index) => *index == description.index,
+            Self::Predicate(predicate) => predicate(description),
+        }
+    }
+}
+
+impl From<usize> for AdapterSelector {
+    fn from(index: usize) -> Self {
+        Self::Index(index)
+    }
+}
+
+impl std::fmt::Debug for AdapterSelector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Index(index) => f.debug_tuple("Index").field(index).finish(),
+            Self::Predicate(_) => f.write_str("Predicate"),
+        }
+    }
+}
+
+/// Application defined score of the adapters, see
+/// [crate::setup::VkTracerAppBuilder::with_adapter_score_fn].
+pub(crate) struct AdapterScoring {
+    /// None rejects the adapter.
+    pub(crate) score_fn: Box<dyn Fn(&PhysicalDeviceInfo) -> Option<u32>>,
+    /// Replace the built-in score instead of adding to it.
+    pub(crate) replace: bool,
+}
+
+/// Environment variable used to force the choice of the adapter.
+/// It can either be the index of the adapter or a part of its name (case insensitive).
+pub const ADAPTER_OVERRIDE_ENV: &str = "VK_TRACER_ADAPTER";
+
+#[derive(Debug)]
+enum AdapterOverride {
+    Index(usize),
+    Name(String),
+}
+
+impl AdapterOverride {
+    fn from_env() -> Option<Self> {
+        let value = std::env::var(ADAPTER_OVERRIDE_ENV).ok()?;
+        let value = value.trim();
+
+        if value.is_empty() {
+            None
+        } else if let Ok(index) = value.parse() {
+            Some(Self::Index(index))
+        } else {
+            Some(Self::Name(value.to_lowercase()))
+        }
+    }
+
+    fn matches(&self, index: usize, properties: &vk::PhysicalDeviceProperties) -> bool {
+        match self {
+            Self::Index(i) => *i == index,
+            Self::Name(name) => cstr_to_str(properties.device_name.as_ptr())
+                .to_lowercase()
+                .contains(name.as_str()),
+        }
+    }
+}
+
 pub fn pick_adapter(
     instance: &ash::Instance,
     requirements: &AdapterRequirements,
+    selector: Option<&AdapterSelector>,
+    scoring: Option<&AdapterScoring>,
 ) -> Result<AdapterInfo> {
-    let physical_devices = unsafe { instance.enumerate_physical_devices()? };
+    let mut physical_devices = unsafe { instance.enumerate_physical_devices()? };
+    let mut overridden = false;
+
+    // *** Apply user override before anything else
+    if let Some(adapter_override) = AdapterOverride::from_env() {
+        let forced = physical_devices
+            .iter()
+            .copied()
+            .enumerate()
+            .filter(|(index, physical_device)| unsafe {
+                adapter_override.matches(
+                    *index,
+                    &instance.get_physical_device_properties(*physical_device),
+                )
+            })
+            .map(|(_, physical_device)| physical_device)
+            .collect::<Vec<_>>();
+
+        if forced.is_empty() {
+            warn!(
+                "{} is set to {:?} but no adapter matches, ignoring",
+                ADAPTER_OVERRIDE_ENV, adapter_override
+            );
+        } else {
+            info!(
+                "{} is set to {:?}, only considering matching adapters",
+                ADAPTER_OVERRIDE_ENV, adapter_override
+            );
+            physical_devices = forced;
+            overridden = true;
+        }
+    }
+
+    // *** Then the choice of the application
+    if let (Some(selector), false) = (selector, overridden) {
+        let selected = physical_devices
+            .iter()
+            .copied()
+            .enumerate()
+            .filter(|(index, physical_device)| unsafe {
+                selector.matches(&AdapterDescription::query(
+                    instance,
+                    *index,
+                    *physical_device,
+                ))
+            })
+            .map(|(_, physical_device)| physical_device)
+            .collect::<Vec<_>>();
+
+        // Never silently fall back to another adapter than the pinned one
+        if selected.is_empty() {
+            error!("No adapter matches {:?}", selector);
+            return Err(VkTracerError::NoMatchingAdapter(format!("{:?}", selector)));
+        }
+        physical_devices = selected;
+    }
 
     let best_device = physical_devices
         .into_iter()
         .map(|physical_device| unsafe {
-            let properties = instance.get_physical_device_properties(physical_device);
-            let extensions = instance
-                .enumerate_device_extension_properties(physical_device)
-                .expect("Failed to enumerate device extensions");
-            let features = instance.get_physical_device_features(physical_device);
-            let queue_families =
-                instance.get_physical_device_queue_family_properties(physical_device);
-            let memory_properties = instance.get_physical_device_memory_properties(physical_device);
-
-            let surface_capabilities =
-                requirements
-                    .compatible_surface
-                    .as_ref()
-                    .map(|(loader, surface)| {
-                        loader
-                            .get_physical_device_surface_capabilities(physical_device, *surface)
-                            .expect("Failed to get surface capabilities")
-                    });
-
-            let surface_formats =
-                requirements
-                    .compatible_surface
-                    .as_ref()
-                    .map(|(loader, surface)| {
-                        loader
-                            .get_physical_device_surface_formats(physical_device, *surface)
-                            .expect("Faild to get surface formats")
-                    });
-            let surface_format_properties = surface_formats.as_ref().map(|surface_formats| {
-                surface_formats
-                    .iter()
-                    .map(|format| {
-                        instance
-                            .get_physical_device_format_properties(physical_device, format.format)
-                    })
-                    .collect::<Vec<_>>()
-            });
-            let surface_present_modes =
-                requirements
-                    .compatible_surface
-                    .as_ref()
-                    .map(|(loader, surface)| {
-                        loader
-                            .get_physical_device_surface_present_modes(physical_device, *surface)
-                            .expect("Failed to get surface present modes")
-                    });
-
-            PhysicalDeviceInfo {
-                handle: physical_device,
-                properties,
-                extensions,
-                features,
-                queue_families,
-                memory_properties,
-                surface_capabilities,
-                surface_formats,
-                surface_format_properties,
-                surface_present_modes,
-            }
+            PhysicalDeviceInfo::query(instance, physical_device, requirements)
         })
         .filter_map(|device_info| {
-            if let Some(res) = process_physical_device(device_info, requirements) {
+            if let Some(res) = process_physical_device(device_info, requirements, scoring) {
                 info!(" => Device is eligible");
                 Some(res)
             } else {
@@ -125,6 +399,8 @@ pub fn pick_adapter(
             "Choosed physical device '{}'",
             cstr_to_str(res.physical_device_info.properties.device_name.as_ptr())
         )
+    } else {
+        error!("No adapter meets the requirements, see the reasons above");
     }
 
     best_device.ok_or(VkTracerError::NoSuitableAdapterError)
@@ -133,6 +409,7 @@ pub fn pick_adapter(
 fn process_physical_device(
     info: PhysicalDeviceInfo,
     requirements: &AdapterRequirements,
+    scoring: Option<&AdapterScoring>,
 ) -> Option<AdapterInfo> {
     info!(
         "Processing physical device {:?}",
@@ -185,6 +462,53 @@ fn process_physical_device(
         }
     }
 
+    let optional_extensions = requirements
+        .optional_extensions
+        .iter()
+        .copied()
+        .filter(|optional| {
+            let found = info.extensions.iter().any(|extension| unsafe {
+                CStr::from_ptr(extension.extension_name.as_ptr()) == *optional
+            });
+            debug!(
+                " - {} (optional) [{}]",
+                optional.to_str().unwrap(),
+                if found { "OK" } else { "NOT FOUND" }
+            );
+            found
+        })
+        .collect::<Vec<_>>();
+
+    // *** Check features
+
+    {
+        debug!(" Checking features...");
+        let missing_features = requirements.features.missing(&info.supported_features);
+        if missing_features.is_empty() {
+            debug!(" - Requested features [OK]");
+        } else {
+            // Visible along with the verdict, the usual reason to reject every adapter
+            for missing in missing_features {
+                info!(" - {} [NOT SUPPORTED]", missing);
+            }
+            return None;
+        }
+
+        // The extension alone doesn't mean shaders can use it
+        let cooperative_matrix_extension = cooperative_matrix_extension_name();
+        if requirements
+            .required_extensions
+            .contains(&cooperative_matrix_extension)
+        {
+            if info.cooperative_matrix {
+                debug!(" - Cooperative matrix [OK]");
+            } else {
+                debug!(" - Cooperative matrix [NOT SUPPORTED]");
+                return None;
+            }
+        }
+    }
+
     // *** Check swapchain formats
     debug!(" Checking swapchain formats...");
 
@@ -215,20 +539,25 @@ fn process_physical_device(
     // *** Check queue families
     debug!(" Checking queue families...");
 
-    // Graphics
+    // Graphics, or only compute when headless
 
+    let main_flags = if requirements.require_graphics {
+        vk::QueueFlags::GRAPHICS
+    } else {
+        vk::QueueFlags::COMPUTE
+    };
     let graphics_queue = info
         .queue_families
         .iter()
         .enumerate()
-        .find(|(_, queue)| queue.queue_flags.contains(vk::QueueFlags::GRAPHICS))
+        .find(|(_, queue)| queue.queue_flags.contains(main_flags))
         .map(|(index, &properties)| QueueFamilyInfo {
             index: index as u32,
             properties,
         });
 
     if graphics_queue.is_none() {
-        debug!(" - No graphics queue found !");
+        debug!(" - No {:?} queue found !", main_flags);
         return None;
     }
     let graphics_queue = graphics_queue.unwrap();
@@ -241,19 +570,49 @@ fn process_physical_device(
 
     // Present
 
-    // Juste check if the graphics queue supports it and fail otherwise
-    if let Some((loader, surface)) = requirements.compatible_surface.as_ref() {
-        let support = unsafe {
+    // Prefer presenting from the graphics queue, fallback to any queue that can
+    let present_queue = if let Some((loader, surface)) = requirements.compatible_surface.as_ref() {
+        let supports_present = |index: usize| unsafe {
             loader
-                .get_physical_device_surface_support(info.handle, graphics_queue.index, *surface)
+                .get_physical_device_surface_support(info.handle, index as u32, *surface)
                 .unwrap()
         };
 
-        if !support {
-            error!(" - Graphics queue doesn't support presentation, that's a problem.");
-            return None;
+        let present_queue = if supports_present(graphics_queue.index as usize) {
+            Some(graphics_queue.clone())
+        } else {
+            info.queue_families
+                .iter()
+                .enumerate()
+                .find(|(index, _)| supports_present(*index))
+                .map(|(index, &properties)| QueueFamilyInfo {
+                    index: index as u32,
+                    properties,
+                })
+        };
+
+        match present_queue {
+            Some(present_queue) if present_queue.index == graphics_queue.index => {
+                debug!(" - Using the graphics queue for presentation");
+                present_queue
+            }
+            Some(present_queue) => {
+                debug!(
+                    " - Using dedicated present queue (ID: {}) (x{}) [{:?}]",
+                    present_queue.index,
+                    present_queue.properties.queue_count,
+                    present_queue.properties.queue_flags
+                );
+                present_queue
+            }
+            None => {
+                debug!(" - No queue can present to the surface !");
+                return None;
+            }
         }
-    }
+    } else {
+        graphics_queue.clone()
+    };
 
     // Transfer
 
@@ -284,6 +643,35 @@ fn process_physical_device(
         );
     }
 
+    // Compute
+
+    let compute_queue = info
+        .queue_families
+        .iter()
+        .enumerate()
+        // Try to find a queue for async compute
+        .find(|(_, queue)| {
+            queue.queue_flags.contains(vk::QueueFlags::COMPUTE)
+                && !queue.queue_flags.contains(vk::QueueFlags::GRAPHICS)
+        })
+        .map(|(index, &properties)| QueueFamilyInfo {
+            index: index as u32,
+            properties,
+        })
+        // Fallback to using the graphics queue
+        .unwrap_or_else(|| graphics_queue.clone());
+
+    if compute_queue.index == graphics_queue.index {
+        debug!(" - Using the graphics queue for compute operations");
+    } else {
+        debug!(
+            " - Using dedicated compute queue (ID: {}) (x{}) [{:?}]",
+            compute_queue.index,
+            compute_queue.properties.queue_count,
+            compute_queue.properties.queue_flags
+        );
+    }
+
     // Score additional properties
 
     let mut score = 0u32;
@@ -292,24 +680,47 @@ fn process_physical_device(
         score += 1000;
     }
 
-    // Count device memory
-    let physical_device_memory_size = info
-        .memory_properties
-        .memory_heaps
-        .iter()
-        .take(info.memory_properties.memory_heap_count as usize)
-        .filter(|heap| heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL))
-        .map(|heap| heap.size)
-        .sum::<vk::DeviceSize>();
     // Count gigabytes of memory
-    score += (physical_device_memory_size / 1_073_741_800) as u32;
+    score += (device_local_memory(&info.memory_properties) / 1_073_741_800) as u32;
+    // Slightly prefer the devices with more optional extensions
+    score += optional_extensions.len() as u32;
 
     debug!(" Additional score of {}", score);
 
+    if let Some(scoring) = scoring {
+        match (scoring.score_fn)(&info) {
+            Some(custom) if scoring.replace => {
+                debug!(" Score replaced by {}", custom);
+                score = custom;
+            }
+            Some(custom) => {
+                debug!(" Custom score of {}", custom);
+                score = score.saturating_add(custom);
+            }
+            None => {
+                info!(" Rejected by the custom score");
+                return None;
+            }
+        }
+    }
+
     Some(AdapterInfo {
         physical_device_info: info,
         graphics_queue,
         transfer_queue,
+        compute_queue,
+        present_queue,
+        optional_extensions,
         score,
     })
 }
+
+fn device_local_memory(memory_properties: &vk::PhysicalDeviceMemoryProperties) -> vk::DeviceSize {
+    memory_properties
+        .memory_heaps
+        .iter()
+        .take(memory_properties.memory_heap_count as usize)
+        .filter(|heap| heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL))
+        .map(|heap| heap.size)
+        .sum()
+}
@@ -7,6 +7,8 @@ const QUEUE_PRIORITIES_ONE: [f32; 1] = [1.0];
 pub struct QueueFamilyIndices {
     pub graphics: u32,
     pub transfer: u32,
+    pub compute: u32,
+    pub present: u32,
 }
 
 impl From<&AdapterInfo> for QueueFamilyIndices {
@@ -14,6 +16,8 @@ impl From<&AdapterInfo> for QueueFamilyIndices {
         Self {
             graphics: device.graphics_queue.index as u32,
             transfer: device.transfer_queue.index as u32,
+            compute: device.compute_queue.index as u32,
+            present: device.present_queue.index as u32,
         }
     }
 }
@@ -38,6 +42,29 @@ impl QueueFamilyIndices {
             );
         }
 
+        // Compute queue
+        if self.compute != self.graphics && self.compute != self.transfer {
+            queues_create_info.push(
+                vk::DeviceQueueCreateInfo::builder()
+                    .queue_family_index(self.compute as u32)
+                    .queue_priorities(&QUEUE_PRIORITIES_ONE)
+                    .build(),
+            );
+        }
+
+        // Present queue
+        if self.present != self.graphics
+            && self.present != self.transfer
+            && self.present != self.compute
+        {
+            queues_create_info.push(
+                vk::DeviceQueueCreateInfo::builder()
+                    .queue_family_index(self.present as u32)
+                    .queue_priorities(&QUEUE_PRIORITIES_ONE)
+                    .build(),
+            );
+        }
+
         queues_create_info
     }
 }
@@ -0,0 +1,94 @@
+//! # Raw handles
+//! Embedding vk_tracer in an application (or an OpenXR runtime) that already owns the Vulkan
+//! instance and device.
+
+use crate::{
+    command_recorder::QueueType,
+    errors::{Result, VkTracerError},
+    setup::{
+        vk_tracer_extensions_to_vk_extensions, Adapter, AdapterInfo, AdapterRequirements,
+        PhysicalDeviceInfo, QueueFamilyInfo, VkTracerExtensions,
+    },
+    VkTracerApp,
+};
+use ash::vk;
+use log::debug;
+use std::collections::HashSet;
+
+/// Queue families of a device created by the application, the first queue of each family is
+/// used. They can all be the same family.
+#[derive(Copy, Clone, Debug)]
+pub struct RawQueues {
+    pub graphics: u32,
+    pub transfer: u32,
+    pub compute: u32,
+}
+
+impl RawQueues {
+    /// Everything on the same family.
+    pub fn single(family: u32) -> Self {
+        Self {
+            graphics: family,
+            transfer: family,
+            compute: family,
+        }
+    }
+}
+
+impl VkTracerApp {
+    /// Wrap an instance and a device created by the application instead of
+    /// [crate::setup::VkTracerAppBuilder::build].
+    ///
+    /// The device must be Vulkan 1.2 and have been created with the device extensions and
+    /// features of `extensions`, along with the features `build` enables when supported. The
+    /// handles stay owned by the application, they must outlive the app and aren't destroyed
+    /// with it. There is no main surface.
+    pub fn from_raw(
+        entry: ash::Entry,
+        instance: ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        device: ash::Device,
+        queues: RawQueues,
+        extensions: &[VkTracerExtensions],
+    ) -> Result<Self> {
+        let extensions = extensions.iter().copied().collect::<HashSet<_>>();
+        let requirements = AdapterRequirements {
+            required_extensions: vk_tracer_extensions_to_vk_extensions(extensions.iter()).collect(),
+            ..Default::default()
+        };
+
+        let physical_device_info =
+            unsafe { PhysicalDeviceInfo::query(&instance, physical_device, &requirements) };
+        let queue_family = |index: u32, ty: QueueType| {
+            physical_device_info
+                .queue_families
+                .get(index as usize)
+                .map(|&properties| QueueFamilyInfo { index, properties })
+                .ok_or(VkTracerError::NoQueueAvailable(ty))
+        };
+        let graphics_queue = queue_family(queues.graphics, QueueType::Graphics)?;
+        let transfer_queue = queue_family(queues.transfer, QueueType::Transfer)?;
+        let compute_queue = queue_family(queues.compute, QueueType::Compute)?;
+        // Nothing to present to
+        let present_queue = graphics_queue.clone();
+
+        let adapter = Adapter::new(
+            physical_device,
+            AdapterInfo {
+                physical_device_info,
+                graphics_queue,
+                transfer_queue,
+                compute_queue,
+                present_queue,
+                optional_extensions: Vec::new(),
+                score: 0,
+            },
+            requirements,
+        );
+
+        let mut app = Self::from_core(entry, instance, adapter, device, &extensions)?;
+        app.external_handles = true;
+        debug!("Wrapped external device");
+        Ok(app)
+    }
+}
@@ -0,0 +1,369 @@
+use crate::{
+    command_recorder::QueueType, errors::Result, synchronization2::Submission, VkTracerApp,
+};
+use ash::{version::DeviceV1_0, vk};
+use parking_lot::Mutex;
+use std::{collections::HashMap, slice::from_ref, sync::Arc};
+
+/// Command buffers waiting to be submitted together, and the resources of the submitted
+/// ones until they complete.
+#[derive(Default)]
+pub(crate) struct SubmissionScheduler {
+    pending: Vec<PendingCommands>,
+    /// Signaled by submitted batches, the next graphics submission waits on them.
+    waits: Vec<vk::Semaphore>,
+    in_flight: Vec<InFlightBatch>,
+    free_semaphores: Vec<vk::Semaphore>,
+    free_fences: Vec<vk::Fence>,
+    /// Executed command buffers, they are reset when recorded again.
+    free_commands: HashMap<vk::CommandPool, Vec<vk::CommandBuffer>>,
+    /// Never submitted, dropped where the app isn't reachable.
+    pub(crate) dropped_commands: DroppedCommands,
+}
+
+pub(crate) type DroppedCommands = Arc<Mutex<Vec<(vk::CommandPool, vk::CommandBuffer)>>>;
+
+struct PendingCommands {
+    queue: vk::Queue,
+    pool: vk::CommandPool,
+    buffer: vk::CommandBuffer,
+}
+
+struct InFlightBatch {
+    fence: vk::Fence,
+    /// Fences of the frames are managed by the swapchain.
+    owned_fence: bool,
+    pool: vk::CommandPool,
+    commands: Vec<vk::CommandBuffer>,
+    semaphores: Vec<vk::Semaphore>,
+}
+
+/// Deferred work of the graphics queue, submitted with the next frame.
+pub(crate) struct GraphicsBatch {
+    pub(crate) commands: Vec<vk::CommandBuffer>,
+    /// What the deferred commands wait on.
+    pub(crate) waits: Vec<vk::Semaphore>,
+    pub(crate) wait_stages: Vec<vk::PipelineStageFlags>,
+    /// Signaled by the deferred commands, if any.
+    pub(crate) signal: Option<vk::Semaphore>,
+}
+
+impl GraphicsBatch {
+    /// Semaphores the frame must wait on, after all the deferred work.
+    pub(crate) fn frame_waits(&self) -> &[vk::Semaphore] {
+        match self.signal.as_ref() {
+            Some(signal) => from_ref(signal),
+            None => &self.waits,
+        }
+    }
+}
+
+impl VkTracerApp {
+    /// Queue commands to be submitted by [VkTracerApp::flush_submissions] or with the next frame.
+    pub(crate) fn defer_commands(
+        &mut self,
+        queue: vk::Queue,
+        pool: vk::CommandPool,
+        buffer: vk::CommandBuffer,
+    ) {
+        self.submissions.pending.push(PendingCommands {
+            queue,
+            pool,
+            buffer,
+        });
+    }
+
+    /// Submit the deferred work of every queue except graphics, in one submission per queue.
+    /// The next graphics submission waits for it, the graphics work itself is submitted along
+    /// with the next frame. Without a main surface no frame ever comes, so the graphics work
+    /// is flushed too, see [VkTracerApp::flush_graphics].
+    pub fn flush_submissions(&mut self) -> Result<()> {
+        self.submit_other_queues()?;
+        if self.main_surface.is_none() {
+            self.flush_graphics()?;
+        }
+        Ok(())
+    }
+
+    fn submit_other_queues(&mut self) -> Result<()> {
+        let graphics_queue = self.queue_and_pool(QueueType::Graphics)?.0;
+        self.retire_submissions(None)?;
+
+        let (mut pending, graphics): (Vec<_>, Vec<_>) =
+            std::mem::take(&mut self.submissions.pending)
+                .into_iter()
+                .partition(|pending| pending.queue != graphics_queue);
+        self.submissions.pending = graphics;
+
+        while let Some(first) = pending.first() {
+            let (queue, pool) = (first.queue, first.pool);
+            let (batch, rest): (Vec<_>, Vec<_>) = pending
+                .into_iter()
+                .partition(|pending| pending.queue == queue);
+            pending = rest;
+
+            let commands = batch
+                .into_iter()
+                .map(|pending| pending.buffer)
+                .collect::<Vec<_>>();
+            let signal = self.get_semaphore()?;
+            let fence = self.get_fence()?;
+
+            unsafe {
+                self.queue_submit(
+                    queue,
+                    from_ref(&Submission {
+                        commands: &commands,
+                        signals: from_ref(&signal),
+                        ..Default::default()
+                    }),
+                    fence,
+                )?;
+            }
+
+            self.submissions.waits.push(signal);
+            self.submissions.in_flight.push(InFlightBatch {
+                fence,
+                owned_fence: true,
+                pool,
+                commands,
+                semaphores: Vec::new(),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Submit the deferred work of the transfer queue now and wait for it to complete, even the
+    /// work that would have been submitted with the next frame when it is the graphics queue.
+    pub fn flush_transfers(&mut self) -> Result<()> {
+        let transfer_queue = self.queue_and_pool(QueueType::Transfer)?.0;
+        if transfer_queue == self.queue_and_pool(QueueType::Graphics)?.0 {
+            self.flush_graphics()?;
+        } else {
+            self.flush_submissions()?;
+        }
+        self.wait_queue_idle(QueueType::Transfer)
+    }
+
+    /// Wait for all the work submitted to a single queue, lighter than waiting for the whole
+    /// device before destroying or reading back resources only used by this queue.
+    pub fn wait_queue_idle(&mut self, queue: QueueType) -> Result<()> {
+        let queue = self.queue_and_pool(queue)?.0;
+        unsafe { self.device.queue_wait_idle(queue)? };
+        self.retire_submissions(None)
+    }
+
+    /// Submit the deferred graphics work on its own instead of with the next frame, after the
+    /// work of the other queues. It also consumes the semaphores the next frame would have
+    /// waited on, apps that never present must call it to get their graphics work executed.
+    pub fn flush_graphics(&mut self) -> Result<()> {
+        let (queue, pool) = self.queue_and_pool(QueueType::Graphics)?;
+        let mut batch = self.take_graphics_batch()?;
+        if batch.commands.is_empty() && batch.waits.is_empty() {
+            return Ok(());
+        }
+
+        // Nothing outside of a frame would wait on it
+        self.submissions.free_semaphores.extend(batch.signal.take());
+        let fence = self.get_fence()?;
+
+        unsafe {
+            self.queue_submit(
+                queue,
+                from_ref(&Submission {
+                    waits: &batch.waits,
+                    wait_stages: &batch.wait_stages,
+                    commands: &batch.commands,
+                    ..Default::default()
+                }),
+                fence,
+            )?;
+        }
+
+        self.submissions.in_flight.push(InFlightBatch {
+            fence,
+            owned_fence: true,
+            pool,
+            commands: batch.commands,
+            semaphores: batch.waits,
+        });
+        Ok(())
+    }
+
+    /// Flush the other queues and gather the deferred graphics work to be submitted in the
+    /// same `vkQueueSubmit` as a frame, then given to [VkTracerApp::track_graphics_batch].
+    pub(crate) fn take_graphics_batch(&mut self) -> Result<GraphicsBatch> {
+        self.submit_other_queues()?;
+
+        let commands = std::mem::take(&mut self.submissions.pending)
+            .into_iter()
+            .map(|pending| pending.buffer)
+            .collect::<Vec<_>>();
+        let waits = std::mem::take(&mut self.submissions.waits);
+        let signal = if commands.is_empty() {
+            None
+        } else {
+            Some(self.get_semaphore()?)
+        };
+
+        Ok(GraphicsBatch {
+            commands,
+            wait_stages: vec![vk::PipelineStageFlags::ALL_COMMANDS; waits.len()],
+            waits,
+            signal,
+        })
+    }
+
+    /// The batch couldn't be submitted, its work is deferred again to the next submission.
+    pub(crate) fn restore_graphics_batch(&mut self, batch: GraphicsBatch) -> Result<()> {
+        let (queue, pool) = self.queue_and_pool(QueueType::Graphics)?;
+        let restored = batch.commands.into_iter().map(|buffer| PendingCommands {
+            queue,
+            pool,
+            buffer,
+        });
+        self.submissions.pending.splice(0..0, restored);
+        self.submissions.waits.splice(0..0, batch.waits);
+        self.submissions.free_semaphores.extend(batch.signal);
+        Ok(())
+    }
+
+    /// The batch was submitted with `fence`, its resources are freed once it is signaled.
+    pub(crate) fn track_graphics_batch(
+        &mut self,
+        batch: GraphicsBatch,
+        fence: vk::Fence,
+    ) -> Result<()> {
+        let mut semaphores = batch.waits;
+        semaphores.extend(batch.signal);
+
+        self.submissions.in_flight.push(InFlightBatch {
+            fence,
+            owned_fence: false,
+            pool: self.queue_and_pool(QueueType::Graphics)?.1,
+            commands: batch.commands,
+            semaphores,
+        });
+        Ok(())
+    }
+
+    /// Free the batches that completed, `signaled` is a frame fence known to be signaled that is
+    /// about to be reset.
+    pub(crate) fn retire_submissions(&mut self, signaled: Option<vk::Fence>) -> Result<()> {
+        let mut i = 0;
+        while i < self.submissions.in_flight.len() {
+            let batch = &self.submissions.in_flight[i];
+            let done = if batch.owned_fence {
+                unsafe { self.device.get_fence_status(batch.fence)? }
+            } else {
+                Some(batch.fence) == signaled
+            };
+            if !done {
+                i += 1;
+                continue;
+            }
+
+            let batch = self.submissions.in_flight.swap_remove(i);
+            if batch.owned_fence {
+                self.recycle_fence(batch.fence)?;
+            }
+            self.recycle_command_buffers(batch.pool, batch.commands);
+            self.submissions.free_semaphores.extend(batch.semaphores);
+        }
+
+        Ok(())
+    }
+
+    fn get_semaphore(&mut self) -> Result<vk::Semaphore> {
+        match self.submissions.free_semaphores.pop() {
+            Some(semaphore) => Ok(semaphore),
+            None => unsafe {
+                Ok(self
+                    .device
+                    .create_semaphore(&vk::SemaphoreCreateInfo::default(), None)?)
+            },
+        }
+    }
+
+    /// A primary command buffer of `pool` that can be recorded, it is allocated only when none
+    /// were recycled.
+    pub(crate) fn get_command_buffer(
+        &mut self,
+        pool: vk::CommandPool,
+    ) -> Result<vk::CommandBuffer> {
+        let dropped = std::mem::take(&mut *self.submissions.dropped_commands.lock());
+        for (pool, buffer) in dropped {
+            self.recycle_command_buffers(pool, Some(buffer));
+        }
+
+        if let Some(buffer) = self
+            .submissions
+            .free_commands
+            .get_mut(&pool)
+            .and_then(Vec::pop)
+        {
+            return Ok(buffer);
+        }
+
+        unsafe {
+            Ok(self.device.allocate_command_buffers(
+                &vk::CommandBufferAllocateInfo::builder()
+                    .command_pool(pool)
+                    .command_buffer_count(1)
+                    .level(vk::CommandBufferLevel::PRIMARY),
+            )?[0])
+        }
+    }
+
+    /// The command buffers must not be pending execution.
+    pub(crate) fn recycle_command_buffers(
+        &mut self,
+        pool: vk::CommandPool,
+        buffers: impl IntoIterator<Item = vk::CommandBuffer>,
+    ) {
+        self.submissions
+            .free_commands
+            .entry(pool)
+            .or_default()
+            .extend(buffers);
+    }
+
+    /// The fence must be signaled.
+    pub(crate) fn recycle_fence(&mut self, fence: vk::Fence) -> Result<()> {
+        unsafe { self.device.reset_fences(from_ref(&fence))? };
+        self.submissions.free_fences.push(fence);
+        Ok(())
+    }
+
+    pub(crate) fn get_fence(&mut self) -> Result<vk::Fence> {
+        match self.submissions.free_fences.pop() {
+            Some(fence) => Ok(fence),
+            None => unsafe {
+                Ok(self
+                    .device
+                    .create_fence(&vk::FenceCreateInfo::default(), None)?)
+            },
+        }
+    }
+}
+
+impl SubmissionScheduler {
+    /// The device must be idle, the command buffers are freed with their pool.
+    pub(crate) unsafe fn destroy(&mut self, device: &ash::Device) {
+        for batch in self.in_flight.drain(..) {
+            if batch.owned_fence {
+                device.destroy_fence(batch.fence, None);
+            }
+            for semaphore in batch.semaphores {
+                device.destroy_semaphore(semaphore, None);
+            }
+        }
+        for semaphore in self.waits.drain(..).chain(self.free_semaphores.drain(..)) {
+            device.destroy_semaphore(semaphore, None);
+        }
+        for fence in self.free_fences.drain(..) {
+            device.destroy_fence(fence, None);
+        }
+    }
+}
@@ -0,0 +1,94 @@
+use crate::{
+    errors::{HandleType, Result},
+    FenceHandle, SemaphoreHandle, VkTracerApp,
+};
+use ash::{version::DeviceV1_0, vk};
+use std::slice::from_ref;
+
+/// Additional synchronization of a frame with user semaphores, for
+/// [VkTracerApp::render_and_present_with_sync].
+#[derive(Copy, Clone, Default)]
+pub struct FrameSync<'a> {
+    /// Waited on before the given stages of the frame.
+    pub wait_semaphores: &'a [(SemaphoreHandle, vk::PipelineStageFlags)],
+    /// Signaled once the frame is rendered.
+    pub signal_semaphores: &'a [SemaphoreHandle],
+}
+
+impl VkTracerApp {
+    pub fn create_fence(&mut self, signaled: bool) -> Result<FenceHandle> {
+        let flags = if signaled {
+            vk::FenceCreateFlags::SIGNALED
+        } else {
+            vk::FenceCreateFlags::empty()
+        };
+        let fence = unsafe {
+            self.device
+                .create_fence(&vk::FenceCreateInfo::builder().flags(flags), None)?
+        };
+        Ok(self.fence_storage.insert(fence))
+    }
+
+    /// Wait at most `timeout` nanoseconds, returns whether the fence was signaled.
+    pub fn wait_fence(&self, fence: FenceHandle, timeout: u64) -> Result<bool> {
+        let fence = storage_access!(self.fence_storage, fence, HandleType::Fence);
+        match unsafe { self.device.wait_for_fences(from_ref(fence), true, timeout) } {
+            Ok(()) => Ok(true),
+            Err(vk::Result::TIMEOUT) => Ok(false),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub fn is_fence_signaled(&self, fence: FenceHandle) -> Result<bool> {
+        let fence = storage_access!(self.fence_storage, fence, HandleType::Fence);
+        Ok(unsafe { self.device.get_fence_status(*fence)? })
+    }
+
+    pub fn reset_fence(&mut self, fence: FenceHandle) -> Result<()> {
+        let fence = storage_access!(self.fence_storage, fence, HandleType::Fence);
+        unsafe { self.device.reset_fences(from_ref(fence))? };
+        Ok(())
+    }
+
+    /// The fence must not be used by a pending submission.
+    pub fn destroy_fence(&mut self, fence: FenceHandle) -> Result<()> {
+        if let Some(fence) = self.fence_storage.remove(fence) {
+            unsafe { self.device.destroy_fence(fence, None) };
+        }
+        Ok(())
+    }
+
+    pub fn create_semaphore(&mut self) -> Result<SemaphoreHandle> {
+        let semaphore = unsafe {
+            self.device
+                .create_semaphore(&vk::SemaphoreCreateInfo::default(), None)?
+        };
+        Ok(self.semaphore_storage.insert(semaphore))
+    }
+
+    /// The semaphore must not be used by a pending submission.
+    pub fn destroy_semaphore(&mut self, semaphore: SemaphoreHandle) -> Result<()> {
+        if let Some(semaphore) = self.semaphore_storage.remove(semaphore) {
+            unsafe { self.device.destroy_semaphore(semaphore, None) };
+        }
+        Ok(())
+    }
+
+    /// The raw fence, to submit work recorded outside of vk-tracer.
+    pub fn get_raw_fence(&self, fence: FenceHandle) -> Result<vk::Fence> {
+        Ok(*storage_access!(
+            self.fence_storage,
+            fence,
+            HandleType::Fence
+        ))
+    }
+
+    /// The raw semaphore, to synchronize work submitted outside of vk-tracer.
+    pub fn get_raw_semaphore(&self, semaphore: SemaphoreHandle) -> Result<vk::Semaphore> {
+        Ok(*storage_access!(
+            self.semaphore_storage,
+            semaphore,
+            HandleType::Semaphore
+        ))
+    }
+}
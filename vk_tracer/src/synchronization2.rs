@@ -0,0 +1,462 @@
+//! # Synchronization2
+//! With [crate::setup::VkTracerExtensions::Synchronization2], the submissions and the barriers
+//! go through `vkQueueSubmit2` and `vkCmdPipelineBarrier2`, with the stages and accesses
+//! attached to each barrier and semaphore. Without it, the same submissions and barriers are
+//! recorded with the classic commands, the call sites don't change.
+//!
+//! `VK_KHR_synchronization2` is too recent for our version of ash, so the structures and the
+//! functions we need are declared by hand here, following the Vulkan registry. It is core in
+//! Vulkan 1.3, the core functions are used once [crate::VULKAN_VERSION] and the device allow it.
+
+use crate::{
+    errors::{Result, VkTracerError},
+    VkTracerApp, VULKAN_VERSION,
+};
+use ash::{
+    version::{DeviceV1_0, InstanceV1_0},
+    vk,
+};
+use std::{ffi::CStr, os::raw::c_void, ptr, slice::from_ref};
+
+pub(crate) fn synchronization2_extension_name() -> &'static CStr {
+    unsafe { CStr::from_bytes_with_nul_unchecked(b"VK_KHR_synchronization2\0") }
+}
+
+/// The extension is only needed before Vulkan 1.3.
+pub(crate) fn synchronization2_is_core() -> bool {
+    VULKAN_VERSION >= vk::make_version(1, 3, 0)
+}
+
+const STRUCTURE_TYPE_MEMORY_BARRIER_2: vk::StructureType =
+    vk::StructureType::from_raw(1_000_314_000);
+const STRUCTURE_TYPE_BUFFER_MEMORY_BARRIER_2: vk::StructureType =
+    vk::StructureType::from_raw(1_000_314_001);
+const STRUCTURE_TYPE_IMAGE_MEMORY_BARRIER_2: vk::StructureType =
+    vk::StructureType::from_raw(1_000_314_002);
+const STRUCTURE_TYPE_DEPENDENCY_INFO: vk::StructureType =
+    vk::StructureType::from_raw(1_000_314_003);
+const STRUCTURE_TYPE_SUBMIT_INFO_2: vk::StructureType = vk::StructureType::from_raw(1_000_314_004);
+const STRUCTURE_TYPE_SEMAPHORE_SUBMIT_INFO: vk::StructureType =
+    vk::StructureType::from_raw(1_000_314_005);
+const STRUCTURE_TYPE_COMMAND_BUFFER_SUBMIT_INFO: vk::StructureType =
+    vk::StructureType::from_raw(1_000_314_006);
+const STRUCTURE_TYPE_PHYSICAL_DEVICE_SYNCHRONIZATION_2_FEATURES: vk::StructureType =
+    vk::StructureType::from_raw(1_000_314_007);
+
+/// `VK_PIPELINE_STAGE_2_ALL_COMMANDS_BIT`, what a classic submission signals its semaphores
+/// with.
+const PIPELINE_STAGE_2_ALL_COMMANDS: u64 = 0x0001_0000;
+
+/// The stages and accesses of synchronization2 are 64 bits wide, the classic ones keep their
+/// value.
+#[inline]
+fn stages2(stages: vk::PipelineStageFlags) -> u64 {
+    stages.as_raw() as u64
+}
+
+#[inline]
+fn access2(access: vk::AccessFlags) -> u64 {
+    access.as_raw() as u64
+}
+
+/// `VkPhysicalDeviceSynchronization2FeaturesKHR`
+#[repr(C)]
+pub(crate) struct PhysicalDeviceSynchronization2Features {
+    s_type: vk::StructureType,
+    p_next: *mut c_void,
+    synchronization2: vk::Bool32,
+}
+
+unsafe impl vk::ExtendsDeviceCreateInfo for PhysicalDeviceSynchronization2Features {}
+
+impl PhysicalDeviceSynchronization2Features {
+    pub(crate) fn enabled() -> Self {
+        Self {
+            s_type: STRUCTURE_TYPE_PHYSICAL_DEVICE_SYNCHRONIZATION_2_FEATURES,
+            p_next: ptr::null_mut(),
+            synchronization2: vk::TRUE,
+        }
+    }
+}
+
+/// `VkMemoryBarrier2KHR`
+#[repr(C)]
+struct MemoryBarrier2 {
+    s_type: vk::StructureType,
+    p_next: *const c_void,
+    src_stage_mask: u64,
+    src_access_mask: u64,
+    dst_stage_mask: u64,
+    dst_access_mask: u64,
+}
+
+/// `VkBufferMemoryBarrier2KHR`
+#[repr(C)]
+struct BufferMemoryBarrier2 {
+    s_type: vk::StructureType,
+    p_next: *const c_void,
+    src_stage_mask: u64,
+    src_access_mask: u64,
+    dst_stage_mask: u64,
+    dst_access_mask: u64,
+    src_queue_family_index: u32,
+    dst_queue_family_index: u32,
+    buffer: vk::Buffer,
+    offset: vk::DeviceSize,
+    size: vk::DeviceSize,
+}
+
+/// `VkImageMemoryBarrier2KHR`
+#[repr(C)]
+struct ImageMemoryBarrier2 {
+    s_type: vk::StructureType,
+    p_next: *const c_void,
+    src_stage_mask: u64,
+    src_access_mask: u64,
+    dst_stage_mask: u64,
+    dst_access_mask: u64,
+    old_layout: vk::ImageLayout,
+    new_layout: vk::ImageLayout,
+    src_queue_family_index: u32,
+    dst_queue_family_index: u32,
+    image: vk::Image,
+    subresource_range: vk::ImageSubresourceRange,
+}
+
+/// `VkDependencyInfoKHR`
+#[repr(C)]
+struct DependencyInfo {
+    s_type: vk::StructureType,
+    p_next: *const c_void,
+    dependency_flags: vk::DependencyFlags,
+    memory_barrier_count: u32,
+    p_memory_barriers: *const MemoryBarrier2,
+    buffer_memory_barrier_count: u32,
+    p_buffer_memory_barriers: *const BufferMemoryBarrier2,
+    image_memory_barrier_count: u32,
+    p_image_memory_barriers: *const ImageMemoryBarrier2,
+}
+
+/// `VkSemaphoreSubmitInfoKHR`
+#[repr(C)]
+struct SemaphoreSubmitInfo {
+    s_type: vk::StructureType,
+    p_next: *const c_void,
+    semaphore: vk::Semaphore,
+    value: u64,
+    stage_mask: u64,
+    device_index: u32,
+}
+
+/// `VkCommandBufferSubmitInfoKHR`
+#[repr(C)]
+struct CommandBufferSubmitInfo {
+    s_type: vk::StructureType,
+    p_next: *const c_void,
+    command_buffer: vk::CommandBuffer,
+    device_mask: u32,
+}
+
+/// `VkSubmitInfo2KHR`
+#[repr(C)]
+struct SubmitInfo2 {
+    s_type: vk::StructureType,
+    p_next: *const c_void,
+    flags: u32,
+    wait_semaphore_info_count: u32,
+    p_wait_semaphore_infos: *const SemaphoreSubmitInfo,
+    command_buffer_info_count: u32,
+    p_command_buffer_infos: *const CommandBufferSubmitInfo,
+    signal_semaphore_info_count: u32,
+    p_signal_semaphore_infos: *const SemaphoreSubmitInfo,
+}
+
+type PfnCmdPipelineBarrier2 =
+    unsafe extern "system" fn(command_buffer: vk::CommandBuffer, info: *const DependencyInfo);
+type PfnQueueSubmit2 = unsafe extern "system" fn(
+    queue: vk::Queue,
+    submit_count: u32,
+    submits: *const SubmitInfo2,
+    fence: vk::Fence,
+) -> vk::Result;
+
+pub(crate) struct Synchronization2Fns {
+    pipeline_barrier2: PfnCmdPipelineBarrier2,
+    queue_submit2: PfnQueueSubmit2,
+}
+
+impl Synchronization2Fns {
+    /// The core functions when both our version and the device's are at least 1.3, those of
+    /// the extension otherwise.
+    pub(crate) fn load(
+        instance: &ash::Instance,
+        device: &ash::Device,
+        device_version: u32,
+    ) -> Result<Self> {
+        let extension = synchronization2_extension_name().to_str().unwrap();
+        let core = synchronization2_is_core() && device_version >= vk::make_version(1, 3, 0);
+        let (pipeline_barrier2, queue_submit2): (&[u8], &[u8]) = if core {
+            (b"vkCmdPipelineBarrier2\0", b"vkQueueSubmit2\0")
+        } else {
+            (b"vkCmdPipelineBarrier2KHR\0", b"vkQueueSubmit2KHR\0")
+        };
+        unsafe {
+            let pipeline_barrier2 = instance
+                .get_device_proc_addr(device.handle(), pipeline_barrier2.as_ptr() as *const _)
+                .ok_or(VkTracerError::ExtensionNotEnabled(extension))?;
+            let queue_submit2 = instance
+                .get_device_proc_addr(device.handle(), queue_submit2.as_ptr() as *const _)
+                .ok_or(VkTracerError::ExtensionNotEnabled(extension))?;
+            Ok(Self {
+                pipeline_barrier2: std::mem::transmute(pipeline_barrier2),
+                queue_submit2: std::mem::transmute(queue_submit2),
+            })
+        }
+    }
+}
+
+/// A batch of a queue submission, in the terms of `VkSubmitInfo`.
+#[derive(Copy, Clone, Default)]
+pub(crate) struct Submission<'a> {
+    pub(crate) waits: &'a [vk::Semaphore],
+    /// One per wait.
+    pub(crate) wait_stages: &'a [vk::PipelineStageFlags],
+    pub(crate) commands: &'a [vk::CommandBuffer],
+    pub(crate) signals: &'a [vk::Semaphore],
+    /// The device of the group running the commands and operating the semaphores, all the
+    /// devices run them when `None`.
+    pub(crate) device_index: Option<u32>,
+}
+
+impl VkTracerApp {
+    /// Record a pipeline barrier, with `vkCmdPipelineBarrier2` when available. The stages apply
+    /// to every barrier.
+    pub(crate) unsafe fn cmd_pipeline_barrier(
+        &self,
+        commands: vk::CommandBuffer,
+        src_stage: vk::PipelineStageFlags,
+        dst_stage: vk::PipelineStageFlags,
+        memory_barriers: &[vk::MemoryBarrier],
+        buffer_barriers: &[vk::BufferMemoryBarrier],
+        image_barriers: &[vk::ImageMemoryBarrier],
+    ) {
+        let sync2 = match self.synchronization2.as_ref() {
+            Some(sync2) => sync2,
+            None => {
+                self.device.cmd_pipeline_barrier(
+                    commands,
+                    src_stage,
+                    dst_stage,
+                    vk::DependencyFlags::empty(),
+                    memory_barriers,
+                    buffer_barriers,
+                    image_barriers,
+                );
+                return;
+            }
+        };
+
+        let (src_stage_mask, dst_stage_mask) = (stages2(src_stage), stages2(dst_stage));
+        let memory_barriers = memory_barriers
+            .iter()
+            .map(|barrier| MemoryBarrier2 {
+                s_type: STRUCTURE_TYPE_MEMORY_BARRIER_2,
+                p_next: ptr::null(),
+                src_stage_mask,
+                src_access_mask: access2(barrier.src_access_mask),
+                dst_stage_mask,
+                dst_access_mask: access2(barrier.dst_access_mask),
+            })
+            .collect::<Vec<_>>();
+        let buffer_barriers = buffer_barriers
+            .iter()
+            .map(|barrier| BufferMemoryBarrier2 {
+                s_type: STRUCTURE_TYPE_BUFFER_MEMORY_BARRIER_2,
+                p_next: ptr::null(),
+                src_stage_mask,
+                src_access_mask: access2(barrier.src_access_mask),
+                dst_stage_mask,
+                dst_access_mask: access2(barrier.dst_access_mask),
+                src_queue_family_index: barrier.src_queue_family_index,
+                dst_queue_family_index: barrier.dst_queue_family_index,
+                buffer: barrier.buffer,
+                offset: barrier.offset,
+                size: barrier.size,
+            })
+            .collect::<Vec<_>>();
+        let image_barriers = image_barriers
+            .iter()
+            .map(|barrier| ImageMemoryBarrier2 {
+                s_type: STRUCTURE_TYPE_IMAGE_MEMORY_BARRIER_2,
+                p_next: ptr::null(),
+                src_stage_mask,
+                src_access_mask: access2(barrier.src_access_mask),
+                dst_stage_mask,
+                dst_access_mask: access2(barrier.dst_access_mask),
+                old_layout: barrier.old_layout,
+                new_layout: barrier.new_layout,
+                src_queue_family_index: barrier.src_queue_family_index,
+                dst_queue_family_index: barrier.dst_queue_family_index,
+                image: barrier.image,
+                subresource_range: barrier.subresource_range,
+            })
+            .collect::<Vec<_>>();
+
+        let info = DependencyInfo {
+            s_type: STRUCTURE_TYPE_DEPENDENCY_INFO,
+            p_next: ptr::null(),
+            dependency_flags: vk::DependencyFlags::empty(),
+            memory_barrier_count: memory_barriers.len() as u32,
+            p_memory_barriers: memory_barriers.as_ptr(),
+            buffer_memory_barrier_count: buffer_barriers.len() as u32,
+            p_buffer_memory_barriers: buffer_barriers.as_ptr(),
+            image_memory_barrier_count: image_barriers.len() as u32,
+            p_image_memory_barriers: image_barriers.as_ptr(),
+        };
+        (sync2.pipeline_barrier2)(commands, &info);
+    }
+
+    /// A single global memory barrier.
+    pub(crate) unsafe fn cmd_memory_barrier(
+        &self,
+        commands: vk::CommandBuffer,
+        (src_stage, src_access): (vk::PipelineStageFlags, vk::AccessFlags),
+        (dst_stage, dst_access): (vk::PipelineStageFlags, vk::AccessFlags),
+    ) {
+        self.cmd_pipeline_barrier(
+            commands,
+            src_stage,
+            dst_stage,
+            from_ref(
+                &vk::MemoryBarrier::builder()
+                    .src_access_mask(src_access)
+                    .dst_access_mask(dst_access)
+                    .build(),
+            ),
+            &[],
+            &[],
+        );
+    }
+
+    /// Submit to a queue, with `vkQueueSubmit2` when available. The semaphores are signaled
+    /// once all the commands of their batch complete.
+    pub(crate) unsafe fn queue_submit(
+        &self,
+        queue: vk::Queue,
+        submissions: &[Submission],
+        fence: vk::Fence,
+    ) -> Result<()> {
+        match self.synchronization2.as_ref() {
+            Some(sync2) => {
+                let semaphore_info = |semaphore: vk::Semaphore, stages: u64, device_index: u32| {
+                    SemaphoreSubmitInfo {
+                        s_type: STRUCTURE_TYPE_SEMAPHORE_SUBMIT_INFO,
+                        p_next: ptr::null(),
+                        semaphore,
+                        value: 0,
+                        stage_mask: stages,
+                        device_index,
+                    }
+                };
+                let infos = submissions
+                    .iter()
+                    .map(|submission| {
+                        let device_index = submission.device_index.unwrap_or(0);
+                        let waits = submission
+                            .waits
+                            .iter()
+                            .zip(submission.wait_stages)
+                            .map(|(&semaphore, &stages)| {
+                                semaphore_info(semaphore, stages2(stages), device_index)
+                            })
+                            .collect::<Vec<_>>();
+                        let commands = submission
+                            .commands
+                            .iter()
+                            .map(|&command_buffer| CommandBufferSubmitInfo {
+                                s_type: STRUCTURE_TYPE_COMMAND_BUFFER_SUBMIT_INFO,
+                                p_next: ptr::null(),
+                                command_buffer,
+                                device_mask: submission.device_index.map_or(0, |i| 1 << i),
+                            })
+                            .collect::<Vec<_>>();
+                        let signals = submission
+                            .signals
+                            .iter()
+                            .map(|&semaphore| {
+                                semaphore_info(
+                                    semaphore,
+                                    PIPELINE_STAGE_2_ALL_COMMANDS,
+                                    device_index,
+                                )
+                            })
+                            .collect::<Vec<_>>();
+                        (waits, commands, signals)
+                    })
+                    .collect::<Vec<_>>();
+                let submits = infos
+                    .iter()
+                    .map(|(waits, commands, signals)| SubmitInfo2 {
+                        s_type: STRUCTURE_TYPE_SUBMIT_INFO_2,
+                        p_next: ptr::null(),
+                        flags: 0,
+                        wait_semaphore_info_count: waits.len() as u32,
+                        p_wait_semaphore_infos: waits.as_ptr(),
+                        command_buffer_info_count: commands.len() as u32,
+                        p_command_buffer_infos: commands.as_ptr(),
+                        signal_semaphore_info_count: signals.len() as u32,
+                        p_signal_semaphore_infos: signals.as_ptr(),
+                    })
+                    .collect::<Vec<_>>();
+                match (sync2.queue_submit2)(queue, submits.len() as u32, submits.as_ptr(), fence) {
+                    vk::Result::SUCCESS => {}
+                    err => return Err(err.into()),
+                }
+            }
+            None => {
+                let device_indices = submissions
+                    .iter()
+                    .map(|submission| {
+                        submission.device_index.map(|index| {
+                            (
+                                vec![index; submission.waits.len()],
+                                // One mask per command buffer
+                                vec![1 << index; submission.commands.len()],
+                                vec![index; submission.signals.len()],
+                            )
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                let mut device_group_infos = device_indices
+                    .iter()
+                    .map(|indices| {
+                        indices.as_ref().map(|(waits, masks, signals)| {
+                            vk::DeviceGroupSubmitInfo::builder()
+                                .wait_semaphore_device_indices(waits)
+                                .command_buffer_device_masks(masks)
+                                .signal_semaphore_device_indices(signals)
+                                .build()
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                let submits = submissions
+                    .iter()
+                    .zip(device_group_infos.iter_mut())
+                    .map(|(submission, device_group_info)| {
+                        let mut info = vk::SubmitInfo::builder()
+                            .wait_semaphores(submission.waits)
+                            .wait_dst_stage_mask(submission.wait_stages)
+                            .command_buffers(submission.commands)
+                            .signal_semaphores(submission.signals);
+                        if let Some(device_group_info) = device_group_info {
+                            info = info.push_next(device_group_info);
+                        }
+                        info.build()
+                    })
+                    .collect::<Vec<_>>();
+                self.device.queue_submit(queue, &submits, fence)?;
+            }
+        }
+        Ok(())
+    }
+}
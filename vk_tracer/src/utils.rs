@@ -16,11 +16,45 @@ mod camera;
 #[cfg(feature = "camera")]
 pub use camera::*;
 
+#[cfg(feature = "camera")]
+mod orbit_controller;
+#[cfg(feature = "camera")]
+pub use orbit_controller::*;
+
 #[cfg(feature = "model_loader")]
 mod model_loader;
 #[cfg(feature = "model_loader")]
 pub use model_loader::*;
 
+#[cfg(feature = "asset_loader")]
+mod asset_loader;
+#[cfg(feature = "asset_loader")]
+pub use asset_loader::*;
+
+#[cfg(feature = "model_loader")]
+mod animation;
+#[cfg(feature = "model_loader")]
+pub use animation::*;
+
+#[cfg(feature = "text")]
+mod text;
+#[cfg(feature = "text")]
+pub use text::*;
+
+/// GLSL snippet to visualize the cost of a fragment shader as a heatmap using the shader clock.
+/// See the comments inside for how to use it.
+pub const SHADER_CLOCK_HEATMAP_GLSL: &str = include_str!("utils/shaders/clock_heatmap.glsl");
+
+/// GLSL snippet to decode the attributes of [crate::mesh::VertexQuantized].
+pub const QUANTIZED_VERTEX_GLSL: &str = include_str!("utils/shaders/quantized_vertex.glsl");
+
+/// GLSL snippet to blend the [MorphTargets] of a primitive in the vertex shader.
+#[cfg(feature = "model_loader")]
+pub const MORPH_TARGETS_GLSL: &str = include_str!("utils/shaders/morph_targets.glsl");
+
+/// Compute shader of [crate::raytracing::ShadowPass], tracing shadow rays with ray queries.
+pub const RT_SHADOWS_GLSL: &str = include_str!("utils/shaders/rt_shadows.comp");
+
 /// Converts a rust string to a CStr in a kinda safe manner.
 /// Can produce strange thing if the input string isn't valid ASCII.
 pub(crate) fn str_to_cstr(s: &str) -> &CStr {
@@ -0,0 +1,426 @@
+use crate::{
+    errors::{HandleType, Result},
+    mem::RawBufferAllocation,
+    utils::{GltfScene, MorphTargets},
+    UboHandle, VkTracerApp,
+};
+use nalgebra_glm as glm;
+
+pub struct SceneAnimation {
+    pub name: Option<String>,
+    pub channels: Vec<AnimationChannel>,
+    /// Time of the last keyframe, in seconds.
+    pub duration: f32,
+}
+
+/// Keyframes of one property of a node.
+pub struct AnimationChannel {
+    pub node: usize,
+    pub property: AnimationProperty,
+    pub interpolation: gltf::animation::Interpolation,
+    pub times: Vec<f32>,
+    /// `property.components()` floats per keyframe, three times that for cubic splines
+    /// (in-tangent, value, out-tangent).
+    pub values: Vec<f32>,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum AnimationProperty {
+    Translation,
+    /// Quaternion as x, y, z, w.
+    Rotation,
+    Scale,
+    /// Weights of the morph targets of the node's mesh, one per target.
+    MorphWeights(usize),
+}
+
+impl AnimationProperty {
+    pub fn components(self) -> usize {
+        match self {
+            AnimationProperty::Rotation => 4,
+            AnimationProperty::MorphWeights(count) => count,
+            _ => 3,
+        }
+    }
+}
+
+/// Local transform of a node, split to be interpolated.
+#[derive(Copy, Clone, Debug)]
+pub struct NodePose {
+    pub translation: glm::Vec3,
+    pub rotation: glm::Quat,
+    pub scale: glm::Vec3,
+}
+
+impl NodePose {
+    pub fn matrix(&self) -> glm::Mat4 {
+        glm::translation(&self.translation)
+            * glm::quat_to_mat4(&self.rotation)
+            * glm::scaling(&self.scale)
+    }
+}
+
+pub(crate) fn load_animation(
+    animation: &gltf::Animation,
+    buffers: &[gltf::buffer::Data],
+) -> SceneAnimation {
+    use gltf::animation::util::ReadOutputs;
+
+    let mut channels = Vec::new();
+    for channel in animation.channels() {
+        let reader = channel.reader(|b| Some(&buffers[b.index()]));
+        let times = match reader.read_inputs() {
+            Some(inputs) => inputs.collect::<Vec<_>>(),
+            None => continue,
+        };
+        let (property, values) = match reader.read_outputs() {
+            Some(ReadOutputs::Translations(outputs)) => (
+                AnimationProperty::Translation,
+                outputs.flat_map(|v| v.to_vec()).collect(),
+            ),
+            Some(ReadOutputs::Rotations(outputs)) => (
+                AnimationProperty::Rotation,
+                outputs.into_f32().flat_map(|v| v.to_vec()).collect(),
+            ),
+            Some(ReadOutputs::Scales(outputs)) => (
+                AnimationProperty::Scale,
+                outputs.flat_map(|v| v.to_vec()).collect(),
+            ),
+            Some(ReadOutputs::MorphTargetWeights(outputs)) => {
+                let values = outputs.into_f32().collect::<Vec<_>>();
+                let keyframes = match channel.sampler().interpolation() {
+                    gltf::animation::Interpolation::CubicSpline => 3 * times.len(),
+                    _ => times.len(),
+                };
+                (
+                    AnimationProperty::MorphWeights(values.len() / keyframes.max(1)),
+                    values,
+                )
+            }
+            None => continue,
+        };
+
+        channels.push(AnimationChannel {
+            node: channel.target().node().index(),
+            property,
+            interpolation: channel.sampler().interpolation(),
+            times,
+            values,
+        });
+    }
+
+    let duration = channels
+        .iter()
+        .filter_map(|channel| channel.times.last().copied())
+        .fold(0.0, f32::max);
+
+    SceneAnimation {
+        name: animation.name().map(String::from),
+        channels,
+        duration,
+    }
+}
+
+impl AnimationChannel {
+    /// Value of the channel at `time`, clamped to the first and last keyframes.
+    fn sample(&self, time: f32) -> Vec<f32> {
+        use gltf::animation::Interpolation;
+
+        let n = self.property.components();
+        let stride = match self.interpolation {
+            Interpolation::CubicSpline => 3 * n,
+            _ => n,
+        };
+        // Skip the in-tangent of cubic splines
+        let value_offset = (stride - n) / 2;
+        let value = |key: usize| &self.values[key * stride + value_offset..][..n];
+
+        let next = self.times.iter().position(|t| *t > time);
+        let (prev, next) = match next {
+            Some(0) => return value(0).to_vec(),
+            Some(next) => (next - 1, next),
+            None => return value(self.times.len() - 1).to_vec(),
+        };
+
+        let dt = self.times[next] - self.times[prev];
+        let t = (time - self.times[prev]) / dt;
+        let (a, b) = (value(prev), value(next));
+
+        let mut result = match self.interpolation {
+            Interpolation::Step => a.to_vec(),
+            Interpolation::Linear => {
+                // Take the shortest path between rotations
+                let sign = if self.property == AnimationProperty::Rotation
+                    && a.iter().zip(b).map(|(a, b)| a * b).sum::<f32>() < 0.0
+                {
+                    -1.0
+                } else {
+                    1.0
+                };
+                a.iter()
+                    .zip(b)
+                    .map(|(a, b)| a + (sign * b - a) * t)
+                    .collect()
+            }
+            Interpolation::CubicSpline => {
+                let out_tangent = &self.values[prev * stride + 2 * n..][..n];
+                let in_tangent = &self.values[next * stride..][..n];
+                let (t2, t3) = (t * t, t * t * t);
+                (0..n)
+                    .map(|i| {
+                        (2.0 * t3 - 3.0 * t2 + 1.0) * a[i]
+                            + (t3 - 2.0 * t2 + t) * dt * out_tangent[i]
+                            + (-2.0 * t3 + 3.0 * t2) * b[i]
+                            + (t3 - t2) * dt * in_tangent[i]
+                    })
+                    .collect()
+            }
+        };
+
+        if self.property == AnimationProperty::Rotation {
+            let length = result.iter().map(|c| c * c).sum::<f32>().sqrt();
+            result.iter_mut().for_each(|c| *c /= length);
+        }
+        result
+    }
+}
+
+/// Plays an animation of a scene, the poses of the nodes are updated with
+/// [AnimationPlayer::advance].
+pub struct AnimationPlayer {
+    animation: usize,
+    time: f32,
+    pub looping: bool,
+    poses: Vec<NodePose>,
+    morph_weights: Vec<Vec<f32>>,
+}
+
+impl AnimationPlayer {
+    pub fn new(scene: &GltfScene, animation: usize) -> Self {
+        let mut player = Self {
+            animation,
+            time: 0.0,
+            looping: true,
+            poses: scene.nodes.iter().map(|node| node.pose).collect(),
+            morph_weights: scene
+                .nodes
+                .iter()
+                .map(|node| node.morph_weights.clone())
+                .collect(),
+        };
+        player.advance(scene, 0.0);
+        player
+    }
+
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
+    /// Move forward by `delta` seconds and sample the keyframes.
+    pub fn advance(&mut self, scene: &GltfScene, delta: f32) {
+        let animation = &scene.animations[self.animation];
+        self.time += delta;
+        if self.looping && animation.duration > 0.0 {
+            self.time %= animation.duration;
+        } else {
+            self.time = self.time.min(animation.duration);
+        }
+
+        for channel in &animation.channels {
+            let value = channel.sample(self.time);
+            let pose = &mut self.poses[channel.node];
+            match channel.property {
+                AnimationProperty::Translation => pose.translation = glm::make_vec3(&value),
+                AnimationProperty::Rotation => {
+                    pose.rotation = glm::quat(value[0], value[1], value[2], value[3])
+                }
+                AnimationProperty::Scale => pose.scale = glm::make_vec3(&value),
+                AnimationProperty::MorphWeights(_) => self.morph_weights[channel.node] = value,
+            }
+        }
+    }
+
+    pub fn poses(&self) -> &[NodePose] {
+        &self.poses
+    }
+
+    /// Animated weights of the morph targets of a node, to upload with
+    /// [VkTracerApp::update_morph_weights].
+    pub fn morph_weights(&self, node: usize) -> &[f32] {
+        &self.morph_weights[node]
+    }
+
+    /// Like [GltfScene::world_transforms] with the animated poses.
+    pub fn world_transforms(&self, scene: &GltfScene) -> Vec<glm::Mat4> {
+        scene.world_transforms_with(|node| self.poses[node].matrix())
+    }
+
+    /// Matrices of the joints of a skin, to upload with [VkTracerApp::update_joint_buffer].
+    pub fn joint_matrices(&self, scene: &GltfScene, skin: usize) -> Vec<glm::Mat4> {
+        let world = self.world_transforms(scene);
+        let skin = &scene.skins[skin];
+        skin.joints
+            .iter()
+            .zip(&skin.inverse_bind_matrices)
+            .map(|(joint, inverse_bind)| world[*joint] * inverse_bind)
+            .collect()
+    }
+}
+
+impl VkTracerApp {
+    /// A uniform buffer of `joint_count` mat4, for the vertex shader of
+    /// [crate::mesh::VertexXyzUvNormSkin] meshes.
+    pub fn create_joint_buffer(&mut self, joint_count: usize) -> Result<UboHandle> {
+        let buffer = RawBufferAllocation::new_uniform_buffer(
+            &self.vma,
+            joint_count * std::mem::size_of::<glm::Mat4>(),
+        )?;
+        Ok(self.ubo_storage.insert(buffer))
+    }
+
+    /// A uniform buffer with the weights of the targets, read by
+    /// [crate::utils::MORPH_TARGETS_GLSL].
+    pub fn create_morph_weights_buffer(&mut self, targets: &MorphTargets) -> Result<UboHandle> {
+        let buffer = RawBufferAllocation::new_uniform_buffer(
+            &self.vma,
+            morph_weights_data(targets, &[]).len() * std::mem::size_of::<f32>(),
+        )?;
+        Ok(self.ubo_storage.insert(buffer))
+    }
+
+    pub fn update_morph_weights(
+        &mut self,
+        handle: UboHandle,
+        targets: &MorphTargets,
+        weights: &[f32],
+    ) -> Result<()> {
+        let buffer = storage_access_mut!(self.ubo_storage, handle, HandleType::Ubo);
+        unsafe { buffer.store(&self.vma, &morph_weights_data(targets, weights)) }
+    }
+
+    pub fn update_joint_buffer(&mut self, handle: UboHandle, joints: &[glm::Mat4]) -> Result<()> {
+        let buffer = storage_access_mut!(self.ubo_storage, handle, HandleType::Ubo);
+        let capacity = buffer.real_size as usize / std::mem::size_of::<glm::Mat4>();
+        unsafe { buffer.store(&self.vma, &joints[..joints.len().min(capacity)]) }
+    }
+}
+
+/// Target and vertex counts followed by the weights packed in vec4s, as in std140.
+fn morph_weights_data(targets: &MorphTargets, weights: &[f32]) -> Vec<f32> {
+    let padded_count = (targets.target_count as usize + 3) / 4 * 4;
+    let mut data = Vec::with_capacity(4 + padded_count);
+    data.extend_from_slice(&[
+        f32::from_bits(targets.target_count),
+        f32::from_bits(targets.vertex_count),
+        0.0,
+        0.0,
+    ]);
+    data.extend(
+        weights
+            .iter()
+            .copied()
+            .chain(std::iter::repeat(0.0))
+            .take(padded_count),
+    );
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gltf::animation::Interpolation;
+
+    fn channel(
+        property: AnimationProperty,
+        interpolation: Interpolation,
+        values: &[f32],
+    ) -> AnimationChannel {
+        AnimationChannel {
+            node: 0,
+            property,
+            interpolation,
+            times: vec![1.0, 2.0, 4.0],
+            values: values.to_vec(),
+        }
+    }
+
+    fn assert_close(a: &[f32], b: &[f32]) {
+        assert_eq!(a.len(), b.len());
+        for (a, b) in a.iter().zip(b) {
+            assert!((a - b).abs() < 1e-5, "{:?} != {:?}", a, b);
+        }
+    }
+
+    #[test]
+    fn clamped_outside_keyframes() {
+        let channel = channel(
+            AnimationProperty::Translation,
+            Interpolation::Linear,
+            &[0.0, 0.0, 0.0, 1.0, 2.0, 3.0, 5.0, 5.0, 5.0],
+        );
+        assert_close(&channel.sample(0.0), &[0.0, 0.0, 0.0]);
+        assert_close(&channel.sample(1.0), &[0.0, 0.0, 0.0]);
+        assert_close(&channel.sample(4.0), &[5.0, 5.0, 5.0]);
+        assert_close(&channel.sample(10.0), &[5.0, 5.0, 5.0]);
+    }
+
+    #[test]
+    fn linear_on_and_between_keyframes() {
+        let channel = channel(
+            AnimationProperty::Translation,
+            Interpolation::Linear,
+            &[0.0, 0.0, 0.0, 1.0, 2.0, 3.0, 5.0, 5.0, 5.0],
+        );
+        assert_close(&channel.sample(1.5), &[0.5, 1.0, 1.5]);
+        assert_close(&channel.sample(2.0), &[1.0, 2.0, 3.0]);
+        assert_close(&channel.sample(3.0), &[3.0, 3.5, 4.0]);
+    }
+
+    #[test]
+    fn step_holds_previous_keyframe() {
+        let channel = channel(
+            AnimationProperty::Scale,
+            Interpolation::Step,
+            &[1.0, 1.0, 1.0, 2.0, 2.0, 2.0, 3.0, 3.0, 3.0],
+        );
+        assert_close(&channel.sample(1.99), &[1.0, 1.0, 1.0]);
+        assert_close(&channel.sample(2.0), &[2.0, 2.0, 2.0]);
+        assert_close(&channel.sample(3.99), &[2.0, 2.0, 2.0]);
+    }
+
+    #[test]
+    fn cubic_spline_skips_tangents() {
+        // In-tangent, value and out-tangent of each keyframe, with flat tangents
+        let channel = channel(
+            AnimationProperty::MorphWeights(1),
+            Interpolation::CubicSpline,
+            &[9.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 2.0, 9.0],
+        );
+        assert_close(&channel.sample(0.0), &[0.0]);
+        assert_close(&channel.sample(2.0), &[1.0]);
+        assert_close(&channel.sample(3.0), &[1.5]);
+        assert_close(&channel.sample(5.0), &[2.0]);
+    }
+
+    #[test]
+    fn rotation_takes_shortest_path() {
+        // The last keyframe is the same rotation as the second one, with a negated quaternion
+        let channel = channel(
+            AnimationProperty::Rotation,
+            Interpolation::Linear,
+            &[0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, -1.0],
+        );
+        assert_close(&channel.sample(3.0), &[0.0, 0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn rotation_is_normalized() {
+        let half = std::f32::consts::FRAC_1_SQRT_2;
+        let channel = channel(
+            AnimationProperty::Rotation,
+            Interpolation::Linear,
+            &[0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0],
+        );
+        assert_close(&channel.sample(1.5), &[0.0, 0.0, half, half]);
+    }
+}
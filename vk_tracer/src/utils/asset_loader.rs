@@ -0,0 +1,384 @@
+use crate::{
+    command_recorder::{QueueType, SubmittedCommands},
+    errors::{Result, VkTracerError},
+    mem::texture_upload_region,
+    mesh::{as_bytes, MeshVertex, RawIndices},
+    utils::{read_first_mesh, GltfToVertex},
+    HostBufferHandle, MeshHandle, TextureHandle, VkTracerApp,
+};
+use ash::vk;
+use parking_lot::Mutex;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{channel, Receiver, Sender},
+        Arc,
+    },
+    thread::JoinHandle,
+};
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct AssetId(u64);
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum AssetStatus {
+    /// Waiting for a worker thread.
+    Queued,
+    /// Read and decoded by a worker thread.
+    Parsing,
+    /// Waiting for [VkTracerApp::poll_asset_loader] or its copies to complete.
+    Uploading,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum LoadedAsset {
+    Mesh(MeshHandle),
+    Texture(TextureHandle),
+}
+
+/// Sent once an asset is ready to be used or failed to load.
+#[derive(Debug)]
+pub struct AssetEvent {
+    pub id: AssetId,
+    pub path: PathBuf,
+    pub result: Result<LoadedAsset>,
+}
+
+/// Decoded on a worker, the GPU resources are created on the thread of the app.
+enum ParsedAsset {
+    Mesh(Box<dyn FnOnce(&mut VkTracerApp, &mut UploadBatch) -> Result<MeshHandle> + Send>),
+    Texture {
+        width: u32,
+        height: u32,
+        format: vk::Format,
+        pixels: Vec<u8>,
+    },
+}
+
+type Job = Box<dyn FnOnce() -> Result<ParsedAsset> + Send>;
+
+/// Loads glTF meshes and images on worker threads, the uploads are then staged and submitted
+/// by [VkTracerApp::poll_asset_loader] without waiting for them.
+/// Dropping it only stops the workers, [VkTracerApp::destroy_asset_loader] also frees the
+/// uploads in progress.
+pub struct AssetLoader {
+    next_id: u64,
+    jobs: Option<Sender<(AssetId, PathBuf, Job)>>,
+    parsed: Receiver<(AssetId, PathBuf, Result<ParsedAsset>)>,
+    statuses: Arc<Mutex<HashMap<AssetId, AssetStatus>>>,
+    events: (Sender<AssetEvent>, Receiver<AssetEvent>),
+    in_flight: Vec<UploadBatch>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+/// Copies submitted together, the assets are ready once all of them complete.
+#[derive(Default)]
+struct UploadBatch {
+    buffer_copies: Vec<(HostBufferHandle, vk::Buffer, vk::DeviceSize)>,
+    texture_copies: Vec<(HostBufferHandle, TextureHandle)>,
+    submitted: Vec<SubmittedCommands>,
+    assets: Vec<(AssetId, PathBuf, LoadedAsset)>,
+}
+
+impl AssetLoader {
+    pub fn new(worker_count: usize) -> Self {
+        let (jobs, job_receiver) = channel::<(AssetId, PathBuf, Job)>();
+        let (parsed_sender, parsed) = channel();
+        let job_receiver = Arc::new(Mutex::new(job_receiver));
+        let statuses = Arc::new(Mutex::new(HashMap::new()));
+
+        let workers = (0..worker_count.max(1))
+            .map(|_| {
+                let job_receiver = Arc::clone(&job_receiver);
+                let parsed_sender = parsed_sender.clone();
+                let statuses = Arc::clone(&statuses);
+                std::thread::spawn(move || loop {
+                    // Stop once the loader is destroyed
+                    let (id, path, job) = match job_receiver.lock().recv() {
+                        Ok(job) => job,
+                        Err(_) => break,
+                    };
+                    statuses.lock().insert(id, AssetStatus::Parsing);
+                    if parsed_sender.send((id, path, job())).is_err() {
+                        break;
+                    }
+                })
+            })
+            .collect();
+
+        Self {
+            next_id: 0,
+            jobs: Some(jobs),
+            parsed,
+            statuses,
+            events: channel(),
+            in_flight: Vec::new(),
+            workers,
+        }
+    }
+
+    /// Queue the first mesh of a glTF file, like [VkTracerApp::load_first_mesh].
+    pub fn load_mesh<V: GltfToVertex + Send>(&mut self, path: impl AsRef<Path>) -> AssetId {
+        let path = path.as_ref().to_path_buf();
+        let job_path = path.clone();
+        self.queue(
+            path,
+            Box::new(move || {
+                let (vertices, indices) = read_first_mesh::<V>(&job_path)?;
+                Ok(ParsedAsset::Mesh(Box::new(move |app, batch| {
+                    app.stage_mesh(&vertices, &indices, batch)
+                })))
+            }),
+        )
+    }
+
+    /// Queue an image file, decoded to RGBA8 in sRGB when `srgb` is set.
+    pub fn load_texture(&mut self, path: impl AsRef<Path>, srgb: bool) -> AssetId {
+        let path = path.as_ref().to_path_buf();
+        let job_path = path.clone();
+        self.queue(
+            path,
+            Box::new(move || {
+                let image = image::open(&job_path)?.into_rgba8();
+                Ok(ParsedAsset::Texture {
+                    width: image.width(),
+                    height: image.height(),
+                    format: if srgb {
+                        vk::Format::R8G8B8A8_SRGB
+                    } else {
+                        vk::Format::R8G8B8A8_UNORM
+                    },
+                    pixels: image.into_raw(),
+                })
+            }),
+        )
+    }
+
+    fn queue(&mut self, path: PathBuf, job: Job) -> AssetId {
+        let id = AssetId(self.next_id);
+        self.next_id += 1;
+        self.statuses.lock().insert(id, AssetStatus::Queued);
+        if let Some(jobs) = self.jobs.as_ref() {
+            // The workers only stop when the loader is destroyed
+            let _ = jobs.send((id, path, job));
+        }
+        id
+    }
+
+    /// `None` once the asset is ready or failed, its [AssetEvent] tells which, and for ids of
+    /// another loader.
+    pub fn status(&self, id: AssetId) -> Option<AssetStatus> {
+        self.statuses.lock().get(&id).copied()
+    }
+
+    /// Whether every queued asset is either ready or failed.
+    pub fn is_idle(&self) -> bool {
+        self.statuses.lock().is_empty()
+    }
+
+    /// Receives the assets as they become ready, filled by [VkTracerApp::poll_asset_loader].
+    pub fn events(&self) -> &Receiver<AssetEvent> {
+        &self.events.1
+    }
+
+    fn finish(&self, id: AssetId, path: PathBuf, result: Result<LoadedAsset>) {
+        self.statuses.lock().remove(&id);
+        let _ = self.events.0.send(AssetEvent { id, path, result });
+    }
+}
+
+impl Drop for AssetLoader {
+    fn drop(&mut self) {
+        // The workers stop once the jobs are all received
+        self.jobs = None;
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+impl VkTracerApp {
+    /// Call once per frame: report the uploads that completed and submit the ones of the newly
+    /// parsed assets, without waiting on the device. Meshes are copied on the transfer queue
+    /// and textures on the graphics queue, as no queue ownership transfer is done.
+    pub fn poll_asset_loader(&mut self, loader: &mut AssetLoader) -> Result<()> {
+        let mut i = 0;
+        while i < loader.in_flight.len() {
+            let mut complete = true;
+            for submitted in &loader.in_flight[i].submitted {
+                complete &= submitted.is_complete(self)?;
+            }
+            if complete {
+                let batch = loader.in_flight.swap_remove(i);
+                for (id, path, asset) in self.retire_upload_batch(batch)? {
+                    loader.finish(id, path, Ok(asset));
+                }
+            } else {
+                i += 1;
+            }
+        }
+
+        let mut batch = UploadBatch::default();
+        while let Ok((id, path, parsed)) = loader.parsed.try_recv() {
+            loader.statuses.lock().insert(id, AssetStatus::Uploading);
+            let asset = parsed.and_then(|parsed| match parsed {
+                ParsedAsset::Mesh(stage) => stage(self, &mut batch).map(LoadedAsset::Mesh),
+                ParsedAsset::Texture {
+                    width,
+                    height,
+                    format,
+                    pixels,
+                } => self
+                    .stage_texture(width, height, format, &pixels, &mut batch)
+                    .map(LoadedAsset::Texture),
+            });
+            match asset {
+                Ok(asset) => batch.assets.push((id, path, asset)),
+                Err(err) => loader.finish(id, path, Err(err)),
+            }
+        }
+        if batch.assets.is_empty() {
+            return Ok(());
+        }
+
+        if let Err(err) = self.submit_upload_batch(&mut batch) {
+            for submitted in &batch.submitted {
+                submitted.wait(self)?;
+            }
+            let assets = self.retire_upload_batch(batch)?;
+            for (id, path, asset) in assets {
+                match asset {
+                    LoadedAsset::Mesh(mesh) => self.destroy_mesh(mesh)?,
+                    LoadedAsset::Texture(texture) => self.destroy_texture(texture)?,
+                }
+                loader.finish(id, path, Err(VkTracerError::AssetUploadFailed));
+            }
+            return Err(err);
+        }
+        loader.in_flight.push(batch);
+        Ok(())
+    }
+
+    /// Record and submit the copies of the batch, the ones submitted before an error are kept
+    /// in the batch.
+    fn submit_upload_batch(&mut self, batch: &mut UploadBatch) -> Result<()> {
+        if !batch.buffer_copies.is_empty() {
+            let mut copies = Vec::with_capacity(batch.buffer_copies.len());
+            for (staging, dst, size) in batch.buffer_copies.iter().copied() {
+                copies.push((self.get_buffer(staging.into())?.buffer, dst, size));
+            }
+            let mut recorder = self.new_transfer_recorder(QueueType::Transfer)?;
+            for (src, dst, size) in copies {
+                recorder = recorder.copy_raw_buffer(src, dst, size);
+            }
+            batch.submitted.push(recorder.submit_unwaited()?);
+        }
+        if !batch.texture_copies.is_empty() {
+            let mut copies = Vec::with_capacity(batch.texture_copies.len());
+            for (staging, texture) in batch.texture_copies.iter().copied() {
+                copies.push((staging, self.get_texture_view(texture)?));
+            }
+            let mut recorder = self.new_transfer_recorder(QueueType::Graphics)?;
+            for (staging, target) in copies {
+                recorder = recorder.copy_buffer_to_image(
+                    staging,
+                    target,
+                    texture_upload_region(target.extent.width, target.extent.height),
+                    vk::ImageLayout::UNDEFINED,
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                )?;
+            }
+            batch.submitted.push(recorder.submit_unwaited()?);
+        }
+        Ok(())
+    }
+
+    /// Wait for the uploads in progress and stop the workers, the assets that weren't
+    /// uploaded yet are dropped.
+    pub fn destroy_asset_loader(&mut self, mut loader: AssetLoader) -> Result<()> {
+        for batch in std::mem::take(&mut loader.in_flight) {
+            for submitted in &batch.submitted {
+                submitted.wait(self)?;
+            }
+            for (id, path, asset) in self.retire_upload_batch(batch)? {
+                loader.finish(id, path, Ok(asset));
+            }
+        }
+        Ok(())
+    }
+
+    fn stage_mesh<V: MeshVertex>(
+        &mut self,
+        vertices: &[V],
+        indices: &[u32],
+        batch: &mut UploadBatch,
+    ) -> Result<MeshHandle> {
+        let indices = RawIndices::new(indices);
+        let mesh = self.new_mesh(vertices, &indices)?;
+        let uploads = [
+            (as_bytes(vertices), mesh.vertices.buffer),
+            (indices.bytes, mesh.indices.buffer),
+        ];
+        let handle = self.insert_mesh(mesh);
+
+        let first_copy = batch.buffer_copies.len();
+        for (data, dst) in uploads.iter().copied() {
+            match self.create_upload_buffer(data) {
+                Ok(staging) => {
+                    batch
+                        .buffer_copies
+                        .push((staging, dst, data.len() as vk::DeviceSize));
+                }
+                Err(err) => {
+                    for (staging, _, _) in batch.buffer_copies.drain(first_copy..) {
+                        self.destroy_host_buffer(staging)?;
+                    }
+                    self.destroy_mesh(handle)?;
+                    return Err(err);
+                }
+            }
+        }
+        Ok(handle)
+    }
+
+    fn stage_texture(
+        &mut self,
+        width: u32,
+        height: u32,
+        format: vk::Format,
+        pixels: &[u8],
+        batch: &mut UploadBatch,
+    ) -> Result<TextureHandle> {
+        let texture = self.new_texture(width, height, format)?;
+        match self.create_upload_buffer(pixels) {
+            Ok(staging) => {
+                batch.texture_copies.push((staging, texture));
+                Ok(texture)
+            }
+            Err(err) => {
+                self.destroy_texture(texture)?;
+                Err(err)
+            }
+        }
+    }
+
+    /// The copies of the batch must be complete.
+    fn retire_upload_batch(
+        &mut self,
+        batch: UploadBatch,
+    ) -> Result<Vec<(AssetId, PathBuf, LoadedAsset)>> {
+        for submitted in batch.submitted {
+            submitted.recycle(self)?;
+        }
+        let staging = batch
+            .buffer_copies
+            .iter()
+            .map(|(staging, _, _)| *staging)
+            .chain(batch.texture_copies.iter().map(|(staging, _)| *staging));
+        for staging in staging.collect::<Vec<_>>() {
+            self.destroy_host_buffer(staging)?;
+        }
+        Ok(batch.assets)
+    }
+}
@@ -1,9 +1,16 @@
+use crate::{
+    errors::{HandleType, Result, VkTracerError},
+    mesh::{Aabb, BoundingSphere, MeshBounds},
+    MeshHandle, VkTracerApp,
+};
+use ash::vk;
 use nalgebra_glm as glm;
 
 pub struct Camera {
     fov: f32,
     view: glm::Mat4,
     projection: glm::Mat4,
+    pre_rotation: glm::Mat4,
 }
 
 impl Camera {
@@ -12,6 +19,7 @@ impl Camera {
             fov,
             view: glm::look_at_lh(&position, &look_at, &glm::vec3(0.0, 1.0, 0.0)),
             projection: corrected_perspective(glm::perspective_lh(aspect, fov, 0.1, 100.0)),
+            pre_rotation: glm::identity(),
         }
     }
 
@@ -24,16 +32,217 @@ impl Camera {
         self.aspect(size.0 as f32 / size.1 as f32)
     }
 
+    /// Move the camera to `position`, looking at `target` with +Y up.
+    pub fn look_at(&mut self, position: glm::Vec3, target: glm::Vec3) {
+        self.view = glm::look_at_lh(&position, &target, &glm::vec3(0.0, 1.0, 0.0));
+    }
+
     pub fn translate(&mut self, delta: glm::Vec3) {
         self.view = glm::translate(&self.view, &delta);
     }
 
+    /// Apply the transform returned by [crate::VkTracerApp::get_swapchain_pre_transform].
+    /// The aspect ratio stays the one of the window, not of the swapchain.
+    pub fn set_pre_transform(&mut self, transform: vk::SurfaceTransformFlagsKHR) {
+        self.pre_rotation = pre_rotation_matrix(transform);
+    }
+
     pub fn compute_mvp(&self, model: &glm::Mat4) -> glm::Mat4 {
-        self.projection * self.view * model
+        self.pre_rotation * self.projection * self.view * model
+    }
+
+    /// From clip space back to world space, without the pre-rotation, to generate primary rays.
+    pub fn inverse_view_projection(&self) -> glm::Mat4 {
+        glm::inverse(&(self.projection * self.view))
+    }
+
+    /// The volume seen by the camera, in world space.
+    pub fn frustum(&self) -> Frustum {
+        Frustum::from_matrix(&self.compute_mvp(&glm::identity()))
+    }
+}
+
+/// Planes bounding what is visible through a projection, facing inwards.
+#[derive(Copy, Clone, Debug)]
+pub struct Frustum {
+    /// Normal in xyz and distance in w.
+    planes: [glm::Vec4; 6],
+}
+
+impl Frustum {
+    /// Extract the planes of a view-projection matrix with a [0, 1] depth range.
+    pub fn from_matrix(m: &glm::Mat4) -> Self {
+        let row = |i: usize| m.row(i).transpose();
+        let planes = [
+            row(3) + row(0),
+            row(3) - row(0),
+            row(3) + row(1),
+            row(3) - row(1),
+            row(2),
+            row(3) - row(2),
+        ];
+        Self {
+            planes: [
+                normalize_plane(planes[0]),
+                normalize_plane(planes[1]),
+                normalize_plane(planes[2]),
+                normalize_plane(planes[3]),
+                normalize_plane(planes[4]),
+                normalize_plane(planes[5]),
+            ],
+        }
+    }
+
+    pub fn intersects_sphere(&self, sphere: &BoundingSphere) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| plane.xyz().dot(&sphere.center) + plane.w >= -sphere.radius)
+    }
+
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        self.planes.iter().all(|plane| {
+            // The corner the furthest along the normal
+            let corner = glm::vec3(
+                if plane.x >= 0.0 {
+                    aabb.max.x
+                } else {
+                    aabb.min.x
+                },
+                if plane.y >= 0.0 {
+                    aabb.max.y
+                } else {
+                    aabb.min.y
+                },
+                if plane.z >= 0.0 {
+                    aabb.max.z
+                } else {
+                    aabb.min.z
+                },
+            );
+            plane.xyz().dot(&corner) + plane.w >= 0.0
+        })
+    }
+
+    /// Test the bounds of a mesh placed in the world by `model`, conservatively.
+    pub fn is_visible(&self, bounds: &MeshBounds, model: &glm::Mat4) -> bool {
+        self.intersects_sphere(&bounds.sphere.transform(model))
+            && self.intersects_aabb(&bounds.aabb.transform(model))
     }
 }
 
+fn normalize_plane(plane: glm::Vec4) -> glm::Vec4 {
+    plane / plane.xyz().norm()
+}
+
+impl VkTracerApp {
+    /// Remove the draws outside of the frustum from a draw list before recording it, `draw`
+    /// gives the mesh and model matrix of each element. Meshes without bounds are kept.
+    pub fn frustum_cull<T>(
+        &self,
+        frustum: &Frustum,
+        draws: &mut Vec<T>,
+        draw: impl Fn(&T) -> (MeshHandle, glm::Mat4),
+    ) -> Result<()> {
+        let mut result = Ok(());
+        draws.retain(|element| {
+            let (mesh, model) = draw(element);
+            match self.mesh_storage.get(mesh) {
+                Some(mesh) => mesh
+                    .bounds
+                    .map_or(true, |bounds| frustum.is_visible(&bounds, &model)),
+                None => {
+                    result = Err(VkTracerError::InvalidHandle(HandleType::Mesh));
+                    true
+                }
+            }
+        });
+        result
+    }
+}
+
+/// Rotation in clip space that compensates a surface transform.
+pub fn pre_rotation_matrix(transform: vk::SurfaceTransformFlagsKHR) -> glm::Mat4 {
+    let angle: f32 = match transform {
+        vk::SurfaceTransformFlagsKHR::ROTATE_90 => 90.0,
+        vk::SurfaceTransformFlagsKHR::ROTATE_180 => 180.0,
+        vk::SurfaceTransformFlagsKHR::ROTATE_270 => 270.0,
+        _ => return glm::identity(),
+    };
+    glm::rotation(angle.to_radians(), &glm::vec3(0.0, 0.0, 1.0))
+}
+
 fn corrected_perspective(mut p: glm::Mat4) -> glm::Mat4 {
     *p.get_mut((1, 1)).unwrap() *= -1.0;
     p
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sphere(x: f32, y: f32, z: f32, radius: f32) -> BoundingSphere {
+        BoundingSphere {
+            center: glm::vec3(x, y, z),
+            radius,
+        }
+    }
+
+    fn aabb(min: glm::Vec3, max: glm::Vec3) -> Aabb {
+        Aabb { min, max }
+    }
+
+    #[test]
+    fn identity_is_clip_volume() {
+        // x and y in [-1, 1], z in [0, 1]
+        let frustum = Frustum::from_matrix(&glm::identity());
+        assert!(frustum.intersects_sphere(&sphere(0.0, 0.0, 0.5, 0.1)));
+        assert!(frustum.intersects_sphere(&sphere(1.5, 0.0, 0.5, 0.6)));
+        assert!(!frustum.intersects_sphere(&sphere(1.5, 0.0, 0.5, 0.4)));
+        assert!(!frustum.intersects_sphere(&sphere(0.0, 0.0, -0.5, 0.4)));
+        assert!(!frustum.intersects_sphere(&sphere(0.0, 0.0, 1.5, 0.4)));
+    }
+
+    #[test]
+    fn aabb_against_clip_volume() {
+        let frustum = Frustum::from_matrix(&glm::identity());
+        assert!(
+            frustum.intersects_aabb(&aabb(glm::vec3(-0.5, -0.5, 0.2), glm::vec3(0.5, 0.5, 0.8)))
+        );
+        // Straddling a plane
+        assert!(frustum.intersects_aabb(&aabb(glm::vec3(0.9, -0.5, 0.2), glm::vec3(2.0, 0.5, 0.8))));
+        // Containing the whole volume
+        assert!(
+            frustum.intersects_aabb(&aabb(glm::vec3(-5.0, -5.0, -5.0), glm::vec3(5.0, 5.0, 5.0)))
+        );
+        assert!(
+            !frustum.intersects_aabb(&aabb(glm::vec3(1.1, -0.5, 0.2), glm::vec3(2.0, 0.5, 0.8)))
+        );
+        assert!(!frustum.intersects_aabb(&aabb(
+            glm::vec3(-0.5, -0.5, -2.0),
+            glm::vec3(0.5, 0.5, -0.1)
+        )));
+    }
+
+    #[test]
+    fn perspective_planes() {
+        let camera = Camera::new_perspective(
+            glm::vec3(0.0, 0.0, -5.0),
+            glm::zero(),
+            1.0,
+            std::f32::consts::FRAC_PI_2,
+        );
+        let frustum = camera.frustum();
+
+        // In front, behind and beside the camera
+        assert!(frustum.intersects_sphere(&sphere(0.0, 0.0, 0.0, 0.5)));
+        assert!(!frustum.intersects_sphere(&sphere(0.0, 0.0, -10.0, 0.5)));
+        assert!(!frustum.intersects_sphere(&sphere(20.0, 0.0, 0.0, 0.5)));
+        assert!(!frustum.intersects_aabb(&aabb(
+            glm::vec3(-1.0, -1.0, -11.0),
+            glm::vec3(1.0, 1.0, -9.0)
+        )));
+        assert!(
+            frustum.intersects_aabb(&aabb(glm::vec3(-1.0, -1.0, -1.0), glm::vec3(1.0, 1.0, 1.0)))
+        );
+    }
+}
@@ -25,6 +25,29 @@ impl FpsLimiter {
         self.last_frame_time.elapsed() >= self.target_frame_time
     }
 
+    /// The interval between two frames, can be given to
+    /// [crate::VkTracerApp::set_target_present_interval] when display timing is available.
+    #[inline]
+    pub fn target_frame_time(&self) -> Duration {
+        self.target_frame_time
+    }
+
+    /// Sleep until the next frame should be rendered instead of polling [FpsLimiter::should_render].
+    /// The last millisecond is spun because sleeping isn't precise enough.
+    pub fn wait_for_next_frame(&self) {
+        const SPIN_MARGIN: Duration = Duration::from_millis(1);
+
+        let remaining = self
+            .target_frame_time
+            .checked_sub(self.last_frame_time.elapsed());
+        if let Some(sleep) = remaining.and_then(|remaining| remaining.checked_sub(SPIN_MARGIN)) {
+            std::thread::sleep(sleep);
+        }
+        while !self.should_render() {
+            std::hint::spin_loop();
+        }
+    }
+
     #[inline]
     pub fn new_frame(&mut self) {
         self.frames += 1.0;
@@ -1,13 +1,48 @@
+#[cfg(feature = "mesh_optimizer")]
+use crate::mesh::{optimize_indices, optimize_vertex_fetch};
 use crate::{
-    errors::Result,
-    mesh::{MeshVertex, VertexXyz, VertexXyzUvNorm},
-    MeshHandle, VkTracerApp,
+    errors::{Result, VkTracerError},
+    mesh::{
+        generate_normals, generate_tangents, quantize_vertices, Aabb, MeshLoadOptions, MeshVertex,
+        NormalMode, VertexQuantized, VertexWithNormal, VertexXyz, VertexXyzUvNorm,
+        VertexXyzUvNormSkin, VertexXyzUvNormTan,
+    },
+    utils::{load_animation, NodePose, SceneAnimation},
+    MeshHandle, SamplerHandle, StorageBufferHandle, TextureHandle, UboHandle, VkTracerApp,
 };
+use ash::vk;
+use glsl_layout::Uniform;
 use nalgebra_glm as glm;
+use std::{collections::HashMap, path::Path};
+
+/// Kind of asset file, detected from its extension.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum AssetKind {
+    Gltf,
+    Obj,
+    Ktx2,
+    Hdr,
+}
+
+impl AssetKind {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "gltf" | "glb" => Some(Self::Gltf),
+            "obj" => Some(Self::Obj),
+            "ktx2" => Some(Self::Ktx2),
+            "hdr" => Some(Self::Hdr),
+            _ => None,
+        }
+    }
+}
 
 pub trait GltfToVertex: MeshVertex + Sized {
     fn is_compatible(primitive: &gltf::Primitive) -> bool;
     fn from_gltf(primitive: &gltf::Primitive, buffers: &[gltf::buffer::Data]) -> Result<Vec<Self>>;
+
+    /// Generate the attributes missing from the file once the indices are known.
+    fn complete(_primitive: &gltf::Primitive, _vertices: &mut Vec<Self>, _indices: &mut Vec<u32>) {}
 }
 
 impl GltfToVertex for VertexXyz {
@@ -29,42 +64,774 @@ impl GltfToVertex for VertexXyzUvNorm {
     fn is_compatible(primitive: &gltf::Primitive) -> bool {
         primitive.get(&gltf::Semantic::Positions).is_some()
             && primitive.get(&gltf::Semantic::TexCoords(0)).is_some()
-            && primitive.get(&gltf::Semantic::Normals).is_some()
     }
 
     fn from_gltf(primitive: &gltf::Primitive, buffers: &[gltf::buffer::Data]) -> Result<Vec<Self>> {
         let reader = primitive.reader(|b| Some(&buffers[b.index()]));
+        // Generated by complete() when missing
+        let mut normals = reader.read_normals();
 
         Ok(reader
             .read_positions()
             .unwrap()
             .zip(reader.read_tex_coords(0).unwrap().into_f32())
-            .zip(reader.read_normals().unwrap())
-            .map(|((pos, uv), norm)| VertexXyzUvNorm {
+            .map(|(pos, uv)| VertexXyzUvNorm {
                 xyz: glm::make_vec3(&pos),
                 uv: glm::make_vec2(&uv),
-                normal: glm::make_vec3(&norm),
+                normal: normals
+                    .as_mut()
+                    .and_then(|normals| normals.next())
+                    .map(|normal| glm::make_vec3(&normal))
+                    .unwrap_or_else(glm::zero),
             })
             .collect())
     }
+
+    fn complete(primitive: &gltf::Primitive, vertices: &mut Vec<Self>, indices: &mut Vec<u32>) {
+        complete_normals(primitive, vertices, indices);
+    }
 }
 
-impl VkTracerApp {
-    pub fn load_first_mesh<V: GltfToVertex>(&mut self, filename: &str) -> Result<MeshHandle> {
-        let (gltf, buffers, _) = gltf::import(filename)?;
-        let primitive = gltf.meshes().nth(0).unwrap().primitives().nth(0).unwrap();
-        assert!(V::is_compatible(&primitive));
+impl GltfToVertex for VertexXyzUvNormTan {
+    fn is_compatible(primitive: &gltf::Primitive) -> bool {
+        VertexXyzUvNorm::is_compatible(primitive)
+    }
+
+    fn from_gltf(primitive: &gltf::Primitive, buffers: &[gltf::buffer::Data]) -> Result<Vec<Self>> {
+        let reader = primitive.reader(|b| Some(&buffers[b.index()]));
+        let mut tangents = reader.read_tangents();
+
+        Ok(VertexXyzUvNorm::from_gltf(primitive, buffers)?
+            .into_iter()
+            .map(|vertex| VertexXyzUvNormTan {
+                xyz: vertex.xyz,
+                uv: vertex.uv,
+                normal: vertex.normal,
+                tangent: tangents
+                    .as_mut()
+                    .and_then(|tangents| tangents.next())
+                    .map(|tangent| glm::make_vec4(&tangent))
+                    .unwrap_or_else(glm::zero),
+            })
+            .collect())
+    }
 
-        let vertices = V::from_gltf(&primitive, &buffers)?;
-        let indices = {
-            primitive
+    fn complete(primitive: &gltf::Primitive, vertices: &mut Vec<Self>, indices: &mut Vec<u32>) {
+        complete_normals(primitive, vertices, indices);
+        if primitive.get(&gltf::Semantic::Tangents).is_none()
+            && !generate_tangents(vertices, indices)
+        {
+            log::warn!("Failed to generate the tangents of a glTF primitive");
+        }
+    }
+}
+
+/// Quantized in the bounding box of the primitive, given by [ScenePrimitive::aabb].
+impl GltfToVertex for VertexQuantized {
+    fn is_compatible(primitive: &gltf::Primitive) -> bool {
+        VertexXyzUvNorm::is_compatible(primitive)
+    }
+
+    fn from_gltf(primitive: &gltf::Primitive, buffers: &[gltf::buffer::Data]) -> Result<Vec<Self>> {
+        let mut vertices = VertexXyzUvNorm::from_gltf(primitive, buffers)?;
+        // Can't be done in complete() once quantized, and the vertices can't be duplicated
+        // here to get flat normals
+        if primitive.get(&gltf::Semantic::Normals).is_none() {
+            if let Some(indices) = primitive
                 .reader(|b| Some(&buffers[b.index()]))
                 .read_indices()
-                .unwrap()
-                .into_u32()
-                .collect::<Vec<_>>()
+            {
+                let mut indices = indices.into_u32().collect();
+                generate_normals(&mut vertices, &mut indices, NormalMode::Smooth);
+            }
+        }
+        Ok(quantize_vertices(&vertices, &primitive_aabb(primitive)))
+    }
+}
+
+impl GltfToVertex for VertexXyzUvNormSkin {
+    fn is_compatible(primitive: &gltf::Primitive) -> bool {
+        VertexXyzUvNorm::is_compatible(primitive)
+            && primitive.get(&gltf::Semantic::Joints(0)).is_some()
+            && primitive.get(&gltf::Semantic::Weights(0)).is_some()
+    }
+
+    fn from_gltf(primitive: &gltf::Primitive, buffers: &[gltf::buffer::Data]) -> Result<Vec<Self>> {
+        let reader = primitive.reader(|b| Some(&buffers[b.index()]));
+
+        Ok(VertexXyzUvNorm::from_gltf(primitive, buffers)?
+            .into_iter()
+            .zip(reader.read_joints(0).unwrap().into_u16())
+            .zip(reader.read_weights(0).unwrap().into_f32())
+            .map(|((vertex, joints), weights)| VertexXyzUvNormSkin {
+                xyz: vertex.xyz,
+                uv: vertex.uv,
+                normal: vertex.normal,
+                joints: [
+                    joints[0] as u32,
+                    joints[1] as u32,
+                    joints[2] as u32,
+                    joints[3] as u32,
+                ],
+                weights: glm::make_vec4(&weights),
+            })
+            .collect())
+    }
+
+    fn complete(primitive: &gltf::Primitive, vertices: &mut Vec<Self>, indices: &mut Vec<u32>) {
+        complete_normals(primitive, vertices, indices);
+    }
+}
+
+fn primitive_aabb(primitive: &gltf::Primitive) -> Aabb {
+    let bounds = primitive.bounding_box();
+    Aabb {
+        min: glm::make_vec3(&bounds.min),
+        max: glm::make_vec3(&bounds.max),
+    }
+}
+
+/// glTF asks for flat normals when they are missing, but the vertices of primitives with morph
+/// targets can't be duplicated so they get smooth ones.
+fn complete_normals<V: VertexWithNormal>(
+    primitive: &gltf::Primitive,
+    vertices: &mut Vec<V>,
+    indices: &mut Vec<u32>,
+) {
+    if primitive.get(&gltf::Semantic::Normals).is_none() {
+        let mode = if primitive.morph_targets().count() == 0 {
+            NormalMode::Flat
+        } else {
+            NormalMode::Smooth
         };
+        generate_normals(vertices, indices, mode);
+    }
+}
+
+/// Node hierarchy of a glTF scene, nodes, meshes and cameras keep their index in the file.
+pub struct GltfScene {
+    pub nodes: Vec<SceneNode>,
+    /// Top level nodes of the scene.
+    pub roots: Vec<usize>,
+    pub meshes: Vec<SceneMesh>,
+    pub materials: Vec<SceneMaterial>,
+    pub cameras: Vec<SceneCamera>,
+    pub skins: Vec<SceneSkin>,
+    pub animations: Vec<SceneAnimation>,
+}
+
+pub struct SceneMesh {
+    pub primitives: Box<[ScenePrimitive]>,
+    /// Default weights of the morph targets of the primitives.
+    pub weights: Vec<f32>,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct ScenePrimitive {
+    pub mesh: MeshHandle,
+    pub material: Option<usize>,
+    pub morph_targets: Option<MorphTargets>,
+    /// Bounds of the positions, also used to decode [VertexQuantized] positions.
+    pub aabb: Aabb,
+}
+
+/// Position and normal displacements of the vertices of a primitive, blended with weights
+/// by [crate::utils::MORPH_TARGETS_GLSL].
+#[derive(Copy, Clone, Debug)]
+pub struct MorphTargets {
+    /// Two vec4 per vertex and per target (position then normal), grouped by target.
+    pub deltas: StorageBufferHandle,
+    pub target_count: u32,
+    pub vertex_count: u32,
+}
+
+/// Metallic-roughness material, the textures are sampled in SHADER_READ_ONLY_OPTIMAL.
+pub struct SceneMaterial {
+    pub name: Option<String>,
+    /// sRGB.
+    pub base_color: Option<MaterialTexture>,
+    pub normal: Option<MaterialTexture>,
+    /// Roughness in G and metallic in B, often the same texture as the occlusion (ORM).
+    pub metallic_roughness: Option<MaterialTexture>,
+    /// Occlusion in R.
+    pub occlusion: Option<MaterialTexture>,
+    /// sRGB.
+    pub emissive: Option<MaterialTexture>,
+    /// The factors of the material as a [MaterialData].
+    pub ubo: UboHandle,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct MaterialTexture {
+    pub texture: TextureHandle,
+    pub sampler: SamplerHandle,
+    /// Index of the UV set to sample it with.
+    pub tex_coord: u32,
+}
+
+/// Content of the UBO of a [SceneMaterial], in std140.
+#[derive(Copy, Clone, Uniform)]
+pub struct MaterialData {
+    pub base_color_factor: glsl_layout::vec4,
+    pub emissive_factor: glsl_layout::vec3,
+    pub metallic_factor: glsl_layout::float,
+    pub roughness_factor: glsl_layout::float,
+    pub normal_scale: glsl_layout::float,
+    pub occlusion_strength: glsl_layout::float,
+}
+
+pub struct SceneNode {
+    pub name: Option<String>,
+    /// Relative to the parent node.
+    pub transform: glm::Mat4,
+    /// Same as `transform`, as animated by an [crate::utils::AnimationPlayer].
+    pub pose: NodePose,
+    pub children: Vec<usize>,
+    pub mesh: Option<usize>,
+    pub camera: Option<usize>,
+    pub skin: Option<usize>,
+    /// Weights of the morph targets of the mesh, those of the mesh unless overridden.
+    pub morph_weights: Vec<f32>,
+}
+
+pub struct SceneSkin {
+    /// Nodes moving the vertices, in the order of the joint indices of the vertices.
+    pub joints: Vec<usize>,
+    pub inverse_bind_matrices: Vec<glm::Mat4>,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum SceneCamera {
+    Perspective {
+        aspect_ratio: Option<f32>,
+        yfov: f32,
+        znear: f32,
+        zfar: Option<f32>,
+    },
+    Orthographic {
+        xmag: f32,
+        ymag: f32,
+        znear: f32,
+        zfar: f32,
+    },
+}
+
+impl GltfScene {
+    /// Transform of each node relative to the scene, nodes outside of the scene keep
+    /// the identity.
+    pub fn world_transforms(&self) -> Vec<glm::Mat4> {
+        self.world_transforms_with(|node| self.nodes[node].transform)
+    }
+
+    pub(crate) fn world_transforms_with(
+        &self,
+        local_transform: impl Fn(usize) -> glm::Mat4,
+    ) -> Vec<glm::Mat4> {
+        let mut transforms = vec![glm::identity(); self.nodes.len()];
+        let mut stack = self
+            .roots
+            .iter()
+            .map(|root| (*root, glm::identity()))
+            .collect::<Vec<_>>();
+        while let Some((index, parent)) = stack.pop() {
+            let node = &self.nodes[index];
+            transforms[index] = parent * local_transform(index);
+            stack.extend(
+                node.children
+                    .iter()
+                    .map(|child| (*child, transforms[index])),
+            );
+        }
+        transforms
+    }
+}
+
+impl VkTracerApp {
+    pub fn load_first_mesh<V: GltfToVertex>(&mut self, filename: &str) -> Result<MeshHandle> {
+        self.load_first_mesh_from_path::<V>(Path::new(filename))
+    }
 
+    fn load_first_mesh_from_path<V: GltfToVertex>(&mut self, path: &Path) -> Result<MeshHandle> {
+        let (vertices, indices) = read_first_mesh::<V>(path)?;
         self.create_mesh_indexed(&vertices, &indices)
     }
+
+    /// Load the default scene of a glTF file, or its first one. All the primitives are uploaded
+    /// at once and must be compatible with `V`.
+    pub fn load_gltf_scene<V: GltfToVertex>(&mut self, filename: &str) -> Result<GltfScene> {
+        self.load_gltf_scene_with_options::<V>(filename, &MeshLoadOptions::default())
+    }
+
+    /// Same as [VkTracerApp::load_gltf_scene], the primitives are optimized or simplified
+    /// before being uploaded.
+    pub fn load_gltf_scene_with_options<V: GltfToVertex>(
+        &mut self,
+        filename: &str,
+        options: &MeshLoadOptions,
+    ) -> Result<GltfScene> {
+        #[cfg(not(feature = "mesh_optimizer"))]
+        if options.optimize || options.simplify_target.is_some() {
+            log::warn!("Mesh optimizations require the mesh_optimizer feature");
+        }
+
+        let path = Path::new(filename);
+        let (gltf, buffers, images) = gltf::import(path)?;
+        let unsupported = || VkTracerError::UnsupportedAsset(path.to_path_buf());
+
+        let mut primitives = Vec::new();
+        let mut primitive_materials = Vec::new();
+        let mut primitive_morph_targets = Vec::new();
+        let mut primitive_aabbs = Vec::new();
+        let mut primitive_counts = Vec::with_capacity(gltf.meshes().len());
+        for mesh in gltf.meshes() {
+            primitive_counts.push(mesh.primitives().len());
+            for primitive in mesh.primitives() {
+                primitive_materials.push(primitive.material().index());
+                primitive_aabbs.push(primitive_aabb(&primitive));
+                if !V::is_compatible(&primitive) {
+                    return Err(unsupported());
+                }
+                let mut vertices = V::from_gltf(&primitive, &buffers)?;
+                let mut indices = read_indices(&primitive, &buffers, vertices.len());
+                V::complete(&primitive, &mut vertices, &mut indices);
+                #[cfg(feature = "mesh_optimizer")]
+                {
+                    optimize_indices(&vertices, &mut indices, options)?;
+                    // The morph target deltas follow the order of the vertices
+                    if options.optimize && primitive.morph_targets().count() == 0 {
+                        optimize_vertex_fetch(&mut vertices, &mut indices);
+                    }
+                }
+                primitive_morph_targets.push(read_morph_targets(
+                    &primitive,
+                    &buffers,
+                    vertices.len(),
+                ));
+                primitives.push((vertices, indices));
+            }
+        }
+
+        let mesh_handles = self.create_meshes_with_index_format(
+            &primitives
+                .iter()
+                .map(|(vertices, indices)| (vertices.as_slice(), indices.as_slice()))
+                .collect::<Vec<_>>(),
+            options.index_format,
+        )?;
+        let mut scene_primitives = Vec::with_capacity(mesh_handles.len());
+        let mut loader = MaterialLoader {
+            images: &images,
+            textures: HashMap::new(),
+            samplers: HashMap::new(),
+        };
+        let mut materials = Vec::with_capacity(gltf.materials().len());
+
+        // Everything already created is freed if a morph target or a material fails
+        let created: Result<()> = (|| {
+            for (((mesh, material), morph_targets), aabb) in mesh_handles
+                .iter()
+                .copied()
+                .zip(primitive_materials)
+                .zip(primitive_morph_targets)
+                .zip(primitive_aabbs)
+            {
+                let morph_targets = match morph_targets {
+                    Some((deltas, target_count)) => Some(MorphTargets {
+                        vertex_count: (deltas.len() / (2 * target_count)) as u32,
+                        target_count: target_count as u32,
+                        deltas: self.create_storage_buffer(&deltas)?,
+                    }),
+                    None => None,
+                };
+                scene_primitives.push(ScenePrimitive {
+                    mesh,
+                    material,
+                    morph_targets,
+                    aabb,
+                });
+            }
+            for material in gltf.materials() {
+                materials.push(loader.load(self, &material)?);
+            }
+            Ok(())
+        })();
+        if let Err(err) = created {
+            self.destroy_partial_scene(&mesh_handles, &scene_primitives, &materials, &loader)?;
+            return Err(err);
+        }
+
+        let mut scene_primitives = scene_primitives.into_iter();
+        let meshes = gltf
+            .meshes()
+            .zip(primitive_counts)
+            .map(|(mesh, count)| SceneMesh {
+                primitives: scene_primitives.by_ref().take(count).collect(),
+                weights: mesh.weights().map(<[f32]>::to_vec).unwrap_or_default(),
+            })
+            .collect();
+
+        let nodes = gltf
+            .nodes()
+            .map(|node| {
+                let (translation, rotation, scale) = node.transform().decomposed();
+                SceneNode {
+                    name: node.name().map(String::from),
+                    transform: glm::Mat4::from(node.transform().matrix()),
+                    pose: NodePose {
+                        translation: glm::make_vec3(&translation),
+                        rotation: glm::quat(rotation[0], rotation[1], rotation[2], rotation[3]),
+                        scale: glm::make_vec3(&scale),
+                    },
+                    children: node.children().map(|child| child.index()).collect(),
+                    mesh: node.mesh().map(|mesh| mesh.index()),
+                    camera: node.camera().map(|camera| camera.index()),
+                    skin: node.skin().map(|skin| skin.index()),
+                    morph_weights: node
+                        .weights()
+                        .or_else(|| node.mesh().and_then(|mesh| mesh.weights()))
+                        .map(<[f32]>::to_vec)
+                        .unwrap_or_default(),
+                }
+            })
+            .collect();
+
+        let skins = gltf
+            .skins()
+            .map(|skin| {
+                let joints = skin.joints().map(|joint| joint.index()).collect::<Vec<_>>();
+                let inverse_bind_matrices = match skin
+                    .reader(|b| Some(&buffers[b.index()]))
+                    .read_inverse_bind_matrices()
+                {
+                    Some(matrices) => matrices.map(glm::Mat4::from).collect(),
+                    None => vec![glm::identity(); joints.len()],
+                };
+                SceneSkin {
+                    joints,
+                    inverse_bind_matrices,
+                }
+            })
+            .collect();
+
+        let animations = gltf
+            .animations()
+            .map(|animation| load_animation(&animation, &buffers))
+            .collect();
+
+        let roots = gltf
+            .default_scene()
+            .or_else(|| gltf.scenes().nth(0))
+            .map(|scene| scene.nodes().map(|node| node.index()).collect())
+            .unwrap_or_default();
+
+        let cameras = gltf
+            .cameras()
+            .map(|camera| match camera.projection() {
+                gltf::camera::Projection::Perspective(p) => SceneCamera::Perspective {
+                    aspect_ratio: p.aspect_ratio(),
+                    yfov: p.yfov(),
+                    znear: p.znear(),
+                    zfar: p.zfar(),
+                },
+                gltf::camera::Projection::Orthographic(o) => SceneCamera::Orthographic {
+                    xmag: o.xmag(),
+                    ymag: o.ymag(),
+                    znear: o.znear(),
+                    zfar: o.zfar(),
+                },
+            })
+            .collect();
+
+        Ok(GltfScene {
+            nodes,
+            roots,
+            meshes,
+            materials,
+            cameras,
+            skins,
+            animations,
+        })
+    }
+
+    /// Free what a failed [VkTracerApp::load_gltf_scene] created before failing.
+    fn destroy_partial_scene(
+        &mut self,
+        meshes: &[MeshHandle],
+        primitives: &[ScenePrimitive],
+        materials: &[SceneMaterial],
+        loader: &MaterialLoader,
+    ) -> Result<()> {
+        for mesh in meshes.iter().copied() {
+            self.destroy_mesh(mesh)?;
+        }
+        for morph_targets in primitives.iter().filter_map(|p| p.morph_targets) {
+            self.destroy_storage_buffer(morph_targets.deltas)?;
+        }
+        for material in materials {
+            if let Some(ubo) = self.ubo_storage.remove(material.ubo) {
+                ubo.destroy(&self.vma)?;
+            }
+        }
+        for texture in loader.textures.values().copied() {
+            self.destroy_texture(texture)?;
+        }
+        for sampler in loader.samplers.values().copied() {
+            self.destroy_sampler(sampler)?;
+        }
+        Ok(())
+    }
+
+    /// Load a file dropped on a window, typically from winit's `WindowEvent::DroppedFile`.
+    /// Only glTF meshes can be loaded for now, other known kinds are reported as unsupported.
+    pub fn load_dropped_file<V: GltfToVertex>(&mut self, path: &Path) -> Result<MeshHandle> {
+        match AssetKind::from_path(path) {
+            Some(AssetKind::Gltf) => self.load_first_mesh_from_path::<V>(path),
+            _ => Err(VkTracerError::UnsupportedAsset(path.to_path_buf())),
+        }
+    }
+}
+
+/// Vertices and indices of the first primitive of the first mesh, without touching the device.
+pub(crate) fn read_first_mesh<V: GltfToVertex>(path: &Path) -> Result<(Vec<V>, Vec<u32>)> {
+    let (gltf, buffers, _) = gltf::import(path)?;
+    // Files can be dropped by the user so anything unexpected is an error, not a panic
+    let unsupported = || VkTracerError::UnsupportedAsset(path.to_path_buf());
+    let primitive = gltf
+        .meshes()
+        .nth(0)
+        .and_then(|mesh| mesh.primitives().nth(0))
+        .filter(|primitive| V::is_compatible(primitive))
+        .ok_or_else(unsupported)?;
+
+    let mut vertices = V::from_gltf(&primitive, &buffers)?;
+    let mut indices = read_indices(&primitive, &buffers, vertices.len());
+    V::complete(&primitive, &mut vertices, &mut indices);
+
+    Ok((vertices, indices))
+}
+
+/// The indices of the primitive, or one per vertex in order when it isn't indexed.
+fn read_indices(
+    primitive: &gltf::Primitive,
+    buffers: &[gltf::buffer::Data],
+    vertex_count: usize,
+) -> Vec<u32> {
+    match primitive
+        .reader(|b| Some(&buffers[b.index()]))
+        .read_indices()
+    {
+        Some(indices) => indices.into_u32().collect(),
+        None => (0..vertex_count as u32).collect(),
+    }
+}
+
+/// Displacements of the morph targets of a primitive, as laid out in [MorphTargets::deltas],
+/// and the number of targets.
+fn read_morph_targets(
+    primitive: &gltf::Primitive,
+    buffers: &[gltf::buffer::Data],
+    vertex_count: usize,
+) -> Option<(Vec<[f32; 4]>, usize)> {
+    let target_count = primitive.morph_targets().count();
+    if target_count == 0 || vertex_count == 0 {
+        return None;
+    }
+
+    let mut deltas = vec![[0.0; 4]; 2 * target_count * vertex_count];
+    let reader = primitive.reader(|b| Some(&buffers[b.index()]));
+    for (target, (positions, normals, _)) in reader.read_morph_targets().enumerate() {
+        let target_deltas = &mut deltas[2 * target * vertex_count..][..2 * vertex_count];
+        if let Some(positions) = positions {
+            for (delta, p) in target_deltas.chunks_exact_mut(2).zip(positions) {
+                delta[0] = [p[0], p[1], p[2], 0.0];
+            }
+        }
+        if let Some(normals) = normals {
+            for (delta, n) in target_deltas.chunks_exact_mut(2).zip(normals) {
+                delta[1] = [n[0], n[1], n[2], 0.0];
+            }
+        }
+    }
+    Some((deltas, target_count))
+}
+
+/// Creates the textures and samplers of the materials, shared between them.
+struct MaterialLoader<'a> {
+    images: &'a [gltf::image::Data],
+    /// By image and whether it's sRGB.
+    textures: HashMap<(usize, bool), TextureHandle>,
+    /// `None` is the default sampler.
+    samplers: HashMap<Option<usize>, SamplerHandle>,
+}
+
+impl MaterialLoader<'_> {
+    fn load(&mut self, app: &mut VkTracerApp, material: &gltf::Material) -> Result<SceneMaterial> {
+        let pbr = material.pbr_metallic_roughness();
+
+        let base_color = match pbr.base_color_texture() {
+            Some(info) => Some(self.texture(app, &info.texture(), info.tex_coord(), true)?),
+            None => None,
+        };
+        let metallic_roughness = match pbr.metallic_roughness_texture() {
+            Some(info) => Some(self.texture(app, &info.texture(), info.tex_coord(), false)?),
+            None => None,
+        };
+        let emissive = match material.emissive_texture() {
+            Some(info) => Some(self.texture(app, &info.texture(), info.tex_coord(), true)?),
+            None => None,
+        };
+        let (normal, normal_scale) = match material.normal_texture() {
+            Some(normal) => (
+                Some(self.texture(app, &normal.texture(), normal.tex_coord(), false)?),
+                normal.scale(),
+            ),
+            None => (None, 1.0),
+        };
+        let (occlusion, occlusion_strength) = match material.occlusion_texture() {
+            Some(occlusion) => (
+                Some(self.texture(app, &occlusion.texture(), occlusion.tex_coord(), false)?),
+                occlusion.strength(),
+            ),
+            None => (None, 1.0),
+        };
+
+        let data = MaterialData {
+            base_color_factor: pbr.base_color_factor().into(),
+            emissive_factor: material.emissive_factor().into(),
+            metallic_factor: pbr.metallic_factor(),
+            roughness_factor: pbr.roughness_factor(),
+            normal_scale,
+            occlusion_strength,
+        };
+
+        Ok(SceneMaterial {
+            name: material.name().map(String::from),
+            base_color,
+            normal,
+            metallic_roughness,
+            occlusion,
+            emissive,
+            ubo: app.create_ubo([data.std140()])?,
+        })
+    }
+
+    fn texture(
+        &mut self,
+        app: &mut VkTracerApp,
+        texture: &gltf::Texture,
+        tex_coord: u32,
+        srgb: bool,
+    ) -> Result<MaterialTexture> {
+        let image_index = texture.source().index();
+        let texture_handle = match self.textures.get(&(image_index, srgb)) {
+            Some(handle) => *handle,
+            None => {
+                let image = &self.images[image_index];
+                let pixels = to_rgba8(image);
+                let format = if srgb {
+                    vk::Format::R8G8B8A8_SRGB
+                } else {
+                    vk::Format::R8G8B8A8_UNORM
+                };
+                let handle = app.create_texture(image.width, image.height, format, &pixels)?;
+                self.textures.insert((image_index, srgb), handle);
+                handle
+            }
+        };
+
+        let sampler = texture.sampler();
+        let sampler_handle = match self.samplers.get(&sampler.index()) {
+            Some(handle) => *handle,
+            None => {
+                let handle = app.create_raw_sampler(&sampler_info(&sampler))?;
+                self.samplers.insert(sampler.index(), handle);
+                handle
+            }
+        };
+
+        Ok(MaterialTexture {
+            texture: texture_handle,
+            sampler: sampler_handle,
+            tex_coord,
+        })
+    }
+}
+
+/// Expand any image to 8 bits RGBA, grey images are copied to the 3 color channels and 16 bits
+/// channels are rounded.
+fn to_rgba8(image: &gltf::image::Data) -> Vec<u8> {
+    use gltf::image::Format;
+
+    let (channels, wide, bgr) = match image.format {
+        Format::R8G8B8A8 => return image.pixels.clone(),
+        Format::R8 => (1, false, false),
+        Format::R8G8 => (2, false, false),
+        Format::R8G8B8 => (3, false, false),
+        Format::B8G8R8 => (3, false, true),
+        Format::B8G8R8A8 => (4, false, true),
+        Format::R16 => (1, true, false),
+        Format::R16G16 => (2, true, false),
+        Format::R16G16B16 => (3, true, false),
+        Format::R16G16B16A16 => (4, true, false),
+    };
+    let channel_size = if wide { 2 } else { 1 };
+    let channel = |bytes: &[u8]| {
+        if wide {
+            ((u16::from_ne_bytes([bytes[0], bytes[1]]) as u32 + 128) / 257) as u8
+        } else {
+            bytes[0]
+        }
+    };
+
+    let mut pixels = Vec::with_capacity(image.pixels.len() / (channels * channel_size) * 4);
+    for texel in image.pixels.chunks_exact(channels * channel_size) {
+        let mut values = [0u8, 0, 0, 255];
+        for (i, bytes) in texel.chunks_exact(channel_size).enumerate() {
+            values[i] = channel(bytes);
+        }
+        let rgba = match channels {
+            1 => [values[0], values[0], values[0], 255],
+            2 => [values[0], values[0], values[0], values[1]],
+            _ if bgr => [values[2], values[1], values[0], values[3]],
+            _ => values,
+        };
+        pixels.extend_from_slice(&rgba);
+    }
+    pixels
+}
+
+fn sampler_info(sampler: &gltf::texture::Sampler) -> vk::SamplerCreateInfo {
+    use gltf::texture::{MagFilter, MinFilter, WrappingMode};
+
+    let mag_filter = match sampler.mag_filter() {
+        Some(MagFilter::Nearest) => vk::Filter::NEAREST,
+        _ => vk::Filter::LINEAR,
+    };
+    let (min_filter, mipmap_mode) = match sampler.min_filter() {
+        Some(MinFilter::Nearest) | Some(MinFilter::NearestMipmapNearest) => {
+            (vk::Filter::NEAREST, vk::SamplerMipmapMode::NEAREST)
+        }
+        Some(MinFilter::NearestMipmapLinear) => {
+            (vk::Filter::NEAREST, vk::SamplerMipmapMode::LINEAR)
+        }
+        Some(MinFilter::LinearMipmapNearest) => {
+            (vk::Filter::LINEAR, vk::SamplerMipmapMode::NEAREST)
+        }
+        _ => (vk::Filter::LINEAR, vk::SamplerMipmapMode::LINEAR),
+    };
+    let address_mode = |mode| match mode {
+        WrappingMode::ClampToEdge => vk::SamplerAddressMode::CLAMP_TO_EDGE,
+        WrappingMode::MirroredRepeat => vk::SamplerAddressMode::MIRRORED_REPEAT,
+        WrappingMode::Repeat => vk::SamplerAddressMode::REPEAT,
+    };
+
+    vk::SamplerCreateInfo::builder()
+        .mag_filter(mag_filter)
+        .min_filter(min_filter)
+        .mipmap_mode(mipmap_mode)
+        .address_mode_u(address_mode(sampler.wrap_s()))
+        .address_mode_v(address_mode(sampler.wrap_t()))
+        .address_mode_w(vk::SamplerAddressMode::REPEAT)
+        .max_lod(vk::LOD_CLAMP_NONE)
+        .build()
 }
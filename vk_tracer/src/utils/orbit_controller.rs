@@ -0,0 +1,143 @@
+use crate::utils::Camera;
+use nalgebra_glm as glm;
+use std::f32::consts::FRAC_PI_2;
+
+/// Keeps the pitch away from the poles where the view flips.
+const MAX_PITCH: f32 = FRAC_PI_2 - 0.01;
+
+/// Turns around a target at a distance, the camera looks at the target. The input is smoothed
+/// over the next frames by [OrbitController::update], which then moves the camera.
+pub struct OrbitController {
+    pub target: glm::Vec3,
+    /// Around the Y axis, 0 looks towards +Z.
+    pub yaw: f32,
+    /// Positive above the target.
+    pub pitch: f32,
+    pub distance: f32,
+    pub min_distance: f32,
+    pub max_distance: f32,
+    /// Radians per pixel of [OrbitController::rotate].
+    pub rotate_speed: f32,
+    /// Fraction of the distance per step of [OrbitController::zoom].
+    pub zoom_speed: f32,
+    /// Fraction of the input still to apply after a second, 0 applies it immediately.
+    pub damping: f32,
+    /// Yaw, pitch and log of the distance not applied yet.
+    pending: glm::Vec3,
+    #[cfg(feature = "camera_winit")]
+    dragging: bool,
+    #[cfg(feature = "camera_winit")]
+    cursor: Option<(f64, f64)>,
+}
+
+impl OrbitController {
+    pub fn new(target: glm::Vec3, distance: f32) -> Self {
+        Self {
+            target,
+            yaw: 0.0,
+            pitch: 0.0,
+            distance,
+            min_distance: 0.1,
+            max_distance: 100.0,
+            rotate_speed: 0.005,
+            zoom_speed: 0.1,
+            damping: 0.001,
+            pending: glm::Vec3::zeros(),
+            #[cfg(feature = "camera_winit")]
+            dragging: false,
+            #[cfg(feature = "camera_winit")]
+            cursor: None,
+        }
+    }
+
+    /// Start from a camera at `position` looking at `target`.
+    pub fn looking_at(position: glm::Vec3, target: glm::Vec3) -> Self {
+        let offset = position - target;
+        let distance = offset.norm();
+        let mut controller = Self::new(target, distance);
+        if distance > 0.0 {
+            controller.yaw = offset.x.atan2(offset.z);
+            controller.pitch = (offset.y / distance).asin().clamp(-MAX_PITCH, MAX_PITCH);
+        }
+        controller
+    }
+
+    /// Rotate by a mouse motion in pixels, dragging to the right turns the camera to the left.
+    pub fn rotate(&mut self, dx: f32, dy: f32) {
+        self.pending.x -= dx * self.rotate_speed;
+        self.pending.y += dy * self.rotate_speed;
+    }
+
+    /// Zoom in by `steps` scroll steps, negative zooms out.
+    pub fn zoom(&mut self, steps: f32) {
+        self.pending.z += (1.0 - self.zoom_speed).ln() * steps;
+    }
+
+    /// Apply the input smoothed over the `delta_time` seconds since the last update and move
+    /// the camera there.
+    pub fn update(&mut self, camera: &mut Camera, delta_time: f32) {
+        let applied = self.pending * (1.0 - self.damping.powf(delta_time));
+        self.pending -= applied;
+
+        self.yaw = (self.yaw + applied.x) % (std::f32::consts::PI * 2.0);
+        self.pitch = (self.pitch + applied.y).clamp(-MAX_PITCH, MAX_PITCH);
+        self.distance =
+            (self.distance * applied.z.exp()).clamp(self.min_distance, self.max_distance);
+
+        camera.look_at(self.position(), self.target);
+    }
+
+    /// Where the camera is, without the input not applied yet.
+    pub fn position(&self) -> glm::Vec3 {
+        let (sin_yaw, cos_yaw) = self.yaw.sin_cos();
+        let (sin_pitch, cos_pitch) = self.pitch.sin_cos();
+        self.target + glm::vec3(cos_pitch * sin_yaw, sin_pitch, cos_pitch * cos_yaw) * self.distance
+    }
+
+    /// Whether the input is applied, to skip redrawing a still image.
+    pub fn is_idle(&self) -> bool {
+        self.pending.abs().max() < 1e-5
+    }
+}
+
+#[cfg(feature = "camera_winit")]
+impl OrbitController {
+    /// Rotate while the left button is held and zoom with the wheel. Returns whether the
+    /// event was used.
+    pub fn handle_window_event(&mut self, event: &winit::event::WindowEvent) -> bool {
+        use winit::event::{ElementState, MouseButton, MouseScrollDelta, WindowEvent};
+
+        match event {
+            WindowEvent::MouseInput {
+                state,
+                button: MouseButton::Left,
+                ..
+            } => {
+                self.dragging = *state == ElementState::Pressed;
+                true
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                let cursor = (position.x, position.y);
+                if let (true, Some((x, y))) = (self.dragging, self.cursor) {
+                    self.rotate((cursor.0 - x) as f32, (cursor.1 - y) as f32);
+                }
+                self.cursor = Some(cursor);
+                self.dragging
+            }
+            WindowEvent::CursorLeft { .. } | WindowEvent::Focused(false) => {
+                self.dragging = false;
+                self.cursor = None;
+                false
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                match delta {
+                    MouseScrollDelta::LineDelta(_, y) => self.zoom(*y),
+                    // Roughly a line per 50 pixels on touchpads
+                    MouseScrollDelta::PixelDelta(position) => self.zoom(position.y as f32 / 50.0),
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+}
@@ -84,6 +84,23 @@ impl<'a> ShaderCompiler<'a> {
         Ok(dst)
     }
 
+    /// Compile GLSL source in memory, like the shaders embedded in this crate.
+    pub fn compile_source(
+        &mut self,
+        src: &str,
+        name: &str,
+        kind: shaderc::ShaderKind,
+        entry_point: &str,
+    ) -> Result<Vec<u8>> {
+        let compiled =
+            self.compiler
+                .compile_into_spirv(src, kind, name, entry_point, Some(&self.options))?;
+        if compiled.get_num_warnings() > 0 {
+            warn!("{}", compiled.get_warning_messages());
+        }
+        Ok(compiled.as_binary_u8().to_vec())
+    }
+
     #[inline(always)]
     pub fn compile(
         &mut self,
@@ -0,0 +1,29 @@
+// Per-pixel shader cost heatmap, requires VkTracerExtensions::ShaderClock.
+// Paste it right after the #version directive, then in the fragment shader:
+//
+//     uint64_t start = heatmap_begin();
+//     // ... code to measure ...
+//     out_color = heatmap_end(start, 50000.0);
+//
+// The second parameter is the amount of cycles that will be displayed as full red.
+
+#extension GL_ARB_shader_clock : require
+#extension GL_ARB_gpu_shader_int64 : require
+
+uint64_t heatmap_begin() {
+    return clockARB();
+}
+
+// Blue (cheap) -> green -> red (expensive)
+vec4 heatmap_color(float t) {
+    vec3 cold = vec3(0.0, 0.0, 1.0);
+    vec3 mid = vec3(0.0, 1.0, 0.0);
+    vec3 hot = vec3(1.0, 0.0, 0.0);
+    vec3 color = t < 0.5 ? mix(cold, mid, t * 2.0) : mix(mid, hot, (t - 0.5) * 2.0);
+    return vec4(color, 1.0);
+}
+
+vec4 heatmap_end(uint64_t start, float max_cycles) {
+    float cycles = float(clockARB() - start);
+    return heatmap_color(clamp(cycles / max_cycles, 0.0, 1.0));
+}
@@ -0,0 +1,17 @@
+#version 460
+
+// Lines of a DebugDraw, already in world space.
+
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec4 color;
+
+layout(push_constant) uniform DebugDrawParams {
+    mat4 view_proj;
+};
+
+layout(location = 0) out vec4 out_color;
+
+void main() {
+    gl_Position = view_proj * vec4(position, 1.0);
+    out_color = color;
+}
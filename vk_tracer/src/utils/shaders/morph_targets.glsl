@@ -0,0 +1,34 @@
+// Morph targets of a glTF primitive, blended in the vertex shader.
+// Paste it after the #version directive and define beforehand:
+//
+//     #define MORPH_SET 1                // Set with the two bindings below
+//     #define MORPH_TARGET_COUNT 2       // MorphTargets::target_count
+//
+// Binding 0 is the storage buffer MorphTargets::deltas and binding 1 the uniform buffer of
+// VkTracerApp::create_morph_weights_buffer. Then in main():
+//
+//     vec3 position = in_position;
+//     vec3 normal = in_normal;
+//     apply_morph_targets(gl_VertexIndex, position, normal);
+
+layout(std430, set = MORPH_SET, binding = 0) readonly buffer MorphDeltas {
+    // Position then normal of each vertex, grouped by target
+    vec4 morph_deltas[];
+};
+
+layout(std140, set = MORPH_SET, binding = 1) uniform MorphWeights {
+    // Target count, vertex count
+    uvec4 morph_header;
+    vec4 morph_weights[(MORPH_TARGET_COUNT + 3) / 4];
+};
+
+void apply_morph_targets(int vertex, inout vec3 position, inout vec3 normal) {
+    uint vertex_count = morph_header.y;
+    for (uint target = 0; target < morph_header.x; target++) {
+        float weight = morph_weights[target / 4][target % 4];
+        uint delta = 2 * (target * vertex_count + uint(vertex));
+        position += weight * morph_deltas[delta].xyz;
+        normal += weight * morph_deltas[delta + 1].xyz;
+    }
+    normal = normalize(normal);
+}
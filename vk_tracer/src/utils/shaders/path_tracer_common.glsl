@@ -0,0 +1,24 @@
+#version 460
+#extension GL_EXT_ray_tracing : require
+#extension GL_EXT_buffer_reference : require
+#extension GL_EXT_shader_explicit_arithmetic_types_int64 : require
+
+// Shared by the stages of the path tracer of VkTracerApp::create_path_tracer, prepended to each
+// of them.
+
+struct HitPayload {
+    vec3 position;
+    // Facing the ray
+    vec3 normal;
+    bool hit;
+};
+
+layout(push_constant) uniform PathTracerParams {
+    mat4 inverse_view_proj;
+    // Address of the geometry records, indexed by the custom index of the instances
+    uint64_t geometries;
+    // Frames accumulated so far, 0 discards the accumulation
+    uint frame;
+    uint max_bounces;
+    vec4 sky_color;
+};
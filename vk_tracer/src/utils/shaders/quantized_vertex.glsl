@@ -0,0 +1,21 @@
+// Decoding of crate::mesh::VertexQuantized, paste it after the #version directive. The
+// attributes are already normalized by the vertex input:
+//
+//     layout(location = 0) in vec4 in_position;
+//     layout(location = 1) in vec2 in_uv;
+//     layout(location = 2) in vec2 in_normal;
+//
+// aabb_min and aabb_max are the bounds of the mesh given by VkTracerApp::get_mesh_bounds.
+
+vec3 dequantize_position(vec4 position, vec3 aabb_min, vec3 aabb_max) {
+    return aabb_min + position.xyz * (aabb_max - aabb_min);
+}
+
+vec3 octahedral_decode(vec2 encoded) {
+    vec3 n = vec3(encoded, 1.0 - abs(encoded.x) - abs(encoded.y));
+    // Unfold the lower half
+    float t = max(-n.z, 0.0);
+    n.x += n.x >= 0.0 ? -t : t;
+    n.y += n.y >= 0.0 ? -t : t;
+    return normalize(n);
+}
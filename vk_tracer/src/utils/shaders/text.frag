@@ -0,0 +1,14 @@
+#version 460
+
+layout(set = 0, binding = 0) uniform texture2D font_atlas;
+layout(set = 0, binding = 1) uniform sampler font_sampler;
+
+layout(location = 0) in vec2 uv;
+layout(location = 1) in vec4 color;
+
+layout(location = 0) out vec4 out_color;
+
+void main() {
+    float coverage = texture(sampler2D(font_atlas, font_sampler), uv).r;
+    out_color = vec4(color.rgb, color.a * coverage);
+}
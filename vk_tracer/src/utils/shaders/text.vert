@@ -0,0 +1,20 @@
+#version 460
+
+// Glyph quads of a TextRenderer, positioned in pixels from the top left corner.
+
+layout(location = 0) in vec2 position;
+layout(location = 1) in vec2 uv;
+layout(location = 2) in vec4 color;
+
+layout(push_constant) uniform TextParams {
+    vec2 target_size;
+};
+
+layout(location = 0) out vec2 out_uv;
+layout(location = 1) out vec4 out_color;
+
+void main() {
+    gl_Position = vec4(position / target_size * 2.0 - 1.0, 0.0, 1.0);
+    out_uv = uv;
+    out_color = color;
+}
@@ -0,0 +1,355 @@
+use crate::{
+    errors::{HandleType, Result},
+    mem::DescriptorSetBuilder,
+    render::{push_constants_as_bytes, GraphicsRecorder, OverlayDescription, OverlayPipeline},
+    DescriptorSetHandle, RenderPlanHandle, SamplerHandle, TextureHandle, VkTracerApp,
+};
+use ash::vk;
+use log::warn;
+use std::{mem::size_of, slice::from_ref};
+
+const VERTEX_GLSL: &str = include_str!("shaders/text.vert");
+const FRAGMENT_GLSL: &str = include_str!("shaders/text.frag");
+
+const GLYPH_SIZE: u32 = 8;
+const ATLAS_COLUMNS: u32 = 16;
+const FIRST_GLYPH: char = ' ';
+/// Drawn for the characters missing from the font.
+const FALLBACK_GLYPH: char = '?';
+
+/// Printable ASCII of the public domain font8x8 by Daniel Hepper, one byte per row with the
+/// leftmost pixel in the lowest bit.
+#[rustfmt::skip]
+const FONT_8X8: [[u8; 8]; 95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // ' '
+    [0x18, 0x3C, 0x3C, 0x18, 0x18, 0x00, 0x18, 0x00], // '!'
+    [0x36, 0x36, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // '"'
+    [0x36, 0x36, 0x7F, 0x36, 0x7F, 0x36, 0x36, 0x00], // '#'
+    [0x0C, 0x3E, 0x03, 0x1E, 0x30, 0x1F, 0x0C, 0x00], // '$'
+    [0x00, 0x63, 0x33, 0x18, 0x0C, 0x66, 0x63, 0x00], // '%'
+    [0x1C, 0x36, 0x1C, 0x6E, 0x3B, 0x33, 0x6E, 0x00], // '&'
+    [0x06, 0x06, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00], // '''
+    [0x18, 0x0C, 0x06, 0x06, 0x06, 0x0C, 0x18, 0x00], // '('
+    [0x06, 0x0C, 0x18, 0x18, 0x18, 0x0C, 0x06, 0x00], // ')'
+    [0x00, 0x66, 0x3C, 0xFF, 0x3C, 0x66, 0x00, 0x00], // '*'
+    [0x00, 0x0C, 0x0C, 0x3F, 0x0C, 0x0C, 0x00, 0x00], // '+'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C, 0x06], // ','
+    [0x00, 0x00, 0x00, 0x3F, 0x00, 0x00, 0x00, 0x00], // '-'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C, 0x00], // '.'
+    [0x60, 0x30, 0x18, 0x0C, 0x06, 0x03, 0x01, 0x00], // '/'
+    [0x3E, 0x63, 0x73, 0x7B, 0x6F, 0x67, 0x3E, 0x00], // '0'
+    [0x0C, 0x0E, 0x0C, 0x0C, 0x0C, 0x0C, 0x3F, 0x00], // '1'
+    [0x1E, 0x33, 0x30, 0x1C, 0x06, 0x33, 0x3F, 0x00], // '2'
+    [0x1E, 0x33, 0x30, 0x1C, 0x30, 0x33, 0x1E, 0x00], // '3'
+    [0x38, 0x3C, 0x36, 0x33, 0x7F, 0x30, 0x78, 0x00], // '4'
+    [0x3F, 0x03, 0x1F, 0x30, 0x30, 0x33, 0x1E, 0x00], // '5'
+    [0x1C, 0x06, 0x03, 0x1F, 0x33, 0x33, 0x1E, 0x00], // '6'
+    [0x3F, 0x33, 0x30, 0x18, 0x0C, 0x0C, 0x0C, 0x00], // '7'
+    [0x1E, 0x33, 0x33, 0x1E, 0x33, 0x33, 0x1E, 0x00], // '8'
+    [0x1E, 0x33, 0x33, 0x3E, 0x30, 0x18, 0x0E, 0x00], // '9'
+    [0x00, 0x0C, 0x0C, 0x00, 0x00, 0x0C, 0x0C, 0x00], // ':'
+    [0x00, 0x0C, 0x0C, 0x00, 0x00, 0x0C, 0x0C, 0x06], // ';'
+    [0x18, 0x0C, 0x06, 0x03, 0x06, 0x0C, 0x18, 0x00], // '<'
+    [0x00, 0x00, 0x3F, 0x00, 0x00, 0x3F, 0x00, 0x00], // '='
+    [0x06, 0x0C, 0x18, 0x30, 0x18, 0x0C, 0x06, 0x00], // '>'
+    [0x1E, 0x33, 0x30, 0x18, 0x0C, 0x00, 0x0C, 0x00], // '?'
+    [0x3E, 0x63, 0x7B, 0x7B, 0x7B, 0x03, 0x1E, 0x00], // '@'
+    [0x0C, 0x1E, 0x33, 0x33, 0x3F, 0x33, 0x33, 0x00], // 'A'
+    [0x3F, 0x66, 0x66, 0x3E, 0x66, 0x66, 0x3F, 0x00], // 'B'
+    [0x3C, 0x66, 0x03, 0x03, 0x03, 0x66, 0x3C, 0x00], // 'C'
+    [0x1F, 0x36, 0x66, 0x66, 0x66, 0x36, 0x1F, 0x00], // 'D'
+    [0x7F, 0x46, 0x16, 0x1E, 0x16, 0x46, 0x7F, 0x00], // 'E'
+    [0x7F, 0x46, 0x16, 0x1E, 0x16, 0x06, 0x0F, 0x00], // 'F'
+    [0x3C, 0x66, 0x03, 0x03, 0x73, 0x66, 0x7C, 0x00], // 'G'
+    [0x33, 0x33, 0x33, 0x3F, 0x33, 0x33, 0x33, 0x00], // 'H'
+    [0x1E, 0x0C, 0x0C, 0x0C, 0x0C, 0x0C, 0x1E, 0x00], // 'I'
+    [0x78, 0x30, 0x30, 0x30, 0x33, 0x33, 0x1E, 0x00], // 'J'
+    [0x67, 0x66, 0x36, 0x1E, 0x36, 0x66, 0x67, 0x00], // 'K'
+    [0x0F, 0x06, 0x06, 0x06, 0x46, 0x66, 0x7F, 0x00], // 'L'
+    [0x63, 0x77, 0x7F, 0x7F, 0x6B, 0x63, 0x63, 0x00], // 'M'
+    [0x63, 0x67, 0x6F, 0x7B, 0x73, 0x63, 0x63, 0x00], // 'N'
+    [0x1C, 0x36, 0x63, 0x63, 0x63, 0x36, 0x1C, 0x00], // 'O'
+    [0x3F, 0x66, 0x66, 0x3E, 0x06, 0x06, 0x0F, 0x00], // 'P'
+    [0x1E, 0x33, 0x33, 0x33, 0x3B, 0x1E, 0x38, 0x00], // 'Q'
+    [0x3F, 0x66, 0x66, 0x3E, 0x36, 0x66, 0x67, 0x00], // 'R'
+    [0x1E, 0x33, 0x07, 0x0E, 0x38, 0x33, 0x1E, 0x00], // 'S'
+    [0x3F, 0x2D, 0x0C, 0x0C, 0x0C, 0x0C, 0x1E, 0x00], // 'T'
+    [0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x3F, 0x00], // 'U'
+    [0x33, 0x33, 0x33, 0x33, 0x33, 0x1E, 0x0C, 0x00], // 'V'
+    [0x63, 0x63, 0x63, 0x6B, 0x7F, 0x77, 0x63, 0x00], // 'W'
+    [0x63, 0x63, 0x36, 0x1C, 0x1C, 0x36, 0x63, 0x00], // 'X'
+    [0x33, 0x33, 0x33, 0x1E, 0x0C, 0x0C, 0x1E, 0x00], // 'Y'
+    [0x7F, 0x63, 0x31, 0x18, 0x4C, 0x66, 0x7F, 0x00], // 'Z'
+    [0x1E, 0x06, 0x06, 0x06, 0x06, 0x06, 0x1E, 0x00], // '['
+    [0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x40, 0x00], // '\'
+    [0x1E, 0x18, 0x18, 0x18, 0x18, 0x18, 0x1E, 0x00], // ']'
+    [0x08, 0x1C, 0x36, 0x63, 0x00, 0x00, 0x00, 0x00], // '^'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xFF], // '_'
+    [0x0C, 0x0C, 0x18, 0x00, 0x00, 0x00, 0x00, 0x00], // '`'
+    [0x00, 0x00, 0x1E, 0x30, 0x3E, 0x33, 0x6E, 0x00], // 'a'
+    [0x07, 0x06, 0x06, 0x3E, 0x66, 0x66, 0x3B, 0x00], // 'b'
+    [0x00, 0x00, 0x1E, 0x33, 0x03, 0x33, 0x1E, 0x00], // 'c'
+    [0x38, 0x30, 0x30, 0x3E, 0x33, 0x33, 0x6E, 0x00], // 'd'
+    [0x00, 0x00, 0x1E, 0x33, 0x3F, 0x03, 0x1E, 0x00], // 'e'
+    [0x1C, 0x36, 0x06, 0x0F, 0x06, 0x06, 0x0F, 0x00], // 'f'
+    [0x00, 0x00, 0x6E, 0x33, 0x33, 0x3E, 0x30, 0x1F], // 'g'
+    [0x07, 0x06, 0x36, 0x6E, 0x66, 0x66, 0x67, 0x00], // 'h'
+    [0x0C, 0x00, 0x0E, 0x0C, 0x0C, 0x0C, 0x1E, 0x00], // 'i'
+    [0x30, 0x00, 0x30, 0x30, 0x30, 0x33, 0x33, 0x1E], // 'j'
+    [0x07, 0x06, 0x66, 0x36, 0x1E, 0x36, 0x67, 0x00], // 'k'
+    [0x0E, 0x0C, 0x0C, 0x0C, 0x0C, 0x0C, 0x1E, 0x00], // 'l'
+    [0x00, 0x00, 0x33, 0x7F, 0x7F, 0x6B, 0x63, 0x00], // 'm'
+    [0x00, 0x00, 0x1F, 0x33, 0x33, 0x33, 0x33, 0x00], // 'n'
+    [0x00, 0x00, 0x1E, 0x33, 0x33, 0x33, 0x1E, 0x00], // 'o'
+    [0x00, 0x00, 0x3B, 0x66, 0x66, 0x3E, 0x06, 0x0F], // 'p'
+    [0x00, 0x00, 0x6E, 0x33, 0x33, 0x3E, 0x30, 0x78], // 'q'
+    [0x00, 0x00, 0x3B, 0x6E, 0x66, 0x06, 0x0F, 0x00], // 'r'
+    [0x00, 0x00, 0x3E, 0x03, 0x1E, 0x30, 0x1F, 0x00], // 's'
+    [0x08, 0x0C, 0x3E, 0x0C, 0x0C, 0x2C, 0x18, 0x00], // 't'
+    [0x00, 0x00, 0x33, 0x33, 0x33, 0x33, 0x6E, 0x00], // 'u'
+    [0x00, 0x00, 0x33, 0x33, 0x33, 0x1E, 0x0C, 0x00], // 'v'
+    [0x00, 0x00, 0x63, 0x6B, 0x7F, 0x7F, 0x36, 0x00], // 'w'
+    [0x00, 0x00, 0x63, 0x36, 0x1C, 0x36, 0x63, 0x00], // 'x'
+    [0x00, 0x00, 0x33, 0x33, 0x33, 0x3E, 0x30, 0x1F], // 'y'
+    [0x00, 0x00, 0x3F, 0x19, 0x0C, 0x26, 0x3F, 0x00], // 'z'
+    [0x38, 0x0C, 0x0C, 0x07, 0x0C, 0x0C, 0x38, 0x00], // '{'
+    [0x18, 0x18, 0x18, 0x00, 0x18, 0x18, 0x18, 0x00], // '|'
+    [0x07, 0x0C, 0x0C, 0x38, 0x0C, 0x0C, 0x07, 0x00], // '}'
+    [0x6E, 0x3B, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // '~'
+];
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct TextVertex {
+    position: [f32; 2],
+    uv: [f32; 2],
+    color: [f32; 4],
+}
+
+/// Screen space text with a built-in 8x8 bitmap font, for FPS counters and debug output.
+/// Text is queued during the frame and drawn on top of the subpass by
+/// [GraphicsRecorder::draw_text], which also clears it.
+pub struct TextRenderer {
+    vertices: Vec<TextVertex>,
+    overlay: OverlayPipeline,
+    atlas: TextureHandle,
+    sampler: SamplerHandle,
+    descriptor_set: DescriptorSetHandle,
+}
+
+impl TextRenderer {
+    /// Queue `text` with its top left corner at `position`, in pixels from the top left of the
+    /// render target. Glyphs are `8 * scale` pixels wide and high, `\n` starts a new line and
+    /// the characters outside of printable ASCII are drawn as `?`.
+    pub fn text(
+        &mut self,
+        position: [f32; 2],
+        scale: f32,
+        color: [f32; 4],
+        text: &str,
+    ) -> &mut Self {
+        let size = GLYPH_SIZE as f32 * scale;
+        let [mut x, mut y] = position;
+        for c in text.chars() {
+            if c == '\n' {
+                x = position[0];
+                y += size;
+                continue;
+            }
+            if c != ' ' {
+                self.glyph([x, y], size, color, c);
+            }
+            x += size;
+        }
+        self
+    }
+
+    /// Size in pixels of `text` queued with [TextRenderer::text].
+    pub fn measure(text: &str, scale: f32) -> [f32; 2] {
+        let size = GLYPH_SIZE as f32 * scale;
+        let columns = text.lines().map(|line| line.chars().count()).max();
+        let lines = text.lines().count();
+        [columns.unwrap_or(0) as f32 * size, lines as f32 * size]
+    }
+
+    fn glyph(&mut self, position: [f32; 2], size: f32, color: [f32; 4], c: char) {
+        let index = if (FIRST_GLYPH..='~').contains(&c) {
+            c as u32 - FIRST_GLYPH as u32
+        } else {
+            FALLBACK_GLYPH as u32 - FIRST_GLYPH as u32
+        };
+        let rows = (FONT_8X8.len() as u32 + ATLAS_COLUMNS - 1) / ATLAS_COLUMNS;
+        let uv_size = [1.0 / ATLAS_COLUMNS as f32, 1.0 / rows as f32];
+        let uv = [
+            (index % ATLAS_COLUMNS) as f32 * uv_size[0],
+            (index / ATLAS_COLUMNS) as f32 * uv_size[1],
+        ];
+
+        let corner = |dx: f32, dy: f32| TextVertex {
+            position: [position[0] + dx * size, position[1] + dy * size],
+            uv: [uv[0] + dx * uv_size[0], uv[1] + dy * uv_size[1]],
+            color,
+        };
+        let (top_left, top_right) = (corner(0.0, 0.0), corner(1.0, 0.0));
+        let (bottom_left, bottom_right) = (corner(0.0, 1.0), corner(1.0, 1.0));
+        self.vertices.extend_from_slice(&[
+            top_left,
+            bottom_left,
+            top_right,
+            top_right,
+            bottom_left,
+            bottom_right,
+        ]);
+    }
+
+    /// Forget the text of this frame without drawing it.
+    pub fn clear(&mut self) {
+        self.vertices.clear();
+    }
+}
+
+/// One R8 glyph per cell, 16 glyphs per row.
+fn font_atlas() -> (u32, u32, Vec<u8>) {
+    let rows = (FONT_8X8.len() as u32 + ATLAS_COLUMNS - 1) / ATLAS_COLUMNS;
+    let width = ATLAS_COLUMNS * GLYPH_SIZE;
+    let height = rows * GLYPH_SIZE;
+    let mut pixels = vec![0; (width * height) as usize];
+    for (index, glyph) in FONT_8X8.iter().enumerate() {
+        let origin_x = (index as u32 % ATLAS_COLUMNS) * GLYPH_SIZE;
+        let origin_y = (index as u32 / ATLAS_COLUMNS) * GLYPH_SIZE;
+        for (y, row) in glyph.iter().enumerate() {
+            for x in 0..GLYPH_SIZE {
+                if row & (1 << x) != 0 {
+                    let pixel = (origin_y + y as u32) * width + origin_x + x;
+                    pixels[pixel as usize] = 0xFF;
+                }
+            }
+        }
+    }
+    (width, height, pixels)
+}
+
+impl VkTracerApp {
+    /// The text is drawn in the given subpass, which should be the last one of the final
+    /// image, over everything. At most `max_glyphs` are drawn per frame.
+    pub fn create_text_renderer(
+        &mut self,
+        render_plan: RenderPlanHandle,
+        subpass: u32,
+        max_glyphs: usize,
+    ) -> Result<TextRenderer> {
+        let (width, height, pixels) = font_atlas();
+        let atlas = self.create_texture(width, height, vk::Format::R8_UNORM, &pixels)?;
+        // Sharp pixels at integer scales
+        let sampler =
+            self.create_sampler(vk::Filter::NEAREST, vk::SamplerAddressMode::CLAMP_TO_EDGE)?;
+
+        let descriptor_set = self
+            .new_descriptor_sets()
+            .new_set(
+                DescriptorSetBuilder::new()
+                    .sampled_images(0, 1, vk::ShaderStageFlags::FRAGMENT)
+                    .immutable_samplers(1, &[sampler], vk::ShaderStageFlags::FRAGMENT),
+            )
+            .build()?[0];
+        let atlas_view = self.get_texture_view(atlas)?;
+        self.write_descriptor_set_sampled_image(descriptor_set, 0, 0, atlas_view)?;
+        let set_layout = storage_access!(
+            self.descriptor_set_storage,
+            descriptor_set,
+            HandleType::DescriptorSet
+        )
+        .layout;
+
+        let overlay = self.create_overlay_pipeline(
+            render_plan,
+            subpass,
+            max_glyphs * 6,
+            &OverlayDescription {
+                name: "text",
+                vertex_glsl: VERTEX_GLSL,
+                fragment_glsl: FRAGMENT_GLSL,
+                stride: size_of::<TextVertex>(),
+                attributes: &[
+                    vk::VertexInputAttributeDescription {
+                        location: 0,
+                        binding: 0,
+                        format: vk::Format::R32G32_SFLOAT,
+                        offset: 0,
+                    },
+                    vk::VertexInputAttributeDescription {
+                        location: 1,
+                        binding: 0,
+                        format: vk::Format::R32G32_SFLOAT,
+                        offset: 8,
+                    },
+                    vk::VertexInputAttributeDescription {
+                        location: 2,
+                        binding: 0,
+                        format: vk::Format::R32G32B32A32_SFLOAT,
+                        offset: 16,
+                    },
+                ],
+                topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+                depth_test: false,
+                set_layouts: from_ref(&set_layout),
+                push_constants_size: size_of::<[f32; 2]>() as u32,
+            },
+        )?;
+
+        Ok(TextRenderer {
+            vertices: Vec::with_capacity(max_glyphs * 6),
+            overlay,
+            atlas,
+            sampler,
+            descriptor_set,
+        })
+    }
+
+    /// The text renderer must not be used by pending commands.
+    pub fn destroy_text_renderer(&mut self, text: TextRenderer) -> Result<()> {
+        self.destroy_overlay_pipeline(text.overlay)?;
+        // The sampler is embedded in the layout of the set
+        self.destroy_descriptor_set(text.descriptor_set)?;
+        self.destroy_sampler(text.sampler)?;
+        self.destroy_texture(text.atlas)
+    }
+}
+
+impl GraphicsRecorder<'_> {
+    /// Draw the text queued in the renderer since the last call and clear it, it can be called
+    /// several times per frame. Binds the text pipeline, a pipeline must be bound again to draw
+    /// anything else.
+    pub fn draw_text(self, text: &mut TextRenderer) -> Result<Self> {
+        // Whole glyphs only
+        let max_vertices = self.overlay_vertices_left(&text.overlay) / 6 * 6;
+        if text.vertices.len() > max_vertices {
+            warn!(
+                "Too many glyphs in this frame ({} > {}), the last ones are dropped",
+                text.vertices.len() / 6,
+                max_vertices / 6
+            );
+        }
+        let count = text.vertices.len().min(max_vertices);
+        if count == 0 {
+            text.clear();
+            return Ok(self);
+        }
+
+        let descriptor_set = storage_access!(
+            self.app.descriptor_set_storage,
+            text.descriptor_set,
+            HandleType::DescriptorSet
+        )
+        .handle;
+        let target_size = [self.extent.width as f32, self.extent.height as f32];
+        let recorder = self.bind_overlay(
+            &text.overlay,
+            &text.vertices[..count],
+            from_ref(&descriptor_set),
+            push_constants_as_bytes(&target_size)?,
+        );
+        text.clear();
+        recorder?.draw(count as u32, 1, 0, 0)
+    }
+}
@@ -0,0 +1,126 @@
+use proc_macro::TokenStream;
+use quote::{quote, ToTokens};
+use syn::{parse_macro_input, spanned::Spanned, Data, DeriveInput, Fields, Ident, Lit, Meta};
+
+/// Implement `vk_tracer::mesh::MeshVertex` for a struct, one attribute per field in order of
+/// declaration starting at location 0, all in binding 0.
+///
+/// The format of a field comes from its type through `vk_tracer::mesh::VertexAttribute`, or
+/// can be given with `#[vertex(format = "R16G16_SFLOAT")]`.
+#[proc_macro_derive(VertexLayout, attributes(vertex))]
+pub fn derive_vertex_layout(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match vertex_layout(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+fn vertex_layout(input: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let name = &input.ident;
+    if !input.generics.params.is_empty() {
+        return Err(syn::Error::new(
+            input.generics.span(),
+            "VertexLayout can't be derived for generic structs",
+        ));
+    }
+
+    let fields = match &input.data {
+        Data::Struct(data) => &data.fields,
+        _ => {
+            return Err(syn::Error::new(
+                input.span(),
+                "VertexLayout can only be derived for structs",
+            ))
+        }
+    };
+
+    let mut attributes = Vec::new();
+    let members: Vec<proc_macro2::TokenStream> = match fields {
+        Fields::Named(fields) => fields
+            .named
+            .iter()
+            .map(|field| field.ident.to_token_stream())
+            .collect(),
+        Fields::Unnamed(fields) => (0..fields.unnamed.len())
+            .map(|i| syn::Index::from(i).to_token_stream())
+            .collect(),
+        Fields::Unit => Vec::new(),
+    };
+
+    for (location, (field, member)) in fields.iter().zip(&members).enumerate() {
+        let location = location as u32;
+        let ty = &field.ty;
+        let format = match format_override(field)? {
+            Some(format) => quote!(::vk_tracer::__private::vk::Format::#format),
+            None => quote!(<#ty as ::vk_tracer::mesh::VertexAttribute>::FORMAT),
+        };
+        attributes.push(quote! {
+            ::vk_tracer::__private::vk::VertexInputAttributeDescription::builder()
+                .binding(0)
+                .location(#location)
+                .format(#format)
+                .offset(::vk_tracer::__private::offset_of!(#name => #member).get_byte_offset() as u32)
+                .build()
+        });
+    }
+    let attribute_count = attributes.len();
+
+    Ok(quote! {
+        impl ::vk_tracer::mesh::MeshVertex for #name {
+            fn binding_description() -> &'static [::vk_tracer::__private::vk::VertexInputBindingDescription] {
+                ::vk_tracer::__private::lazy_static! {
+                    static ref BINDING_DESC: [::vk_tracer::__private::vk::VertexInputBindingDescription; 1] = [
+                        ::vk_tracer::__private::vk::VertexInputBindingDescription::builder()
+                            .binding(0)
+                            .stride(::std::mem::size_of::<#name>() as u32)
+                            .input_rate(::vk_tracer::__private::vk::VertexInputRate::VERTEX)
+                            .build(),
+                    ];
+                }
+                &*BINDING_DESC
+            }
+
+            fn attribute_description() -> &'static [::vk_tracer::__private::vk::VertexInputAttributeDescription] {
+                ::vk_tracer::__private::lazy_static! {
+                    static ref ATTRIBUTE_DESC: [::vk_tracer::__private::vk::VertexInputAttributeDescription; #attribute_count] = [
+                        #(#attributes),*
+                    ];
+                }
+                &*ATTRIBUTE_DESC
+            }
+        }
+    })
+}
+
+/// The format in `#[vertex(format = "...")]`, a variant of `vk::Format`.
+fn format_override(field: &syn::Field) -> syn::Result<Option<Ident>> {
+    let mut format = None;
+    for attr in field
+        .attrs
+        .iter()
+        .filter(|attr| attr.path.is_ident("vertex"))
+    {
+        let list = match attr.parse_meta()? {
+            Meta::List(list) => list,
+            meta => {
+                return Err(syn::Error::new(
+                    meta.span(),
+                    "expected #[vertex(format = \"...\")]",
+                ))
+            }
+        };
+        for nested in list.nested {
+            match nested {
+                syn::NestedMeta::Meta(Meta::NameValue(value)) if value.path.is_ident("format") => {
+                    format = Some(match value.lit {
+                        Lit::Str(s) => Ident::new(&s.value(), s.span()),
+                        lit => return Err(syn::Error::new(lit.span(), "expected a format name")),
+                    });
+                }
+                nested => return Err(syn::Error::new(nested.span(), "unknown vertex attribute")),
+            }
+        }
+    }
+    Ok(format)
+}